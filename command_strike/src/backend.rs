@@ -0,0 +1,356 @@
+use crate::cost::TokenUsage;
+use crate::llm::{ApiError, OpenAiResponse};
+use anyhow::{Context, Result};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// A single model call: the prompt, its system prompt and the model to ask
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlmRequest {
+    pub model: String,
+    pub system: Option<String>,
+    pub prompt: String,
+    /// Sampling options for backends that generate; replaying ignores them
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub seed: Option<u64>,
+}
+
+/// A model's complete answer to an `LlmRequest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LlmResponse {
+    /// The response exactly as generated
+    pub text: String,
+    /// Why the model stopped ("stop", "length", "load"), if known
+    pub done_reason: Option<String>,
+    /// Tokens used, when the server reported them
+    pub usage: Option<TokenUsage>,
+    /// Time the server spent generating, when it reported it
+    pub eval_duration: Option<Duration>,
+}
+
+/// One item of a backend's streamed answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamPiece {
+    /// The next piece of text
+    Text(String),
+    /// The end of the answer, with whatever the server said about it
+    Finished {
+        done_reason: Option<String>,
+        usage: Option<TokenUsage>,
+    },
+}
+
+/// Something that answers model calls in place of the Ollama server
+///
+/// When an `OllamaClient` has a backend set, every generation (streamed or not) is
+/// sent to it; the prompt building and response cleaning around it are unchanged.
+pub trait LlmBackend: Send + Sync + fmt::Debug {
+    /// Answer one request
+    fn generate<'a>(&'a self, request: &'a LlmRequest) -> BoxFuture<'a, Result<LlmResponse>>;
+
+    /// Answer one request as a stream of text pieces followed by `Finished`, ending
+    /// early with an error if it fails
+    ///
+    /// Backends that can't stream send the whole response as one piece.
+    fn stream<'a>(&'a self, request: &'a LlmRequest) -> BoxFuture<'a, Result<mpsc::Receiver<Result<StreamPiece>>>> {
+        Box::pin(async move {
+            let response = self.generate(request).await?;
+            let (tx, rx) = mpsc::channel(2);
+            let _ = tx.try_send(Ok(StreamPiece::Text(response.text)));
+            let _ = tx.try_send(Ok(StreamPiece::Finished { done_reason: response.done_reason, usage: response.usage }));
+            Ok(rx)
+        })
+    }
+}
+
+/// Which API the configured server speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiFormat {
+    /// Ollama's `/api/generate` and `/api/chat`
+    #[default]
+    Ollama,
+    /// OpenAI-style `/v1/chat/completions`, as served by vLLM, llama.cpp, LM Studio and others
+    OpenAi,
+}
+
+impl ApiFormat {
+    /// Parse a format name: `ollama` or `openai`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "ollama" => Some(Self::Ollama),
+            "openai" | "open-ai" | "openai-compatible" => Some(Self::OpenAi),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApiFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiFormat::Ollama => write!(f, "ollama"),
+            ApiFormat::OpenAi => write!(f, "openai"),
+        }
+    }
+}
+
+/// Talks to any server implementing the OpenAI chat completions API
+///
+/// The system prompt is sent as a system message and the prompt as a user message.
+/// Extra headers such as `Authorization: Bearer ...` come from the HTTP client.
+#[derive(Debug, Clone)]
+pub struct OpenAiBackend {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatCompletionMessage<'a>>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Serialize)]
+struct StreamOptions {
+    /// Ask for a final chunk carrying the token counts
+    include_usage: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+impl OpenAiBackend {
+    /// A backend for the server at `base_url`, with or without a trailing `/v1`
+    pub fn new(client: reqwest::Client, base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        let base_url = base_url.strip_suffix("/v1").unwrap_or(base_url).to_string();
+        Self { client, base_url }
+    }
+
+    /// Send a chat completion request, failing on an error status
+    async fn send(&self, request: &LlmRequest, stream: bool) -> Result<reqwest::Response> {
+        let mut messages = Vec::new();
+        if let Some(system) = &request.system {
+            messages.push(ChatCompletionMessage { role: "system", content: system });
+        }
+        messages.push(ChatCompletionMessage { role: "user", content: &request.prompt });
+        let body = ChatCompletionRequest {
+            model: &request.model,
+            messages,
+            stream,
+            temperature: request.temperature,
+            max_tokens: request.max_tokens,
+            stop: request.stop.as_deref(),
+            seed: request.seed,
+            stream_options: stream.then_some(StreamOptions { include_usage: true }),
+        };
+        
+        let url = format!("{}/v1/chat/completions", self.base_url);
+        debug!("Sending chat completion request to {}", url);
+        let response = self.client.post(&url)
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to connect to {}", url))?;
+        
+        let status = response.status().as_u16();
+        if !response.status().is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(ApiError { api: ApiFormat::OpenAi, status, message: openai_error_message(&body) }.into());
+        }
+        Ok(response)
+    }
+}
+
+impl LlmBackend for OpenAiBackend {
+    fn generate<'a>(&'a self, request: &'a LlmRequest) -> BoxFuture<'a, Result<LlmResponse>> {
+        Box::pin(async move {
+            let response: OpenAiResponse = self.send(request, false).await?
+                .json()
+                .await
+                .context("Failed to parse chat completion response")?;
+            let choice = response.choices.into_iter().next().context("Chat completion response has no choices")?;
+            Ok(LlmResponse {
+                text: choice.message.and_then(|message| message.content).unwrap_or_default(),
+                done_reason: choice.finish_reason,
+                usage: response.usage.as_ref().map(TokenUsage::from),
+                eval_duration: None,
+            })
+        })
+    }
+
+    fn stream<'a>(&'a self, request: &'a LlmRequest) -> BoxFuture<'a, Result<mpsc::Receiver<Result<StreamPiece>>>> {
+        Box::pin(async move {
+            let response = self.send(request, true).await?;
+            let (tx, rx) = mpsc::channel(100);
+            
+            // Server-sent events: `data: {json}` lines, ending with `data: [DONE]`
+            tokio::spawn(async move {
+                let mut body = response.bytes_stream();
+                // Raw bytes, so a character split between chunks is decoded whole
+                let mut pending: Vec<u8> = Vec::new();
+                let mut done_reason = None;
+                let mut usage = None;
+                'body: while let Some(chunk) = body.next().await {
+                    let chunk = match chunk {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            warn!("Chat completion stream failed: {}", e);
+                            let _ = tx.send(Err(anyhow::anyhow!("Stream interrupted: {}", e))).await;
+                            return;
+                        }
+                    };
+                    pending.extend_from_slice(&chunk);
+                    while let Some(line) = next_line(&mut pending) {
+                        let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else { continue };
+                        if data == "[DONE]" {
+                            break 'body;
+                        }
+                        let Ok(event) = serde_json::from_str::<OpenAiResponse>(data) else { continue };
+                        // With `include_usage` the counts arrive in a last chunk with no choices
+                        if let Some(event_usage) = &event.usage {
+                            usage = Some(TokenUsage::from(event_usage));
+                        }
+                        let Some(choice) = event.choices.into_iter().next() else { continue };
+                        if choice.finish_reason.is_some() {
+                            done_reason = choice.finish_reason;
+                        }
+                        let content = choice.delta.and_then(|delta| delta.content);
+                        if let Some(content) = content.filter(|content| !content.is_empty()) {
+                            if tx.send(Ok(StreamPiece::Text(content))).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                let _ = tx.send(Ok(StreamPiece::Finished { done_reason, usage })).await;
+            });
+            Ok(rx)
+        })
+    }
+}
+
+/// Take the first complete line out of `buffer`, leaving any partial line for the next chunk
+pub(crate) fn next_line(buffer: &mut Vec<u8>) -> Option<String> {
+    let newline = buffer.iter().position(|b| *b == b'\n')?;
+    let line: Vec<u8> = buffer.drain(..=newline).collect();
+    Some(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// The message from an OpenAI-style error body, or the body itself
+fn openai_error_message(body: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|value| value["error"]["message"].as_str().map(ToString::to_string))
+        .unwrap_or_else(|| body.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn request(prompt: &str) -> LlmRequest {
+        LlmRequest {
+            model: "qwen2.5-coder".to_string(),
+            system: Some("Reply with a command".to_string()),
+            prompt: prompt.to_string(),
+            temperature: Some(0.2),
+            max_tokens: Some(64),
+            stop: None,
+            seed: None,
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_openai_generate_and_stream() {
+        let mut server = mockito::Server::new_async().await;
+        let generate = server.mock("POST", "/v1/chat/completions")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"model":"qwen2.5-coder","stream":false,"messages":[{"role":"system","content":"Reply with a command"},{"role":"user","content":"list files"}]}"#.to_string(),
+            ))
+            .with_body(r#"{"choices":[{"index":0,"message":{"role":"assistant","content":"ls -la"},"finish_reason":"stop"}],"usage":{"prompt_tokens":20,"completion_tokens":3}}"#)
+            .create_async()
+            .await;
+        let stream = server.mock("POST", "/v1/chat/completions")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"stream":true,"stream_options":{"include_usage":true}}"#.to_string()))
+            .with_body(concat!(
+                "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"ls\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\" -la\"},\"finish_reason\":\"stop\"}]}\n\n",
+                "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":20,\"completion_tokens\":3}}\n\n",
+                "data: [DONE]\n\n",
+            ))
+            .create_async()
+            .await;
+        
+        let backend = OpenAiBackend::new(reqwest::Client::new(), &format!("{}/v1/", server.url()));
+        let response = backend.generate(&request("list files")).await.unwrap();
+        assert_eq!(response.text, "ls -la");
+        assert_eq!(response.done_reason.as_deref(), Some("stop"));
+        let usage = TokenUsage { input_tokens: 20, output_tokens: 3 };
+        assert_eq!(response.usage, Some(usage));
+        generate.assert_async().await;
+        
+        let mut pieces = backend.stream(&request("list files")).await.unwrap();
+        let mut received = Vec::new();
+        while let Some(piece) = pieces.recv().await {
+            received.push(piece.unwrap());
+        }
+        assert_eq!(received, [
+            StreamPiece::Text("ls".to_string()),
+            StreamPiece::Text(" -la".to_string()),
+            StreamPiece::Finished { done_reason: Some("stop".to_string()), usage: Some(usage) },
+        ]);
+        stream.assert_async().await;
+    }
+    
+    #[tokio::test]
+    async fn test_openai_error_message() {
+        let mut server = mockito::Server::new_async().await;
+        let _missing = server.mock("POST", "/v1/chat/completions")
+            .with_status(404)
+            .with_body(r#"{"error":{"message":"The model `qwen2.5-coder` does not exist.","type":"NotFoundError"}}"#)
+            .create_async()
+            .await;
+        
+        let backend = OpenAiBackend::new(reqwest::Client::new(), &server.url());
+        let error = backend.generate(&request("list files")).await.unwrap_err();
+        assert_eq!(error.to_string(), "OpenAI-compatible API error (404): The model `qwen2.5-coder` does not exist.");
+        // The status is kept so retries can tell a busy server from a bad request
+        assert_eq!(error.downcast_ref::<ApiError>().map(|e| e.status), Some(404));
+        assert_eq!(ApiFormat::parse("OpenAI"), Some(ApiFormat::OpenAi));
+    }
+    
+    #[test]
+    fn test_lines_split_across_chunks() {
+        let line = "data: {\"choices\":[{\"delta\":{\"content\":\"caf\u{e9}\"}}]}\n".as_bytes();
+        let split = line.iter().position(|b| *b == 0xc3).unwrap() + 1;
+        
+        let mut buffer = line[..split].to_vec();
+        assert_eq!(next_line(&mut buffer), None);
+        buffer.extend_from_slice(&line[split..]);
+        assert_eq!(next_line(&mut buffer).unwrap(), "data: {\"choices\":[{\"delta\":{\"content\":\"caf\u{e9}\"}}]}\n");
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::llm::{GeneratedCommand, OllamaClient};
+use anyhow::Result;
+use log::warn;
+use serde::Serialize;
+use std::io::{BufRead, Write};
+
+/// How batch results are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchFormat {
+    /// A shell script with each request as a comment above its command
+    Script,
+    /// One JSON object per line, written as each request completes
+    Ndjson,
+}
+
+/// A generated command for one request, as written in NDJSON batch output and `--json` mode
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandResult {
+    pub request: String,
+    pub command: String,
+    pub model: String,
+    pub elapsed_ms: u64,
+    /// Risk level of the command ("Safe", "Caution" or "Destructive")
+    pub risk: String,
+    /// Why the command got that risk level
+    pub risk_reasons: Vec<String>,
+    /// True if generation hit the token limit, so the command may be incomplete
+    pub truncated: bool,
+    /// Token counts and speed, when the server reported them
+    pub prompt_tokens: Option<u64>,
+    pub tokens_generated: Option<u64>,
+    pub tokens_per_sec: Option<f64>,
+    /// The full explanation, when one was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+}
+
+impl CommandResult {
+    pub fn new(request: &str, generated: GeneratedCommand) -> Self {
+        let stats = generated.stats();
+        Self {
+            request: request.to_string(),
+            risk: generated.risk.level.to_string(),
+            risk_reasons: generated.risk.reasons,
+            truncated: generated.truncated,
+            prompt_tokens: stats.as_ref().map(|stats| stats.prompt_tokens),
+            tokens_generated: stats.as_ref().map(|stats| stats.tokens_generated),
+            tokens_per_sec: stats.as_ref().and_then(|stats| stats.tokens_per_sec()),
+            elapsed_ms: generated.elapsed.as_millis() as u64,
+            command: generated.command,
+            model: generated.params.model,
+            explanation: None,
+        }
+    }
+}
+
+/// A batch request that failed to generate
+#[derive(Debug, Clone, Serialize)]
+struct BatchError<'a> {
+    request: &'a str,
+    error: String,
+}
+
+/// Requests in a batch file: one per line, skipping blank lines and `#` comments
+pub fn read_requests(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Generate a command for each request in turn, writing each result as soon as it completes
+///
+/// Requests are independent of each other (no history is shared). A failed request is
+/// reported in the output and the batch carries on. Returns the number of failures.
+pub async fn run_batch(client: &OllamaClient, requests: &[String], format: BatchFormat, out: &mut impl Write) -> Result<usize> {
+    if format == BatchFormat::Script {
+        writeln!(out, "#!/bin/sh")?;
+        writeln!(out, "# Generated by CommandStrike with {}", client.model())?;
+    }
+    
+    let mut failures = 0;
+    for request in requests {
+        match client.generate_command_detailed(request, &[]).await {
+            Ok(generated) => {
+                let result = CommandResult::new(request, generated);
+                match format {
+                    BatchFormat::Script => writeln!(out, "\n# {}\n{}", result.request, result.command)?,
+                    BatchFormat::Ndjson => writeln!(out, "{}", serde_json::to_string(&result)?)?,
+                }
+            }
+            Err(e) => {
+                warn!("Batch request '{}' failed: {:#}", request, e);
+                failures += 1;
+                let error = format!("{:#}", e);
+                match format {
+                    BatchFormat::Script => writeln!(out, "\n# {}\n# error: {}", request, error)?,
+                    BatchFormat::Ndjson => writeln!(out, "{}", serde_json::to_string(&BatchError { request, error })?)?,
+                }
+            }
+        }
+        out.flush()?;
+    }
+    Ok(failures)
+}
+
+/// Answer each request read from `input` (one per line) with a single JSON object on `out`
+///
+/// Lines are handled as they arrive, so another program can hold a conversation over a
+/// pipe. With `explain` each object also carries the command's explanation, collected
+/// from the stream before anything is written. Failed requests produce
+/// `{"request": ..., "error": ...}`. Returns the number of failures.
+pub async fn run_json(client: &OllamaClient, input: impl BufRead, explain: bool, out: &mut impl Write) -> Result<usize> {
+    let mut failures = 0;
+    for line in input.lines() {
+        let line = line?;
+        let request = line.trim();
+        if request.is_empty() || request.starts_with('#') {
+            continue;
+        }
+        
+        match client.generate_command_detailed(request, &[]).await {
+            Ok(generated) => {
+                let mut result = CommandResult::new(request, generated);
+                if explain {
+                    let explanation = match client.stream_explanation(&result.command, client.concise_explanations()).await {
+                        Ok(stream) => stream.into_final().await,
+                        Err(e) => Err(e),
+                    };
+                    match explanation {
+                        Ok(text) => result.explanation = Some(text.trim().to_string()),
+                        Err(e) => warn!("Could not explain '{}': {:#}", result.command, e),
+                    }
+                }
+                writeln!(out, "{}", serde_json::to_string(&result)?)?;
+            }
+            Err(e) => {
+                warn!("Request '{}' failed: {:#}", request, e);
+                failures += 1;
+                let error = format!("{:#}", e);
+                writeln!(out, "{}", serde_json::to_string(&BatchError { request, error })?)?;
+            }
+        }
+        out.flush()?;
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::OllamaConfig;
+    
+    #[tokio::test]
+    async fn test_ndjson_batch() {
+        let mut server = mockito::Server::new_async().await;
+        let _ports = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("list open ports".to_string()))
+            .with_body(r#"{"model":"test","response":"ss -tuln","done":true}"#)
+            .create_async()
+            .await;
+        let _broken = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("wipe the disk".to_string()))
+            .with_status(500)
+            .with_body("model crashed")
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).model("test").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let requests = read_requests("# recon\nlist open ports\n\nwipe the disk\n");
+        assert_eq!(requests, ["list open ports", "wipe the disk"]);
+        
+        let mut out = Vec::new();
+        let failures = run_batch(&client, &requests, BatchFormat::Ndjson, &mut out).await.unwrap();
+        assert_eq!(failures, 1);
+        
+        let lines: Vec<serde_json::Value> = String::from_utf8(out).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["request"], "list open ports");
+        assert_eq!(lines[0]["command"], "ss -tuln");
+        assert_eq!(lines[0]["model"], "test");
+        assert_eq!(lines[0]["risk"], "Safe");
+        assert!(lines[0]["elapsed_ms"].is_u64());
+        assert!(lines[1]["error"].as_str().unwrap().contains("model crashed"));
+    }
+    
+    #[tokio::test]
+    async fn test_json_mode_explains_in_one_object() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"stream":false}"#.to_string()))
+            .with_body(r#"{"model":"test","response":"rm -rf /tmp/scan","done":true,"prompt_eval_count":40,"eval_count":8,"eval_duration":400000000}"#)
+            .create_async()
+            .await;
+        let _explain = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"stream":true}"#.to_string()))
+            .with_body(concat!(
+                r#"{"model":"test","response":"Deletes the ","done":false}"#, "\n",
+                r#"{"model":"test","response":"scan directory.","done":false}"#, "\n",
+                r#"{"model":"test","response":"","done":true}"#, "\n",
+            ))
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).model("test").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let mut out = Vec::new();
+        let failures = run_json(&client, "clean up\n\n".as_bytes(), true, &mut out).await.unwrap();
+        assert_eq!(failures, 0);
+        
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        let result: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(result["command"], "rm -rf /tmp/scan");
+        assert_eq!(result["risk"], "Destructive");
+        assert_eq!(result["risk_reasons"][0], "recursively or forcibly deletes files");
+        assert_eq!(result["tokens_generated"], 8);
+        assert_eq!(result["tokens_per_sec"], 20.0);
+        assert_eq!(result["explanation"], "Deletes the scan directory.");
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use colored::Colorize;
-use command_strike::llm::{OllamaClient, OllamaConfig, HistoryItem, check_ollama_running, validate_model};
+use command_strike::llm::{OllamaClient, OllamaConfig, HistoryItem, StreamChunk};
 use std::io::{self, Write};
 use tokio::time::Instant;
 
@@ -8,9 +8,22 @@ use tokio::time::Instant;
 async fn main() -> Result<()> {
     // Initialize the OllamaClient with default settings (gemma3:12b)
     println!("{}", "CommandStrike Ollama Integration Test".green().bold());
+    let model = "gemma3:12b";
+    
+    // Create client with custom configuration
+    let config = OllamaConfig {
+        model: model.to_string(),
+        temperature: Some(0.5),  // Lower for more deterministic responses
+        max_tokens: 2048,
+        ..OllamaConfig::default()
+    };
+    
+    let client = OllamaClient::with_config(config)?;
+    println!("{}", "OllamaClient initialized successfully".green());
+    
     println!("Checking if Ollama is running...");
     
-    if !check_ollama_running().await {
+    if !client.check_available().await {
         println!("{}", "Error: Ollama is not running. Please start Ollama first.".red().bold());
         println!("You can start Ollama with: ollama serve");
         return Ok(());
@@ -19,27 +32,15 @@ async fn main() -> Result<()> {
     println!("{}", "✓ Ollama is running".green());
     
     // Validate that gemma3:12b model is available
-    let model = "gemma3:12b";
     println!("Checking if model '{}' is available...", model);
     
-    if !validate_model(model).await? {
+    if !client.validate_model(model).await? {
         println!("{}", format!("Error: Model '{}' is not available.", model).red().bold());
         println!("You can pull it with: ollama pull {}", model);
         return Ok(());
     }
     
     println!("{}", format!("✓ Model '{}' is available", model).green());
-
-    // Create client with custom configuration
-    let config = OllamaConfig {
-        model: model.to_string(),
-        temperature: 0.5,  // Lower for more deterministic responses
-        max_tokens: 2048,
-        ..OllamaConfig::default()
-    };
-    
-    let client = OllamaClient::with_config(config)?;
-    println!("{}", "OllamaClient initialized successfully".green());
     
     // Store command history
     let mut history: Vec<HistoryItem> = Vec::new();
@@ -99,6 +100,7 @@ async fn main() -> Result<()> {
                             user_input: input.to_string(),
                             command: command.clone(),
                             result: simulated_output.to_string(),
+                            ..Default::default()
                         });
                     },
                     "2" => {
@@ -113,8 +115,14 @@ async fn main() -> Result<()> {
                         
                         // Print streaming response
                         while let Some(chunk) = stream.receiver.recv().await {
-                            print!("{}", chunk);
-                            io::stdout().flush()?;
+                            match chunk {
+                                StreamChunk::Token(text) => {
+                                    print!("{}", text);
+                                    io::stdout().flush()?;
+                                },
+                                StreamChunk::Error(e) => println!("\n{}: {}", "Error".red().bold(), e),
+                                StreamChunk::Done => break,
+                            }
                         }
                         println!("\n");
                     },
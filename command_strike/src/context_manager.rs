@@ -0,0 +1,442 @@
+use crate::llm::HistoryItem;
+use crate::parsers::Finding;
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    // Placeholders look like [target] or [attacker_ip]
+    static ref PLACEHOLDER: Regex = Regex::new(r"\[([A-Za-z_][A-Za-z0-9_]*)\]").unwrap();
+    // Durations such as 90m, 2h, 1h30m or 45s
+    static ref DURATION_PART: Regex = Regex::new(r"(\d+)([hms])").unwrap();
+}
+
+/// Session variable whose value tags history items with the host they were aimed at
+pub const TARGET_VARIABLE: &str = "target";
+
+// Warn this long before the time limit, or at 80% of it for limits under 50 minutes
+const DEADLINE_WARNING: Duration = Duration::from_secs(10 * 60);
+
+/// State kept for the duration of an interactive session
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Whether executed commands are added to `history` (off for privacy-sensitive sessions)
+    pub record_history: bool,
+    /// Whether running the same command twice in a row updates the last history entry
+    /// instead of adding another
+    pub merge_duplicates: bool,
+    /// Executed commands and their results
+    pub history: Vec<HistoryItem>,
+    /// Named values substituted into `[name]` placeholders
+    pub variables: HashMap<String, String>,
+    /// Output of the most recently executed command
+    pub last_output: Option<String>,
+    /// Vulnerabilities mentioned in interpretations, for the report
+    pub findings: Vec<Finding>,
+    /// Observations jotted down with `note`
+    pub notes: Vec<Note>,
+    /// When the session started, for the clock shown in the prompt
+    pub start_time: Instant,
+    /// Time allowed for the session (e.g. a timed CTF), if any
+    pub time_limit: Option<Duration>,
+    /// The last deadline alert returned by `take_time_alert`
+    last_alert: Option<TimeAlert>,
+}
+
+/// A point on the way to the session's time limit worth telling the user about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeAlert {
+    /// Less than the warning period remains
+    Approaching,
+    /// The time limit has passed
+    Expired,
+}
+
+/// A timestamped observation added by the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub text: String,
+}
+
+impl Note {
+    /// Create a note stamped with the current time
+    pub fn new(text: &str) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            timestamp,
+            text: text.trim().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", format_timestamp(self.timestamp), self.text)
+    }
+}
+
+/// Format Unix seconds as "YYYY-MM-DD HH:MM UTC"
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+    
+    // Convert days since the epoch to a civil date (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, seconds / 3_600, seconds % 3_600 / 60)
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            record_history: true,
+            merge_duplicates: true,
+            history: Vec::new(),
+            variables: HashMap::new(),
+            last_output: None,
+            findings: Vec::new(),
+            notes: Vec::new(),
+            start_time: Instant::now(),
+            time_limit: None,
+            last_alert: None,
+        }
+    }
+}
+
+impl Session {
+    /// Create an empty session
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an executed command to the history, unless history is disabled
+    ///
+    /// Items without a target are tagged with the current `target` variable, if set.
+    /// With `merge_duplicates`, an item whose command repeats the previous one replaces
+    /// it, so the history keeps only the latest result.
+    pub fn record(&mut self, mut item: HistoryItem) {
+        if !self.record_history {
+            return;
+        }
+        if item.target.is_none() {
+            item.target = self.variables.get(TARGET_VARIABLE).cloned();
+        }
+        match self.history.last_mut() {
+            Some(last) if self.merge_duplicates && last.command.trim() == item.command.trim() => *last = item,
+            _ => self.history.push(item),
+        }
+    }
+
+    /// History items tagged with `target` ("unspecified" matches untagged items), with
+    /// their 1-based positions in the full history
+    pub fn history_for_target<'a>(&'a self, target: &'a str) -> impl Iterator<Item = (usize, &'a HistoryItem)> + 'a {
+        self.history
+            .iter()
+            .enumerate()
+            .filter(move |(_, item)| item.target_label() == target)
+            .map(|(i, item)| (i + 1, item))
+    }
+
+    /// Add findings that aren't already known, unless history is disabled
+    pub fn record_findings(&mut self, findings: Vec<Finding>) {
+        if !self.record_history {
+            return;
+        }
+        for finding in findings {
+            if !self.findings.contains(&finding) {
+                self.findings.push(finding);
+            }
+        }
+    }
+
+    /// Add a timestamped note, returning it
+    pub fn add_note(&mut self, text: &str) -> &Note {
+        self.notes.push(Note::new(text));
+        self.notes.last().unwrap()
+    }
+
+    /// Elapsed time, plus the time left when there is a limit, e.g. "[0:12:05, 0:47:55 left]"
+    pub fn clock(&self) -> String {
+        let elapsed = self.start_time.elapsed();
+        match self.time_limit {
+            Some(limit) if elapsed >= limit => format!("[{}, time's up]", format_duration(elapsed)),
+            Some(limit) => format!("[{}, {} left]", format_duration(elapsed), format_duration(limit - elapsed)),
+            None => format!("[{}]", format_duration(elapsed)),
+        }
+    }
+
+    /// Time left before the limit, if there is one (zero once it has passed)
+    pub fn remaining(&self) -> Option<Duration> {
+        self.time_limit.map(|limit| limit.saturating_sub(self.start_time.elapsed()))
+    }
+
+    /// A deadline alert that hasn't been returned before, so each is shown only once
+    pub fn take_time_alert(&mut self) -> Option<TimeAlert> {
+        let alert = deadline_alert(self.start_time.elapsed(), self.time_limit?)?;
+        if self.last_alert == Some(alert) {
+            return None;
+        }
+        self.last_alert = Some(alert);
+        Some(alert)
+    }
+
+    /// Set a session variable
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        self.variables.insert(name.to_string(), value.to_string());
+    }
+
+    /// Store part of the last command's output in a variable, returning the captured value
+    ///
+    /// See `capture_value` for the selector syntax.
+    pub fn capture(&mut self, name: &str, selector: Option<&str>) -> Result<String> {
+        let output = self.last_output.as_deref()
+            .context("No command output to capture from yet")?;
+        let value = capture_value(output, selector)?;
+        self.set_variable(name, &value);
+        Ok(value)
+    }
+
+    /// Replace placeholders in a command with session variables
+    pub fn substitute(&self, command: &str) -> String {
+        substitute_placeholders(command, &self.variables)
+    }
+}
+
+/// Select a value from command output
+///
+/// - `/regex/` takes the first match, or its first capture group if it has one
+/// - a number takes that line (1-based)
+/// - no selector takes the first non-empty line
+pub fn capture_value(output: &str, selector: Option<&str>) -> Result<String> {
+    let selector = selector.map(str::trim).filter(|s| !s.is_empty());
+    
+    match selector {
+        Some(sel) if sel.len() >= 2 && sel.starts_with('/') && sel.ends_with('/') => {
+            let pattern = &sel[1..sel.len() - 1];
+            let regex = Regex::new(pattern).context("Invalid regex")?;
+            let captures = regex.captures(output)
+                .ok_or_else(|| anyhow!("No match for /{}/ in the output", pattern))?;
+            let value = captures.get(1).or_else(|| captures.get(0)).map(|m| m.as_str()).unwrap_or("");
+            Ok(value.to_string())
+        }
+        Some(sel) => {
+            let index: usize = sel.parse()
+                .map_err(|_| anyhow!("Invalid selector '{}': use /regex/ or a line number", sel))?;
+            output.lines()
+                .nth(index.saturating_sub(1))
+                .filter(|_| index > 0)
+                .map(|line| line.trim().to_string())
+                .ok_or_else(|| anyhow!("The output has no line {}", index))
+        }
+        None => output.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(ToString::to_string)
+            .context("The output is empty"),
+    }
+}
+
+/// Where `elapsed` stands relative to `limit`, if it is close or past
+fn deadline_alert(elapsed: Duration, limit: Duration) -> Option<TimeAlert> {
+    let warning = DEADLINE_WARNING.min(limit / 5);
+    if elapsed >= limit {
+        Some(TimeAlert::Expired)
+    } else if limit - elapsed <= warning {
+        Some(TimeAlert::Approaching)
+    } else {
+        None
+    }
+}
+
+/// Format a duration as H:MM:SS
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}:{:02}", seconds / 3_600, seconds % 3_600 / 60, seconds % 60)
+}
+
+/// Parse a duration such as "90m", "2h", "1h30m" or "45s"; a bare number is minutes
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim().to_lowercase();
+    if let Ok(minutes) = text.parse::<u64>() {
+        return Some(Duration::from_secs(minutes * 60));
+    }
+    
+    // Every character must belong to a number+unit part
+    let mut seconds = 0;
+    let mut matched = 0;
+    for captures in DURATION_PART.captures_iter(&text) {
+        let value: u64 = captures[1].parse().ok()?;
+        seconds += match &captures[2] {
+            "h" => value * 3_600,
+            "m" => value * 60,
+            _ => value,
+        };
+        matched += captures[0].len();
+    }
+    (matched == text.len() && matched > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// List the `[name]` placeholders in a command, in order of appearance
+pub fn extract_placeholders(command: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for captures in PLACEHOLDER.captures_iter(command) {
+        let name = captures[1].to_string();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Replace `[name]` placeholders with known variable values, leaving unknown ones in place
+pub fn substitute_placeholders(command: &str, variables: &HashMap<String, String>) -> String {
+    PLACEHOLDER
+        .replace_all(command, |captures: &regex::Captures| {
+            variables.get(&captures[1]).cloned().unwrap_or_else(|| captures[0].to_string())
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const OUTPUT: &str = "Nmap scan report for 10.10.11.42\nHost is up (0.031s latency).\n22/tcp open ssh";
+    
+    #[test]
+    fn test_capture_value() {
+        assert_eq!(capture_value(OUTPUT, Some(r"/(\d+\.\d+\.\d+\.\d+)/")).unwrap(), "10.10.11.42");
+        assert_eq!(capture_value(OUTPUT, Some("3")).unwrap(), "22/tcp open ssh");
+        assert_eq!(capture_value(OUTPUT, None).unwrap(), "Nmap scan report for 10.10.11.42");
+        assert!(capture_value(OUTPUT, Some("/ftp/")).is_err());
+        assert!(capture_value(OUTPUT, Some("0")).is_err());
+    }
+    
+    #[test]
+    fn test_record_respects_disabled_history() {
+        let item = HistoryItem {
+            user_input: "list files".to_string(),
+            command: "ls".to_string(),
+            result: "a.txt".to_string(),
+            ..Default::default()
+        };
+        
+        let mut session = Session::new();
+        session.record(item.clone());
+        assert_eq!(session.history.len(), 1);
+        
+        session.record_history = false;
+        session.record(item);
+        assert_eq!(session.history.len(), 1);
+    }
+    
+    #[test]
+    fn test_repeated_command_updates_last_entry() {
+        let item = |command: &str, result: &str| HistoryItem {
+            command: command.to_string(),
+            result: result.to_string(),
+            ..Default::default()
+        };
+        
+        let mut session = Session::new();
+        session.record(item("nmap -sV 10.0.0.5", "22/tcp open ssh"));
+        session.record(item("nmap -sV 10.0.0.5 ", "22/tcp open ssh\n80/tcp open http"));
+        assert_eq!(session.history.len(), 1);
+        assert_eq!(session.history[0].result, "22/tcp open ssh\n80/tcp open http");
+        
+        // Only consecutive repeats are merged
+        session.record(item("id", "uid=0(root)"));
+        session.record(item("nmap -sV 10.0.0.5", "22/tcp open ssh"));
+        assert_eq!(session.history.len(), 3);
+        
+        session.merge_duplicates = false;
+        session.record(item("nmap -sV 10.0.0.5", "22/tcp open ssh"));
+        assert_eq!(session.history.len(), 4);
+    }
+    
+    #[test]
+    fn test_history_is_tagged_and_filtered_by_target() {
+        let item = |command: &str| HistoryItem { command: command.to_string(), ..Default::default() };
+        
+        let mut session = Session::new();
+        session.record(item("ip a"));
+        session.set_variable("target", "10.0.0.5");
+        session.record(item("nmap -sV 10.0.0.5"));
+        session.set_variable("target", "10.0.0.9");
+        session.record(item("nmap -sV 10.0.0.9"));
+        session.record(HistoryItem { target: Some("10.0.0.5".to_string()), ..item("smbclient -L //10.0.0.5 -N") });
+        
+        let commands = |target| session.history_for_target(target).map(|(n, item)| (n, item.command.as_str())).collect::<Vec<_>>();
+        assert_eq!(commands("10.0.0.5"), [(2, "nmap -sV 10.0.0.5"), (4, "smbclient -L //10.0.0.5 -N")]);
+        assert_eq!(commands("10.0.0.9"), [(3, "nmap -sV 10.0.0.9")]);
+        assert_eq!(commands("unspecified"), [(1, "ip a")]);
+        assert!(commands("10.0.0.1").is_empty());
+    }
+    
+    #[test]
+    fn test_notes_are_timestamped() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(1_709_210_096), "2024-02-29 12:34 UTC");
+        
+        let mut session = Session::new();
+        let note = session.add_note("  admin panel at /manage ");
+        assert_eq!(note.text, "admin panel at /manage");
+        assert!(note.to_string().ends_with("UTC] admin panel at /manage"));
+        assert_eq!(session.notes.len(), 1);
+    }
+    
+    #[test]
+    fn test_session_clock_and_deadline() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("2 hours"), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(format_duration(Duration::from_secs(3_725)), "1:02:05");
+        
+        let hour = Duration::from_secs(3_600);
+        assert_eq!(deadline_alert(Duration::from_secs(40 * 60), hour), None);
+        assert_eq!(deadline_alert(Duration::from_secs(55 * 60), hour), Some(TimeAlert::Approaching));
+        assert_eq!(deadline_alert(hour, hour), Some(TimeAlert::Expired));
+        // Short limits warn at 80%
+        assert_eq!(deadline_alert(Duration::from_secs(17 * 60), Duration::from_secs(20 * 60)), Some(TimeAlert::Approaching));
+        
+        let mut session = Session::new();
+        assert!(session.clock().starts_with("[0:00:0"));
+        assert_eq!(session.take_time_alert(), None);
+        
+        session.time_limit = Some(Duration::ZERO);
+        assert!(session.clock().ends_with(", time's up]"));
+        assert_eq!(session.remaining(), Some(Duration::ZERO));
+        assert_eq!(session.take_time_alert(), Some(TimeAlert::Expired));
+        assert_eq!(session.take_time_alert(), None);
+    }
+    
+    #[test]
+    fn test_substitute_placeholders() {
+        let mut session = Session::new();
+        session.last_output = Some(OUTPUT.to_string());
+        session.capture("target", Some(r"/\d+\.\d+\.\d+\.\d+/")).unwrap();
+        
+        let command = "nmap -sV -p [ports] [target]";
+        assert_eq!(extract_placeholders(command), vec!["ports", "target"]);
+        assert_eq!(session.substitute(command), "nmap -sV -p [ports] 10.10.11.42");
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Price of a paid model in USD per 1,000 tokens
+///
+/// Models without a configured price are treated as local and free.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelCost {
+    pub input_per_1k: f64,
+    pub output_per_1k: f64,
+}
+
+impl ModelCost {
+    /// Cost of a request with the given prompt and completion token counts
+    pub fn cost(&self, usage: TokenUsage) -> f64 {
+        usage.input_tokens as f64 / 1000.0 * self.input_per_1k
+            + usage.output_tokens as f64 / 1000.0 * self.output_per_1k
+    }
+}
+
+/// Tokens consumed by one or more requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl TokenUsage {
+    pub(crate) fn add(&mut self, other: TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+/// Running token usage per model for the session
+#[derive(Debug, Clone, Default)]
+pub struct CostTracker {
+    usage: HashMap<String, TokenUsage>,
+}
+
+impl CostTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the tokens used by a request to `model`
+    pub fn record(&mut self, model: &str, usage: TokenUsage) {
+        self.usage.entry(model.to_string()).or_default().add(usage);
+    }
+
+    /// Usage and cost per model, sorted by model name
+    pub fn breakdown(&self, costs: &HashMap<String, ModelCost>) -> Vec<(String, TokenUsage, f64)> {
+        let mut rows: Vec<_> = self.usage
+            .iter()
+            .map(|(model, usage)| {
+                let cost = costs.get(model).map(|c| c.cost(*usage)).unwrap_or(0.0);
+                (model.clone(), *usage, cost)
+            })
+            .collect();
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        rows
+    }
+
+    /// Total session cost across all models
+    pub fn total_cost(&self, costs: &HashMap<String, ModelCost>) -> f64 {
+        self.breakdown(costs).iter().map(|(_, _, cost)| cost).sum()
+    }
+}
+
+/// Parse a `MODEL=INPUT,OUTPUT` price argument (USD per 1k tokens)
+pub fn parse_model_cost(arg: &str) -> Result<(String, ModelCost), String> {
+    let usage = || format!("expected MODEL=INPUT,OUTPUT (USD per 1k tokens), got '{}'", arg);
+    let (model, prices) = arg.split_once('=').ok_or_else(usage)?;
+    let (input, output) = prices.split_once(',').ok_or_else(usage)?;
+    
+    let parse = |price: &str| price.trim().parse::<f64>().ok().filter(|p| *p >= 0.0);
+    match (model.trim(), parse(input), parse(output)) {
+        (model, Some(input_per_1k), Some(output_per_1k)) if !model.is_empty() => {
+            Ok((model.to_string(), ModelCost { input_per_1k, output_per_1k }))
+        }
+        _ => Err(usage()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_session_cost() {
+        let costs: HashMap<String, ModelCost> = [parse_model_cost("gpt-4o=0.005,0.015").unwrap()].into();
+        
+        let mut tracker = CostTracker::new();
+        tracker.record("gpt-4o", TokenUsage { input_tokens: 1200, output_tokens: 300 });
+        tracker.record("gpt-4o", TokenUsage { input_tokens: 800, output_tokens: 700 });
+        tracker.record("llama3:8b", TokenUsage { input_tokens: 5000, output_tokens: 5000 });
+        
+        // 2000 input tokens at $0.005/1k plus 1000 output tokens at $0.015/1k; local models are free
+        assert!((tracker.total_cost(&costs) - 0.025).abs() < 1e-9);
+        let breakdown = tracker.breakdown(&costs);
+        assert_eq!(breakdown[0].1, TokenUsage { input_tokens: 2000, output_tokens: 1000 });
+        assert_eq!(breakdown[1].2, 0.0);
+        
+        assert!(parse_model_cost("gpt-4o=0.005").is_err());
+        assert!(parse_model_cost("=1,2").is_err());
+    }
+}
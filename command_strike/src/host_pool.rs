@@ -0,0 +1,118 @@
+use log::{debug, warn};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A set of Ollama hosts that requests are spread across round-robin
+///
+/// Hosts that fail to connect are marked unhealthy and skipped until a request to
+/// them succeeds again. If every host is unhealthy, all of them are retried.
+#[derive(Debug)]
+pub struct HostPool {
+    hosts: Vec<Host>,
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Host {
+    url: String,
+    healthy: AtomicBool,
+}
+
+impl HostPool {
+    /// Create a pool from a non-empty list of base URLs
+    ///
+    /// Surrounding whitespace and trailing slashes are ignored, so URLs that differ only
+    /// in those are one host.
+    pub fn new(urls: &[String]) -> Self {
+        let mut hosts: Vec<Host> = Vec::new();
+        for url in urls {
+            let url = url.trim().trim_end_matches('/');
+            if !hosts.iter().any(|host| host.url == url) {
+                hosts.push(Host {
+                    url: url.to_string(),
+                    healthy: AtomicBool::new(true),
+                });
+            }
+        }
+        
+        Self {
+            hosts,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of hosts in the pool
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Returns true if the pool has no hosts
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Pick the next healthy host in round-robin order
+    pub fn next_host(&self) -> String {
+        let count = self.hosts.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        
+        for offset in 0..count {
+            let index = (start + offset) % count;
+            let host = &self.hosts[index];
+            if host.healthy.load(Ordering::Relaxed) {
+                // Continue the rotation after the host we skipped to
+                if offset > 0 {
+                    self.next.store(index + 1, Ordering::Relaxed);
+                }
+                debug!("Selected host: {}", host.url);
+                return host.url.clone();
+            }
+        }
+        
+        // Nothing is healthy; give every host another chance
+        warn!("All Ollama hosts are marked unreachable, retrying all of them");
+        for host in &self.hosts {
+            host.healthy.store(true, Ordering::Relaxed);
+        }
+        self.hosts[start % count].url.clone()
+    }
+
+    /// Record that a request to a host succeeded
+    pub fn mark_healthy(&self, url: &str) {
+        if let Some(host) = self.hosts.iter().find(|h| h.url == url) {
+            host.healthy.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that a host could not be reached
+    pub fn mark_unhealthy(&self, url: &str) {
+        if let Some(host) = self.hosts.iter().find(|h| h.url == url) {
+            warn!("Marking Ollama host {} as unreachable", url);
+            host.healthy.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_skips_unhealthy_hosts() {
+        let pool = HostPool::new(&["http://a".to_string(), "http://b".to_string(), "http://c".to_string()]);
+        pool.mark_unhealthy("http://b");
+        
+        assert_eq!(pool.next_host(), "http://a");
+        assert_eq!(pool.next_host(), "http://c");
+        assert_eq!(pool.next_host(), "http://a");
+        
+        pool.mark_healthy("http://b");
+        assert_eq!(pool.next_host(), "http://b");
+    }
+    
+    #[test]
+    fn test_duplicate_urls_are_one_host() {
+        let pool = HostPool::new(&["http://a:11434".to_string(), "http://a:11434/".to_string(), " http://a:11434 ".to_string()]);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.next_host(), "http://a:11434");
+    }
+}
@@ -0,0 +1,197 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// How close a request must be to a known phrasing to be answered locally (Dice coefficient of their words)
+const MATCH_THRESHOLD: f32 = 0.8;
+
+// Words that don't change what a request asks for
+const FILLER_WORDS: &[&str] = &[
+    "a", "an", "the", "on", "of", "for", "to", "in", "at", "me", "my", "please", "this", "that",
+    "machine", "system", "host", "box", "target", "can", "you", "how", "do", "i",
+];
+
+lazy_static! {
+    // An IPv4 address or CIDR range in a request, used to fill `[target]`
+    static ref IPV4_TARGET: Regex = Regex::new(r"\b\d{1,3}(?:\.\d{1,3}){3}(?:/\d{1,2})?\b").unwrap();
+}
+
+/// Standard requests with canonical commands (Unix), as (phrasings, command)
+const BUILTIN_ENTRIES: &[(&[&str], &str)] = &[
+    (&["scan all ports", "full port scan", "scan every port", "scan all tcp ports"], "nmap -p- -T4 [target]"),
+    (&["service version scan", "detect service versions", "scan services and versions"], "nmap -sV -sC [target]"),
+    (&["find suid binaries", "find suid files", "list setuid binaries"], "find / -perm -4000 -type f 2>/dev/null"),
+    (&["find files with capabilities", "list file capabilities", "check capabilities"], "getcap -r / 2>/dev/null"),
+    (&["find writable directories", "list world writable directories"], "find / -type d -writable 2>/dev/null"),
+    (&["list sudo privileges", "check sudo permissions", "what can i run with sudo"], "sudo -l"),
+    (&["list listening ports", "show open ports", "show listening services"], "ss -tulpn"),
+    (&["list cron jobs", "show scheduled tasks", "check cron jobs"], "cat /etc/crontab; ls -la /etc/cron.*"),
+    (&["show kernel version", "what kernel is running", "check kernel version"], "uname -a"),
+    (&["who am i", "show current user", "show user and groups"], "id"),
+    (&["start a web server", "serve files over http", "start http server"], "python3 -m http.server 8000"),
+    (&["enumerate web directories", "brute force directories", "directory brute force"], "gobuster dir -u http://[target] -w /usr/share/wordlists/dirb/common.txt"),
+];
+
+/// A curated map of common requests to canonical commands, answered without the model
+///
+/// Requests are matched on their words, ignoring filler words, plurals, single-letter
+/// typos in longer words and any IPv4 target (which fills the command's `[target]`).
+#[derive(Debug, Clone)]
+pub struct KnowledgeBase {
+    entries: Vec<KnowledgeEntry>,
+}
+
+/// Known phrasings of a request and the command that answers it
+#[derive(Debug, Clone)]
+pub struct KnowledgeEntry {
+    pub phrasings: Vec<String>,
+    pub command: String,
+}
+
+/// A request answered from the knowledge base
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnowledgeMatch {
+    /// The canonical command, with `[target]` filled if the request named one
+    pub command: String,
+    /// The known phrasing the request matched
+    pub phrasing: String,
+    /// Similarity to that phrasing, from `MATCH_THRESHOLD` to 1.0
+    pub score: f32,
+}
+
+impl Default for KnowledgeBase {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl KnowledgeBase {
+    /// The built-in entries
+    pub fn builtin() -> Self {
+        let entries = BUILTIN_ENTRIES
+            .iter()
+            .map(|(phrasings, command)| KnowledgeEntry {
+                phrasings: phrasings.iter().map(ToString::to_string).collect(),
+                command: command.to_string(),
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Add an entry, checked before the built-in ones
+    pub fn add(&mut self, phrasings: &[&str], command: &str) {
+        self.entries.insert(0, KnowledgeEntry {
+            phrasings: phrasings.iter().map(ToString::to_string).collect(),
+            command: command.to_string(),
+        });
+    }
+
+    /// The command for the known phrasing closest to `request`, if any is close enough
+    ///
+    /// A request that names a target only matches entries with a `[target]` to fill, so
+    /// "show kernel version of 10.0.0.5" isn't answered with a local `uname -a`.
+    pub fn lookup(&self, request: &str) -> Option<KnowledgeMatch> {
+        let target = IPV4_TARGET.find(request).map(|m| m.as_str());
+        let words = request_words(&IPV4_TARGET.replace_all(request, " "));
+        if words.is_empty() {
+            return None;
+        }
+        
+        let mut best: Option<(f32, &KnowledgeEntry, &str)> = None;
+        for entry in self.entries.iter().filter(|entry| target.is_none() || entry.command.contains("[target]")) {
+            for phrasing in &entry.phrasings {
+                let score = similarity(&words, &request_words(phrasing));
+                if score >= MATCH_THRESHOLD && best.is_none_or(|(best_score, _, _)| score > best_score) {
+                    best = Some((score, entry, phrasing));
+                }
+            }
+        }
+        
+        best.map(|(score, entry, phrasing)| KnowledgeMatch {
+            command: match target {
+                Some(target) => entry.command.replace("[target]", target),
+                None => entry.command.clone(),
+            },
+            phrasing: phrasing.to_string(),
+            score,
+        })
+    }
+}
+
+/// Distinct lowercase words of a request without filler words or plural endings
+fn request_words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for word in text.to_lowercase().split(|c: char| !c.is_alphanumeric()) {
+        if word.is_empty() || FILLER_WORDS.contains(&word) {
+            continue;
+        }
+        let word = match word.strip_suffix('s') {
+            Some(stem) if stem.len() >= 3 && !stem.ends_with('s') => stem.to_string(),
+            _ => word.to_string(),
+        };
+        if !words.contains(&word) {
+            words.push(word);
+        }
+    }
+    words
+}
+
+/// Dice coefficient of two word lists, counting near-identical words as equal
+fn similarity(a: &[String], b: &[String]) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let shared = a.iter().filter(|word| b.iter().any(|other| words_match(word, other))).count();
+    2.0 * shared as f32 / (a.len() + b.len()) as f32
+}
+
+/// Whether two words are equal, allowing one typo in words of five letters or more
+fn words_match(a: &str, b: &str) -> bool {
+    a == b || (a.len() >= 5 && b.len() >= 5 && edit_distance(a, b) <= 1)
+}
+
+/// Levenshtein distance between two words
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_lookup_matches_phrasings() {
+        let kb = KnowledgeBase::builtin();
+        
+        assert_eq!(kb.lookup("Find SUID binaries on this box").unwrap().command, "find / -perm -4000 -type f 2>/dev/null");
+        assert_eq!(kb.lookup("find suid binaris").unwrap().command, "find / -perm -4000 -type f 2>/dev/null");
+        assert_eq!(kb.lookup("scan all ports on 10.10.11.42").unwrap().command, "nmap -p- -T4 10.10.11.42");
+        assert_eq!(kb.lookup("please scan all the ports").unwrap().command, "nmap -p- -T4 [target]");
+        assert_eq!(kb.lookup("What can I run with sudo?").unwrap().command, "sudo -l");
+    }
+    
+    #[test]
+    fn test_lookup_leaves_other_requests_to_the_model() {
+        let mut kb = KnowledgeBase::builtin();
+        assert!(kb.lookup("scan all ports with a stealthy scan through a proxy").is_none());
+        assert!(kb.lookup("crack this NTLM hash").is_none());
+        assert!(kb.lookup("10.0.0.1").is_none());
+        // Local commands can't answer a request about another host
+        assert!(kb.lookup("show kernel version of 10.0.0.5").is_none());
+        assert!(kb.lookup("find suid binaries on 10.0.0.5").is_none());
+        
+        kb.add(&["crack ntlm hash"], "hashcat -m 1000 hash.txt rockyou.txt");
+        let found = kb.lookup("crack this NTLM hash").unwrap();
+        assert_eq!(found.phrasing, "crack ntlm hash");
+        assert_eq!(found.score, 1.0);
+    }
+}
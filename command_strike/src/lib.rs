@@ -1,4 +1,22 @@
+pub mod backend;
+pub mod batch;
+pub mod cli;
+pub mod command_executor;
+pub mod command_parser;
+pub mod context_manager;
+pub mod cost;
+pub mod host_pool;
+pub mod knowledge_base;
 pub mod llm;
+pub mod macros;
+pub mod parsers;
+pub mod repl;
+pub mod replay;
+pub mod report;
+pub mod request_queue;
+pub mod safety;
+pub mod shutdown;
+pub mod ui;
 
 // Re-export key types for convenience
-pub use llm::{OllamaClient, OllamaConfig, HistoryItem}; 
\ No newline at end of file
+pub use llm::{OllamaClient, OllamaConfig, OllamaConfigBuilder, HistoryItem};
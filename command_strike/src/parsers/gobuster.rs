@@ -0,0 +1,57 @@
+use super::Finding;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // "/admin                (Status: 301) [Size: 178] [--> http://host/admin/]"
+    static ref RESULT_LINE: Regex = Regex::new(
+        r"^(/\S*)\s+\(Status:\s*(\d{3})\)(?:\s*\[Size:\s*(\d+)\])?(?:\s*\[-->\s*([^\]]+)\])?"
+    ).unwrap();
+}
+
+/// Extract discovered paths and their status codes from `gobuster dir` output
+pub fn parse_gobuster(output: &str) -> Vec<Finding> {
+    output
+        .lines()
+        .filter_map(|line| RESULT_LINE.captures(line.trim()))
+        .map(|caps| {
+            let mut detail = format!("status {}", &caps[2]);
+            if let Some(redirect) = caps.get(4) {
+                detail.push_str(&format!(", redirects to {}", redirect.as_str().trim()));
+            }
+            Finding::new("gobuster", Some(caps[1].to_string()), detail)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_gobuster() {
+        let output = "\
+===============================================================
+Gobuster v3.6
+===============================================================
+[+] Url:                     http://10.10.10.5
+[+] Wordlist:                /usr/share/wordlists/dirb/common.txt
+===============================================================
+Starting gobuster in directory enumeration mode
+===============================================================
+/.htpasswd            (Status: 403) [Size: 277]
+/admin                (Status: 301) [Size: 314] [--> http://10.10.10.5/admin/]
+/index.php            (Status: 200) [Size: 11321]
+Progress: 4614 / 4615 (99.98%)
+===============================================================
+Finished
+===============================================================";
+        
+        let findings = parse_gobuster(output);
+        assert_eq!(findings.len(), 3);
+        assert_eq!(findings[0].location.as_deref(), Some("/.htpasswd"));
+        assert_eq!(findings[0].detail, "status 403");
+        assert_eq!(findings[1].detail, "status 301, redirects to http://10.10.10.5/admin/");
+        assert_eq!(findings[2].location.as_deref(), Some("/index.php"));
+    }
+}
@@ -0,0 +1,111 @@
+use super::Finding;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// Longest sentence kept as a finding's detail
+const MAX_DETAIL_CHARS: usize = 200;
+
+// Words that mark a sentence as describing a weakness
+const VULNERABILITY_KEYWORDS: &[&str] = &[
+    "vulnerable", "vulnerability", "exploitable", "outdated", "end-of-life", "misconfigur",
+    "anonymous login", "default credentials",
+];
+
+lazy_static! {
+    static ref CVE_ID: Regex = Regex::new(r"(?i)\bCVE-\d{4}-\d{4,7}\b").unwrap();
+    // A service name followed by its version, e.g. "Apache 2.4.49", "OpenSSH 7.2p2" or "vsftpd 2.3.4"
+    static ref SERVICE_VERSION: Regex = Regex::new(
+        r"\b([A-Za-z][A-Za-z0-9_-]*)[ /]v?(\d+\.\d+(?:\.\d+)*(?:[a-z]+\d*)?)\b"
+    ).unwrap();
+}
+
+/// Pull vulnerability mentions out of a model's interpretation
+///
+/// Each CVE ID becomes a finding located at the ID; other sentences that call
+/// something vulnerable, exploitable or outdated become findings located at the
+/// service and version they mention, if any.
+pub fn extract_findings(interpretation: &str) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = Vec::new();
+    
+    for sentence in sentences(interpretation) {
+        let cves: Vec<String> = CVE_ID
+            .find_iter(&sentence)
+            .map(|m| m.as_str().to_uppercase())
+            .collect();
+        for cve in cves {
+            if !findings.iter().any(|f| f.location.as_deref() == Some(cve.as_str())) {
+                findings.push(Finding::new("interpretation", Some(cve), detail(&sentence)));
+            }
+        }
+        if CVE_ID.is_match(&sentence) {
+            continue;
+        }
+        
+        let lower = sentence.to_lowercase();
+        if VULNERABILITY_KEYWORDS.iter().any(|k| lower.contains(k)) {
+            let service = SERVICE_VERSION
+                .captures(&sentence)
+                .map(|caps| format!("{} {}", &caps[1], &caps[2]));
+            findings.push(Finding::new("interpretation", service, detail(&sentence)));
+        }
+    }
+    
+    findings
+}
+
+/// Split prose into sentences, dropping markdown bullets and emphasis
+fn sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    for line in text.lines() {
+        let line = line.trim().trim_start_matches(['-', '*', '#', '>']).replace("**", "").replace('`', "");
+        let mut current = String::new();
+        let mut chars = line.trim().chars().peekable();
+        while let Some(c) = chars.next() {
+            current.push(c);
+            // A sentence ends at ". " (but not inside version numbers like "2.4.49")
+            if matches!(c, '.' | '!' | '?') && chars.peek().is_none_or(|next| next.is_whitespace()) {
+                sentences.push(std::mem::take(&mut current).trim().to_string());
+            }
+        }
+        if !current.trim().is_empty() {
+            sentences.push(current.trim().to_string());
+        }
+    }
+    sentences.retain(|s| !s.is_empty());
+    sentences
+}
+
+/// Shorten a sentence for use as a finding's detail
+fn detail(sentence: &str) -> String {
+    if sentence.chars().count() <= MAX_DETAIL_CHARS {
+        return sentence.to_string();
+    }
+    let short: String = sentence.chars().take(MAX_DETAIL_CHARS).collect();
+    format!("{}...", short.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_extract_findings() {
+        let interpretation = "\
+The scan shows two open ports.
+
+- **Port 80**: Apache httpd 2.4.49 is running. This version is affected by cve-2021-41773, a path traversal that can lead to RCE.
+- **Port 21**: vsftpd 2.3.4 is vulnerable to a backdoor that opens a shell on port 6200.
+- Port 22 runs OpenSSH 8.9p1, which looks up to date.
+
+Next, try reading /etc/passwd via the traversal.";
+        
+        let findings = extract_findings(interpretation);
+        assert_eq!(findings.len(), 2);
+        
+        assert_eq!(findings[0].location.as_deref(), Some("CVE-2021-41773"));
+        assert!(findings[0].detail.contains("path traversal"));
+        
+        assert_eq!(findings[1].location.as_deref(), Some("vsftpd 2.3.4"));
+        assert!(findings[1].detail.starts_with("Port 21: vsftpd 2.3.4 is vulnerable to a backdoor"));
+    }
+}
@@ -0,0 +1,76 @@
+//! Lightweight extractors that pull findings out of common tool outputs
+
+pub mod gobuster;
+pub mod interpretation;
+pub mod nikto;
+pub mod nmap;
+pub mod sqlmap;
+
+pub use interpretation::extract_findings;
+
+use crate::command_parser::parse_command;
+use std::fmt;
+
+/// A single notable result extracted from a tool's output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Tool that produced the finding (e.g. "gobuster")
+    pub tool: String,
+    /// Where the finding applies, such as a path or parameter, when known
+    pub location: Option<String>,
+    /// Short description of the finding
+    pub detail: String,
+}
+
+impl Finding {
+    fn new(tool: &str, location: Option<String>, detail: impl Into<String>) -> Self {
+        Self {
+            tool: tool.to_string(),
+            location,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "[{}] {}: {}", self.tool, location, self.detail),
+            None => write!(f, "[{}] {}", self.tool, self.detail),
+        }
+    }
+}
+
+/// Extract findings from the output of a known tool
+///
+/// Returns an empty list for tools without a parser or when nothing was recognised.
+pub fn parse_tool_output(tool: &str, output: &str) -> Vec<Finding> {
+    match tool {
+        "gobuster" => gobuster::parse_gobuster(output),
+        "nikto" | "nikto.pl" => nikto::parse_nikto(output),
+        "nmap" => nmap::parse_nmap_findings(output),
+        "sqlmap" | "sqlmap.py" => sqlmap::parse_sqlmap(output),
+        _ => Vec::new(),
+    }
+}
+
+/// Extract findings from a command's output, detecting the tool from the command line
+pub fn parse_command_output(command: &str, output: &str) -> Vec<Finding> {
+    parse_tool_output(&parse_command(command).program, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_dispatch_by_command() {
+        let output = "/admin                (Status: 301) [Size: 178]";
+        
+        let findings = parse_command_output("sudo gobuster dir -u http://10.0.0.1 -w words.txt", output);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].to_string(), "[gobuster] /admin: status 301");
+        
+        assert!(parse_command_output("curl http://10.0.0.1", output).is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+use super::Finding;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// Informational "+ Key: value" lines that describe the scan rather than a finding
+const SCAN_INFO_PREFIXES: &[&str] = &[
+    "Target IP:", "Target Hostname:", "Target Port:", "Start Time:", "End Time:",
+    "SSL Info:", "Subject:", "Ciphers:", "Issuer:",
+];
+
+lazy_static! {
+    // Optional "OSVDB-1234: " reference followed by "/path: description"
+    static ref PATH_FINDING: Regex = Regex::new(r"^(?:OSVDB-\d+:\s*)?(/[^\s:]*):\s*(.+)$").unwrap();
+}
+
+/// Extract reported issues from nikto output
+pub fn parse_nikto(output: &str) -> Vec<Finding> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("+ "))
+        .map(str::trim)
+        .filter(|item| {
+            !SCAN_INFO_PREFIXES.iter().any(|p| item.starts_with(p))
+                && !item.contains("host(s) tested")
+                && !item.contains("requests:")
+        })
+        .map(|item| match PATH_FINDING.captures(item) {
+            Some(caps) => Finding::new("nikto", Some(caps[1].to_string()), caps[2].trim()),
+            None => Finding::new("nikto", None, item),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_nikto() {
+        let output = "\
+- Nikto v2.5.0
+---------------------------------------------------------------------------
++ Target IP:          10.10.10.5
++ Target Hostname:    10.10.10.5
++ Target Port:        80
++ Start Time:         2024-03-01 10:00:00 (GMT0)
+---------------------------------------------------------------------------
++ Server: Apache/2.4.41 (Ubuntu)
++ /: The anti-clickjacking X-Frame-Options header is not present.
++ OSVDB-3092: /admin/: This might be interesting.
++ Apache/2.4.41 appears to be outdated (current is at least Apache/2.4.54).
++ 8102 requests: 0 error(s) and 3 item(s) reported on remote host
++ End Time:           2024-03-01 10:05:00 (GMT0) (300 seconds)
+---------------------------------------------------------------------------
++ 1 host(s) tested";
+        
+        let findings = parse_nikto(output);
+        assert_eq!(findings.len(), 4);
+        assert_eq!(findings[0].detail, "Server: Apache/2.4.41 (Ubuntu)");
+        assert_eq!(findings[1].location.as_deref(), Some("/"));
+        assert_eq!(findings[2].location.as_deref(), Some("/admin/"));
+        assert_eq!(findings[2].detail, "This might be interesting.");
+        assert!(findings[3].location.is_none());
+    }
+}
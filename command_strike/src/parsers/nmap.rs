@@ -0,0 +1,162 @@
+use super::Finding;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // "Nmap scan report for box.htb (10.10.10.5)" or "Nmap scan report for 10.10.10.5"
+    static ref REPORT_LINE: Regex = Regex::new(
+        r"^Nmap scan report for (?:(\S+) \(([^)]+)\)|(\S+))$"
+    ).unwrap();
+    // "22/tcp open  ssh     OpenSSH 8.2p1 Ubuntu 4ubuntu0.5 (Ubuntu Linux; protocol 2.0)"
+    static ref PORT_LINE: Regex = Regex::new(
+        r"^(\d{1,5})/(tcp|udp|sctp)\s+(\S+)\s+(\S+)(?:\s+(.+))?$"
+    ).unwrap();
+}
+
+/// A scanned host and the ports nmap reported for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostResult {
+    pub ip: String,
+    /// The name the host was scanned by, when it wasn't an IP
+    pub hostname: Option<String>,
+    pub ports: Vec<PortResult>,
+}
+
+/// One line of nmap's port table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortResult {
+    pub port: u16,
+    /// "tcp", "udp" or "sctp"
+    pub protocol: String,
+    /// "open", "closed", "filtered", "open|filtered", ...
+    pub state: String,
+    pub service: String,
+    /// Product and version from `-sV`, when detected
+    pub version: Option<String>,
+}
+
+impl PortResult {
+    pub fn is_open(&self) -> bool {
+        self.state == "open"
+    }
+}
+
+/// Parse the hosts and port tables out of nmap's normal output
+///
+/// Script output (`|` lines), OS detection and the trailer are skipped. Port lines
+/// that appear before any "Nmap scan report" line are ignored.
+pub fn parse_nmap(output: &str) -> Vec<HostResult> {
+    let mut hosts: Vec<HostResult> = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if let Some(caps) = REPORT_LINE.captures(line) {
+            let (ip, hostname) = match (caps.get(1), caps.get(2)) {
+                (Some(name), Some(ip)) => (ip.as_str().to_string(), Some(name.as_str().to_string())),
+                _ => (caps[3].to_string(), None),
+            };
+            hosts.push(HostResult { ip, hostname, ports: Vec::new() });
+        } else if let (Some(caps), Some(host)) = (PORT_LINE.captures(line), hosts.last_mut()) {
+            let Ok(port) = caps[1].parse() else { continue };
+            host.ports.push(PortResult {
+                port,
+                protocol: caps[2].to_string(),
+                state: caps[3].to_string(),
+                service: caps[4].to_string(),
+                version: caps.get(5).map(|version| version.as_str().trim().to_string()),
+            });
+        }
+    }
+    hosts
+}
+
+/// One finding per open port, as "host:port/protocol: service version"
+pub fn parse_nmap_findings(output: &str) -> Vec<Finding> {
+    parse_nmap(output)
+        .into_iter()
+        .flat_map(|host| {
+            let ip = host.ip;
+            host.ports.into_iter().filter(PortResult::is_open).map(move |port| {
+                let detail = match &port.version {
+                    Some(version) => format!("{} ({})", port.service, version),
+                    None => port.service.clone(),
+                };
+                Finding::new("nmap", Some(format!("{}:{}/{}", ip, port.port, port.protocol)), detail)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_nmap_service_scan() {
+        let output = "\
+Starting Nmap 7.94SVN ( https://nmap.org ) at 2024-03-02 14:21 UTC
+Nmap scan report for box.htb (10.10.11.42)
+Host is up (0.031s latency).
+Not shown: 997 closed tcp ports (reset)
+PORT     STATE    SERVICE VERSION
+22/tcp   open     ssh     OpenSSH 8.2p1 Ubuntu 4ubuntu0.5 (Ubuntu Linux; protocol 2.0)
+| ssh-hostkey: 
+|   3072 48:ad:d5:b8:3a:9f:bc:be:f7:e8:20:1e:f6:bf:de:ae (RSA)
+80/tcp   open     http    Apache httpd 2.4.41 ((Ubuntu))
+|_http-title: Did not follow redirect to http://box.htb/
+3306/tcp filtered mysql
+Service Info: OS: Linux; CPE: cpe:/o:linux:linux_kernel
+
+Service detection performed. Please report any incorrect results at https://nmap.org/submit/ .
+Nmap done: 1 IP address (1 host up) scanned in 9.87 seconds";
+        
+        let hosts = parse_nmap(output);
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].ip, "10.10.11.42");
+        assert_eq!(hosts[0].hostname.as_deref(), Some("box.htb"));
+        assert_eq!(hosts[0].ports.len(), 3);
+        assert_eq!(hosts[0].ports[0], PortResult {
+            port: 22,
+            protocol: "tcp".to_string(),
+            state: "open".to_string(),
+            service: "ssh".to_string(),
+            version: Some("OpenSSH 8.2p1 Ubuntu 4ubuntu0.5 (Ubuntu Linux; protocol 2.0)".to_string()),
+        });
+        assert_eq!(hosts[0].ports[2].state, "filtered");
+        assert_eq!(hosts[0].ports[2].version, None);
+        
+        let findings = parse_nmap_findings(output);
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[1].to_string(), "[nmap] 10.10.11.42:80/tcp: http (Apache httpd 2.4.41 ((Ubuntu)))");
+    }
+    
+    #[test]
+    fn test_parse_nmap_multiple_hosts() {
+        let output = "\
+Nmap scan report for 192.168.56.101
+Host is up (0.00042s latency).
+PORT    STATE SERVICE     VERSION
+139/tcp open  netbios-ssn Samba smbd 3.X - 4.X (workgroup: WORKGROUP)
+445/tcp open  netbios-ssn Samba smbd 4.6.2
+MAC Address: 08:00:27:5C:11:9E (Oracle VirtualBox virtual NIC)
+
+Nmap scan report for 192.168.56.102
+Host is up (0.00051s latency).
+PORT    STATE         SERVICE VERSION
+53/udp  open|filtered domain
+161/udp open          snmp    SNMPv1 server; net-snmp SNMPv3 server (public)
+
+Nmap done: 256 IP addresses (2 hosts up) scanned in 21.09 seconds";
+        
+        let hosts = parse_nmap(output);
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(hosts[0].hostname, None);
+        assert_eq!(hosts[0].ports[1].version.as_deref(), Some("Samba smbd 4.6.2"));
+        assert_eq!(hosts[1].ip, "192.168.56.102");
+        assert_eq!(hosts[1].ports[0].state, "open|filtered");
+        assert_eq!(hosts[1].ports[1].protocol, "udp");
+        
+        // Only definitely open ports are findings
+        let findings = parse_nmap_findings(output);
+        assert_eq!(findings.len(), 3);
+        assert_eq!(findings[2].location.as_deref(), Some("192.168.56.102:161/udp"));
+    }
+}
@@ -0,0 +1,64 @@
+use super::Finding;
+
+/// Extract injection points and the detected back-end DBMS from sqlmap output
+pub fn parse_sqlmap(output: &str) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut parameter: Option<String> = None;
+    
+    for line in output.lines() {
+        let line = line.trim();
+        
+        if let Some(name) = line.strip_prefix("Parameter:") {
+            parameter = Some(name.trim().to_string());
+        } else if let Some(kind) = line.strip_prefix("Type:") {
+            findings.push(Finding::new(
+                "sqlmap",
+                parameter.clone(),
+                format!("injectable ({})", kind.trim()),
+            ));
+        } else if line == "---" {
+            // Sections of injection points are delimited by "---"
+            parameter = None;
+        } else if let Some(dbms) = line.split_once("back-end DBMS:").map(|(_, dbms)| dbms.trim()) {
+            let detail = format!("back-end DBMS: {}", dbms);
+            if !findings.iter().any(|f| f.detail == detail) {
+                findings.push(Finding::new("sqlmap", None, detail));
+            }
+        }
+    }
+    
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_sqlmap() {
+        let output = "\
+[10:00:01] [INFO] testing connection to the target URL
+[10:00:05] [INFO] GET parameter 'id' is 'AND boolean-based blind - WHERE or HAVING clause' injectable
+sqlmap identified the following injection point(s) with a total of 46 HTTP(s) requests:
+---
+Parameter: id (GET)
+    Type: boolean-based blind
+    Title: AND boolean-based blind - WHERE or HAVING clause
+    Payload: id=1 AND 4512=4512
+
+    Type: time-based blind
+    Title: MySQL >= 5.0.12 AND time-based blind (query SLEEP)
+    Payload: id=1 AND (SELECT 1 FROM (SELECT(SLEEP(5)))a)
+---
+[10:00:09] [INFO] the back-end DBMS is MySQL
+web server operating system: Linux Ubuntu
+back-end DBMS: MySQL >= 5.0.12";
+        
+        let findings = parse_sqlmap(output);
+        assert_eq!(findings.len(), 3);
+        assert_eq!(findings[0].location.as_deref(), Some("id (GET)"));
+        assert_eq!(findings[0].detail, "injectable (boolean-based blind)");
+        assert_eq!(findings[1].detail, "injectable (time-based blind)");
+        assert_eq!(findings[2].detail, "back-end DBMS: MySQL >= 5.0.12");
+    }
+}
@@ -0,0 +1,408 @@
+use crate::command_parser::ToolOutputFormat;
+use crate::llm::InterpretFocus;
+use crate::report::ReportOrder;
+
+/// File the `report` command writes to when none is given
+pub const DEFAULT_REPORT_FILE: &str = "commandstrike_report.md";
+
+/// A line typed at the CommandStrike prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplCommand {
+    /// Nothing was typed
+    Empty,
+    /// `exit` or `quit`
+    Exit,
+    /// `switch [model]` or `model [model]`: change model, from the menu when none is given
+    Switch(Option<String>),
+    Help,
+    Models,
+    Templates,
+    /// `capture <var> [/regex/|line]`
+    Capture { name: String, selector: Option<String> },
+    /// `set <var>=<value>`
+    Set { name: String, value: String },
+    Vars,
+    /// `note <text>`
+    Note(String),
+    Notes,
+    /// `macro ...`
+    Macro(MacroCommand),
+    /// `focus` shows the current focus, `focus <name>` sets it
+    Focus(Option<InterpretFocus>),
+    /// `output-format xml|json|off`; None stops requesting structured output
+    OutputFormat(Option<ToolOutputFormat>),
+    /// `interpret [--focus <name>]`; None keeps the current focus
+    Interpret(Option<InterpretFocus>),
+    /// `recommend: <task>`
+    Recommend(String),
+    /// `temp-sweep <request>`
+    TempSweep(String),
+    /// `warmup <model> [model...]`
+    Warmup(Vec<String>),
+    /// `prompt-stats [sample request]`
+    PromptStats(Option<String>),
+    Cost,
+    Abort,
+    /// `reproduce <n>`, with n as typed (1-based)
+    Reproduce(usize),
+    /// `reinterpret <n>`, with n as typed (1-based)
+    Reinterpret(usize),
+    /// `ask: <question>`, `why: <question>` or `why` on its own: follow up on the last interpretation
+    Ask(String),
+    /// `compare-interpret <n> <model A> <model B>`
+    CompareInterpret { n: usize, models: [String; 2] },
+    /// `resume-pull [model]`
+    ResumePull(Option<String>),
+    /// `explain [-s]`: explain the last generated command, as a short summary with `-s`
+    Explain { short: bool },
+    /// `explain save <file>`
+    ExplainSave(String),
+    /// `config save <file> [--with-secrets]`
+    ConfigSave { path: String, include_secrets: bool },
+    /// `history [--target <host>]`
+    History { target: Option<String> },
+    /// `report [--chronological|--by-target] [file]`
+    Report { order: ReportOrder, path: String },
+    /// `more`: an alternative command for the last request
+    More,
+    /// A natural-language request to generate a command for
+    Generate(String),
+    /// A built-in command used incorrectly; holds the message to show
+    Usage(String),
+}
+
+/// Subcommands of `macro`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacroCommand {
+    /// `macro define <name> <step>; <step>...`
+    Define { name: String, steps: Vec<String> },
+    /// `macro run <name> [--execute]`
+    Run { name: String, execute: bool },
+    List,
+    /// `macro remove <name>`
+    Remove(String),
+}
+
+const CONFIG_SAVE_USAGE: &str = "Usage: config save <file> [--with-secrets]";
+/// Question asked by a bare `why`
+pub const WHY_QUESTION: &str = "Why? Explain the reasoning behind your interpretation and what in the output supports it.";
+const MACRO_USAGE: &str = "Usage: macro define <name> <step>; <step>... | macro run <name> [--execute] | macro list | macro remove <name>";
+const COMPARE_INTERPRET_USAGE: &str = "Usage: compare-interpret <n> <model A> <model B>";
+const INTERPRET_USAGE: &str = "Usage: interpret [--focus general|privesc|credentials|network|web]";
+
+/// Work out which command a line of input is
+///
+/// A line is only a built-in command when it matches that command's full argument
+/// grammar; anything else, such as "set up a netcat listener" or "report all open ports
+/// on 10.0.0.1", is a request to generate a command for. Commands that take free text
+/// (`ask:`, `why:`, `recommend:`) need a colon after the keyword so that ordinary
+/// requests starting with those words aren't taken over.
+pub fn parse_repl_input(input: &str) -> ReplCommand {
+    let input = input.trim();
+    let (keyword, rest) = match input.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None => (input, ""),
+    };
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    let generate = || ReplCommand::Generate(input.to_string());
+    
+    match (keyword, rest) {
+        ("", _) => ReplCommand::Empty,
+        ("exit" | "quit", "") => ReplCommand::Exit,
+        ("switch" | "model", "") => ReplCommand::Switch(None),
+        ("switch" | "model", model) if words.len() == 1 => ReplCommand::Switch(Some(model.to_string())),
+        ("help", "") => ReplCommand::Help,
+        ("models", "") => ReplCommand::Models,
+        ("templates", "") => ReplCommand::Templates,
+        ("vars", "") => ReplCommand::Vars,
+        ("notes", "") => ReplCommand::Notes,
+        ("cost", "") => ReplCommand::Cost,
+        ("abort", "") => ReplCommand::Abort,
+        ("more", "") => ReplCommand::More,
+        ("capture", "") => usage("Usage: capture <var> [/regex/|line]"),
+        ("capture", args) => match args.split_once(char::is_whitespace) {
+            None if is_variable_name(args) => ReplCommand::Capture { name: args.to_string(), selector: None },
+            Some((name, selector)) if is_variable_name(name) && is_capture_selector(selector.trim()) => ReplCommand::Capture {
+                name: name.to_string(),
+                selector: Some(selector.trim().to_string()),
+            },
+            _ => generate(),
+        },
+        ("set", "") => usage("Usage: set <var>=<value>"),
+        ("set", args) => match args.split_once('=') {
+            Some((name, value)) if is_variable_name(name.trim()) && !value.trim().is_empty() => {
+                ReplCommand::Set { name: name.trim().to_string(), value: value.trim().to_string() }
+            }
+            _ => generate(),
+        },
+        ("note", "") => usage("Usage: note <text>"),
+        ("note", text) => ReplCommand::Note(text.to_string()),
+        ("macro", args) => parse_macro(args),
+        ("focus", "") => ReplCommand::Focus(None),
+        ("focus", name) if words.len() == 1 => match InterpretFocus::parse(name) {
+            Some(focus) => ReplCommand::Focus(Some(focus)),
+            None => usage(&format!("Unknown focus '{}'. Type `focus` to list them.", name)),
+        },
+        ("output-format", "off") => ReplCommand::OutputFormat(None),
+        ("output-format", name) if words.len() <= 1 => match ToolOutputFormat::parse(name) {
+            Some(format) => ReplCommand::OutputFormat(Some(format)),
+            None => usage("Usage: output-format xml|json|off"),
+        },
+        ("interpret", "") => ReplCommand::Interpret(None),
+        ("interpret", args) if args.starts_with("--") => match words.as_slice() {
+            ["--focus", name] => InterpretFocus::parse(name)
+                .map(|focus| ReplCommand::Interpret(Some(focus)))
+                .unwrap_or_else(|| usage(INTERPRET_USAGE)),
+            _ => usage(INTERPRET_USAGE),
+        },
+        ("why" | "why:", "") => ReplCommand::Ask(WHY_QUESTION.to_string()),
+        ("why:", question) => ReplCommand::Ask(format!("Why {}", question)),
+        ("ask" | "ask:", "") => usage("Usage: ask: <question>"),
+        ("ask:", question) => ReplCommand::Ask(question.to_string()),
+        ("recommend" | "recommend:", "") => usage("Usage: recommend: <task>"),
+        ("recommend:", task) => ReplCommand::Recommend(task.to_string()),
+        ("temp-sweep", "") => usage("Usage: temp-sweep <request>"),
+        ("temp-sweep", request) => ReplCommand::TempSweep(request.to_string()),
+        ("warmup", "") => usage("Usage: warmup <model> [model...]"),
+        ("warmup", models) => ReplCommand::Warmup(models.split_whitespace().map(ToString::to_string).collect()),
+        ("prompt-stats", sample) => ReplCommand::PromptStats(non_empty(sample)),
+        ("reproduce", n) if words.len() <= 1 => match n.parse::<usize>() {
+            Ok(n) if n >= 1 => ReplCommand::Reproduce(n),
+            _ => usage("Usage: reproduce <n>"),
+        },
+        ("reinterpret", n) if words.len() <= 1 => match n.parse::<usize>() {
+            Ok(n) if n >= 1 => ReplCommand::Reinterpret(n),
+            _ => usage("Usage: reinterpret <n>"),
+        },
+        ("compare-interpret", args) => match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [n, a, b] => match n.parse::<usize>() {
+                Ok(n) if n >= 1 => ReplCommand::CompareInterpret { n, models: [a.to_string(), b.to_string()] },
+                _ => usage(COMPARE_INTERPRET_USAGE),
+            },
+            _ => usage(COMPARE_INTERPRET_USAGE),
+        },
+        ("resume-pull", model) => ReplCommand::ResumePull(non_empty(model)),
+        ("explain", "") => ReplCommand::Explain { short: false },
+        ("explain", "-s" | "--short") => ReplCommand::Explain { short: true },
+        ("explain", args) if args == "save" || args.starts_with("save ") => {
+            match args["save".len()..].trim() {
+                "" => usage("Usage: explain save <file>"),
+                path => ReplCommand::ExplainSave(path.to_string()),
+            }
+        }
+        ("config", args) => match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["save", path] if !path.starts_with("--") => ReplCommand::ConfigSave { path: path.to_string(), include_secrets: false },
+            ["save", path, "--with-secrets"] | ["save", "--with-secrets", path] => {
+                ReplCommand::ConfigSave { path: path.to_string(), include_secrets: true }
+            }
+            _ => usage(CONFIG_SAVE_USAGE),
+        },
+        ("history", "") => ReplCommand::History { target: None },
+        ("history", _) if words.len() == 1 || rest.starts_with("--") => match words.as_slice() {
+            ["--target", target] => ReplCommand::History { target: Some(target.to_string()) },
+            _ => usage("Usage: history [--target <host>]"),
+        },
+        ("report", _) => parse_report(&words).unwrap_or_else(generate),
+        _ => generate(),
+    }
+}
+
+/// Parse the arguments of `report`: an optional order flag and an optional file
+///
+/// Returns None unless every word is a known flag or a single file path (ending in a
+/// report extension or containing a `/`), so requests like "report all open ports on
+/// 10.0.0.1" aren't mistaken for it.
+fn parse_report(words: &[&str]) -> Option<ReplCommand> {
+    let mut order = ReportOrder::ByPhase;
+    let mut path = None;
+    for &word in words {
+        match word {
+            "--chronological" => order = ReportOrder::Chronological,
+            "--by-target" => order = ReportOrder::ByTarget,
+            _ if path.is_none() && is_report_path(word) => path = Some(word.to_string()),
+            _ => return None,
+        }
+    }
+    Some(ReplCommand::Report { order, path: path.unwrap_or_else(|| DEFAULT_REPORT_FILE.to_string()) })
+}
+
+/// Whether a word looks like a file to write a report to rather than a host or a word
+fn is_report_path(word: &str) -> bool {
+    const REPORT_EXTENSIONS: &[&str] = &[".md", ".markdown", ".txt", ".html"];
+    word.contains('/') || REPORT_EXTENSIONS.iter().any(|extension| word.to_lowercase().ends_with(extension))
+}
+
+/// Whether a word can name a session variable: letters, digits, `_` and `-`
+fn is_variable_name(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Whether `capture` understands a selector: a `/regex/` or a line number
+fn is_capture_selector(selector: &str) -> bool {
+    (selector.len() >= 2 && selector.starts_with('/') && selector.ends_with('/')) || selector.parse::<usize>().is_ok()
+}
+
+/// Parse the arguments of `macro`
+fn parse_macro(args: &str) -> ReplCommand {
+    let (subcommand, rest) = match args.split_once(char::is_whitespace) {
+        Some((subcommand, rest)) => (subcommand, rest.trim()),
+        None => (args, ""),
+    };
+    
+    let command = match (subcommand, rest) {
+        ("list", "") => MacroCommand::List,
+        ("remove", name) if !name.is_empty() && !name.contains(char::is_whitespace) => MacroCommand::Remove(name.to_string()),
+        ("run", rest) => {
+            let words: Vec<&str> = rest.split_whitespace().collect();
+            match words.iter().find(|word| !word.starts_with("--")) {
+                Some(name) => MacroCommand::Run { name: name.to_string(), execute: words.contains(&"--execute") },
+                None => return usage(MACRO_USAGE),
+            }
+        }
+        ("define", rest) => match rest.split_once(char::is_whitespace) {
+            Some((name, steps)) => MacroCommand::Define {
+                name: name.to_string(),
+                steps: steps.split(';').map(ToString::to_string).collect(),
+            },
+            None => return usage(MACRO_USAGE),
+        },
+        _ => return usage(MACRO_USAGE),
+    };
+    ReplCommand::Macro(command)
+}
+
+fn usage(message: &str) -> ReplCommand {
+    ReplCommand::Usage(message.to_string())
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_keywords() {
+        assert_eq!(parse_repl_input("   "), ReplCommand::Empty);
+        assert_eq!(parse_repl_input("exit"), ReplCommand::Exit);
+        assert_eq!(parse_repl_input("quit"), ReplCommand::Exit);
+        assert_eq!(parse_repl_input("switch"), ReplCommand::Switch(None));
+        assert_eq!(parse_repl_input("model"), ReplCommand::Switch(None));
+        assert_eq!(parse_repl_input("switch coder"), ReplCommand::Switch(Some("coder".to_string())));
+        assert_eq!(parse_repl_input("help"), ReplCommand::Help);
+        assert_eq!(parse_repl_input("models"), ReplCommand::Models);
+        assert_eq!(parse_repl_input("templates"), ReplCommand::Templates);
+        assert_eq!(parse_repl_input("vars"), ReplCommand::Vars);
+        assert_eq!(parse_repl_input("notes"), ReplCommand::Notes);
+        assert_eq!(parse_repl_input("cost"), ReplCommand::Cost);
+        assert_eq!(parse_repl_input("abort"), ReplCommand::Abort);
+        assert_eq!(parse_repl_input("more"), ReplCommand::More);
+    }
+    
+    #[test]
+    fn test_parse_commands_with_arguments() {
+        assert_eq!(
+            parse_repl_input("capture target /(\\d+\\.\\d+\\.\\d+\\.\\d+)/"),
+            ReplCommand::Capture { name: "target".to_string(), selector: Some("/(\\d+\\.\\d+\\.\\d+\\.\\d+)/".to_string()) }
+        );
+        assert_eq!(parse_repl_input("capture ip"), ReplCommand::Capture { name: "ip".to_string(), selector: None });
+        assert_eq!(parse_repl_input("capture port 3"), ReplCommand::Capture { name: "port".to_string(), selector: Some("3".to_string()) });
+        assert_eq!(parse_repl_input("set lhost = 10.10.14.2 "), ReplCommand::Set { name: "lhost".to_string(), value: "10.10.14.2".to_string() });
+        assert_eq!(parse_repl_input("set wordlist=/usr/share/wordlists/rockyou.txt"), ReplCommand::Set { name: "wordlist".to_string(), value: "/usr/share/wordlists/rockyou.txt".to_string() });
+        assert!(matches!(parse_repl_input("set"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("note creds in backup.zip"), ReplCommand::Note("creds in backup.zip".to_string()));
+        assert_eq!(parse_repl_input("focus"), ReplCommand::Focus(None));
+        assert_eq!(parse_repl_input("focus privesc"), ReplCommand::Focus(Some(InterpretFocus::PrivEsc)));
+        assert!(matches!(parse_repl_input("focus everything"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("output-format json"), ReplCommand::OutputFormat(Some(ToolOutputFormat::Json)));
+        assert_eq!(parse_repl_input("output-format off"), ReplCommand::OutputFormat(None));
+        assert!(matches!(parse_repl_input("output-format"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("interpret"), ReplCommand::Interpret(None));
+        assert_eq!(parse_repl_input("interpret --focus web"), ReplCommand::Interpret(Some(InterpretFocus::WebVulns)));
+        assert_eq!(parse_repl_input("why"), ReplCommand::Ask(WHY_QUESTION.to_string()));
+        assert_eq!(parse_repl_input("why: is port 21 risky?"), ReplCommand::Ask("Why is port 21 risky?".to_string()));
+        assert_eq!(parse_repl_input("ask: which share first?"), ReplCommand::Ask("which share first?".to_string()));
+        assert!(matches!(parse_repl_input("ask"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("recommend: web fuzzing"), ReplCommand::Recommend("web fuzzing".to_string()));
+        assert_eq!(parse_repl_input("temp-sweep list open ports"), ReplCommand::TempSweep("list open ports".to_string()));
+        assert_eq!(parse_repl_input("warmup llama3:8b gemma3:12b"), ReplCommand::Warmup(vec!["llama3:8b".to_string(), "gemma3:12b".to_string()]));
+        assert_eq!(parse_repl_input("prompt-stats"), ReplCommand::PromptStats(None));
+        assert_eq!(parse_repl_input("prompt-stats list files"), ReplCommand::PromptStats(Some("list files".to_string())));
+        assert_eq!(parse_repl_input("reproduce 2"), ReplCommand::Reproduce(2));
+        assert!(matches!(parse_repl_input("reproduce 0"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("reinterpret 3"), ReplCommand::Reinterpret(3));
+        assert!(matches!(parse_repl_input("reinterpret last"), ReplCommand::Usage(_)));
+        assert_eq!(
+            parse_repl_input("compare-interpret 2 llama3:8b qwen2.5:32b"),
+            ReplCommand::CompareInterpret { n: 2, models: ["llama3:8b".to_string(), "qwen2.5:32b".to_string()] }
+        );
+        assert!(matches!(parse_repl_input("compare-interpret 2 llama3:8b"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("config save tuned.toml"), ReplCommand::ConfigSave { path: "tuned.toml".to_string(), include_secrets: false });
+        assert_eq!(parse_repl_input("config save tuned.toml --with-secrets"), ReplCommand::ConfigSave { path: "tuned.toml".to_string(), include_secrets: true });
+        assert!(matches!(parse_repl_input("config load tuned.toml"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("resume-pull"), ReplCommand::ResumePull(None));
+        assert_eq!(parse_repl_input("resume-pull phi3:14b"), ReplCommand::ResumePull(Some("phi3:14b".to_string())));
+        assert_eq!(parse_repl_input("explain"), ReplCommand::Explain { short: false });
+        assert_eq!(parse_repl_input("explain -s"), ReplCommand::Explain { short: true });
+        assert_eq!(parse_repl_input("explain save notes.md"), ReplCommand::ExplainSave("notes.md".to_string()));
+        assert!(matches!(parse_repl_input("explain save"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("history"), ReplCommand::History { target: None });
+        assert_eq!(parse_repl_input("history --target 10.0.0.5"), ReplCommand::History { target: Some("10.0.0.5".to_string()) });
+        assert!(matches!(parse_repl_input("history 10.0.0.5"), ReplCommand::Usage(_)));
+        assert_eq!(parse_repl_input("report --by-target"), ReplCommand::Report { order: ReportOrder::ByTarget, path: DEFAULT_REPORT_FILE.to_string() });
+        assert_eq!(parse_repl_input("report"), ReplCommand::Report { order: ReportOrder::ByPhase, path: DEFAULT_REPORT_FILE.to_string() });
+        assert_eq!(
+            parse_repl_input("report --chronological out.md"),
+            ReplCommand::Report { order: ReportOrder::Chronological, path: "out.md".to_string() }
+        );
+    }
+    
+    #[test]
+    fn test_parse_macro_commands() {
+        assert_eq!(
+            parse_repl_input("macro define web scan [target]; enumerate directories"),
+            ReplCommand::Macro(MacroCommand::Define {
+                name: "web".to_string(),
+                steps: vec!["scan [target]".to_string(), " enumerate directories".to_string()],
+            })
+        );
+        assert_eq!(
+            parse_repl_input("macro run web --execute"),
+            ReplCommand::Macro(MacroCommand::Run { name: "web".to_string(), execute: true })
+        );
+        assert_eq!(parse_repl_input("macro list"), ReplCommand::Macro(MacroCommand::List));
+        assert_eq!(parse_repl_input("macro remove web"), ReplCommand::Macro(MacroCommand::Remove("web".to_string())));
+        assert!(matches!(parse_repl_input("macro"), ReplCommand::Usage(_)));
+    }
+    
+    #[test]
+    fn test_freeform_requests_generate() {
+        assert_eq!(parse_repl_input("scan 10.0.0.1 for open ports"), ReplCommand::Generate("scan 10.0.0.1 for open ports".to_string()));
+        // Keywords only count on their own or with the arguments they expect
+        for request in [
+            "models of routers vulnerable to CVE-2023-1389",
+            "explain how sudo -l works",
+            "set up a netcat listener on 4444",
+            "set permissions on the web root",
+            "capture traffic on eth0",
+            "capture the login request with burp",
+            "report all open ports on 10.0.0.1",
+            "report 10.0.0.1",
+            "switch to root user",
+            "model the attack surface of the web app",
+            "history of bash commands for the www-data user",
+            "reproduce the CVE exploit",
+            "reinterpret the nmap output as json",
+            "recommend a wordlist for directory brute forcing",
+            "why does this port scan take so long",
+            "ask the smb server for its shares",
+            "focus on port 8080 and enumerate it",
+            "interpret the pcap with tshark",
+            "output-format of nmap as grepable",
+        ] {
+            assert_eq!(parse_repl_input(request), ReplCommand::Generate(request.to_string()), "{}", request);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::backend::{LlmBackend, LlmRequest, LlmResponse};
+use anyhow::{Context, Result};
+use futures_util::future::BoxFuture;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// One model call in a recording
+///
+/// A recording is a JSON Lines file with one entry per model call, in the order the
+/// calls were made:
+///
+/// ```text
+/// {"model":"llama3:8b","system":"You are CommandStrike...","prompt":"Generate a shell command...","response":"nmap -sV 10.0.0.1","done_reason":"stop"}
+/// ```
+///
+/// `response` is the model's answer before any cleaning, so replaying it exercises
+/// the same cleaning and parsing as the original session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    pub model: String,
+    #[serde(default)]
+    pub system: Option<String>,
+    pub prompt: String,
+    pub response: String,
+    #[serde(default)]
+    pub done_reason: Option<String>,
+}
+
+/// Appends every model call of a session to a recording file
+#[derive(Debug)]
+pub struct SessionRecorder {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl SessionRecorder {
+    /// Start a new recording at `path`, replacing any existing file
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::create(&path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        Ok(Self { path, file: Mutex::new(file) })
+    }
+
+    /// Add a call and its response to the recording
+    pub fn record(&self, request: &LlmRequest, response: &LlmResponse) -> Result<()> {
+        let entry = ReplayEntry {
+            model: request.model.clone(),
+            system: request.system.clone(),
+            prompt: request.prompt.clone(),
+            response: response.text.clone(),
+            done_reason: response.done_reason.clone(),
+        };
+        let line = serde_json::to_string(&entry)?;
+        
+        let mut file = self.file.lock().map_err(|_| anyhow::anyhow!("Recording lock poisoned"))?;
+        writeln!(file, "{}", line)
+            .and_then(|_| file.flush())
+            .with_context(|| format!("Failed to write recording {}", self.path.display()))
+    }
+}
+
+/// Answers model calls from a recording instead of calling Ollama
+///
+/// Responses are given back in the order they were recorded, whatever the request,
+/// so a session replays deterministically even though prompts contain random seeds
+/// or timing-dependent history. A prompt that differs from the recorded one is logged.
+#[derive(Debug)]
+pub struct ReplayBackend {
+    path: PathBuf,
+    entries: Mutex<VecDeque<ReplayEntry>>,
+}
+
+impl ReplayBackend {
+    /// Load a recording made with `SessionRecorder`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read recording {}", path.display()))?;
+        let entries = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Invalid entry on line {} of {}", i + 1, path.display()))
+            })
+            .collect::<Result<VecDeque<_>>>()?;
+        Ok(Self { path, entries: Mutex::new(entries) })
+    }
+
+    /// Number of recorded responses not yet replayed
+    pub fn remaining(&self) -> usize {
+        self.entries.lock().map(|entries| entries.len()).unwrap_or(0)
+    }
+}
+
+impl LlmBackend for ReplayBackend {
+    fn generate<'a>(&'a self, request: &'a LlmRequest) -> BoxFuture<'a, Result<LlmResponse>> {
+        Box::pin(async move {
+            let entry = self.entries.lock()
+                .map_err(|_| anyhow::anyhow!("Replay lock poisoned"))?
+                .pop_front()
+                .with_context(|| format!("Recording {} has no more responses to replay", self.path.display()))?;
+            
+            if entry.prompt != request.prompt {
+                warn!("Replaying a response recorded for a different prompt");
+                debug!("Recorded prompt: {}", entry.prompt);
+            }
+            Ok(LlmResponse { text: entry.response, done_reason: entry.done_reason, usage: None, eval_duration: None })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{OllamaClient, OllamaConfig, StreamChunk};
+    use std::sync::Arc;
+    
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let path = std::env::temp_dir().join(format!("commandstrike-replay-{}.jsonl", std::process::id()));
+        
+        let mut server = mockito::Server::new_async().await;
+        let generate = server.mock("POST", "/api/generate")
+            .with_body(r#"{"model":"test","response":"```bash\nnmap -sV 10.0.0.1\n```","done":true,"done_reason":"stop"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let config = OllamaConfig::builder().api_url(&server.url()).model("test").build().unwrap();
+        let mut client = OllamaClient::with_config(config.clone()).unwrap();
+        client.set_recorder(SessionRecorder::create(&path).unwrap());
+        assert_eq!(client.generate_command("scan 10.0.0.1", &[]).await.unwrap(), "nmap -sV 10.0.0.1");
+        generate.assert_async().await;
+        
+        let recorded = fs::read_to_string(&path).unwrap();
+        let entry: ReplayEntry = serde_json::from_str(recorded.lines().next().unwrap()).unwrap();
+        assert_eq!(entry.response, "```bash\nnmap -sV 10.0.0.1\n```");
+        assert_eq!(entry.done_reason.as_deref(), Some("stop"));
+        
+        // The recorded response is replayed and cleaned the same way, without the server
+        drop(server);
+        let backend = Arc::new(ReplayBackend::load(&path).unwrap());
+        fs::write(&path, format!("{}{}\n", recorded, serde_json::to_string(&ReplayEntry {
+            response: "Port 22 is open".to_string(),
+            ..entry
+        }).unwrap())).unwrap();
+        let backend_with_stream = Arc::new(ReplayBackend::load(&path).unwrap());
+        
+        let mut client = OllamaClient::with_config(config.clone()).unwrap();
+        client.set_backend(backend.clone());
+        assert_eq!(client.generate_command("scan 10.0.0.1", &[]).await.unwrap(), "nmap -sV 10.0.0.1");
+        assert_eq!(backend.remaining(), 0);
+        assert!(client.generate_command("scan 10.0.0.1", &[]).await.unwrap_err().to_string().contains("no more responses"));
+        
+        let mut client = OllamaClient::with_config(config).unwrap();
+        client.set_backend(backend_with_stream.clone());
+        client.generate_command("scan 10.0.0.1", &[]).await.unwrap();
+        let mut stream = client.interpret_result_streaming("22/tcp open ssh", &[]).await.unwrap();
+        assert_eq!(stream.receiver.recv().await, Some(StreamChunk::Token("Port 22 is open".to_string())));
+        assert_eq!(stream.into_final().await.unwrap(), "Port 22 is open");
+        
+        let _ = fs::remove_file(&path);
+    }
+}
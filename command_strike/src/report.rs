@@ -0,0 +1,275 @@
+use crate::command_parser::parse_command;
+use crate::context_manager::{format_timestamp, Note};
+use crate::llm::HistoryItem;
+use crate::parsers::Finding;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Engagement phase a command belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Recon,
+    Enumeration,
+    Exploitation,
+    PrivEsc,
+    Exfil,
+}
+
+impl Phase {
+    /// All phases in methodology order
+    pub const ALL: [Phase; 5] = [
+        Phase::Recon,
+        Phase::Enumeration,
+        Phase::Exploitation,
+        Phase::PrivEsc,
+        Phase::Exfil,
+    ];
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::Recon => "Reconnaissance",
+            Phase::Enumeration => "Enumeration",
+            Phase::Exploitation => "Exploitation",
+            Phase::PrivEsc => "Privilege Escalation",
+            Phase::Exfil => "Exfiltration",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How report sections are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportOrder {
+    /// Group commands under their engagement phase
+    #[default]
+    ByPhase,
+    /// Keep commands in the order they were run
+    Chronological,
+    /// Group commands under the target they were aimed at, in the order targets appeared
+    ByTarget,
+}
+
+// Keywords that identify a phase regardless of the program used
+const PRIVESC_KEYWORDS: &[&str] = &[
+    "sudo -l", "-perm -4000", "-perm -u=s", "getcap", "linpeas", "winpeas", "pspy",
+    "linux-exploit-suggester", "/etc/sudoers", "crontab",
+];
+const EXFIL_KEYWORDS: &[&str] = &[
+    "--upload-file", "curl -t ", "scp ", "rsync ", "exfil", "/dev/tcp/",
+];
+
+// Programs mapped to the phase they are usually used in
+const RECON_PROGRAMS: &[&str] = &[
+    "nmap", "masscan", "ping", "fping", "arp-scan", "netdiscover", "whois", "dig",
+    "nslookup", "host", "amass", "subfinder", "theharvester", "traceroute", "rustscan",
+];
+const EXPLOITATION_PROGRAMS: &[&str] = &[
+    "hydra", "medusa", "sqlmap", "msfconsole", "msfvenom", "hashcat", "john",
+    "xsser", "crackmapexec", "nxc", "evil-winrm", "impacket-psexec",
+];
+
+/// Classify a command into the engagement phase it most likely belongs to
+///
+/// Uses phase-specific keywords first, then the parsed program name. Everything else
+/// (gobuster, nikto, smbclient, ...) is treated as enumeration.
+pub fn classify_phase(command: &str) -> Phase {
+    let lower = command.to_lowercase();
+    
+    if PRIVESC_KEYWORDS.iter().any(|k| lower.contains(k)) {
+        return Phase::PrivEsc;
+    }
+    if EXFIL_KEYWORDS.iter().any(|k| lower.contains(k)) {
+        return Phase::Exfil;
+    }
+    
+    let program = parse_command(&lower).program;
+    if RECON_PROGRAMS.contains(&program.as_str()) {
+        Phase::Recon
+    } else if EXPLOITATION_PROGRAMS.contains(&program.as_str()) {
+        Phase::Exploitation
+    } else {
+        Phase::Enumeration
+    }
+}
+
+/// Generate a markdown engagement report from the session history and findings
+pub fn generate_report(history: &[HistoryItem], findings: &[Finding], notes: &[Note], order: ReportOrder) -> String {
+    let mut report = String::from("# CommandStrike Engagement Report\n\n");
+    
+    if !findings.is_empty() {
+        report.push_str("## Findings\n\n");
+        for finding in findings {
+            match &finding.location {
+                Some(location) => report.push_str(&format!("- **{}**: {}\n", location, finding.detail)),
+                None => report.push_str(&format!("- {}\n", finding.detail)),
+            }
+        }
+        report.push('\n');
+    }
+    
+    if !notes.is_empty() {
+        report.push_str("## Notes\n\n");
+        for note in notes {
+            report.push_str(&format!("- _{}_: {}\n", format_timestamp(note.timestamp), note.text));
+        }
+        report.push('\n');
+    }
+    
+    if history.is_empty() {
+        report.push_str("No commands were executed in this session.\n");
+        return report;
+    }
+    
+    match order {
+        ReportOrder::ByPhase => {
+            for phase in Phase::ALL {
+                let items: Vec<&HistoryItem> = history
+                    .iter()
+                    .filter(|item| classify_phase(&item.command) == phase)
+                    .collect();
+                if items.is_empty() {
+                    continue;
+                }
+                
+                report.push_str(&format!("## {}\n\n", phase));
+                for item in items {
+                    push_entry(&mut report, item);
+                }
+            }
+        }
+        ReportOrder::Chronological => {
+            report.push_str("## Timeline\n\n");
+            for item in history {
+                push_entry(&mut report, item);
+            }
+        }
+        ReportOrder::ByTarget => {
+            let mut targets: Vec<&str> = Vec::new();
+            for item in history {
+                if !targets.contains(&item.target_label()) {
+                    targets.push(item.target_label());
+                }
+            }
+            for target in targets {
+                report.push_str(&format!("## Target: {}\n\n", target));
+                for item in history.iter().filter(|item| item.target_label() == target) {
+                    push_entry(&mut report, item);
+                }
+            }
+        }
+    }
+    
+    report
+}
+
+/// Append a single history entry to the report
+fn push_entry(report: &mut String, item: &HistoryItem) {
+    report.push_str(&format!("### {}\n\n", item.user_input));
+    
+    let params = &item.params;
+    if !params.model.is_empty() {
+        let seed = params.seed.map(|seed| seed.to_string()).unwrap_or_else(|| "none".to_string());
+        let temperature = params.temperature.map(|t| format!("{:.2}", t)).unwrap_or_else(|| "model default".to_string());
+        report.push_str(&format!(
+            "_Generated by {} (temperature {}, seed {})_\n\n",
+            params.model, temperature, seed
+        ));
+    }
+    
+    report.push_str(&format!(
+        "```bash\n{}\n```\n\n```\n{}\n```\n\n",
+        item.command,
+        item.result.trim_end()
+    ));
+}
+
+/// Format a command and its explanation as an annotated markdown section
+pub fn explanation_markdown(command: &str, explanation: &str) -> String {
+    format!("## {}\n\n{}\n\n", command, explanation.trim())
+}
+
+/// Append a command explanation to a markdown notes file, creating it if needed
+pub fn append_explanation(path: impl AsRef<Path>, command: &str, explanation: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(explanation_markdown(command, explanation).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::extract_findings;
+    
+    fn item(user_input: &str, command: &str) -> HistoryItem {
+        HistoryItem {
+            user_input: user_input.to_string(),
+            command: command.to_string(),
+            result: "output".to_string(),
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn test_classify_phase() {
+        assert_eq!(classify_phase("nmap -sV -p- 10.0.0.1"), Phase::Recon);
+        assert_eq!(classify_phase("sudo nmap -sS 10.0.0.0/24"), Phase::Recon);
+        assert_eq!(classify_phase("hydra -l admin -P rockyou.txt ssh://10.0.0.1"), Phase::Exploitation);
+        assert_eq!(classify_phase("gobuster dir -u http://target -w common.txt"), Phase::Enumeration);
+        assert_eq!(classify_phase("find / -perm -4000 -type f 2>/dev/null"), Phase::PrivEsc);
+        assert_eq!(classify_phase("scp loot.tar.gz user@10.0.0.5:/tmp"), Phase::Exfil);
+    }
+    
+    #[test]
+    fn test_report_groups_by_phase() {
+        let history = vec![
+            item("brute force ssh", "hydra -l root -P list.txt ssh://host"),
+            item("scan ports", "nmap -sV host"),
+        ];
+        
+        let grouped = generate_report(&history, &[], &[], ReportOrder::ByPhase);
+        assert!(grouped.find("## Reconnaissance").unwrap() < grouped.find("## Exploitation").unwrap());
+        assert!(!grouped.contains("## Findings"));
+        
+        let chronological = generate_report(&history, &[], &[], ReportOrder::Chronological);
+        assert!(chronological.find("hydra").unwrap() < chronological.find("nmap").unwrap());
+    }
+    
+    #[test]
+    fn test_report_groups_by_target() {
+        let history = vec![
+            HistoryItem { target: Some("10.0.0.9".to_string()), ..item("scan b", "nmap -sV 10.0.0.9") },
+            item("check my ip", "ip a"),
+            HistoryItem { target: Some("10.0.0.5".to_string()), ..item("scan a", "nmap -sV 10.0.0.5") },
+            HistoryItem { target: Some("10.0.0.9".to_string()), ..item("brute b", "hydra ssh://10.0.0.9") },
+        ];
+        
+        let report = generate_report(&history, &[], &[], ReportOrder::ByTarget);
+        let b = report.find("## Target: 10.0.0.9").unwrap();
+        let unspecified = report.find("## Target: unspecified").unwrap();
+        let a = report.find("## Target: 10.0.0.5").unwrap();
+        assert!(b < unspecified && unspecified < a);
+        assert!(report.find("hydra").unwrap() < unspecified);
+    }
+    
+    #[test]
+    fn test_report_lists_findings_first() {
+        let history = vec![item("scan ports", "nmap -sV host")];
+        let findings = extract_findings("Apache 2.4.49 is affected by CVE-2021-41773.");
+        
+        let report = generate_report(&history, &findings, &[], ReportOrder::ByPhase);
+        assert!(report.contains("- **CVE-2021-41773**: Apache 2.4.49 is affected by CVE-2021-41773."));
+        assert!(report.find("## Findings").unwrap() < report.find("## Reconnaissance").unwrap());
+    }
+    
+    #[test]
+    fn test_report_includes_notes() {
+        let notes = vec![Note { timestamp: 0, text: "creds in backup.zip".to_string() }];
+        
+        let report = generate_report(&[], &[], &notes, ReportOrder::ByPhase);
+        assert!(report.contains("## Notes\n\n- _1970-01-01 00:00 UTC_: creds in backup.zip\n"));
+        assert!(report.contains("No commands were executed"));
+    }
+}
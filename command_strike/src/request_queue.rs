@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use std::future::Future;
+use std::pin::Pin;
+use tokio::sync::{mpsc, oneshot};
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// How urgently a queued request should be served
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    #[default]
+    /// A live request the user is waiting on
+    Interactive,
+    /// Work such as summarization that can wait
+    Background,
+}
+
+/// Queue in front of a single backend that runs one request at a time
+///
+/// Interactive requests always jump ahead of queued background work, so a long
+/// summarization never delays the user's next command by more than the request
+/// already in flight.
+#[derive(Debug, Clone)]
+pub struct RequestQueue {
+    interactive: mpsc::UnboundedSender<Job>,
+    background: mpsc::UnboundedSender<Job>,
+}
+
+impl RequestQueue {
+    /// Create a queue and spawn its dispatcher on the current tokio runtime
+    pub fn new() -> Self {
+        let (interactive, mut interactive_rx) = mpsc::unbounded_channel::<Job>();
+        let (background, mut background_rx) = mpsc::unbounded_channel::<Job>();
+        
+        tokio::spawn(async move {
+            loop {
+                let job = tokio::select! {
+                    // Always check for interactive work first
+                    biased;
+                    Some(job) = interactive_rx.recv() => job,
+                    Some(job) = background_rx.recv() => job,
+                    else => break,
+                };
+                job.await;
+            }
+        });
+        
+        Self { interactive, background }
+    }
+
+    /// Queue a job and wait for its result
+    ///
+    /// The job is enqueued immediately, before the returned future is first polled.
+    pub fn submit<F, T>(&self, priority: Priority, job: F) -> impl Future<Output = Result<T>>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::pin(async move {
+            let _ = tx.send(job.await);
+        });
+        let queued = match priority {
+            Priority::Interactive => self.interactive.send(job),
+            Priority::Background => self.background.send(job),
+        };
+        
+        async move {
+            queued.map_err(|_| anyhow::anyhow!("Request queue dispatcher has stopped"))?;
+            rx.await.context("Queued request was dropped before completing")
+        }
+    }
+
+    /// Wait for the backend to be free, and keep it until the returned slot is dropped
+    ///
+    /// This suits streamed responses, which occupy the backend long after the request
+    /// that started them has returned.
+    pub async fn acquire(&self, priority: Priority) -> Result<QueueSlot> {
+        let (started, wait_for_start) = oneshot::channel();
+        let (release, wait_for_release) = oneshot::channel::<()>();
+        let queued = self.submit(priority, async move {
+            if started.send(()).is_ok() {
+                // Sends nothing; resolves once the slot is dropped
+                let _ = wait_for_release.await;
+            }
+        });
+        // The job is queued on submission and holds the backend until released, so only
+        // its start is waited for
+        drop(queued);
+        wait_for_start.await.context("Request queue dispatcher has stopped")?;
+        Ok(QueueSlot { _release: release })
+    }
+}
+
+/// Exclusive use of the backend, handed back to the queue when dropped
+#[derive(Debug)]
+pub struct QueueSlot {
+    _release: oneshot::Sender<()>,
+}
+
+impl Default for RequestQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    
+    #[tokio::test]
+    async fn test_interactive_request_jumps_queue() {
+        let queue = RequestQueue::new();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str| {
+            let order = order.clone();
+            async move { order.lock().unwrap().push(name) }
+        };
+        
+        // Keep the backend busy until both requests are queued
+        let (release, gate) = oneshot::channel::<()>();
+        let busy = queue.submit(Priority::Background, async move {
+            let _ = gate.await;
+        });
+        tokio::task::yield_now().await;
+        
+        let background = queue.submit(Priority::Background, record("background"));
+        let interactive = queue.submit(Priority::Interactive, record("interactive"));
+        release.send(()).unwrap();
+        
+        busy.await.unwrap();
+        background.await.unwrap();
+        interactive.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), vec!["interactive", "background"]);
+    }
+    
+    #[tokio::test]
+    async fn test_slot_holds_backend_until_dropped() {
+        let queue = RequestQueue::new();
+        let slot = queue.acquire(Priority::Background).await.unwrap();
+        
+        let next = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire(Priority::Interactive).await.map(|_| ()) }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!next.is_finished());
+        
+        drop(slot);
+        next.await.unwrap().unwrap();
+    }
+}
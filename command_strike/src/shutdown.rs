@@ -0,0 +1,203 @@
+use log::{debug, info};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+/// Work to run exactly once before the process ends
+///
+/// Features that hold state worth keeping (open logs, history, pending output)
+/// register a hook; every exit path then calls [`Shutdown::shutdown`], which runs
+/// the hooks in registration order. Later calls do nothing, so an `exit` racing a
+/// signal is safe.
+#[derive(Default)]
+pub struct Shutdown {
+    done: AtomicBool,
+    hooks: Mutex<Vec<Hook>>,
+}
+
+impl Shutdown {
+    /// Create a shutdown routine with no hooks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register work to run on shutdown
+    pub fn on_shutdown(&self, hook: impl FnOnce() + Send + 'static) {
+        if let Ok(mut hooks) = self.hooks.lock() {
+            hooks.push(Box::new(hook));
+        }
+    }
+
+    /// Run the registered hooks, returning false if shutdown had already happened
+    pub fn shutdown(&self) -> bool {
+        if self.done.swap(true, Ordering::SeqCst) {
+            debug!("Shutdown already performed");
+            return false;
+        }
+        
+        let hooks = match self.hooks.lock() {
+            Ok(mut hooks) => std::mem::take(&mut *hooks),
+            Err(_) => Vec::new(),
+        };
+        debug!("Running {} shutdown hook(s)", hooks.len());
+        for hook in hooks {
+            hook();
+        }
+        true
+    }
+
+    /// Returns true once shutdown has started
+    pub fn is_shut_down(&self) -> bool {
+        self.done.load(Ordering::SeqCst)
+    }
+}
+
+impl std::fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("done", &self.is_shut_down())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Marks work that Ctrl-C should cancel instead of ending the process
+#[derive(Debug, Clone, Default)]
+pub struct Interruptible {
+    /// Guards currently alive; guards may nest
+    busy: Arc<AtomicUsize>,
+}
+
+/// Keeps an `Interruptible` marked as busy until dropped
+#[derive(Debug)]
+pub struct InterruptibleGuard {
+    busy: Arc<AtomicUsize>,
+}
+
+impl Interruptible {
+    /// Create a marker with no work in progress
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark work as in progress until the returned guard is dropped
+    pub fn start(&self) -> InterruptibleGuard {
+        self.busy.fetch_add(1, Ordering::SeqCst);
+        InterruptibleGuard { busy: self.busy.clone() }
+    }
+
+    /// Returns true while a guard from `start` is alive
+    pub fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::SeqCst) > 0
+    }
+
+    /// Await `work` while marked busy, dropping it and returning `None` if `cancel` fires first
+    ///
+    /// Dropping is what stops work that doesn't watch the token itself, such as a
+    /// running command, which is killed along with its handle.
+    pub async fn run<T>(&self, cancel: CancellationToken, work: impl Future<Output = T>) -> Option<T> {
+        let _busy = self.start();
+        tokio::select! {
+            output = work => Some(output),
+            _ = cancel.cancelled() => None,
+        }
+    }
+}
+
+impl Drop for InterruptibleGuard {
+    fn drop(&mut self) {
+        self.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Handle Ctrl-C: call `cancel` while `work` is busy, otherwise shut down and exit
+pub fn install_interrupt_handler(shutdown: Arc<Shutdown>, 
+                                 work: Interruptible, 
+                                 cancel: impl Fn() + Send + 'static) {
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if work.is_busy() {
+                info!("Received Ctrl-C, cancelling the current request");
+                cancel();
+            } else {
+                shutdown.shutdown();
+                // 128 + SIGINT, as a shell would report it
+                std::process::exit(130);
+            }
+        }
+    });
+}
+
+/// Shut down cleanly and exit when the process receives SIGTERM
+#[cfg(unix)]
+pub fn install_signal_handler(shutdown: Arc<Shutdown>) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        if terminate.recv().await.is_some() {
+            info!("Received SIGTERM, shutting down");
+            shutdown.shutdown();
+            // 128 + SIGTERM, as a shell would report it
+            std::process::exit(143);
+        }
+    });
+    Ok(())
+}
+
+/// SIGTERM isn't available on this platform; exit paths still call `shutdown`
+#[cfg(not(unix))]
+pub fn install_signal_handler(_shutdown: Arc<Shutdown>) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    
+    #[test]
+    fn test_shutdown_is_idempotent() {
+        let shutdown = Shutdown::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        for _ in 0..2 {
+            let calls = calls.clone();
+            shutdown.on_shutdown(move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        
+        assert!(shutdown.shutdown());
+        assert!(!shutdown.shutdown());
+        assert!(shutdown.is_shut_down());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+    
+    #[test]
+    fn test_interruptible_is_busy_while_guarded() {
+        let work = Interruptible::new();
+        assert!(!work.is_busy());
+        
+        let guard = work.start();
+        let nested = work.start();
+        assert!(work.clone().is_busy());
+        drop(nested);
+        assert!(work.is_busy());
+        drop(guard);
+        assert!(!work.is_busy());
+    }
+    
+    #[tokio::test]
+    async fn test_run_gives_up_when_cancelled() {
+        let work = Interruptible::new();
+        let cancel = CancellationToken::new();
+        assert_eq!(work.run(cancel.clone(), async { 42 }).await, Some(42));
+        
+        cancel.cancel();
+        let never = work.run(cancel, std::future::pending::<()>()).await;
+        assert_eq!(never, None);
+        assert!(!work.is_busy());
+    }
+}
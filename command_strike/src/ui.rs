@@ -0,0 +1,525 @@
+use crate::command_parser::split_words;
+use colored::Colorize;
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the CommandStrike banner should be printed
+static BANNER_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Enable or disable the startup banner (useful when wrapping CommandStrike in other tools)
+pub fn set_banner_enabled(enabled: bool) {
+    BANNER_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns true if the startup banner should be printed
+pub fn banner_enabled() -> bool {
+    BANNER_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Print the CommandStrike header, unless it has been suppressed
+pub fn print_banner() {
+    if !banner_enabled() {
+        return;
+    }
+    
+    println!("{}", "CommandStrike - CTF Assistant".green().bold());
+    println!("{}", "================================".green());
+}
+
+/// Format a streaming progress line such as "Interpreting... (342 tokens, 18 tok/s)"
+pub fn token_progress(label: &str, tokens: usize, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f32();
+    let rate = if secs > 0.0 { tokens as f32 / secs } else { 0.0 };
+    format!("{} ({} tokens, {:.0} tok/s)", label, tokens, rate)
+}
+
+/// Overwrite the current terminal line with a progress message
+pub fn update_progress(message: &str) -> io::Result<()> {
+    print!("\r\x1b[2K{}", message);
+    io::stdout().flush()
+}
+
+/// Clear a progress line written by `update_progress`
+pub fn clear_progress() -> io::Result<()> {
+    print!("\r\x1b[2K");
+    io::stdout().flush()
+}
+
+/// One word of a word-level diff between two commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordDiff {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// Diff two commands word by word (longest common subsequence)
+pub fn diff_words(old: &str, new: &str) -> Vec<WordDiff> {
+    let old = split_words(old);
+    let new = split_words(new);
+    
+    // lcs[i][j] = length of the common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    
+    let (mut i, mut j) = (0, 0);
+    let mut diff = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push(WordDiff::Same(old[i].clone()));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(WordDiff::Removed(old[i].clone()));
+            i += 1;
+        } else {
+            diff.push(WordDiff::Added(new[j].clone()));
+            j += 1;
+        }
+    }
+    diff
+}
+
+/// Render a word diff on one line: removed words in red, added words in green
+pub fn command_diff(old: &str, new: &str) -> String {
+    diff_words(old, new)
+        .into_iter()
+        .map(|word| match word {
+            WordDiff::Same(w) => w,
+            WordDiff::Removed(w) => w.red().strikethrough().to_string(),
+            WordDiff::Added(w) => w.green().bold().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lay out two titled texts in columns of `width` characters, wrapping at word boundaries
+pub fn side_by_side(left: (&str, &str), right: (&str, &str), width: usize) -> String {
+    let mut columns = [left, right].map(|(title, text)| {
+        let mut lines = vec![title.to_string(), "-".repeat(title.chars().count().min(width))];
+        lines.extend(wrap(text, width));
+        lines
+    });
+    let rows = columns[0].len().max(columns[1].len());
+    for column in &mut columns {
+        column.resize(rows, String::new());
+    }
+    
+    columns[0]
+        .iter()
+        .zip(&columns[1])
+        .map(|(l, r)| format!("{:<width$} | {}", l, r, width = width).trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Wrap text to lines of at most `width` characters, keeping its line breaks
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            // Break words that can't fit on a line of their own
+            let chars: Vec<char> = word.chars().collect();
+            for piece in chars.chunks(width) {
+                let piece: String = piece.iter().collect();
+                let len = line.chars().count();
+                if len > 0 && len + 1 + piece.chars().count() > width {
+                    lines.push(std::mem::take(&mut line));
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(&piece);
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Output shorter than this many lines is printed directly rather than paged
+pub const PAGER_MIN_LINES: usize = 30;
+
+/// Pager command line: `$PAGER` when set, otherwise `less`; `-R` is added for less so colours survive
+pub fn pager_command(pager: Option<&str>) -> Vec<String> {
+    let mut words = pager.map(split_words).filter(|words| !words.is_empty()).unwrap_or_else(|| vec!["less".to_string()]);
+    let is_less = words[0].rsplit('/').next() == Some("less");
+    if is_less && !words.iter().any(|w| w.starts_with('-') && !w.starts_with("--") && w.contains('R')) {
+        words.push("-R".to_string());
+    }
+    words
+}
+
+/// Show long text in the user's pager, returning to the caller once it is closed
+///
+/// Returns false without showing anything when stdout isn't a terminal, the text is
+/// short, or the pager can't be started, so the caller should print the text itself.
+pub fn page(text: &str) -> io::Result<bool> {
+    if !io::stdout().is_terminal() || text.lines().count() < PAGER_MIN_LINES {
+        return Ok(false);
+    }
+    
+    let words = pager_command(std::env::var("PAGER").ok().as_deref());
+    let mut child = match Command::new(&words[0]).args(&words[1..]).stdin(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(_) => return Ok(false),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // Quitting the pager before the end closes the pipe; that isn't an error
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(true)
+}
+
+/// Let the user edit a command, in `$EDITOR` when set or inline otherwise
+pub fn edit_command(command: &str) -> io::Result<String> {
+    match std::env::var("EDITOR") {
+        Ok(editor) if !editor.trim().is_empty() => edit_with_editor(&editor, command),
+        _ => edit_inline(command),
+    }
+}
+
+/// Open the command in an external editor (e.g. "vim" or "code --wait") and read it back
+///
+/// Every line of the edited file is kept, so a command can be turned into a multi-line
+/// script; an empty file keeps the original command.
+pub fn edit_with_editor(editor: &str, command: &str) -> io::Result<String> {
+    let words = split_words(editor);
+    let (program, args) = words
+        .split_first()
+        .ok_or_else(|| io::Error::other("Editor command is empty"))?;
+    
+    let (path, mut file) = create_edit_file()?;
+    let written = writeln!(file, "{}", command);
+    drop(file);
+    
+    let status = written.and_then(|_| Command::new(program).args(args).arg(&path).status());
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    
+    if !status?.success() {
+        return Err(io::Error::other(format!("Editor '{}' exited with an error", editor)));
+    }
+    let edited = edited?;
+    let edited = edited.trim();
+    Ok(if edited.is_empty() { command.to_string() } else { edited.to_string() })
+}
+
+/// Create a new, private temporary file for editing a command
+///
+/// The name is random and the file must not already exist, so another user can't
+/// plant or read it in a shared temp directory.
+fn create_edit_file() -> io::Result<(PathBuf, File)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        let path = std::env::temp_dir().join(format!("commandstrike-edit-{:016x}.sh", hasher.finish()));
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Ask for a replacement command on the terminal, keeping the original on empty input
+fn edit_inline(command: &str) -> io::Result<String> {
+    println!("{}: {}", "Current command".cyan(), command);
+    print!("New command (leave empty to keep): ");
+    io::stdout().flush()?;
+    
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(first_command_line(&line).unwrap_or_else(|| command.to_string()))
+}
+
+/// What happens after a command is generated in interactive mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DefaultAction {
+    /// Ask whether to execute, explain, skip or edit the command
+    #[default]
+    Menu,
+    /// Explain the command straight away, then show the menu
+    ExplainThenMenu,
+    /// Print the command and go straight back to the prompt
+    None,
+}
+
+impl DefaultAction {
+    /// Parse an action name: `menu`, `explain` or `none`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "menu" => Some(Self::Menu),
+            "explain" | "explain-then-menu" => Some(Self::ExplainThenMenu),
+            "none" => Some(Self::None),
+            _ => Option::None,
+        }
+    }
+}
+
+/// Write a one-shot command to `out`
+///
+/// In raw mode exactly the command bytes are written, with no trailing newline, so
+/// `$(command_strike --prompt ... --raw)` captures a clean value.
+pub fn write_command(out: &mut impl Write, command: &str, raw: bool) -> io::Result<()> {
+    if raw {
+        out.write_all(command.as_bytes())?;
+    } else {
+        writeln!(out, "{}", command)?;
+    }
+    out.flush()
+}
+
+/// What a piece of a command line is, for syntax highlighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// The program being run, at the start of the line or after an operator
+    Program,
+    Flag,
+    /// A quoted string, which may still be unterminated while a command streams in
+    Quoted,
+    /// A `$VARIABLE`, `[placeholder]` or `NAME=value` assignment
+    Variable,
+    /// Pipes, command separators and redirections
+    Operator,
+    Comment,
+    Plain,
+    Whitespace,
+}
+
+const SHELL_OPERATORS: &[&str] = &["|", "||", "&&", ";", "&", ">", ">>", "<", "2>", "2>&1", "&>", "|&"];
+
+/// Split a (possibly incomplete) command line into highlightable pieces
+///
+/// The pieces concatenate back to exactly `text`, so a command can be re-highlighted
+/// as each streamed token arrives.
+pub fn command_tokens(text: &str) -> Vec<(TokenKind, &str)> {
+    let mut tokens = Vec::new();
+    let mut expect_program = true;
+    let mut rest = text;
+    
+    while let Some(first) = rest.chars().next() {
+        let end = if first.is_whitespace() {
+            rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len())
+        } else if first == '#' {
+            rest.find('\n').unwrap_or(rest.len())
+        } else {
+            word_end(rest)
+        };
+        let (piece, remaining) = rest.split_at(end);
+        rest = remaining;
+        
+        let kind = if first.is_whitespace() {
+            if piece.contains('\n') {
+                expect_program = true;
+            }
+            TokenKind::Whitespace
+        } else if first == '#' {
+            TokenKind::Comment
+        } else if SHELL_OPERATORS.contains(&piece) {
+            expect_program = !piece.contains('>') && piece != "<";
+            TokenKind::Operator
+        } else if first == '\'' || first == '"' {
+            TokenKind::Quoted
+        } else if piece.starts_with('$') || piece.starts_with('[') || (expect_program && piece.contains('=')) {
+            TokenKind::Variable
+        } else if expect_program {
+            expect_program = false;
+            TokenKind::Program
+        } else if first == '-' {
+            TokenKind::Flag
+        } else {
+            TokenKind::Plain
+        };
+        tokens.push((kind, piece));
+    }
+    tokens
+}
+
+/// Length of the word at the start of `text`, keeping quoted whitespace and escapes inside it
+fn word_end(text: &str) -> usize {
+    let mut quote: Option<char> = None;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, c) if c.is_whitespace() => return i,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Colour a (possibly incomplete) command line for display
+pub fn highlight_command(text: &str) -> String {
+    command_tokens(text)
+        .into_iter()
+        .map(|(kind, piece)| match kind {
+            TokenKind::Program => piece.green().bold().to_string(),
+            TokenKind::Flag => piece.cyan().to_string(),
+            TokenKind::Quoted => piece.yellow().to_string(),
+            TokenKind::Variable => piece.magenta().to_string(),
+            TokenKind::Operator => piece.red().bold().to_string(),
+            TokenKind::Comment => piece.dimmed().to_string(),
+            TokenKind::Plain | TokenKind::Whitespace => piece.to_string(),
+        })
+        .collect()
+}
+
+/// The edited command: the first non-empty, non-comment line
+fn first_command_line(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_side_by_side() {
+        let layout = side_by_side(("llama3", "SSH is open on port 22"), ("qwen", "Outdated OpenSSH\nTry user enum"), 12);
+        assert_eq!(layout, [
+            "llama3       | qwen",
+            "------       | ----",
+            "SSH is open  | Outdated",
+            "on port 22   | OpenSSH",
+            "             | Try user",
+            "             | enum",
+        ].join("\n"));
+        assert_eq!(wrap("abcdefgh", 3), ["abc", "def", "gh"]);
+    }
+    
+    #[test]
+    fn test_pager_command() {
+        assert_eq!(pager_command(None), ["less", "-R"]);
+        assert_eq!(pager_command(Some("")), ["less", "-R"]);
+        assert_eq!(pager_command(Some("/usr/bin/less -SR")), ["/usr/bin/less", "-SR"]);
+        assert_eq!(pager_command(Some("less --quit-if-one-screen")), ["less", "--quit-if-one-screen", "-R"]);
+        assert_eq!(pager_command(Some("most")), ["most"]);
+        
+        // Short text and non-terminal output are never paged
+        assert!(!page("one line").unwrap());
+    }
+    
+    #[test]
+    fn test_parse_default_action() {
+        assert_eq!(DefaultAction::parse("Explain"), Some(DefaultAction::ExplainThenMenu));
+        assert_eq!(DefaultAction::parse("none"), Some(DefaultAction::None));
+        assert_eq!(DefaultAction::parse("menu"), Some(DefaultAction::Menu));
+        assert_eq!(DefaultAction::parse("run"), Option::None);
+        assert_eq!(DefaultAction::default(), DefaultAction::Menu);
+    }
+    
+    #[test]
+    fn test_token_progress() {
+        assert_eq!(
+            token_progress("Interpreting...", 342, Duration::from_secs(19)),
+            "Interpreting... (342 tokens, 18 tok/s)"
+        );
+        assert_eq!(token_progress("Interpreting...", 0, Duration::ZERO), "Interpreting... (0 tokens, 0 tok/s)");
+    }
+    
+    #[test]
+    fn test_edit_with_editor() {
+        let edited = edit_with_editor("sed -i s/-sS/-sV/", "nmap -sS 10.0.0.1").unwrap();
+        assert_eq!(edited, "nmap -sV 10.0.0.1");
+        
+        // An editor that empties the file keeps the original command
+        let edited = edit_with_editor("truncate -s 0", "nmap -sS 10.0.0.1").unwrap();
+        assert_eq!(edited, "nmap -sS 10.0.0.1");
+        
+        assert!(edit_with_editor("false", "id").is_err());
+        
+        // Lines after the first are kept rather than silently dropped
+        let edited = edit_with_editor("sed -i $a\\id", "whoami").unwrap();
+        assert_eq!(edited, "whoami\nid");
+    }
+    
+    #[test]
+    fn test_write_command_raw() {
+        let command = "nmap -sV -p 22,80 10.0.0.1";
+        
+        let mut raw = Vec::new();
+        write_command(&mut raw, command, true).unwrap();
+        assert_eq!(raw.len(), command.len());
+        
+        let mut plain = Vec::new();
+        write_command(&mut plain, command, false).unwrap();
+        assert_eq!(plain, format!("{}\n", command).into_bytes());
+    }
+    
+    #[test]
+    fn test_command_tokens() {
+        let command = "TERM=xterm nmap -sV -p 22 [target] | grep \"open port\" > out.txt # note";
+        let tokens = command_tokens(command);
+        assert_eq!(tokens.iter().map(|(_, piece)| *piece).collect::<String>(), command);
+        
+        let kinds: Vec<(TokenKind, &str)> = tokens.into_iter().filter(|(kind, _)| *kind != TokenKind::Whitespace).collect();
+        assert_eq!(kinds, [
+            (TokenKind::Variable, "TERM=xterm"),
+            (TokenKind::Program, "nmap"),
+            (TokenKind::Flag, "-sV"),
+            (TokenKind::Flag, "-p"),
+            (TokenKind::Plain, "22"),
+            (TokenKind::Variable, "[target]"),
+            (TokenKind::Operator, "|"),
+            (TokenKind::Program, "grep"),
+            (TokenKind::Quoted, "\"open port\""),
+            (TokenKind::Operator, ">"),
+            (TokenKind::Plain, "out.txt"),
+            (TokenKind::Comment, "# note"),
+        ]);
+        
+        // A half-streamed quote runs to the end of the text
+        assert_eq!(command_tokens("echo 'half done").last(), Some(&(TokenKind::Quoted, "'half done")));
+        assert_eq!(command_tokens("id\nwhoami")[2], (TokenKind::Program, "whoami"));
+    }
+    
+    #[test]
+    fn test_diff_words() {
+        let diff = diff_words("nmap -sS -p- 10.0.0.1", "nmap -sU --top-ports 100 10.0.0.1");
+        assert_eq!(diff, vec![
+            WordDiff::Same("nmap".to_string()),
+            WordDiff::Removed("-sS".to_string()),
+            WordDiff::Removed("-p-".to_string()),
+            WordDiff::Added("-sU".to_string()),
+            WordDiff::Added("--top-ports".to_string()),
+            WordDiff::Added("100".to_string()),
+            WordDiff::Same("10.0.0.1".to_string()),
+        ]);
+        assert!(diff_words("id", "id").iter().all(|w| matches!(w, WordDiff::Same(_))));
+    }
+}